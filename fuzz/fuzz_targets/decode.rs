@@ -0,0 +1,34 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use tifs::fs::dir::{decode, decode_item, encode};
+use tifs::fs::index::{HashedIndex, Index};
+use tifs::fs::inode::Inode;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(inode) = Inode::deserialize(data) {
+        assert_eq!(
+            inode,
+            Inode::deserialize(&inode.serialize().unwrap()).unwrap()
+        );
+    }
+    if let Ok(dir) = decode(data) {
+        assert_eq!(
+            encode(&dir).unwrap(),
+            encode(&decode(&encode(&dir).unwrap()).unwrap()).unwrap()
+        );
+    }
+    let _ = decode_item(data);
+    if let Ok(index) = Index::deserialize(data) {
+        assert_eq!(
+            index,
+            Index::deserialize(&index.serialize().unwrap()).unwrap()
+        );
+    }
+    if let Ok(index) = HashedIndex::deserialize(data) {
+        assert_eq!(
+            index,
+            HashedIndex::deserialize(&index.serialize().unwrap()).unwrap()
+        );
+    }
+});
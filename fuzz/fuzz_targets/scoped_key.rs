@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use tikv_client::Key;
+
+use tifs::fs::key::ScopedKey;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(key) = ScopedKey::parse(data) {
+        let encoded = Key::from(key);
+        let bytes: &[u8] = (&encoded).into();
+        assert_eq!(key.len(), bytes.len());
+        assert!(data.starts_with(bytes));
+        assert_eq!(key, ScopedKey::parse(bytes).unwrap());
+    }
+});
@@ -1,2 +1,6 @@
 #!/usr/bin/env sh
-install ./bin/release/mount /sbin/mount.tifs
+install ./bin/release/tifs /usr/local/bin/tifs
+for applet in mount.tifs mkfs.tifs fsck.tifs; do
+    ln -sf /usr/local/bin/tifs /sbin/$applet
+done
+ln -sf /usr/local/bin/tifs /usr/local/bin/tifs-admin
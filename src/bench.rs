@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use fuser::FileType;
+
+use crate::fs::async_fs::AsyncFileSystem;
+use crate::fs::key::ROOT_INODE;
+use crate::fs::mode::{make_mode, FileMode};
+use crate::fs::tikv_fs::TiFs;
+
+const DATA_FILE: &str = "data";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    Create,
+    Stat,
+    SeqWrite,
+    SeqRead,
+    RandWrite,
+    RandRead,
+    Unlink,
+}
+
+impl Workload {
+    /// All workloads, in the order they are run.
+    pub const ALL: [Workload; 7] = [
+        Workload::Create,
+        Workload::Stat,
+        Workload::SeqWrite,
+        Workload::SeqRead,
+        Workload::RandWrite,
+        Workload::RandRead,
+        Workload::Unlink,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        use Workload::*;
+
+        match self {
+            Create => "create",
+            Stat => "stat",
+            SeqWrite => "seq-write",
+            SeqRead => "seq-read",
+            RandWrite => "rand-write",
+            RandRead => "rand-read",
+            Unlink => "unlink",
+        }
+    }
+}
+
+impl FromStr for Workload {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::ALL
+            .iter()
+            .find(|workload| workload.name() == name)
+            .copied()
+            .ok_or_else(|| anyhow!("unknown workload `{}`", name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// Number of files used by the metadata workloads.
+    pub files: usize,
+    /// Size of the file used by the data workloads.
+    pub file_size: u64,
+    /// Size of each read or write.
+    pub io_size: usize,
+    /// Number of requests issued by the random workloads.
+    pub random_ops: usize,
+    pub workloads: Vec<Workload>,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            files: 1000,
+            file_size: 64 << 20,
+            io_size: 4 << 10,
+            random_ops: 1000,
+            workloads: Workload::ALL.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub workload: Workload,
+    pub latencies: Vec<Duration>,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    fn new(workload: Workload) -> Self {
+        Self {
+            workload,
+            latencies: Vec::new(),
+            bytes: 0,
+            elapsed: Duration::default(),
+        }
+    }
+
+    pub fn percentile(&self, p: f64) -> Duration {
+        percentile(&self.latencies, p)
+    }
+
+    pub fn print_header() {
+        println!(
+            "{:<12}{:>10}{:>12}{:>12}{:>12}{:>12}{:>12}",
+            "workload", "ops", "p50", "p90", "p99", "max", "MiB/s"
+        );
+    }
+
+    pub fn print(&self) {
+        let throughput = if self.bytes == 0 || self.elapsed.as_secs_f64() == 0.0 {
+            "-".to_owned()
+        } else {
+            format!(
+                "{:.2}",
+                self.bytes as f64 / (1 << 20) as f64 / self.elapsed.as_secs_f64()
+            )
+        };
+        println!(
+            "{:<12}{:>10}{:>12?}{:>12?}{:>12?}{:>12?}{:>12}",
+            self.workload.name(),
+            self.latencies.len(),
+            self.percentile(0.5),
+            self.percentile(0.9),
+            self.percentile(0.99),
+            self.percentile(1.0),
+            throughput,
+        );
+    }
+}
+
+pub fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::default();
+    }
+    let mut sorted = latencies.to_vec();
+    sorted.sort();
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+/// A volume to run the workloads against, all files live in one scratch directory.
+#[async_trait]
+pub trait BenchTarget: Send + Sync {
+    async fn create(&self, name: &str) -> Result<()>;
+    async fn stat(&self, name: &str) -> Result<()>;
+    async fn unlink(&self, name: &str) -> Result<()>;
+    async fn write_at(&self, name: &str, offset: u64, data: Vec<u8>) -> Result<()>;
+    async fn read_at(&self, name: &str, offset: u64, size: usize) -> Result<()>;
+    /// Remove the scratch directory.
+    async fn teardown(&self) -> Result<()>;
+}
+
+/// Runs the workloads through the kernel against a mounted volume.
+pub struct MountedTarget {
+    dir: PathBuf,
+    files: Mutex<HashMap<String, File>>,
+}
+
+impl MountedTarget {
+    pub fn new(mountpoint: PathBuf, dir_name: &str) -> Result<Self> {
+        let dir = mountpoint.join(dir_name);
+        fs::create_dir(&dir)?;
+        Ok(Self {
+            dir,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn with_file<T, F>(&self, name: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&File) -> std::io::Result<T>,
+    {
+        let files = self.files.lock().unwrap();
+        let file = files
+            .get(name)
+            .ok_or_else(|| anyhow!("file `{}` is not created", name))?;
+        Ok(f(file)?)
+    }
+}
+
+#[async_trait]
+impl BenchTarget for MountedTarget {
+    async fn create(&self, name: &str) -> Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(self.dir.join(name))?;
+        self.files.lock().unwrap().insert(name.to_owned(), file);
+        Ok(())
+    }
+
+    async fn stat(&self, name: &str) -> Result<()> {
+        fs::metadata(self.dir.join(name))?;
+        Ok(())
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        self.files.lock().unwrap().remove(name);
+        fs::remove_file(self.dir.join(name))?;
+        Ok(())
+    }
+
+    async fn write_at(&self, name: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+        self.with_file(name, |file| file.write_all_at(&data, offset))
+    }
+
+    async fn read_at(&self, name: &str, offset: u64, size: usize) -> Result<()> {
+        let mut buffer = vec![0; size];
+        self.with_file(name, |file| file.read_at(&mut buffer, offset))?;
+        Ok(())
+    }
+
+    async fn teardown(&self) -> Result<()> {
+        self.files.lock().unwrap().clear();
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// Runs the workloads against the library directly, bypassing FUSE and the kernel.
+pub struct LibraryTarget {
+    fs: TiFs,
+    dir_name: String,
+    dir: u64,
+    uid: u32,
+    gid: u32,
+    // name -> (ino, fh)
+    handles: Mutex<HashMap<String, (u64, u64)>>,
+}
+
+impl LibraryTarget {
+    pub async fn new(fs: TiFs, dir_name: &str) -> Result<Self> {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        fs.init_volume(gid, uid, FileMode::DEFAULT_ROOT).await?;
+        let dir = fs
+            .mkdir(ROOT_INODE, dir_name.into(), 0o755, gid, uid, 0)
+            .await?
+            .stat
+            .ino;
+        Ok(Self {
+            fs,
+            dir_name: dir_name.to_owned(),
+            dir,
+            uid,
+            gid,
+            handles: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn handle(&self, name: &str) -> Result<(u64, u64)> {
+        self.handles
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("file `{}` is not created", name))
+    }
+}
+
+#[async_trait]
+impl BenchTarget for LibraryTarget {
+    async fn create(&self, name: &str) -> Result<()> {
+        let created = self
+            .fs
+            .create(
+                self.uid,
+                self.gid,
+                self.dir,
+                name.into(),
+                make_mode(FileType::RegularFile, 0o644),
+                0,
+                libc::O_RDWR,
+            )
+            .await?;
+        self.handles
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), (created.attr.ino, created.fh));
+        Ok(())
+    }
+
+    async fn stat(&self, name: &str) -> Result<()> {
+        let entry = self.fs.lookup(self.dir, name.into()).await?;
+        self.fs.getattr(entry.stat.ino).await?;
+        Ok(())
+    }
+
+    async fn unlink(&self, name: &str) -> Result<()> {
+        let (ino, fh) = self.handle(name)?;
+        self.handles.lock().unwrap().remove(name);
+        self.fs.release(ino, fh, 0, None, false).await?;
+        self.fs.unlink(0, self.dir, name.into()).await?;
+        Ok(())
+    }
+
+    async fn write_at(&self, name: &str, offset: u64, data: Vec<u8>) -> Result<()> {
+        let (ino, fh) = self.handle(name)?;
+        self.fs
+            .write(ino, fh, offset as i64, data, 0, 0, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn read_at(&self, name: &str, offset: u64, size: usize) -> Result<()> {
+        let (ino, fh) = self.handle(name)?;
+        self.fs
+            .read(ino, fh, offset as i64, size as u32, 0, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn teardown(&self) -> Result<()> {
+        let handles: Vec<_> = self.handles.lock().unwrap().drain().collect();
+        for (name, (ino, fh)) in handles {
+            self.fs.release(ino, fh, 0, None, false).await?;
+            self.fs.unlink(0, self.dir, name.into()).await?;
+        }
+        self.fs
+            .rmdir(0, ROOT_INODE, self.dir_name.as_str().into())
+            .await?;
+        Ok(())
+    }
+}
+
+/// xorshift64, good enough to pick offsets.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+async fn timed<F>(report: &mut Report, bytes: u64, f: F) -> Result<()>
+where
+    F: std::future::Future<Output = Result<()>>,
+{
+    let start = Instant::now();
+    f.await?;
+    report.latencies.push(start.elapsed());
+    report.bytes += bytes;
+    Ok(())
+}
+
+fn file_name(index: usize) -> String {
+    format!("file-{}", index)
+}
+
+pub async fn run(target: &dyn BenchTarget, cfg: &BenchConfig) -> Result<Vec<Report>> {
+    if cfg.io_size == 0 || cfg.file_size < cfg.io_size as u64 {
+        return Err(anyhow!(
+            "io size({}) must be in (0, file size({})]",
+            cfg.io_size,
+            cfg.file_size
+        ));
+    }
+
+    let enabled = |workload| cfg.workloads.contains(&workload);
+    let io_size = cfg.io_size as u64;
+    let chunks = cfg.file_size / io_size;
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut reports = Vec::new();
+
+    // metadata workloads depend on the files being created, so creation always runs.
+    let mut report = Report::new(Workload::Create);
+    let start = Instant::now();
+    for i in 0..cfg.files {
+        timed(&mut report, 0, target.create(&file_name(i))).await?;
+    }
+    report.elapsed = start.elapsed();
+    if enabled(Workload::Create) {
+        reports.push(report);
+    }
+
+    if enabled(Workload::Stat) {
+        let mut report = Report::new(Workload::Stat);
+        let start = Instant::now();
+        for i in 0..cfg.files {
+            timed(&mut report, 0, target.stat(&file_name(i))).await?;
+        }
+        report.elapsed = start.elapsed();
+        reports.push(report);
+    }
+
+    // the read workloads depend on the data file being written, so writing always runs.
+    target.create(DATA_FILE).await?;
+    let mut report = Report::new(Workload::SeqWrite);
+    let start = Instant::now();
+    for chunk in 0..chunks {
+        let data = vec![chunk as u8; cfg.io_size];
+        timed(
+            &mut report,
+            io_size,
+            target.write_at(DATA_FILE, chunk * io_size, data),
+        )
+        .await?;
+    }
+    report.elapsed = start.elapsed();
+    if enabled(Workload::SeqWrite) {
+        reports.push(report);
+    }
+
+    if enabled(Workload::SeqRead) {
+        let mut report = Report::new(Workload::SeqRead);
+        let start = Instant::now();
+        for chunk in 0..chunks {
+            timed(
+                &mut report,
+                io_size,
+                target.read_at(DATA_FILE, chunk * io_size, cfg.io_size),
+            )
+            .await?;
+        }
+        report.elapsed = start.elapsed();
+        reports.push(report);
+    }
+
+    if enabled(Workload::RandWrite) {
+        let mut report = Report::new(Workload::RandWrite);
+        let start = Instant::now();
+        for _ in 0..cfg.random_ops {
+            let offset = rng.next() % chunks * io_size;
+            let data = vec![offset as u8; cfg.io_size];
+            timed(
+                &mut report,
+                io_size,
+                target.write_at(DATA_FILE, offset, data),
+            )
+            .await?;
+        }
+        report.elapsed = start.elapsed();
+        reports.push(report);
+    }
+
+    if enabled(Workload::RandRead) {
+        let mut report = Report::new(Workload::RandRead);
+        let start = Instant::now();
+        for _ in 0..cfg.random_ops {
+            let offset = rng.next() % chunks * io_size;
+            timed(
+                &mut report,
+                io_size,
+                target.read_at(DATA_FILE, offset, cfg.io_size),
+            )
+            .await?;
+        }
+        report.elapsed = start.elapsed();
+        reports.push(report);
+    }
+
+    target.unlink(DATA_FILE).await?;
+    if enabled(Workload::Unlink) {
+        let mut report = Report::new(Workload::Unlink);
+        let start = Instant::now();
+        for i in 0..cfg.files {
+            timed(&mut report, 0, target.unlink(&file_name(i))).await?;
+        }
+        report.elapsed = start.elapsed();
+        reports.push(report);
+    }
+
+    target.teardown().await?;
+    Ok(reports)
+}
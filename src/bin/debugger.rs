@@ -4,13 +4,12 @@ use std::io::{stdin, stdout, BufRead, BufReader, Write};
 use anyhow::{anyhow, Result};
 use clap::{crate_version, App, Arg};
 use tikv_client::TransactionClient;
-use tracing_libatrace as tracing_atrace;
-use tracing_subscriber::{layer::SubscriberExt, registry::Registry};
 
 use tifs::fs::inode::Inode;
 use tifs::fs::key::{ScopedKey, ROOT_INODE};
 use tifs::fs::tikv_fs::TiFs;
 use tifs::fs::transaction::Txn;
+use tifs::telemetry::setup_global_subscriber;
 
 #[async_std::main]
 async fn main() -> Result<()> {
@@ -28,7 +27,7 @@ async fn main() -> Result<()> {
         )
         .get_matches();
 
-    setup_global_subscriber();
+    let _telemetry = setup_global_subscriber();
 
     let endpoints: Vec<&str> = matches
         .values_of("pd")
@@ -47,14 +46,6 @@ async fn main() -> Result<()> {
     }
 }
 
-fn setup_global_subscriber() {
-    let layer = tracing_atrace::layer()
-        .unwrap()
-        .with_data_field(Option::Some("data".to_string()));
-    let subscriber = Registry::default().with(layer);
-    tracing::subscriber::set_global_default(subscriber).unwrap();
-}
-
 struct Console {
     pd_endpoints: Vec<String>,
     client: TransactionClient,
@@ -0,0 +1,185 @@
+//! The `tifs` binary, a multicall binary which mounts, formats, checks and administers volumes.
+//!
+//! It dispatches on the name it is invoked by, so it can be installed as `mount.tifs`,
+//! `mkfs.tifs`, `fsck.tifs` and `tifs-admin` as well.
+pub mod admin;
+pub mod bench;
+pub mod completions;
+pub mod fsck;
+pub mod load;
+pub mod manpage;
+pub mod mkfs;
+pub mod mount;
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+use clap::ArgMatches;
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::fs::tikv_fs::TiFs;
+use crate::{load_client_config, MountOption};
+
+/// Names the binary can be invoked by, and the subcommands they stand for.
+const APPLETS: &[(&str, &str)] = &[
+    ("mount.tifs", "mount"),
+    ("mkfs.tifs", "mkfs"),
+    ("fsck.tifs", "fsck"),
+    ("tifs-admin", "admin"),
+];
+
+/// The subcommand standing for the name the binary is invoked by, if any.
+pub fn applet(argv0: &OsStr) -> Option<&'static str> {
+    let name = Path::new(argv0).file_name()?;
+    APPLETS
+        .iter()
+        .find(|(applet, _)| name == OsStr::new(applet))
+        .map(|(_, subcommand)| *subcommand)
+}
+
+/// Insert the subcommand of the applet after the name of the binary, so `mount.tifs <args>`
+/// is parsed as `tifs mount <args>`.
+pub fn expand_applet<I: IntoIterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.into_iter().collect();
+    if let Some(subcommand) = args.first().and_then(|argv0| applet(argv0)) {
+        args.insert(1, subcommand.into());
+    }
+    args
+}
+
+/// Subcommands of `tifs`, each section of the man page is one of them.
+pub const SUBCOMMANDS: &[&str] = &[
+    "mount",
+    "mkfs",
+    "fsck",
+    "admin",
+    "bench",
+    "load",
+    "completions",
+    "manpage",
+];
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "tifs",
+    author = "Hexi Lee",
+    about = "A distributed POSIX filesystem based on TiKV"
+)]
+pub enum Tifs {
+    #[structopt(about = "Mount a volume, also invoked as `mount.tifs` by mount(8)")]
+    Mount(mount::Mount),
+    #[structopt(
+        about = "Initialize a volume with the block size of `-o blksize`, also invoked as `mkfs.tifs`. `-o journal`, `-o inode_reuse`, `-o no_inline` and `-o hashed_names` are turned on for all mounts of a new volume"
+    )]
+    Mkfs(mkfs::Mkfs),
+    #[structopt(
+        about = "Check the directory tree of an unmounted volume, also invoked as `fsck.tifs`"
+    )]
+    Fsck(fsck::Fsck),
+    #[structopt(about = "Administer a volume, also invoked as `tifs-admin`")]
+    Admin(admin::Admin),
+    #[structopt(about = "Run micro benchmarks and print latency percentiles")]
+    Bench(bench::Bench),
+    #[structopt(about = "Generate synthetic load on the transaction layer, bypassing FUSE")]
+    Load(load::Load),
+    #[structopt(about = "Print the completion script of a shell")]
+    Completions(completions::Completions),
+    #[structopt(about = "Print the man page of tifs in roff")]
+    Manpage,
+}
+
+/// Parse the command line, with `--json` given to any of the nested subcommands.
+pub fn parse<I: IntoIterator<Item = OsString>>(args: I) -> (Tifs, bool) {
+    let matches = Tifs::clap().get_matches_from(expand_applet(args));
+    let json = json_requested(&matches);
+    (Tifs::from_clap(&matches), json)
+}
+
+pub async fn run(command: Tifs, json: bool) -> anyhow::Result<()> {
+    match command {
+        Tifs::Mount(mount) => mount::run(mount).await,
+        Tifs::Mkfs(mkfs) => mkfs::run(mkfs).await,
+        Tifs::Fsck(fsck) => fsck::run(fsck, json).await,
+        Tifs::Admin(admin) => admin::run(admin, json).await,
+        Tifs::Bench(bench) => bench::run(bench).await,
+        Tifs::Load(load) => load::run(load).await,
+        Tifs::Completions(completions) => completions::run(completions),
+        Tifs::Manpage => manpage::run(),
+    }
+}
+
+/// The `-o` options of a command. Each `-o` takes one value, so positional arguments can
+/// follow it.
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    #[structopt(
+        short = "o",
+        long = "option",
+        value_name = "OPTION",
+        number_of_values = 1,
+        help = "filesystem mount options"
+    )]
+    pub values: Vec<String>,
+}
+
+impl Options {
+    /// Parse the options, exiting with a usage error if any of them is invalid.
+    pub fn parse(&self) -> Vec<MountOption> {
+        MountOption::parse_all(self.values.iter().map(String::as_str)).unwrap_or_else(|err| {
+            clap::Error::with_description(&err.to_string(), clap::ErrorKind::InvalidValue).exit()
+        })
+    }
+}
+
+/// The volume a command connects to, by the `-p` endpoints and `-o` options.
+#[derive(Debug, StructOpt)]
+pub struct Volume {
+    #[structopt(
+        short = "p",
+        long = "pd-endpoints",
+        value_name = "ENDPOINTS",
+        default_value = "127.0.0.1:2379",
+        help = "set all pd endpoints of the tikv cluster"
+    )]
+    pub pd: Vec<String>,
+    #[structopt(flatten)]
+    pub options: Options,
+}
+
+impl Volume {
+    pub async fn connect(&self) -> anyhow::Result<TiFs> {
+        let options = self.options.parse();
+        let client_cfg = load_client_config(&options).await?;
+        TiFs::construct(self.pd.clone(), client_cfg, options).await
+    }
+}
+
+/// Whether `--json` is given to the command or any of its nested subcommands, clap only
+/// passes global flags down to the subcommands.
+pub fn json_requested(matches: &ArgMatches<'_>) -> bool {
+    matches.is_present("json") || matches.subcommand().1.map_or(false, json_requested)
+}
+
+pub fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+/// Size the worker threads of async-std by `-o fuse_threads` of a mount, before the runtime
+/// starts. The session reads requests in one thread and serves each of them as a task on
+/// these threads, so they bound how many requests are served in parallel.
+pub fn configure_runtime(command: &Tifs) {
+    if let Tifs::Mount(mount) = command {
+        let threads = mount.options.parse().into_iter().find_map(|option| {
+            if let MountOption::FuseThreads(threads) = option {
+                Some(threads)
+            } else {
+                None
+            }
+        });
+        if let Some(threads) = threads {
+            std::env::set_var("ASYNC_STD_THREAD_COUNT", threads.to_string());
+        }
+    }
+}
@@ -0,0 +1,763 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::json;
+use structopt::StructOpt;
+use tar::{Archive, Builder};
+
+use super::{print_json, Volume};
+use crate::fs::archive::{export, import};
+use crate::fs::async_fs::AsyncFileSystem;
+use crate::fs::backup::{backup, read_base, restore};
+use crate::fs::copy::copy_tree;
+use crate::fs::hot::{report, FileUsage};
+use crate::fs::key::ROOT_INODE;
+use crate::fs::mode::FileMode;
+use crate::fs::snapshot::{parse_timestamp, versions};
+use crate::fs::tikv_fs::TiFs;
+use crate::{load_client_config, MountOption};
+
+#[derive(Debug, StructOpt)]
+pub struct Admin {
+    #[structopt(flatten)]
+    pub volume: Volume,
+    #[structopt(long, global = true, help = "print results as JSON")]
+    pub json: bool,
+    #[structopt(subcommand)]
+    pub command: AdminCommand,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AdminCommand {
+    #[structopt(
+        about = "List earlier versions of a file, readable as `<file>@<timestamp>` in the mount"
+    )]
+    Versions {
+        #[structopt(
+            value_name = "PATH",
+            help = "path of the file, relative to the root of the volume"
+        )]
+        path: String,
+        #[structopt(
+            long,
+            value_name = "COUNT",
+            default_value = "20",
+            help = "max number of versions to list"
+        )]
+        limit: usize,
+    },
+    #[structopt(about = "Tail the change journal, which is recorded by mounts with `-o journal`")]
+    Watch {
+        #[structopt(
+            long,
+            value_name = "SEQ",
+            default_value = "0",
+            help = "sequence number of the first entry to print"
+        )]
+        from: u64,
+        #[structopt(
+            long,
+            value_name = "MILLIS",
+            default_value = "1000",
+            help = "milliseconds to wait before polling again at the end of the journal"
+        )]
+        interval: u64,
+    },
+    #[structopt(about = "Export a directory as a tar archive, keeping hard links")]
+    Export {
+        #[structopt(
+            value_name = "PATH",
+            help = "path of the directory, relative to the root of the volume"
+        )]
+        path: String,
+        #[structopt(
+            short = "f",
+            long,
+            value_name = "FILE",
+            default_value = "-",
+            help = "archive to write, `-` for stdout"
+        )]
+        file: String,
+    },
+    #[structopt(about = "Import a tar archive into a directory, keeping hard links")]
+    Import {
+        #[structopt(
+            value_name = "PATH",
+            help = "path of the directory, relative to the root of the volume"
+        )]
+        path: String,
+        #[structopt(
+            short = "f",
+            long,
+            value_name = "FILE",
+            default_value = "-",
+            help = "archive to read, `-` for stdin"
+        )]
+        file: String,
+    },
+    #[structopt(about = "Manage the safepoints which `gc` keeps MVCC versions for")]
+    Safepoint(SafepointCommand),
+    #[structopt(about = "Show or change the options applied to every mount of the volume")]
+    Config {
+        #[structopt(
+            value_name = "OPTION=on|off|unset",
+            help = "journal, inode_reuse or no_inline, taking effect for new mounts"
+        )]
+        settings: Vec<String>,
+    },
+    #[structopt(about = "Change the owner and mode of the root directory")]
+    Root {
+        #[structopt(long, value_name = "UID", help = "new owner of the root directory")]
+        uid: Option<u32>,
+        #[structopt(long, value_name = "GID", help = "new group of the root directory")]
+        gid: Option<u32>,
+        #[structopt(
+            long,
+            value_name = "MODE",
+            help = "new mode of the root directory in octal"
+        )]
+        mode: Option<FileMode>,
+    },
+    #[structopt(about = "Change the owner, group or mode of a file, or of a whole tree with -R")]
+    Chown {
+        #[structopt(value_name = "PATH", help = "path in the volume")]
+        path: String,
+        #[structopt(short = "R", long, help = "change everything under the directory too")]
+        recursive: bool,
+        #[structopt(long, value_name = "UID", help = "new owner")]
+        uid: Option<u32>,
+        #[structopt(long, value_name = "GID", help = "new group")]
+        gid: Option<u32>,
+        #[structopt(
+            long,
+            value_name = "MODE",
+            help = "new mode in octal, of files and directories alike"
+        )]
+        mode: Option<FileMode>,
+    },
+    #[structopt(
+        about = "Copy the entries of a directory into another, e.g. of a volume on another cluster"
+    )]
+    Copy {
+        #[structopt(
+            value_name = "PATH",
+            help = "path of the directory to copy, relative to the root of the volume"
+        )]
+        path: String,
+        #[structopt(
+            value_name = "DEST",
+            help = "path of the directory to copy into, relative to the root of the target"
+        )]
+        dest: String,
+        #[structopt(
+            long = "to-pd-endpoints",
+            value_name = "ENDPOINTS",
+            help = "pd endpoints of the target volume, the source volume if not given"
+        )]
+        to_pd: Vec<String>,
+        #[structopt(
+            long = "to-option",
+            value_name = "OPTION",
+            number_of_values = 1,
+            help = "mount options of the target volume, e.g. `tls=...`"
+        )]
+        to_options: Vec<String>,
+    },
+    #[structopt(about = "Resolve the path of an inode, e.g. of a file handle or an fsck finding")]
+    Path {
+        #[structopt(value_name = "INO", help = "inode number")]
+        ino: u64,
+    },
+    #[structopt(about = "Show the cumulative statistics of the volume, persisted by its mounts")]
+    Info {
+        #[structopt(
+            long,
+            help = "count the files and blocks by scanning all inodes, and correct the counters statfs reports"
+        )]
+        exact: bool,
+    },
+    #[structopt(about = "Report the largest files and the files changed most in the journal")]
+    Hot {
+        #[structopt(
+            long,
+            value_name = "FILES",
+            default_value = "20",
+            help = "files listed in each ranking"
+        )]
+        top: usize,
+        #[structopt(
+            long,
+            value_name = "ENTRIES",
+            default_value = "65536",
+            help = "latest journal entries counted as changes"
+        )]
+        recent: u64,
+    },
+    #[structopt(about = "Hold the writes of every mount, e.g. while taking a backup of TiKV")]
+    Freeze {
+        #[structopt(
+            long,
+            value_name = "SECONDS",
+            default_value = "600",
+            help = "seconds after which the volume is thawed if `thaw` is not run"
+        )]
+        timeout: u64,
+        #[structopt(
+            long,
+            value_name = "SECONDS",
+            default_value = "30",
+            help = "seconds to wait for the writes in progress to finish"
+        )]
+        wait: u64,
+    },
+    #[structopt(about = "Let mounts write again after `freeze`")]
+    Thaw,
+    #[structopt(about = "Advance the GC safepoint of TiKV, keeping versions needed by safepoints")]
+    Gc {
+        #[structopt(
+            long,
+            value_name = "SECONDS",
+            default_value = "600",
+            help = "seconds of history to keep"
+        )]
+        life_time: u64,
+    },
+    #[structopt(about = "Back up the whole volume at a single timestamp")]
+    Backup {
+        #[structopt(
+            long,
+            value_name = "FILE",
+            help = "file to write the backup into, `-` for stdout"
+        )]
+        to: String,
+        #[structopt(
+            long,
+            value_name = "FILE",
+            help = "earlier backup to make an incremental backup based on"
+        )]
+        base: Option<String>,
+    },
+    #[structopt(
+        about = "Restore a full backup and its incremental backups into an uninitialized volume"
+    )]
+    Restore {
+        #[structopt(
+            long,
+            value_name = "FILE",
+            required = true,
+            help = "the full backup followed by incremental backups in order"
+        )]
+        from: Vec<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum SafepointCommand {
+    #[structopt(about = "List registered safepoints")]
+    List,
+    #[structopt(about = "Register or move the safepoint of a service, e.g. of BR")]
+    Set {
+        #[structopt(value_name = "SERVICE")]
+        service: String,
+        #[structopt(value_name = "TIME", help = "TiKV timestamp, unix seconds or UTC time")]
+        time: String,
+        #[structopt(
+            long,
+            value_name = "SECONDS",
+            default_value = "86400",
+            help = "seconds to keep the safepoint"
+        )]
+        ttl: u64,
+    },
+    #[structopt(about = "Remove the safepoint of a service")]
+    Remove {
+        #[structopt(value_name = "SERVICE")]
+        service: String,
+    },
+}
+
+pub async fn run(admin: Admin, json: bool) -> Result<()> {
+    let fs = &admin.volume.connect().await?;
+
+    match admin.command {
+        AdminCommand::Versions { path, limit } => list_versions(fs, &path, limit, json).await,
+        AdminCommand::Watch { from, interval } => {
+            watch(fs, from, Duration::from_millis(interval), json).await
+        }
+        AdminCommand::Export { path, file } => export_archive(fs, &path, &file, json).await,
+        AdminCommand::Import { path, file } => import_archive(fs, &path, &file, json).await,
+        AdminCommand::Safepoint(command) => safepoint(fs, command, json).await,
+        AdminCommand::Config { settings } => volume_config(fs, &settings, json).await,
+        AdminCommand::Root { uid, gid, mode } => set_root(fs, uid, gid, mode, json).await,
+        AdminCommand::Chown {
+            path,
+            recursive,
+            uid,
+            gid,
+            mode,
+        } => chown(fs, &path, recursive, uid, gid, mode, json).await,
+        AdminCommand::Copy {
+            path,
+            dest,
+            to_pd,
+            to_options,
+        } => copy(fs, &path, &dest, to_pd, &to_options, json).await,
+        AdminCommand::Path { ino } => resolve_path(fs, ino, json).await,
+        AdminCommand::Info { exact } => info(fs, exact, json).await,
+        AdminCommand::Hot { top, recent } => hot(fs, top, recent, json).await,
+        AdminCommand::Freeze { timeout, wait } => {
+            let (timeout, wait) = (Duration::from_secs(timeout), Duration::from_secs(wait));
+            freeze(fs, timeout, wait, json).await
+        }
+        AdminCommand::Thaw => Ok(fs.thaw_volume().await?),
+        AdminCommand::Gc { life_time } => gc(fs, Duration::from_secs(life_time), json).await,
+        AdminCommand::Backup { to, base } => backup_volume(fs, &to, base.as_deref(), json).await,
+        AdminCommand::Restore { from } => restore_volume(fs, &from, json).await,
+    }
+}
+
+/// Stats are printed to stderr, unless they are printed as JSON and stdout is not taken by
+/// the data of the command.
+fn print_stats<T: Debug + Serialize>(stats: &T, json: bool, data_on_stdout: bool) -> Result<()> {
+    if !json {
+        eprintln!("{:?}", stats);
+    } else if data_on_stdout {
+        eprintln!("{}", serde_json::to_string(stats)?);
+    } else {
+        print_json(stats)?;
+    }
+    Ok(())
+}
+
+async fn list_versions(fs: &TiFs, path: &str, limit: usize, json: bool) -> Result<()> {
+    let ino = fs.resolve_path(path).await?;
+    let versions = versions(&fs.client, ino, fs.block_size, limit).await?;
+
+    if json {
+        let versions: Vec<_> = versions
+            .iter()
+            .map(|version| {
+                let modified = version
+                    .inode
+                    .mtime
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                json!({
+                    "timestamp": version.timestamp,
+                    "modified": modified.as_secs_f64(),
+                    "size": version.inode.size,
+                })
+            })
+            .collect();
+        return print_json(&versions);
+    }
+
+    println!("{:>20} {:>20} {:>16}", "timestamp", "modified", "size");
+    for version in versions {
+        let modified = version
+            .inode
+            .mtime
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        println!(
+            "{:>20} {:>16}.{:03} {:>16}",
+            version.timestamp,
+            modified.as_secs(),
+            modified.subsec_millis(),
+            version.inode.size
+        );
+    }
+    Ok(())
+}
+
+async fn watch(fs: &TiFs, from: u64, interval: Duration, json: bool) -> Result<()> {
+    fs.tail_journal(from, interval, |entry| {
+        if !json {
+            println!("{}", entry);
+            return true;
+        }
+        match print_json(&entry) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("{}", err);
+                false
+            }
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+async fn export_archive(fs: &TiFs, path: &str, file: &str, json: bool) -> Result<()> {
+    let ino = fs.resolve_path(path).await?;
+    let writer: Box<dyn Write> = match file {
+        "-" => Box::new(io::stdout()),
+        file => Box::new(File::create(file)?),
+    };
+    let mut builder = Builder::new(BufWriter::new(writer));
+    let stats = export(fs, ino, &mut builder).await?;
+    print_stats(&stats, json, file == "-")
+}
+
+async fn import_archive(fs: &TiFs, path: &str, file: &str, json: bool) -> Result<()> {
+    let ino = fs.resolve_path(path).await?;
+    let reader: Box<dyn Read> = match file {
+        "-" => Box::new(io::stdin()),
+        file => Box::new(File::open(file)?),
+    };
+    let mut archive = Archive::new(BufReader::new(reader));
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let stats = import(fs, ino, &mut archive, uid, gid).await?;
+    print_stats(&stats, json, false)
+}
+
+async fn safepoint(fs: &TiFs, command: SafepointCommand, json: bool) -> Result<()> {
+    match command {
+        SafepointCommand::List if json => {
+            let safepoints: Vec<_> = fs
+                .safepoints()
+                .await?
+                .iter()
+                .map(|safepoint| {
+                    let expires = safepoint
+                        .expires
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    json!({
+                        "service": safepoint.service,
+                        "timestamp": safepoint.timestamp,
+                        "expires": expires.as_secs(),
+                        "expired": safepoint.is_expired(),
+                    })
+                })
+                .collect();
+            print_json(&safepoints)?;
+        }
+        SafepointCommand::List => {
+            println!("{:<24} {:>20} {:>16}", "service", "timestamp", "expires");
+            for safepoint in fs.safepoints().await? {
+                let expires = safepoint
+                    .expires
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                println!(
+                    "{:<24} {:>20} {:>16}{}",
+                    safepoint.service,
+                    safepoint.timestamp,
+                    expires.as_secs(),
+                    if safepoint.is_expired() {
+                        " (expired)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        SafepointCommand::Set { service, time, ttl } => {
+            let ts = parse_timestamp(&time).ok_or_else(|| anyhow!("invalid time: {}", time))?;
+            fs.register_safepoint(&service, ts, Duration::from_secs(ttl))
+                .await?;
+        }
+        SafepointCommand::Remove { service } => fs.remove_safepoint(&service).await?,
+    }
+    Ok(())
+}
+
+async fn volume_config(fs: &TiFs, settings: &[String], json: bool) -> Result<()> {
+    let mut config = fs.volume_config().await?;
+    if !settings.is_empty() {
+        for setting in settings {
+            config.set(setting)?;
+        }
+        fs.set_volume_config(config).await?;
+    }
+    if json {
+        print_json(&config)?;
+    } else {
+        println!("{}", config);
+    }
+    Ok(())
+}
+
+async fn set_root(
+    fs: &TiFs,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mode: Option<FileMode>,
+    json: bool,
+) -> Result<()> {
+    let attr = fs.set_root_attr(uid, gid, mode).await?;
+    let mode = FileMode(attr.perm);
+    if json {
+        print_json(&json!({ "uid": attr.uid, "gid": attr.gid, "mode": mode.to_string() }))?;
+    } else {
+        println!("root: uid={} gid={} mode={}", attr.uid, attr.gid, mode);
+    }
+    Ok(())
+}
+
+async fn chown(
+    fs: &TiFs,
+    path: &str,
+    recursive: bool,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mode: Option<FileMode>,
+    json: bool,
+) -> Result<()> {
+    if uid.is_none() && gid.is_none() && mode.is_none() {
+        return Err(anyhow!("nothing to change, pass --uid, --gid or --mode"));
+    }
+    let ino = fs.resolve_path(path).await?;
+    let changed = fs.chown_tree(ino, uid, gid, mode, recursive).await?;
+    if json {
+        print_json(&json!({ "changed": changed }))?;
+    } else {
+        println!("{} inodes changed", changed);
+    }
+    Ok(())
+}
+
+/// The target volume is the source one unless `to_pd` is given.
+async fn copy(
+    fs: &TiFs,
+    path: &str,
+    dest: &str,
+    to_pd: Vec<String>,
+    to_options: &[String],
+    json: bool,
+) -> Result<()> {
+    let root = fs.resolve_path(path).await?;
+    let target = if to_pd.is_empty() {
+        None
+    } else {
+        let options = MountOption::parse_all(to_options.iter().map(String::as_str))?;
+        let client_cfg = load_client_config(&options).await?;
+        Some(TiFs::construct(to_pd, client_cfg, options).await?)
+    };
+    let dst = target.as_ref().unwrap_or(fs);
+    let dest = dst.resolve_path(dest).await?;
+    let stats = copy_tree(fs, root, dst, dest).await?;
+    print_stats(&stats, json, false)
+}
+
+async fn resolve_path(fs: &TiFs, ino: u64, json: bool) -> Result<()> {
+    let path = fs.path_of(ino).await?;
+    if json {
+        print_json(&json!({ "ino": ino, "path": path }))?;
+    } else {
+        println!("{}", path);
+    }
+    Ok(())
+}
+
+async fn info(fs: &TiFs, exact: bool, json: bool) -> Result<()> {
+    if exact {
+        fs.rebase_usage().await?;
+    }
+    let (mounts, stats) = fs.volume_stats().await?;
+    let usage = fs.statfs(ROOT_INODE).await?;
+    let secs = |time: Option<SystemTime>| {
+        time.map(|time| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        })
+    };
+    if json {
+        return print_json(&json!({
+            "mounts": mounts,
+            "since": secs(stats.since),
+            "updated": secs(stats.updated),
+            "files": usage.files,
+            "blocks": usage.blocks,
+            "block_size": usage.bsize,
+            "files_high": stats.files_high,
+            "blocks_high": stats.blocks_high,
+            "ops": stats.ops,
+            "gets": stats.gets,
+            "puts": stats.puts,
+            "deletes": stats.deletes,
+            "scans": stats.scans,
+            "read_bytes": stats.read_bytes,
+            "written_bytes": stats.written_bytes,
+            "retries": stats.retries,
+            "meta_retries": stats.meta_retries,
+            "dir_retries": stats.dir_retries,
+            "inode_retries": stats.inode_retries,
+            "block_retries": stats.block_retries,
+        }));
+    }
+
+    let or_never = |secs: Option<u64>| secs.map_or("never".to_owned(), |secs| secs.to_string());
+    println!("mounts:        {}", mounts);
+    println!("since:         {}", or_never(secs(stats.since)));
+    println!("updated:       {}", or_never(secs(stats.updated)));
+    println!("files:         {} (high {})", usage.files, stats.files_high);
+    println!(
+        "blocks:        {} (high {}) of {} bytes",
+        usage.blocks, stats.blocks_high, usage.bsize
+    );
+    println!("ops:           {}", stats.ops);
+    println!(
+        "round trips:   {} gets, {} puts, {} deletes, {} scans",
+        stats.gets, stats.puts, stats.deletes, stats.scans
+    );
+    println!("read bytes:    {}", stats.read_bytes);
+    println!("written bytes: {}", stats.written_bytes);
+    println!(
+        "retries:       {} (meta {}, dir {}, inode {}, block {})",
+        stats.retries,
+        stats.meta_retries,
+        stats.dir_retries,
+        stats.inode_retries,
+        stats.block_retries
+    );
+    Ok(())
+}
+
+async fn hot(fs: &TiFs, top: usize, recent: u64, json: bool) -> Result<()> {
+    let report = report(fs, recent, top).await?;
+    let mut paths = HashMap::new();
+    for file in report.largest.iter().chain(&report.hottest) {
+        if !paths.contains_key(&file.ino) {
+            let path = fs.path_of(file.ino).await.ok();
+            paths.insert(file.ino, path);
+        }
+    }
+    let path_of = |file: &FileUsage| paths[&file.ino].clone();
+    let secs = |file: &FileUsage| {
+        file.last_change.map(|time| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        })
+    };
+    if json {
+        let files = |files: &[FileUsage]| -> Vec<_> {
+            files
+                .iter()
+                .map(|file| {
+                    json!({
+                        "ino": file.ino,
+                        "path": path_of(file),
+                        "blocks": file.blocks,
+                        "bytes": file.bytes,
+                        "changes": file.changes,
+                        "last_change": secs(file),
+                    })
+                })
+                .collect()
+        };
+        return print_json(&json!({
+            "journal_entries": report.journal_entries,
+            "largest": files(&report.largest),
+            "hottest": files(&report.hottest),
+        }));
+    }
+
+    let print = |title: &str, files: &[FileUsage]| {
+        println!("{}:", title);
+        println!(
+            "{:>20} {:>12} {:>16} {:>10} {:>12}  path",
+            "ino", "blocks", "bytes", "changes", "last change"
+        );
+        for file in files {
+            println!(
+                "{:>20} {:>12} {:>16} {:>10} {:>12}  {}",
+                file.ino,
+                file.blocks,
+                file.bytes,
+                file.changes,
+                secs(file).map_or("-".to_owned(), |secs| secs.to_string()),
+                path_of(file).unwrap_or_else(|| "?".to_owned())
+            );
+        }
+    };
+    print("largest", &report.largest);
+    println!();
+    print(
+        &format!("hottest in {} journal entries", report.journal_entries),
+        &report.hottest,
+    );
+    Ok(())
+}
+
+async fn freeze(fs: &TiFs, timeout: Duration, wait: Duration, json: bool) -> Result<()> {
+    let freeze = fs.freeze_volume(timeout, wait).await?;
+    let expires = freeze.expires.duration_since(UNIX_EPOCH)?.as_secs();
+    if json {
+        print_json(&json!({ "id": freeze.id, "expires": expires }))?;
+    } else {
+        println!("frozen until {}, run `thaw` once done", expires);
+    }
+    Ok(())
+}
+
+async fn gc(fs: &TiFs, life_time: Duration, json: bool) -> Result<()> {
+    let safepoint = fs.gc(life_time).await?;
+    if json {
+        print_json(&json!({ "gc_safepoint": safepoint }))?;
+    } else {
+        println!("gc safepoint: {}", safepoint);
+    }
+    Ok(())
+}
+
+/// Backups are written to files or pipes, like `--to - | aws s3 cp - s3://bucket/backup`.
+fn check_local(file: &str) -> Result<()> {
+    if file.contains("://") {
+        return Err(anyhow!(
+            "{} is not a local file, pipe the backup from or to `-` instead",
+            file
+        ));
+    }
+    Ok(())
+}
+
+async fn backup_volume(fs: &TiFs, to: &str, base: Option<&str>, json: bool) -> Result<()> {
+    let base = match base {
+        Some(file) => {
+            check_local(file)?;
+            Some(read_base(&mut BufReader::new(File::open(file)?))?)
+        }
+        None => None,
+    };
+    check_local(to)?;
+    let writer: Box<dyn Write> = match to {
+        "-" => Box::new(io::stdout()),
+        file => Box::new(File::create(file)?),
+    };
+    let (header, stats) = backup(fs, &mut BufWriter::new(writer), base).await?;
+    if json {
+        print_stats(
+            &json!({ "header": header, "stats": stats }),
+            json,
+            to == "-",
+        )
+    } else {
+        eprintln!("{}: {:?}", header.describe(), stats);
+        Ok(())
+    }
+}
+
+async fn restore_volume(fs: &TiFs, files: &[String], json: bool) -> Result<()> {
+    for file in files {
+        check_local(file)?;
+    }
+    let mut chain = Vec::with_capacity(files.len());
+    for file in files {
+        let reader: Box<dyn Read> = match file.as_str() {
+            "-" => Box::new(io::stdin()),
+            file => Box::new(File::open(file)?),
+        };
+        chain.push(BufReader::new(reader));
+    }
+    let stats = restore(fs, chain).await?;
+    print_stats(&stats, json, false)
+}
@@ -0,0 +1,88 @@
+use structopt::StructOpt;
+
+use super::Volume;
+use crate::bench::{run as run_bench, BenchConfig, LibraryTarget, MountedTarget, Report, Workload};
+
+#[derive(Debug, StructOpt)]
+pub struct Bench {
+    #[structopt(flatten)]
+    pub volume: Volume,
+    #[structopt(
+        long,
+        value_name = "MOUNT_POINT",
+        help = "benchmark a mounted volume instead of calling the library directly"
+    )]
+    pub mounted: Option<String>,
+    #[structopt(
+        long,
+        value_name = "COUNT",
+        default_value = "1000",
+        help = "number of files of the metadata workloads"
+    )]
+    pub files: usize,
+    #[structopt(
+        long,
+        value_name = "KiB",
+        default_value = "65536",
+        help = "size of the file of the data workloads"
+    )]
+    pub file_size: u64,
+    #[structopt(
+        long,
+        value_name = "KiB",
+        default_value = "4",
+        help = "size of each read or write"
+    )]
+    pub io_size: usize,
+    #[structopt(
+        long,
+        value_name = "COUNT",
+        default_value = "1000",
+        help = "number of requests of the random workloads"
+    )]
+    pub ops: usize,
+    #[structopt(
+        short = "w",
+        long,
+        value_name = "WORKLOAD",
+        possible_values = &[
+            "create",
+            "stat",
+            "seq-write",
+            "seq-read",
+            "rand-write",
+            "rand-read",
+            "unlink",
+        ],
+        help = "workloads to report, all by default"
+    )]
+    pub workload: Vec<Workload>,
+}
+
+pub async fn run(bench: Bench) -> anyhow::Result<()> {
+    let mut cfg = BenchConfig::default();
+    cfg.files = bench.files;
+    cfg.file_size = bench.file_size << 10;
+    cfg.io_size = bench.io_size << 10;
+    cfg.random_ops = bench.ops;
+    if !bench.workload.is_empty() {
+        cfg.workloads = bench.workload;
+    }
+
+    let dir_name = format!("tifs-bench-{}", std::process::id());
+    let reports = match &bench.mounted {
+        Some(mountpoint) => {
+            run_bench(&MountedTarget::new(mountpoint.into(), &dir_name)?, &cfg).await?
+        }
+        None => {
+            let fs = bench.volume.connect().await?;
+            run_bench(&LibraryTarget::new(fs, &dir_name).await?, &cfg).await?
+        }
+    };
+
+    Report::print_header();
+    for report in reports.iter() {
+        report.print();
+    }
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use std::io;
+
+use clap::Shell;
+use structopt::StructOpt;
+
+use super::Tifs;
+
+#[derive(Debug, StructOpt)]
+pub struct Completions {
+    #[structopt(value_name = "SHELL", possible_values = &Shell::variants())]
+    pub shell: Shell,
+}
+
+pub fn run(completions: Completions) -> anyhow::Result<()> {
+    Tifs::clap().gen_completions_to("tifs", completions.shell, &mut io::stdout());
+    Ok(())
+}
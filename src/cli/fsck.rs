@@ -0,0 +1,57 @@
+use anyhow::anyhow;
+use structopt::StructOpt;
+
+use super::{print_json, Volume};
+use crate::fs::fsck::{check, repair};
+
+#[derive(Debug, StructOpt)]
+pub struct Fsck {
+    #[structopt(flatten)]
+    pub volume: Volume,
+    #[structopt(long, global = true, help = "print results as JSON")]
+    pub json: bool,
+    #[structopt(
+        long,
+        help = "fix the types of directory entries which differ from their inodes and recount open handles"
+    )]
+    pub repair: bool,
+    #[structopt(
+        long,
+        value_name = "SCANS",
+        default_value = "8",
+        help = "max number of directories read and key ranges scanned at once"
+    )]
+    pub parallel: usize,
+}
+
+pub async fn run(fsck: Fsck, json: bool) -> anyhow::Result<()> {
+    let fs = fsck.volume.connect().await?;
+    let parallelism = fsck.parallel;
+    if parallelism == 0 {
+        return Err(anyhow!("--parallel must be at least 1"));
+    }
+    let mut report = check(&fs, parallelism).await?;
+    if fsck.repair && !report.is_clean() {
+        let fixed = repair(&fs, &report).await?;
+        eprintln!("fixed {} problems", fixed);
+        report = check(&fs, parallelism).await?;
+    }
+    if json {
+        print_json(&report)?;
+    } else {
+        for problem in report.problems.iter() {
+            println!("{}", problem);
+        }
+        eprintln!(
+            "checked {} dirs and {} files, found {} problems",
+            report.dirs,
+            report.files,
+            report.problems.len()
+        );
+    }
+    if report.is_clean() {
+        Ok(())
+    } else {
+        Err(anyhow!("volume is inconsistent"))
+    }
+}
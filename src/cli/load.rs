@@ -0,0 +1,55 @@
+use structopt::StructOpt;
+
+use super::Volume;
+use crate::loadgen::{generate_load, LoadConfig};
+
+#[derive(Debug, StructOpt)]
+pub struct Load {
+    #[structopt(flatten)]
+    pub volume: Volume,
+    #[structopt(
+        long,
+        value_name = "COUNT",
+        default_value = "10000",
+        help = "number of files to generate"
+    )]
+    pub files: usize,
+    #[structopt(
+        long,
+        value_name = "KiB",
+        default_value = "1024",
+        help = "size of each file"
+    )]
+    pub file_size: u64,
+    #[structopt(
+        short = "c",
+        long,
+        value_name = "COUNT",
+        default_value = "16",
+        help = "number of concurrent workers"
+    )]
+    pub concurrency: usize,
+    #[structopt(
+        long,
+        value_name = "KiB",
+        default_value = "1024",
+        help = "max bytes written by one transaction"
+    )]
+    pub txn_size: u64,
+    #[structopt(long, help = "keep the generated files")]
+    pub keep: bool,
+}
+
+pub async fn run(load: Load) -> anyhow::Result<()> {
+    let cfg = LoadConfig {
+        files: load.files,
+        file_size: load.file_size << 10,
+        concurrency: load.concurrency,
+        txn_size: load.txn_size << 10,
+        keep: load.keep,
+    };
+
+    let fs = load.volume.connect().await?;
+    generate_load(fs, cfg).await?.print();
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::io::{self, Write};
+use std::iter::once;
+
+use anyhow::anyhow;
+use clap::{crate_version, ErrorKind};
+use structopt::StructOpt;
+
+use super::{Tifs, SUBCOMMANDS};
+
+/// Escape text for roff, so lines of help starting with `.` or `'` are not taken as requests.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .lines()
+        .map(|line| {
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The long help printed by `tifs <subcommand> --help`, or by `tifs --help` without one.
+fn long_help(subcommand: Option<&str>) -> anyhow::Result<String> {
+    let args = once("tifs").chain(subcommand).chain(once("--help"));
+    match Tifs::clap().get_matches_from_safe(args) {
+        Err(err) if err.kind == ErrorKind::HelpDisplayed => Ok(escape(err.message.trim_end())),
+        Err(err) => Err(err.into()),
+        Ok(_) => Err(anyhow!("no help of {:?}", subcommand)),
+    }
+}
+
+pub fn run() -> anyhow::Result<()> {
+    let mut page = String::new();
+    page.push_str(&format!(
+        ".TH TIFS 8 \"\" \"tifs {}\" \"System Administration\"\n",
+        crate_version!()
+    ));
+    page.push_str(".SH NAME\ntifs \\- a distributed POSIX filesystem based on TiKV\n");
+    page.push_str(".SH SYNOPSIS\n.B tifs\n\\fISUBCOMMAND\\fR [\\fIOPTIONS\\fR]\n");
+    page.push_str(
+        ".br\n.B mount.tifs\n\\fIDEVICE\\fR \\fIMOUNT_POINT\\fR [\\fB\\-o\\fR \\fIOPTIONS\\fR]\n",
+    );
+    page.push_str(".SH DESCRIPTION\n.nf\n");
+    page.push_str(&long_help(None)?);
+    page.push_str("\n.fi\n.PP\nInvoked as \\fBmount.tifs\\fR, \\fBmkfs.tifs\\fR, \\fBfsck.tifs\\fR or \\fBtifs\\-admin\\fR, tifs runs the corresponding subcommand.\n");
+    page.push_str(".SH SUBCOMMANDS\n");
+    for &name in SUBCOMMANDS {
+        page.push_str(&format!(".SS tifs {}\n.nf\n", name));
+        page.push_str(&long_help(Some(name))?);
+        page.push_str("\n.fi\n");
+    }
+    io::stdout().write_all(page.as_bytes())?;
+    Ok(())
+}
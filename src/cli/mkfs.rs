@@ -0,0 +1,66 @@
+use structopt::StructOpt;
+
+use super::Volume;
+use crate::fs::meta::VolumeConfig;
+use crate::fs::mode::FileMode;
+
+#[derive(Debug, StructOpt)]
+pub struct Mkfs {
+    #[structopt(flatten)]
+    pub volume: Volume,
+    #[structopt(
+        long,
+        value_name = "UID",
+        help = "owner of the root directory, the current user by default"
+    )]
+    pub uid: Option<u32>,
+    #[structopt(
+        long,
+        value_name = "GID",
+        help = "group of the root directory, the current group by default"
+    )]
+    pub gid: Option<u32>,
+    #[structopt(
+        long,
+        value_name = "MODE",
+        help = "mode of the root directory in octal, 0777 by default"
+    )]
+    pub mode: Option<FileMode>,
+}
+
+/// Flags take precedence over the `root_uid`, `root_gid` and `root_mode` options.
+pub async fn run(mkfs: Mkfs) -> anyhow::Result<()> {
+    let fs = mkfs.volume.connect().await?;
+    let uid = mkfs
+        .uid
+        .or(fs.root_uid)
+        .unwrap_or_else(|| unsafe { libc::getuid() });
+    let gid = mkfs
+        .gid
+        .or(fs.root_gid)
+        .unwrap_or_else(|| unsafe { libc::getgid() });
+    let mode = mkfs.mode.or(fs.root_mode).unwrap_or(FileMode::DEFAULT_ROOT);
+
+    let initialized = fs.has_meta().await?;
+    fs.init_volume(gid, uid, mode).await?;
+    if initialized {
+        eprintln!(
+            "volume is already initialized with block size {}, its root is kept as it is",
+            fs.block_size
+        );
+    } else {
+        let on = |enabled: bool| Some(true).filter(|_| enabled);
+        let config = VolumeConfig {
+            journal: on(fs.journal),
+            inode_reuse: on(fs.inode_reuse),
+            no_inline: on(fs.no_inline),
+            hashed_names: on(fs.hashed_names),
+        };
+        fs.set_volume_config(config).await?;
+        eprintln!(
+            "initialized volume with block size {}, options: {}",
+            fs.block_size, config
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::fmt::Display;
+use std::os::unix::process::CommandExt;
+use std::process::exit;
+use std::time::Duration;
+
+use structopt::StructOpt;
+use tracing::{debug, info, trace};
+
+use super::Options;
+use crate::mount_tifs_daemonize;
+
+/// Exit codes of mount(8), see "RETURN CODES" in its man page. Invalid arguments are
+/// reported by clap, which exits with `EX_USAGE` as well.
+pub const EX_USAGE: i32 = 1;
+pub const EX_SYSERR: i32 = 2;
+pub const EX_FAIL: i32 = 32;
+
+/// How long to wait for the server to exit after it closes its output, before taking it
+/// as mounted and daemonized.
+const SERVER_EXIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+fn fail(code: i32, err: impl Display) -> ! {
+    eprintln!("mount.tifs: {}", err);
+    exit(code)
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Mount {
+    #[structopt(
+        value_name = "ENDPOINTS",
+        help = "all pd endpoints of the tikv cluster, separated by commas (e.g. tifs:127.0.0.1:2379)"
+    )]
+    pub device: String,
+    #[structopt(
+        value_name = "MOUNT_POINT",
+        help = "Act as a client, and mount FUSE at given path"
+    )]
+    pub mount_point: String,
+    #[structopt(flatten)]
+    pub options: Options,
+    #[structopt(short = "f", long, help = "foreground operation")]
+    pub foreground: bool,
+    #[structopt(
+        long,
+        hidden = true,
+        help = "run in server mode (implies --foreground)"
+    )]
+    pub serve: bool,
+    #[structopt(
+        long = "log-file",
+        value_name = "LOGFILE",
+        help = "log file in server mode (ignored if --foreground is present)"
+    )]
+    pub logfile: Option<String>,
+}
+
+pub async fn run(mount: Mount) -> anyhow::Result<()> {
+    let serve = mount.serve;
+    let foreground = serve || mount.foreground;
+    let logfile = match &mount.logfile {
+        Some(v) => match std::fs::canonicalize(v) {
+            Ok(path) => Some(path.to_string_lossy().into_owned()),
+            Err(err) => fail(EX_USAGE, format_args!("log file {}: {}", v, err)),
+        },
+        None => None,
+    };
+
+    trace!("serve={} foreground={}", serve, foreground);
+
+    let device = mount.device.as_str();
+
+    let endpoints: Vec<&str> = device
+        .strip_prefix("tifs:")
+        .unwrap_or(device)
+        .split(",")
+        .collect();
+
+    let mountpoint: String = match std::fs::canonicalize(&mount.mount_point) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(err) => fail(
+            EX_FAIL,
+            format_args!("mount point {}: {}", mount.mount_point, err),
+        ),
+    };
+
+    let options = mount.options.parse();
+
+    let runtime_config_string = format!(
+        "mountpoint={:?} endpoints={:?} opt={:?}",
+        mountpoint, endpoints, options
+    );
+
+    if !foreground {
+        use std::io::{Read, Write};
+        use std::process::{Command, Stdio};
+
+        let exe = std::env::current_exe().unwrap_or_else(|err| fail(EX_SYSERR, err));
+        debug!("Launching server, current_exe={:?}", exe);
+        info!("{}", runtime_config_string);
+
+        let mut args = vec![
+            "mount".to_owned(),
+            "--serve".to_owned(),
+            format!("tifs:{}", endpoints.join(",")),
+            mountpoint,
+        ];
+        // pass the options as they are given, which are validated already, so options not
+        // modeled by `MountOption` and values containing commas survive the re-exec
+        for option in &mount.options.values {
+            args.push("-o".to_owned());
+            args.push(option.clone());
+        }
+        if let Some(f) = logfile {
+            args.push("--log-file".to_owned());
+            args.push(f);
+        }
+        // the server is invoked as `tifs mount`, whatever name this one is invoked by
+        let mut child = Command::new(exe)
+            .arg0("tifs")
+            .args(args)
+            .current_dir("/")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|err| fail(EX_SYSERR, format_args!("cannot start server: {}", err)));
+
+        if let Some(mut stdout) = child.stdout.take() {
+            let mut my_stdout = std::io::stdout();
+            let mut buffer: [u8; 256] = [0; 256];
+            while let Ok(size) = stdout.read(&mut buffer) {
+                if size == 0 {
+                    break; // EOF
+                }
+                let _ = my_stdout.write_all(&buffer[0..size]);
+            }
+        }
+        if let Some(mut stderr) = child.stderr.take() {
+            let mut my_stderr = std::io::stderr();
+            let mut buffer: [u8; 256] = [0; 256];
+            while let Ok(size) = stderr.read(&mut buffer) {
+                if size == 0 {
+                    break; // EOF
+                }
+                let _ = my_stderr.write_all(&buffer[0..size]);
+            }
+        }
+
+        // the server closes its output once it is mounted, or exits if it fails to mount
+        let waited = std::time::Instant::now();
+        while waited.elapsed() < SERVER_EXIT_TIMEOUT {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => break,
+                Ok(Some(status)) => fail(EX_FAIL, format_args!("server exited with {}", status)),
+                Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+                Err(err) => fail(EX_SYSERR, err),
+            }
+        }
+        return Ok(());
+    }
+
+    mount_tifs_daemonize(mountpoint, endpoints, options, move || {
+        if serve {
+            use anyhow::bail;
+            use libc;
+            use std::ffi::CString;
+            use std::io::Error;
+            use std::io::Write;
+
+            debug!("Using log file: {:?}", logfile);
+
+            std::io::stdout().flush()?;
+            std::io::stderr().flush()?;
+
+            let mut logfd = None;
+            if let Some(f) = logfile {
+                let log_file_name = CString::new(f)?;
+                unsafe {
+                    let fd = libc::open(log_file_name.as_ptr(), libc::O_WRONLY | libc::O_APPEND, 0);
+                    if fd == -1 {
+                        bail!(Error::last_os_error());
+                    }
+                    logfd = Some(fd);
+
+                    libc::dup2(fd, 1);
+                    libc::dup2(fd, 2);
+                    if fd > 2 {
+                        libc::close(fd);
+                    }
+                }
+                debug!("output redirected");
+            }
+
+            let null_file_name = CString::new("/dev/null")?;
+
+            unsafe {
+                let nullfd = libc::open(null_file_name.as_ptr(), libc::O_RDWR, 0);
+                if nullfd != -1 {
+                    libc::dup2(nullfd, 0);
+                    if logfd.is_none() {
+                        libc::dup2(nullfd, 1);
+                        libc::dup2(nullfd, 2);
+                    }
+                    if nullfd > 2 {
+                        libc::close(nullfd);
+                    }
+                }
+            }
+        }
+        debug!("{}", runtime_config_string);
+
+        Ok(())
+    })
+    .await
+    .unwrap_or_else(|err| fail(EX_FAIL, err));
+    Ok(())
+}
@@ -1,15 +1,53 @@
+pub mod acl;
+pub mod archive;
 pub mod async_fs;
+pub mod atime;
+pub mod backup;
+pub mod batch;
 pub mod block;
+pub mod browse;
+pub mod chaos;
 pub mod client;
+pub mod copy;
+pub mod credentials;
+pub mod degrade;
+pub mod delegation;
+pub mod deletion;
+pub mod deny;
 pub mod dir;
+pub mod disk_cache;
 pub mod error;
 pub mod file_handler;
+pub mod flags;
+pub mod freeze;
+pub mod fsck;
+pub mod hash;
+pub mod hot;
 pub mod index;
 pub mod inode;
+pub mod inode_cache;
+pub mod interval;
+pub mod journal;
 pub mod key;
+pub mod lookup;
+pub mod mem_store;
 pub mod meta;
 pub mod mode;
+pub mod notify;
+pub mod parallel;
+pub mod pin;
+pub mod posix;
+pub mod region;
+pub mod registry;
 pub mod reply;
+pub mod seal;
 pub mod serialize;
+pub mod shard;
+pub mod size;
+pub mod snapshot;
+pub mod stats;
+pub mod symlink;
 pub mod tikv_fs;
 pub mod transaction;
+pub mod ttl;
+pub mod worm;
@@ -0,0 +1,135 @@
+use std::convert::TryInto;
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{FsError, Result};
+
+/// Access ACL of a file, in the format of the kernel.
+pub const ACL_ACCESS_XATTR: &str = "system.posix_acl_access";
+
+/// Default ACL of a directory, inherited by the files created in it.
+pub const ACL_DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+const ACL_VERSION: u32 = 2;
+const HEADER_LEN: usize = 4;
+const ENTRY_LEN: usize = 8;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AclEntry {
+    pub tag: u16,
+    pub perm: u16,
+    pub id: u32,
+}
+
+/// POSIX ACL, kept in the inode and exchanged with the kernel as `system.posix_acl_*` xattrs.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Acl(pub Vec<AclEntry>);
+
+impl Acl {
+    /// Decode the value of the xattr `name`, which must have the entries of the owner,
+    /// the owning group and others.
+    pub fn decode(name: &str, data: &[u8]) -> Result<Self> {
+        let invalid = || FsError::InvalidXattrValue {
+            name: name.to_owned(),
+        };
+        if data.len() < HEADER_LEN || (data.len() - HEADER_LEN) % ENTRY_LEN != 0 {
+            return Err(invalid());
+        }
+        if u32::from_le_bytes(data[..HEADER_LEN].try_into().unwrap()) != ACL_VERSION {
+            return Err(invalid());
+        }
+        let entries: Vec<AclEntry> = data[HEADER_LEN..]
+            .chunks(ENTRY_LEN)
+            .map(|entry| AclEntry {
+                tag: u16::from_le_bytes(entry[..2].try_into().unwrap()),
+                perm: u16::from_le_bytes(entry[2..4].try_into().unwrap()) & 0o7,
+                id: u32::from_le_bytes(entry[4..].try_into().unwrap()),
+            })
+            .collect();
+        let known = entries.iter().all(|entry| {
+            [
+                ACL_USER_OBJ,
+                ACL_USER,
+                ACL_GROUP_OBJ,
+                ACL_GROUP,
+                ACL_MASK,
+                ACL_OTHER,
+            ]
+            .contains(&entry.tag)
+        });
+        let acl = Acl(entries);
+        let named = acl.find(ACL_USER).is_some() || acl.find(ACL_GROUP).is_some();
+        if !known
+            || acl.find(ACL_USER_OBJ).is_none()
+            || acl.find(ACL_GROUP_OBJ).is_none()
+            || acl.find(ACL_OTHER).is_none()
+            || (named && acl.find(ACL_MASK).is_none())
+        {
+            return Err(invalid());
+        }
+        Ok(acl)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.0.len() * ENTRY_LEN);
+        data.extend_from_slice(&ACL_VERSION.to_le_bytes());
+        for entry in &self.0 {
+            data.extend_from_slice(&entry.tag.to_le_bytes());
+            data.extend_from_slice(&entry.perm.to_le_bytes());
+            data.extend_from_slice(&entry.id.to_le_bytes());
+        }
+        data
+    }
+
+    fn find(&self, tag: u16) -> Option<usize> {
+        self.0.iter().position(|entry| entry.tag == tag)
+    }
+
+    /// Entries behind the permission bits of the owner, the group class and others. The group
+    /// class is the mask if there is one.
+    fn classes(&self) -> [usize; 3] {
+        let unwrap = |index: Option<usize>| index.expect("decoded acl has required entries");
+        [
+            unwrap(self.find(ACL_USER_OBJ)),
+            unwrap(self.find(ACL_MASK).or_else(|| self.find(ACL_GROUP_OBJ))),
+            unwrap(self.find(ACL_OTHER)),
+        ]
+    }
+
+    /// An ACL of only the owner, the owning group and others is equivalent to the permission
+    /// bits, so it is not stored.
+    pub fn is_minimal(&self) -> bool {
+        self.0.iter().all(|entry| {
+            entry.tag == ACL_USER_OBJ || entry.tag == ACL_GROUP_OBJ || entry.tag == ACL_OTHER
+        })
+    }
+
+    /// The permission bits equivalent to this ACL.
+    pub fn perm(&self) -> u16 {
+        let [owner, group, other] = self.classes();
+        self.0[owner].perm << 6 | self.0[group].perm << 3 | self.0[other].perm
+    }
+
+    /// Update the entries behind the permission bits, as `chmod` does.
+    pub fn chmod(&mut self, perm: u16) {
+        for (shift, index) in [6u16, 3, 0].iter().zip(self.classes().iter()) {
+            self.0[*index].perm = perm >> shift & 0o7;
+        }
+    }
+
+    /// The access ACL of a file created with `perm` in a directory with this default ACL.
+    pub fn inherit(&self, perm: u16) -> Acl {
+        let mut acl = self.clone();
+        for (shift, index) in [6u16, 3, 0].iter().zip(self.classes().iter()) {
+            acl.0[*index].perm &= perm >> shift & 0o7;
+        }
+        acl
+    }
+}
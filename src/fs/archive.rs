@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use fuser::{FileAttr, FileType};
+use serde::Serialize;
+use tar::{Archive, Builder, EntryType, Header};
+use tracing::{debug, warn};
+
+use super::batch::{Batch, Parent};
+use super::error::Result;
+use super::tikv_fs::TiFs;
+
+/// Bytes of a file read at once while exporting.
+const EXPORT_CHUNK_SIZE: u64 = 1 << 20;
+
+/// Operations and bytes of file contents committed at once while importing.
+const IMPORT_BATCH_OPS: usize = 1 << 10;
+const IMPORT_BATCH_BYTES: usize = 4 << 20;
+
+/// Tar records are padded to 512 bytes.
+const RECORD_SIZE: u64 = 512;
+
+#[derive(Debug, Default, Serialize)]
+pub struct ArchiveStats {
+    pub dirs: u64,
+    pub files: u64,
+    pub symlinks: u64,
+    /// Entries recorded as links to a file earlier in the archive.
+    pub hardlinks: u64,
+    pub bytes: u64,
+    /// Entries of types which are not supported, like devices.
+    pub skipped: u64,
+}
+
+fn header_of(attr: &FileAttr, entry_type: EntryType) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(attr.perm as u32);
+    header.set_uid(attr.uid as u64);
+    header.set_gid(attr.gid as u64);
+    header.set_mtime(
+        attr.mtime
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    );
+    header.set_size(0);
+    header
+}
+
+/// Export the subtree of `root` as a tar archive.
+///
+/// A file with more than one link is stored once, its other paths are stored as hard links
+/// to the first one, so trees of hard links do not grow on a round trip.
+pub async fn export<W: Write>(
+    fs: &TiFs,
+    root: u64,
+    builder: &mut Builder<W>,
+) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut linked: HashMap<u64, PathBuf> = HashMap::new();
+    let mut pending = vec![(root, PathBuf::new())];
+
+    while let Some((dir, dir_path)) = pending.pop() {
+        for item in fs.read_dir(dir).await? {
+            if item.name == "." || item.name == ".." {
+                continue;
+            }
+            let path = dir_path.join(&item.name);
+            let attr = fs.read_inode(item.ino).await?;
+            match attr.kind {
+                FileType::Directory => {
+                    let mut header = header_of(&attr, EntryType::Directory);
+                    builder.append_data(&mut header, &path, io::empty())?;
+                    pending.push((attr.ino, path));
+                    stats.dirs += 1;
+                }
+                FileType::Symlink => {
+                    let target = fs.read_file(attr.ino, 0, attr.size).await?;
+                    let mut header = header_of(&attr, EntryType::Symlink);
+                    let target = String::from_utf8_lossy(&target).into_owned();
+                    set_link_name(builder, &mut header, Path::new(&target))?;
+                    builder.append_data(&mut header, &path, io::empty())?;
+                    stats.symlinks += 1;
+                }
+                FileType::RegularFile => {
+                    if attr.nlink > 1 {
+                        if let Some(first) = linked.get(&attr.ino) {
+                            let mut header = header_of(&attr, EntryType::Link);
+                            set_link_name(builder, &mut header, first)?;
+                            builder.append_data(&mut header, &path, io::empty())?;
+                            stats.hardlinks += 1;
+                            continue;
+                        }
+                        linked.insert(attr.ino, path.clone());
+                    }
+                    export_file(fs, &attr, &path, builder).await?;
+                    stats.files += 1;
+                    stats.bytes += attr.size;
+                }
+                kind => {
+                    debug!("skip exporting {:?}({:?})", path, kind);
+                    stats.skipped += 1;
+                }
+            }
+        }
+    }
+    builder.finish()?;
+    Ok(stats)
+}
+
+/// Stream the content of a file into the archive chunk by chunk.
+async fn export_file<W: Write>(
+    fs: &TiFs,
+    attr: &FileAttr,
+    path: &Path,
+    builder: &mut Builder<W>,
+) -> Result<()> {
+    let writer = builder.get_mut();
+    let mut header = header_of(attr, EntryType::Regular);
+    header.set_size(attr.size);
+    if header.set_path(path).is_err() {
+        write_long_name(writer, EntryType::GNULongName, path)?;
+        let name = path.to_string_lossy();
+        let old = header.as_old_mut();
+        let len = old.name.len().min(name.len());
+        old.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+    header.set_cksum();
+    writer.write_all(header.as_bytes())?;
+
+    let mut written = 0;
+    while written < attr.size {
+        let size = EXPORT_CHUNK_SIZE.min(attr.size - written);
+        let mut data = fs.read_file(attr.ino, written, size).await?;
+        // the file may be truncated since its attributes are read
+        data.resize(size as usize, 0);
+        writer.write_all(&data)?;
+        written += size;
+    }
+    pad(writer, attr.size)
+}
+
+fn pad<W: Write>(writer: &mut W, size: u64) -> io::Result<()> {
+    let padding = (RECORD_SIZE - size % RECORD_SIZE) % RECORD_SIZE;
+    writer.write_all(&[0; RECORD_SIZE as usize][..padding as usize])
+}
+
+/// Write a GNU long name (or long link name) entry for a path which does not fit in a header,
+/// `Builder::append_data` does the same for long names.
+fn write_long_name<W: Write>(writer: &mut W, entry_type: EntryType, path: &Path) -> io::Result<()> {
+    let mut name = path.to_string_lossy().into_owned().into_bytes();
+    name.push(0);
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_path("././@LongLink")?;
+    header.set_mode(0o644);
+    header.set_size(name.len() as u64);
+    header.set_cksum();
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(&name)?;
+    pad(writer, name.len() as u64)
+}
+
+fn set_link_name<W: Write>(
+    builder: &mut Builder<W>,
+    header: &mut Header,
+    target: &Path,
+) -> io::Result<()> {
+    if header.set_link_name(target).is_err() {
+        write_long_name(builder.get_mut(), EntryType::GNULongLink, target)?;
+        let name = target.to_string_lossy();
+        let old = header.as_old_mut();
+        let len = old.linkname.len().min(name.len());
+        old.linkname[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+    Ok(())
+}
+
+/// Path of an entry relative to the root of the archive, `None` if it escapes the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Commit `batch`, turning the references to its operations into inode numbers.
+async fn commit(
+    fs: &TiFs,
+    batch: &mut Batch,
+    imported: &mut HashMap<PathBuf, Parent>,
+) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let next = Batch::new(batch.uid, batch.gid);
+    let committed = mem::replace(batch, next);
+    let attrs = fs.batch(committed).await?;
+    for target in imported.values_mut() {
+        if let Parent::Created(index) = *target {
+            *target = Parent::Inode(attrs[index].ino);
+        }
+    }
+    Ok(())
+}
+
+/// Import a tar archive into the directory `dest`, owned by `uid` and `gid`.
+///
+/// Hard link entries are linked to the files imported before, so hard links exported by
+/// `export` or by tar itself are kept.
+pub async fn import<R: Read>(
+    fs: &TiFs,
+    dest: u64,
+    archive: &mut Archive<R>,
+    uid: u32,
+    gid: u32,
+) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut imported: HashMap<PathBuf, Parent> = HashMap::new();
+    let mut batch = Batch::new(uid, gid);
+    let mut batch_bytes = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = match normalize(&entry.path()?) {
+            Some(path) => path,
+            None => {
+                warn!("skip importing {:?} outside of the archive", entry.path()?);
+                stats.skipped += 1;
+                continue;
+            }
+        };
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let parent = match path.parent() {
+            Some(parent) if parent != Path::new("") => match imported.get(parent) {
+                Some(parent) => *parent,
+                None => {
+                    warn!("skip importing {:?} without its parent", path);
+                    stats.skipped += 1;
+                    continue;
+                }
+            },
+            _ => Parent::Inode(dest),
+        };
+        let mode = entry.header().mode()?;
+
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                let dir = batch.mkdir(parent, name, mode);
+                imported.insert(path, dir);
+                stats.dirs += 1;
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                batch_bytes += data.len();
+                stats.bytes += data.len() as u64;
+                let file = batch.create(parent, name, mode, data);
+                imported.insert(path, file);
+                stats.files += 1;
+            }
+            EntryType::Symlink => match entry.link_name()? {
+                Some(target) => {
+                    batch.symlink(parent, name, target.to_string_lossy().to_string());
+                    stats.symlinks += 1;
+                }
+                None => stats.skipped += 1,
+            },
+            EntryType::Link => {
+                let target = entry
+                    .link_name()?
+                    .and_then(|target| normalize(&target))
+                    .and_then(|target| imported.get(&target).copied());
+                match target {
+                    Some(target) => {
+                        batch.link(parent, name, target);
+                        stats.hardlinks += 1;
+                    }
+                    None => {
+                        warn!("skip importing hard link {:?} to a missing file", path);
+                        stats.skipped += 1;
+                    }
+                }
+            }
+            entry_type => {
+                debug!("skip importing {:?}({:?})", path, entry_type);
+                stats.skipped += 1;
+            }
+        }
+
+        if batch.len() >= IMPORT_BATCH_OPS || batch_bytes >= IMPORT_BATCH_BYTES {
+            commit(fs, &mut batch, &mut imported).await?;
+            batch_bytes = 0;
+        }
+    }
+    commit(fs, &mut batch, &mut imported).await?;
+    Ok(stats)
+}
@@ -8,29 +8,44 @@ use async_std::task::{block_on, spawn};
 use async_trait::async_trait;
 use bytestring::ByteString;
 use fuser::{
-    Filesystem, KernelConfig, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyDirectory,
-    ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyLock, ReplyLseek, ReplyOpen, ReplyStatfs,
-    ReplyWrite, ReplyXattr, Request, TimeOrNow,
+    fuse_forget_one, Filesystem, KernelConfig, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek,
+    ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
 };
-use tracing::{debug_span, trace};
+use tracing::{debug_span, field, trace, Span};
 use tracing_libatrace::InstrumentExt;
 
+use super::chaos::Chaos;
+use super::credentials::Credentials;
+use super::degrade::CommitHealth;
 use super::error::{FsError, Result};
 use super::reply::{
-    Attr, Bmap, Create, Data, Dir, DirPlus, Entry, FsReply, Lock, Lseek, Open, StatFs, Write, Xattr,
+    Attr, Bmap, Create, Data, Dir, DirPlus, Entry, FsReply, Ioctl, Lock, Lseek, Open, StatFs,
+    Write, Xattr,
 };
-
-pub fn spawn_reply<F, R, V>(id: u64, reply: R, f: F)
-where
-    F: Future<Output = Result<V>> + Send + 'static,
-    R: FsReply<V> + Send + 'static,
-    V: Debug,
-{
-    spawn(async move {
-        let result = f.await;
-        trace!("reply result to fuser request unique id:{}", id);
-        reply.reply(id, result);
-    });
+use super::stats::OpStats;
+
+/// Create the name and span of a FUSE operation, with the fields filled by `OpStats::record`.
+macro_rules! op_span {
+    ($name: literal) => {
+        (
+            $name,
+            debug_span!(
+                $name,
+                gets = field::Empty,
+                puts = field::Empty,
+                deletes = field::Empty,
+                scans = field::Empty,
+                read_bytes = field::Empty,
+                written_bytes = field::Empty,
+                retries = field::Empty,
+                meta_retries = field::Empty,
+                dir_retries = field::Empty,
+                inode_retries = field::Empty,
+                block_retries = field::Empty,
+            ),
+        )
+    };
 }
 
 #[async_trait]
@@ -46,6 +61,25 @@ pub trait AsyncFileSystem: Send + Sync {
     /// Called on filesystem exit.
     async fn destroy(&self) {}
 
+    /// Faults to inject into operations before they are served.
+    fn chaos(&self) -> Option<&Chaos> {
+        None
+    }
+
+    /// Whether `op` is refused by the mount, see `DeniedOps`.
+    fn denies(&self, _op: &str) -> bool {
+        false
+    }
+
+    /// Outcomes of the commits, by which mutating operations are refused while the cluster
+    /// takes no writes.
+    fn commit_health(&self) -> Option<&CommitHealth> {
+        None
+    }
+
+    /// Account an operation `op` served with the round trips of `stats`.
+    fn account(&self, _op: &str, _stats: &OpStats) {}
+
     /// Look up a directory entry by name and get its attributes.
     async fn lookup(&self, _parent: u64, _name: ByteString) -> Result<Entry> {
         Err(FsError::unimplemented())
@@ -60,6 +94,13 @@ pub trait AsyncFileSystem: Send + Sync {
     /// inodes will receive a forget message.
     async fn forget(&self, _ino: u64, _nlookup: u64) {}
 
+    /// Forget about the inodes of `nodes`, as `(ino, nlookup)`, like `forget`.
+    async fn batch_forget(&self, nodes: Vec<(u64, u64)>) {
+        for (ino, nlookup) in nodes {
+            self.forget(ino, nlookup).await;
+        }
+    }
+
     /// Get file attributes.
     async fn getattr(&self, _ino: u64) -> Result<Attr> {
         Err(FsError::unimplemented())
@@ -119,12 +160,12 @@ pub trait AsyncFileSystem: Send + Sync {
     }
 
     /// Remove a file.
-    async fn unlink(&self, _parent: u64, _name: ByteString) -> Result<()> {
+    async fn unlink(&self, _uid: u32, _parent: u64, _name: ByteString) -> Result<()> {
         Err(FsError::unimplemented())
     }
 
     /// Remove a directory.
-    async fn rmdir(&self, _parent: u64, _name: ByteString) -> Result<()> {
+    async fn rmdir(&self, _uid: u32, _parent: u64, _name: ByteString) -> Result<()> {
         Err(FsError::unimplemented())
     }
 
@@ -143,6 +184,7 @@ pub trait AsyncFileSystem: Send + Sync {
     /// Rename a file.
     async fn rename(
         &self,
+        _uid: u32,
         _parent: u64,
         _name: ByteString,
         _newparent: u64,
@@ -343,7 +385,8 @@ pub trait AsyncFileSystem: Send + Sync {
     /// This will be called for the access() system call. If the 'default_permissions'
     /// mount option is given, this method is not called. This method is not called
     /// under Linux kernel versions 2.4.x
-    async fn access(&self, _ino: u64, _mask: i32) -> Result<()> {
+    /// `cred` carries the supplementary groups of the caller besides its uid and gid.
+    async fn access(&self, _cred: Credentials, _ino: u64, _mask: i32) -> Result<()> {
         Err(FsError::unimplemented())
     }
 
@@ -443,6 +486,20 @@ pub trait AsyncFileSystem: Send + Sync {
     ) -> Result<Write> {
         Err(FsError::unimplemented())
     }
+
+    /// Control device, only the requests of `lsattr` and `chattr` are served
+    async fn ioctl(
+        &self,
+        _uid: u32,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _in_data: Vec<u8>,
+        _out_size: u32,
+    ) -> Result<Ioctl> {
+        Err(FsError::unimplemented())
+    }
 }
 
 pub struct AsyncFs<T>(Arc<T>);
@@ -453,6 +510,44 @@ impl<T: AsyncFileSystem> From<T> for AsyncFs<T> {
     }
 }
 
+impl<T: AsyncFileSystem + 'static> AsyncFs<T> {
+    /// The shared filesystem, to serve it beyond FUSE requests.
+    pub fn inner(&self) -> Arc<T> {
+        self.0.clone()
+    }
+
+    fn spawn_reply<F, R, V>(&self, id: u64, reply: R, (op, span): (&'static str, Span), f: F)
+    where
+        F: Future<Output = Result<V>> + Send + 'static,
+        R: FsReply<V> + Send + 'static,
+        V: Debug,
+    {
+        let inner = self.0.clone();
+        spawn(async move {
+            let fs = inner.clone();
+            let result = async move {
+                if fs.denies(op) {
+                    return Err(FsError::Denied { op });
+                }
+                if let Some(health) = fs.commit_health() {
+                    health.check(op)?;
+                }
+                if let Some(chaos) = fs.chaos() {
+                    chaos.inject(op).await?;
+                }
+                f.await
+            }
+            .instrument(span.clone())
+            .await;
+            let stats = OpStats::take();
+            stats.record(&span);
+            inner.account(op, &stats);
+            trace!("reply result to fuser request unique id:{}", id);
+            reply.reply(id, result);
+        });
+    }
+}
+
 impl<T: Debug> Debug for AsyncFs<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -486,11 +581,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             parent,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .lookup(parent, name)
-                .instrument(debug_span!("lookup"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("lookup"), async move {
+            async_impl.lookup(parent, name).await
         });
     }
 
@@ -512,14 +604,31 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
         });
     }
 
+    fn batch_forget(&mut self, req: &Request, nodes: &[fuse_forget_one]) {
+        let async_impl = self.0.clone();
+        let nodes: Vec<_> = nodes
+            .iter()
+            .map(|node| (node.nodeid, node.nlookup))
+            .collect();
+
+        trace!(
+            "fs batch_forget nodes:{}, req id:{}",
+            nodes.len(),
+            req.unique()
+        );
+        spawn(async move {
+            async_impl
+                .batch_forget(nodes)
+                .instrument(debug_span!("batch_forget"))
+                .await;
+        });
+    }
+
     fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
         let async_impl = self.0.clone();
         trace!("fs getattr ino:{}, req id:{}", ino, req.unique());
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .getattr(ino)
-                .instrument(debug_span!("getattr"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("getattr"), async move {
+            async_impl.getattr(ino).await
         });
     }
 
@@ -543,13 +652,12 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
     ) {
         let async_impl = self.0.clone();
         trace!("fs setattr ino:{}, req id:{}", ino, req.unique());
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("setattr"), async move {
             async_impl
                 .setattr(
                     ino, mode, uid, gid, size, atime, mtime, ctime, fh, crtime, chgtime, bkuptime,
                     flags,
                 )
-                .instrument(debug_span!("setattr"))
                 .await
         });
     }
@@ -557,11 +665,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
     fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
         let async_impl = self.0.clone();
         trace!("fs readlink ino:{}, req id:{}", ino, req.unique());
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .readlink(ino)
-                .instrument(debug_span!("readlink"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("readlink"), async move {
+            async_impl.readlink(ino).await
         });
     }
 
@@ -586,10 +691,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             name,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("mknod"), async move {
             async_impl
                 .mknod(parent, name, mode, gid, uid, umask, rdev)
-                .instrument(debug_span!("mknod"))
                 .await
         });
     }
@@ -614,15 +718,13 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             name,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .mkdir(parent, name, mode, gid, uid, umask)
-                .instrument(debug_span!("mkdir"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("mkdir"), async move {
+            async_impl.mkdir(parent, name, mode, gid, uid, umask).await
         });
     }
 
     fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let uid = req.uid();
         let async_impl = self.0.clone();
         let name = name.to_string_lossy().to_string().into();
         trace!(
@@ -631,15 +733,13 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             name,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .unlink(parent, name)
-                .instrument(debug_span!("unlink"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("unlink"), async move {
+            async_impl.unlink(uid, parent, name).await
         });
     }
 
     fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let uid = req.uid();
         let async_impl = self.0.clone();
         let name = name.to_string_lossy().to_string().into();
         trace!(
@@ -648,11 +748,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             name,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .rmdir(parent, name)
-                .instrument(debug_span!("rmdir"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("rmdir"), async move {
+            async_impl.rmdir(uid, parent, name).await
         });
     }
 
@@ -676,11 +773,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             name,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .symlink(gid, uid, parent, name, link)
-                .instrument(debug_span!("symlink"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("symlink"), async move {
+            async_impl.symlink(gid, uid, parent, name, link).await
         });
     }
 
@@ -694,6 +788,7 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
         flags: u32,
         reply: ReplyEmpty,
     ) {
+        let uid = req.uid();
         let async_impl = self.0.clone();
         let name = name.to_string_lossy().to_string().into();
         let newname = newname.to_string_lossy().to_string().into();
@@ -705,10 +800,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             newname,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("rename"), async move {
             async_impl
-                .rename(parent, name, newparent, newname, flags)
-                .instrument(debug_span!("rename"))
+                .rename(uid, parent, name, newparent, newname, flags)
                 .await
         });
     }
@@ -730,11 +824,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             newname,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .link(ino, newparent, newname)
-                .instrument(debug_span!("link"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("link"), async move {
+            async_impl.link(ino, newparent, newname).await
         });
     }
 
@@ -746,11 +837,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flags,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .open(ino, flags)
-                .instrument(debug_span!("open"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("open"), async move {
+            async_impl.open(ino, flags).await
         });
     }
 
@@ -775,10 +863,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flags,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("read"), async move {
             async_impl
                 .read(ino, fh, offset, size, flags, lock_owner)
-                .instrument(debug_span!("read"))
                 .await
         });
     }
@@ -806,10 +893,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             data.len(),
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("write"), async move {
             async_impl
                 .write(ino, fh, offset, data, write_flags, flags, lock_owner)
-                .instrument(debug_span!("write"))
                 .await
         });
     }
@@ -817,11 +903,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
     fn flush(&mut self, req: &Request, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
         let async_impl = self.0.clone();
         trace!("fs flush ino:{}, fh:{}, req id:{}", ino, fh, req.unique());
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .flush(ino, fh, lock_owner)
-                .instrument(debug_span!("flush"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("flush"), async move {
+            async_impl.flush(ino, fh, lock_owner).await
         });
     }
 
@@ -844,11 +927,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flush,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .release(ino, fh, flags, lock_owner, flush)
-                .instrument(debug_span!("release"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("release"), async move {
+            async_impl.release(ino, fh, flags, lock_owner, flush).await
         });
     }
 
@@ -861,11 +941,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             datasync,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .fsync(ino, fh, datasync)
-                .instrument(debug_span!("fsync"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("fsync"), async move {
+            async_impl.fsync(ino, fh, datasync).await
         });
     }
 
@@ -877,11 +954,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flags,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .opendir(ino, flags)
-                .instrument(debug_span!("opendir"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("opendir"), async move {
+            async_impl.opendir(ino, flags).await
         });
     }
 
@@ -894,11 +968,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             offset,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .readdir(ino, fh, offset)
-                .instrument(debug_span!("readdir"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("readdir"), async move {
+            async_impl.readdir(ino, fh, offset).await
         });
     }
 
@@ -918,11 +989,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             offset,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .readdirplus(ino, fh, offset)
-                .instrument(debug_span!("readdirplus"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("readdirplus"), async move {
+            async_impl.readdirplus(ino, fh, offset).await
         });
     }
 
@@ -935,22 +1003,16 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             datasync,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .fsyncdir(ino, fh, datasync)
-                .instrument(debug_span!("fsyncdir"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("fsyncdir"), async move {
+            async_impl.fsyncdir(ino, fh, datasync).await
         });
     }
 
     fn statfs(&mut self, req: &Request, ino: u64, reply: ReplyStatfs) {
         let async_impl = self.0.clone();
         trace!("fs statfs ino:{}, req id:{}", ino, req.unique());
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .statfs(ino)
-                .instrument(debug_span!("statfs"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("statfs"), async move {
+            async_impl.statfs(ino).await
         });
     }
 
@@ -974,11 +1036,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flags,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .setxattr(ino, name, value, flags, position)
-                .instrument(debug_span!("setxattr"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("setxattr"), async move {
+            async_impl.setxattr(ino, name, value, flags, position).await
         });
     }
 
@@ -992,11 +1051,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             size,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .getxattr(ino, name, size)
-                .instrument(debug_span!("getxattr"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("getxattr"), async move {
+            async_impl.getxattr(ino, name, size).await
         });
     }
 
@@ -1008,11 +1064,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             size,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .listxattr(ino, size)
-                .instrument(debug_span!("listxattr"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("listxattr"), async move {
+            async_impl.listxattr(ino, size).await
         });
     }
 
@@ -1025,11 +1078,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             name,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .removexattr(ino, name)
-                .instrument(debug_span!("removeattr"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("removexattr"), async move {
+            async_impl.removexattr(ino, name).await
         });
     }
     fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
@@ -1040,11 +1090,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             mask,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .access(ino, mask)
-                .instrument(debug_span!("access"))
-                .await
+        let cred = Credentials::resolve(req.uid(), req.gid(), req.pid());
+        self.spawn_reply(req.unique(), reply, op_span!("access"), async move {
+            async_impl.access(cred, ino, mask).await
         });
     }
 
@@ -1070,10 +1118,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flags,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("create"), async move {
             async_impl
                 .create(uid, gid, parent, name, mode, umask, flags)
-                .instrument(debug_span!("create"))
                 .await
         });
     }
@@ -1101,10 +1148,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             typ,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("getlk"), async move {
             async_impl
                 .getlk(ino, fh, lock_owner, start, end, typ, pid)
-                .instrument(debug_span!("getlk"))
                 .await
         });
     }
@@ -1133,10 +1179,9 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             typ,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(req.unique(), reply, op_span!("setlk"), async move {
             async_impl
                 .setlk(ino, fh, lock_owner, start, end, typ, pid, sleep)
-                .instrument(debug_span!("setlk"))
                 .await
         });
     }
@@ -1150,11 +1195,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             idx,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .bmap(ino, blocksize, idx)
-                .instrument(debug_span!("bmap"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("bmap"), async move {
+            async_impl.bmap(ino, blocksize, idx).await
         });
     }
 
@@ -1178,11 +1220,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             mode,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .fallocate(ino, fh, offset, length, mode)
-                .instrument(debug_span!("fallocate"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("fallocate"), async move {
+            async_impl.fallocate(ino, fh, offset, length, mode).await
         });
     }
 
@@ -1204,11 +1243,8 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             whence,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
-            async_impl
-                .lseek(ino, fh, offset, whence)
-                .instrument(debug_span!("lseek"))
-                .await
+        self.spawn_reply(req.unique(), reply, op_span!("lseek"), async move {
+            async_impl.lseek(ino, fh, offset, whence).await
         });
     }
 
@@ -1239,12 +1275,47 @@ impl<T: AsyncFileSystem + 'static> Filesystem for AsyncFs<T> {
             flags,
             req.unique()
         );
-        spawn_reply(req.unique(), reply, async move {
+        self.spawn_reply(
+            req.unique(),
+            reply,
+            op_span!("copy_file_range"),
+            async move {
+                async_impl
+                    .copy_file_range(
+                        ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags,
+                    )
+                    .await
+            },
+        );
+    }
+
+    fn ioctl(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        let async_impl = self.0.clone();
+        let uid = req.uid();
+        let in_data = in_data.to_vec();
+        trace!(
+            "fs ioctl ino:{}, fh:{}, flags:{}, cmd:{:#x}, in:{}, out_size:{}, req id:{}",
+            ino,
+            fh,
+            flags,
+            cmd,
+            in_data.len(),
+            out_size,
+            req.unique()
+        );
+        self.spawn_reply(req.unique(), reply, op_span!("ioctl"), async move {
             async_impl
-                .copy_file_range(
-                    ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len, flags,
-                )
-                .instrument(debug_span!("copy_file_range"))
+                .ioctl(uid, ino, fh, flags, cmd, in_data, out_size)
                 .await
         });
     }
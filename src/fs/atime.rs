@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// How often the access times recorded by reads are saved, see `AtimeBatch`.
+pub const ATIME_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Access times recorded by reads of a mount and not saved yet.
+///
+/// Saving the inode on every read makes reads write, and reads of the same file conflict with
+/// each other and with writers. Reads record the access time here instead, it is saved along
+/// with the next change of the inode, or by `TiFs::flush_atimes` periodically.
+#[derive(Debug, Default)]
+pub struct AtimeBatch {
+    pending: Mutex<HashMap<u64, SystemTime>>,
+}
+
+impl AtimeBatch {
+    pub fn touch(&self, ino: u64, atime: SystemTime) {
+        let mut pending = self.pending.lock().unwrap();
+        let entry = pending.entry(ino).or_insert(atime);
+        *entry = (*entry).max(atime);
+    }
+
+    pub fn pending(&self, ino: u64) -> Option<SystemTime> {
+        self.pending.lock().unwrap().get(&ino).copied()
+    }
+
+    pub fn inos(&self) -> Vec<u64> {
+        self.pending.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Forget the access times saved by a committed transaction, as `(ino, atime)`. Times
+    /// recorded after the transaction read the inode are kept.
+    pub fn settle(&self, saved: &[(u64, SystemTime)]) {
+        if saved.is_empty() {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        for (ino, atime) in saved {
+            if matches!(pending.get(ino), Some(time) if time <= atime) {
+                pending.remove(ino);
+            }
+        }
+    }
+
+    /// Forget the access time of a removed inode.
+    pub fn discard(&self, ino: u64) {
+        self.pending.lock().unwrap().remove(&ino);
+    }
+}
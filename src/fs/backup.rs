@@ -0,0 +1,479 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+
+use fuser::FileType;
+use serde::{Deserialize, Serialize};
+use tikv_client::{Key, KvPair, TimestampExt};
+use tracing::{debug, info, warn};
+
+use super::error::{FsError, Result};
+use super::inode::Inode;
+use super::key::ScopedKey;
+use super::parallel::{self, SCAN_PARALLELISM};
+use super::serialize::{deserialize, serialize, ENCODING};
+use super::snapshot::SnapshotView;
+use super::tikv_fs::TiFs;
+
+const MAGIC: &[u8; 8] = b"TIFSBAK1";
+
+/// Time to keep the versions of a backup from the GC, if it is interrupted before removing
+/// its safepoint.
+const BACKUP_SAFEPOINT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Bytes of pairs written by one transaction while restoring.
+const RESTORE_BATCH_BYTES: usize = 4 << 20;
+
+const META_RECORD: u8 = 0;
+const DATA_RECORD: u8 = 1;
+const COMPLETE_RECORD: u8 = 2;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupHeader {
+    /// TSO the whole backup is read at.
+    pub timestamp: u64,
+    /// Timestamp of the backup this one is incremental to.
+    pub base: Option<u64>,
+    pub block_size: u64,
+}
+
+/// What tells the data of a file from its data at another time. Clients can set the times of
+/// a file at will, but not its data version, which every write bumps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DataVersion {
+    generation: u64,
+    version: u64,
+    mtime: SystemTime,
+    size: u64,
+}
+
+impl DataVersion {
+    fn of(inode: &Inode) -> Self {
+        Self {
+            generation: inode.generation,
+            version: inode.data_version,
+            mtime: inode.mtime,
+            size: inode.size,
+        }
+    }
+}
+
+/// The backup an incremental one is based on, with the data versions of its files.
+#[derive(Debug)]
+pub struct BaseBackup {
+    pub timestamp: u64,
+    files: HashMap<u64, DataVersion>,
+}
+
+impl BaseBackup {
+    /// Whether the data of `inode` may differ from the one backed up.
+    fn is_modified(&self, inode: &Inode) -> bool {
+        self.files.get(&inode.ino) != Some(&DataVersion::of(inode))
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BackupStats {
+    pub meta_keys: u64,
+    pub files: u64,
+    pub data_keys: u64,
+    pub bytes: u64,
+}
+
+/// A record of the backup stream.
+///
+/// Metadata (the meta, inodes, indexes and directories) is always backed up as a whole,
+/// the blocks of a file only if it is modified since the base backup. The blocks of a file
+/// are followed by `Complete`, so a restore takes the blocks of every file from the latest
+/// backup of the chain that completes it.
+enum Record {
+    Meta(Vec<u8>, Vec<u8>),
+    Data(Vec<u8>, Vec<u8>),
+    Complete(u64),
+}
+
+pub(super) fn write_frame<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+pub(super) fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut data = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &Record) -> io::Result<()> {
+    match record {
+        Record::Meta(key, value) | Record::Data(key, value) => {
+            let tag = match record {
+                Record::Meta(_, _) => META_RECORD,
+                _ => DATA_RECORD,
+            };
+            writer.write_all(&[tag])?;
+            write_frame(writer, key)?;
+            write_frame(writer, value)
+        }
+        Record::Complete(ino) => {
+            writer.write_all(&[COMPLETE_RECORD])?;
+            writer.write_all(&ino.to_le_bytes())
+        }
+    }
+}
+
+/// The next record, `None` at the end of the stream.
+fn read_record<R: Read>(reader: &mut R) -> Result<Option<Record>> {
+    let mut tag = [0; 1];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let record = match tag[0] {
+        META_RECORD => Record::Meta(read_frame(reader)?, read_frame(reader)?),
+        DATA_RECORD => Record::Data(read_frame(reader)?, read_frame(reader)?),
+        COMPLETE_RECORD => {
+            let mut ino = [0; 8];
+            reader.read_exact(&mut ino)?;
+            Record::Complete(u64::from_le_bytes(ino))
+        }
+        tag => {
+            return Err(FsError::InvalidBackup(format!(
+                "unknown record tag {}",
+                tag
+            )))
+        }
+    };
+    Ok(Some(record))
+}
+
+pub fn write_header<W: Write>(writer: &mut W, header: &BackupHeader) -> Result<()> {
+    writer.write_all(MAGIC)?;
+    let data = serialize(header).map_err(|err| FsError::Serialize {
+        target: "backup header",
+        typ: ENCODING,
+        msg: err.to_string(),
+    })?;
+    write_frame(writer, &data)?;
+    Ok(())
+}
+
+/// Read a whole backup to base an incremental one on. Every backup has the inodes of the whole
+/// volume, so the data versions of the files are taken from its metadata.
+pub fn read_base<R: Read>(reader: &mut R) -> Result<BaseBackup> {
+    let header = read_header(reader)?;
+    let mut files = HashMap::new();
+    while let Some(record) = read_record(reader)? {
+        if let Record::Meta(key, value) = record {
+            if let Ok(ScopedKey::Inode(ino)) = ScopedKey::parse(&key) {
+                let inode = Inode::deserialize(&value)?;
+                if inode.kind == FileType::RegularFile {
+                    files.insert(ino, DataVersion::of(&inode));
+                }
+            }
+        }
+    }
+    Ok(BaseBackup {
+        timestamp: header.timestamp,
+        files,
+    })
+}
+
+pub fn read_header<R: Read>(reader: &mut R) -> Result<BackupHeader> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(FsError::InvalidBackup("not a tifs backup".to_owned()));
+    }
+    deserialize(&read_frame(reader)?).map_err(|err| FsError::Serialize {
+        target: "backup header",
+        typ: ENCODING,
+        msg: err.to_string(),
+    })
+}
+
+fn next_key(key: &Key) -> Key {
+    let mut data: Vec<u8> = key.clone().into();
+    data.push(0);
+    data.into()
+}
+
+/// Scan the whole `range`, `page` keys at a time.
+pub(crate) async fn scan_all<F>(
+    view: &mut SnapshotView,
+    range: Range<Key>,
+    page: u32,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(KvPair) -> Result<()>,
+{
+    let mut start = range.start;
+    loop {
+        let pairs = view.scan(start.clone()..range.end.clone(), page).await?;
+        let full = pairs.len() == page as usize;
+        if let Some(last) = pairs.last() {
+            start = next_key(last.key());
+        }
+        for pair in pairs {
+            f(pair)?;
+        }
+        if !full {
+            return Ok(());
+        }
+    }
+}
+
+/// Back up the volume at a single TSO into `writer`, with the blocks of only the files whose
+/// data differs from the one in `base` if it is given.
+///
+/// A safepoint is registered while backing up, so the versions at the TSO are not collected.
+pub async fn backup<W: Write>(
+    fs: &TiFs,
+    writer: &mut W,
+    base: Option<BaseBackup>,
+) -> Result<(BackupHeader, BackupStats)> {
+    let ts = fs.client.current_timestamp().await?.version();
+    let service = format!("backup-{}", ts);
+    fs.register_safepoint(&service, ts, BACKUP_SAFEPOINT_TTL)
+        .await?;
+    let result = backup_at(fs, writer, ts, base.as_ref()).await;
+    if let Err(err) = fs.remove_safepoint(&service).await {
+        warn!("fail to remove safepoint {}: {}", service, err);
+    }
+    result
+}
+
+async fn backup_at<W: Write>(
+    fs: &TiFs,
+    writer: &mut W,
+    ts: u64,
+    base: Option<&BaseBackup>,
+) -> Result<(BackupHeader, BackupStats)> {
+    let header = BackupHeader {
+        timestamp: ts,
+        base: base.map(|base| base.timestamp),
+        block_size: fs.block_size,
+    };
+    info!("back up at {}, base {:?}", ts, header.base);
+    write_header(writer, &header)?;
+
+    let mut view = SnapshotView::at(&fs.client, ts, fs.block_size);
+    let mut stats = BackupStats::default();
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+
+    let meta_key: Key = ScopedKey::meta().into();
+    let metadata = vec![
+        meta_key.clone()..next_key(&meta_key),
+        ScopedKey::inode_range(0..std::u64::MAX),
+        ScopedKey::index_range(),
+        ScopedKey::scratch_range(),
+    ];
+    // the regions holding the metadata are scanned side by side, and written in order
+    for range in metadata {
+        parallel::scan_at(fs, ts, range, SCAN_PARALLELISM, Ok, |pair| {
+            let mut value = pair.value().clone();
+            if let Ok(ScopedKey::Inode(_)) = ScopedKey::parse(pair.key().into()) {
+                let mut inode = Inode::deserialize(&value)?;
+                // handlers, locks and delegations do not survive a restore
+                inode.opened_fh = 0;
+                inode.lock_state.owner_set.clear();
+                inode.lock_state.lk_type = libc::F_UNLCK;
+                inode.delegation = None;
+                value = inode.serialize()?;
+
+                match inode.kind {
+                    FileType::Directory => dirs.push(inode.ino),
+                    FileType::RegularFile
+                        if inode.inline_data.is_none()
+                            && base.map_or(true, |base| base.is_modified(&inode)) =>
+                    {
+                        files.push(inode.ino)
+                    }
+                    _ => (),
+                }
+            }
+            write_record(writer, &Record::Meta(pair.key().clone().into(), value))?;
+            stats.meta_keys += 1;
+            Ok(())
+        })
+        .await?;
+    }
+
+    for ino in dirs {
+        scan_all(
+            &mut view,
+            ScopedKey::block_range(ino, 0..std::u64::MAX),
+            fs.scan_page,
+            |pair| {
+                write_record(
+                    writer,
+                    &Record::Meta(pair.key().clone().into(), pair.into_value()),
+                )?;
+                stats.meta_keys += 1;
+                Ok(())
+            },
+        )
+        .await?;
+    }
+
+    for ino in files {
+        scan_all(
+            &mut view,
+            ScopedKey::block_range(ino, 0..std::u64::MAX),
+            fs.scan_page,
+            |pair| {
+                stats.bytes += pair.value().len() as u64;
+                write_record(
+                    writer,
+                    &Record::Data(pair.key().clone().into(), pair.into_value()),
+                )?;
+                stats.data_keys += 1;
+                Ok(())
+            },
+        )
+        .await?;
+        write_record(writer, &Record::Complete(ino))?;
+        stats.files += 1;
+    }
+    writer.flush()?;
+    Ok((header, stats))
+}
+
+/// Restore a chain of backups, a full one followed by the incremental ones in order,
+/// into an uninitialized volume.
+///
+/// Metadata is restored from the last backup, and the blocks of every file from the latest
+/// backup which has them.
+pub async fn restore<R: Read>(fs: &TiFs, mut chain: Vec<R>) -> Result<BackupStats> {
+    if chain.is_empty() {
+        return Err(FsError::InvalidBackup("no backup to restore".to_owned()));
+    }
+    if fs.has_meta().await? {
+        return Err(FsError::InvalidBackup(
+            "the volume to restore into is initialized".to_owned(),
+        ));
+    }
+    fs.ensure_thawed().await?;
+
+    let mut headers: Vec<BackupHeader> = Vec::with_capacity(chain.len());
+    for (index, reader) in chain.iter_mut().enumerate() {
+        let header = read_header(reader)?;
+        let expected_base = headers.last().map(|base| base.timestamp);
+        if header.base != expected_base {
+            return Err(FsError::InvalidBackup(format!(
+                "backup {} is based on {:?} instead of {:?}",
+                index, header.base, expected_base
+            )));
+        }
+        if header.block_size != fs.block_size {
+            return Err(FsError::block_size_conflict(
+                header.block_size,
+                fs.block_size,
+            ));
+        }
+        headers.push(header);
+    }
+
+    let mut stats = BackupStats::default();
+    let mut pending: HashSet<u64> = HashSet::new();
+    let mut batch = RestoreBatch::default();
+    // the meta is written at last, so an interrupted restore can be started over
+    let mut meta = None;
+
+    let last = chain.len() - 1;
+    for (index, reader) in chain.iter_mut().enumerate().rev() {
+        let mut completed = Vec::new();
+        while let Some(record) = read_record(reader)? {
+            match record {
+                Record::Meta(key, value) if index == last => {
+                    stats.meta_keys += 1;
+                    match ScopedKey::parse(&key) {
+                        Ok(ScopedKey::Meta) => {
+                            meta = Some((key, value));
+                            continue;
+                        }
+                        Ok(ScopedKey::Inode(ino)) => {
+                            let inode = Inode::deserialize(&value)?;
+                            if inode.kind == FileType::RegularFile && inode.inline_data.is_none() {
+                                pending.insert(ino);
+                            }
+                        }
+                        _ => (),
+                    }
+                    batch.push(fs, key, value).await?;
+                }
+                Record::Meta(_, _) => (),
+                Record::Data(key, value) => {
+                    let ino = match ScopedKey::parse(&key)? {
+                        ScopedKey::Block { ino, block: _ } => ino,
+                        _ => return Err(FsError::InvalidScopedKey(key.clone())),
+                    };
+                    if pending.contains(&ino) {
+                        stats.data_keys += 1;
+                        stats.bytes += value.len() as u64;
+                        batch.push(fs, key, value).await?;
+                    }
+                }
+                Record::Complete(ino) => completed.push(ino),
+            }
+        }
+        for ino in completed {
+            if pending.remove(&ino) {
+                stats.files += 1;
+            }
+        }
+        debug!("restored backup {} at {}", index, headers[index].timestamp);
+    }
+    batch.flush(fs).await?;
+    if let Some((key, value)) = meta {
+        batch.push(fs, key, value).await?;
+        batch.flush(fs).await?;
+    }
+    // the restored inodes are written raw, count the usage of the volume on them
+    fs.rebase_usage().await?;
+
+    if !pending.is_empty() {
+        warn!(
+            "blocks of {} files are missing in the backups",
+            pending.len()
+        );
+    }
+    Ok(stats)
+}
+
+#[derive(Default)]
+struct RestoreBatch {
+    pairs: Vec<(Key, Vec<u8>)>,
+    bytes: usize,
+}
+
+impl RestoreBatch {
+    async fn push(&mut self, fs: &TiFs, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.bytes += key.len() + value.len();
+        self.pairs.push((key.into(), value));
+        if self.bytes >= RESTORE_BATCH_BYTES {
+            self.flush(fs).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self, fs: &TiFs) -> Result<()> {
+        if !self.pairs.is_empty() {
+            fs.put_pairs(std::mem::take(&mut self.pairs)).await?;
+        }
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+impl BackupHeader {
+    pub fn describe(&self) -> String {
+        match self.base {
+            Some(base) => format!("incremental backup at {} based on {}", self.timestamp, base),
+            None => format!("full backup at {}", self.timestamp),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use bytes::Bytes;
+use bytestring::ByteString;
+use fuser::FileType;
+
+use super::error::{FsError, Result};
+use super::inode::Inode;
+
+/// Parent directory of an operation in a batch.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Parent {
+    /// An existing directory.
+    Inode(u64),
+    /// The directory created by the operation at this index of the same batch.
+    Created(usize),
+}
+
+#[derive(Clone, Debug)]
+pub enum BatchOp {
+    Mkdir {
+        parent: Parent,
+        name: ByteString,
+        mode: u32,
+    },
+    Create {
+        parent: Parent,
+        name: ByteString,
+        mode: u32,
+        data: Bytes,
+    },
+    Symlink {
+        parent: Parent,
+        name: ByteString,
+        target: ByteString,
+    },
+    /// Hard link to `target`, which is referred to like a parent.
+    Link {
+        parent: Parent,
+        name: ByteString,
+        target: Parent,
+    },
+    /// Write `data` at `offset` of `target`, which is referred to like a parent.
+    Write {
+        target: Parent,
+        offset: u64,
+        data: Bytes,
+    },
+    /// Move an entry, replacing the entry named `newname` if it exists.
+    Rename {
+        parent: Parent,
+        name: ByteString,
+        newparent: Parent,
+        newname: ByteString,
+    },
+    /// Remove an entry, an empty directory or a link of a file.
+    Unlink { parent: Parent, name: ByteString },
+}
+
+impl BatchOp {
+    /// The names of the entries the operation makes or removes.
+    pub fn names(&self) -> Vec<&ByteString> {
+        match self {
+            BatchOp::Mkdir { name, .. }
+            | BatchOp::Create { name, .. }
+            | BatchOp::Symlink { name, .. }
+            | BatchOp::Link { name, .. }
+            | BatchOp::Unlink { name, .. } => vec![name],
+            BatchOp::Rename { name, newname, .. } => vec![name, newname],
+            BatchOp::Write { .. } => vec![],
+        }
+    }
+}
+
+/// Mutations applied in one transaction, so they commit atomically: for ingestion tools which
+/// would otherwise pay a commit per file, and for applications replacing files, which no
+/// reader sees half written or missing in between.
+///
+/// TiKV limits the size of a transaction, so keep batches to some megabytes.
+///
+/// ```ignore
+/// let mut batch = Batch::new(uid, gid);
+/// let dir = batch.mkdir(Parent::Inode(ROOT_INODE), "dir", 0o755);
+/// batch.create(dir, "file", 0o644, "content");
+/// let attrs = fs.batch(batch).await?;
+///
+/// // replace `config` by a new version, with the file of another version linked as well
+/// let mut batch = Batch::new(uid, gid);
+/// let tmp = batch.create(Parent::Inode(dir), ".config.tmp", 0o644, "");
+/// batch.write(tmp, 0, header);
+/// batch.write(tmp, header.len() as u64, body);
+/// batch.rename(Parent::Inode(dir), ".config.tmp", Parent::Inode(dir), "config");
+/// fs.batch(batch).await?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Batch {
+    pub uid: u32,
+    pub gid: u32,
+    pub ops: Vec<BatchOp>,
+}
+
+impl Batch {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            uid,
+            gid,
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn push(&mut self, op: BatchOp) -> Parent {
+        self.ops.push(op);
+        Parent::Created(self.ops.len() - 1)
+    }
+
+    /// Create a directory, the result can be the parent of later operations.
+    pub fn mkdir(&mut self, parent: Parent, name: impl Into<ByteString>, mode: u32) -> Parent {
+        self.push(BatchOp::Mkdir {
+            parent,
+            name: name.into(),
+            mode,
+        })
+    }
+
+    pub fn create(
+        &mut self,
+        parent: Parent,
+        name: impl Into<ByteString>,
+        mode: u32,
+        data: impl Into<Bytes>,
+    ) -> Parent {
+        self.push(BatchOp::Create {
+            parent,
+            name: name.into(),
+            mode,
+            data: data.into(),
+        })
+    }
+
+    pub fn symlink(
+        &mut self,
+        parent: Parent,
+        name: impl Into<ByteString>,
+        target: impl Into<ByteString>,
+    ) -> Parent {
+        self.push(BatchOp::Symlink {
+            parent,
+            name: name.into(),
+            target: target.into(),
+        })
+    }
+
+    /// Hard link `target`, e.g. a file created earlier in the batch.
+    pub fn link(&mut self, parent: Parent, name: impl Into<ByteString>, target: Parent) -> Parent {
+        self.push(BatchOp::Link {
+            parent,
+            name: name.into(),
+            target,
+        })
+    }
+
+    /// Write to `target`, e.g. a file created earlier in the batch.
+    pub fn write(&mut self, target: Parent, offset: u64, data: impl Into<Bytes>) -> Parent {
+        self.push(BatchOp::Write {
+            target,
+            offset,
+            data: data.into(),
+        })
+    }
+
+    /// Move an entry like `rename`, the result refers to the moved file.
+    pub fn rename(
+        &mut self,
+        parent: Parent,
+        name: impl Into<ByteString>,
+        newparent: Parent,
+        newname: impl Into<ByteString>,
+    ) -> Parent {
+        self.push(BatchOp::Rename {
+            parent,
+            name: name.into(),
+            newparent,
+            newname: newname.into(),
+        })
+    }
+
+    /// Remove an entry like `unlink` or `rmdir`, the result refers to the file as it was
+    /// before and must not be used as a parent.
+    pub fn unlink(&mut self, parent: Parent, name: impl Into<ByteString>) -> Parent {
+        self.push(BatchOp::Unlink {
+            parent,
+            name: name.into(),
+        })
+    }
+}
+
+/// The inode number of `target`, given the inodes created by the earlier operations.
+pub fn resolve(target: Parent, created: &[Inode]) -> Result<u64> {
+    match target {
+        Parent::Inode(ino) => Ok(ino),
+        Parent::Created(index) => created
+            .get(index)
+            .map(|inode| inode.ino)
+            .ok_or(FsError::InvalidBatchParent { index }),
+    }
+}
+
+/// The inode number of `parent`, which must be a directory if created in the batch.
+pub fn resolve_parent(parent: Parent, created: &[Inode]) -> Result<u64> {
+    match parent {
+        Parent::Created(index) => match created.get(index) {
+            Some(inode) if inode.kind != FileType::Directory => {
+                Err(FsError::NotDirectory { ino: inode.ino })
+            }
+            _ => resolve(parent, created),
+        },
+        Parent::Inode(_) => resolve(parent, created),
+    }
+}
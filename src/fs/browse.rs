@@ -0,0 +1,518 @@
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_std::channel::{bounded, Receiver, Sender};
+use async_std::future::timeout;
+use async_std::io::{self, BufReader, BufWriter, Write};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task::spawn;
+use fuser::FileType;
+use tracing::{debug, info};
+
+use super::error::{FsError, Result};
+use super::tikv_fs::TiFs;
+
+/// Bytes of a file read and sent at once.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// Bytes of a request head accepted at most, larger ones are refused.
+const MAX_HEAD_SIZE: u64 = 16 << 10;
+
+/// Time a client is given to send the head of its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time a client is given to take each write of a response.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Connections served at once, the next ones wait in the backlog of the listener.
+const MAX_CONNECTIONS: usize = 64;
+
+struct Request {
+    head_only: bool,
+    path: String,
+    range: Option<String>,
+}
+
+/// Byte range of a file to send, `end` excluded.
+#[derive(Debug, PartialEq)]
+enum Span {
+    Full(u64),
+    Partial { start: u64, end: u64, size: u64 },
+    Unsatisfiable(u64),
+}
+
+/// Parse the value of a `Range` header against a file of `size` bytes. Only a single range
+/// of bytes is served, anything else is answered with the whole file, as the header allows.
+fn parse_range(range: Option<&str>, size: u64) -> Span {
+    let spec = match range.and_then(|range| range.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Span::Full(size),
+    };
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return Span::Full(size),
+    };
+    let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+    let (start, end) = if first.is_empty() {
+        // `bytes=-n`, the last n bytes
+        match last.parse::<u64>() {
+            Ok(0) => return Span::Unsatisfiable(size),
+            Ok(n) => (size.saturating_sub(n), size),
+            Err(_) => return Span::Full(size),
+        }
+    } else {
+        let start = match first.parse::<u64>() {
+            Ok(start) => start,
+            Err(_) => return Span::Full(size),
+        };
+        let end = if last.is_empty() {
+            size
+        } else {
+            match last.parse::<u64>() {
+                Ok(last) if last >= start => size.min(last.saturating_add(1)),
+                _ => return Span::Full(size),
+            }
+        };
+        (start, end)
+    };
+    if start >= size {
+        Span::Unsatisfiable(size)
+    } else {
+        Span::Partial { start, end, size }
+    }
+}
+
+fn decode_path(path: &str) -> Option<String> {
+    let path = path.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn encode_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Read the head of a request, `None` if the client closed the connection before sending one.
+async fn read_request(stream: &TcpStream) -> Result<Option<std::result::Result<Request, u16>>> {
+    // the limit keeps a line without end from being buffered whole
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_SIZE));
+    let mut head = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await?;
+        if !line.ends_with('\n') {
+            if reader.get_ref().limit() == 0 {
+                return Ok(Some(Err(431)));
+            }
+            return Ok(None);
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        head.push(line.trim_end().to_owned());
+    }
+    let mut request_line = match head.first() {
+        Some(line) => line.split_whitespace(),
+        None => return Ok(Some(Err(400))),
+    };
+    let head_only = match request_line.next() {
+        Some("GET") => false,
+        Some("HEAD") => true,
+        Some(_) => return Ok(Some(Err(405))),
+        None => return Ok(Some(Err(400))),
+    };
+    let path = match request_line.next().and_then(decode_path) {
+        Some(path) => path,
+        None => return Ok(Some(Err(400))),
+    };
+    let range = head[1..].iter().find_map(|header| {
+        let colon = header.find(':')?;
+        if header[..colon].eq_ignore_ascii_case("range") {
+            Some(header[colon + 1..].trim().to_owned())
+        } else {
+            None
+        }
+    });
+    Ok(Some(Ok(Request {
+        head_only,
+        path,
+        range,
+    })))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        416 => "Range Not Satisfiable",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+async fn write_head<W: Write + Unpin>(
+    writer: &mut W,
+    status: u16,
+    headers: &[(&str, String)],
+) -> Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+    for (name, value) in headers {
+        write!(head, "{}: {}\r\n", name, value).unwrap();
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    io::timeout(WRITE_TIMEOUT, writer.write_all(head.as_bytes())).await?;
+    Ok(())
+}
+
+async fn write_error<W: Write + Unpin>(writer: &mut W, status: u16) -> Result<()> {
+    let body = format!("{} {}\n", status, reason(status));
+    let mut headers = vec![
+        ("Content-Type", "text/plain; charset=utf-8".to_owned()),
+        ("Content-Length", body.len().to_string()),
+    ];
+    if status == 405 {
+        headers.push(("Allow", "GET, HEAD".to_owned()));
+    }
+    write_head(writer, status, &headers).await?;
+    io::timeout(WRITE_TIMEOUT, writer.write_all(body.as_bytes())).await?;
+    Ok(())
+}
+
+fn status_of(err: FsError) -> u16 {
+    let errno: libc::c_int = err.into();
+    match errno {
+        libc::ENOENT | libc::ENOTDIR => 404,
+        libc::EACCES | libc::EPERM => 403,
+        _ => 500,
+    }
+}
+
+async fn write_listing<W: Write + Unpin>(
+    fs: &TiFs,
+    writer: &mut W,
+    path: &str,
+    ino: u64,
+    head_only: bool,
+) -> Result<()> {
+    let mut items = fs.read_dir(ino).await?;
+    items.retain(|item| item.name != "." && item.name != "..");
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let title = escape_html(path);
+    let mut body = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+         <body><h1>{0}</h1>\n<ul>\n",
+        title
+    );
+    if path != "/" {
+        body.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for item in items {
+        let suffix = if item.typ == FileType::Directory {
+            "/"
+        } else {
+            ""
+        };
+        writeln!(
+            body,
+            "<li><a href=\"{}{}\">{}{}</a></li>",
+            encode_name(&item.name),
+            suffix,
+            escape_html(&item.name),
+            suffix
+        )
+        .unwrap();
+    }
+    body.push_str("</ul></body></html>\n");
+
+    write_head(
+        writer,
+        200,
+        &[
+            ("Content-Type", "text/html; charset=utf-8".to_owned()),
+            ("Content-Length", body.len().to_string()),
+        ],
+    )
+    .await?;
+    if !head_only {
+        io::timeout(WRITE_TIMEOUT, writer.write_all(body.as_bytes())).await?;
+    }
+    Ok(())
+}
+
+async fn write_file<W: Write + Unpin>(
+    fs: &TiFs,
+    writer: &mut W,
+    ino: u64,
+    size: u64,
+    request: &Request,
+) -> Result<()> {
+    let mut headers = vec![
+        ("Content-Type", "application/octet-stream".to_owned()),
+        ("Accept-Ranges", "bytes".to_owned()),
+    ];
+    let (status, start, end) = match parse_range(request.range.as_deref(), size) {
+        Span::Full(size) => (200, 0, size),
+        Span::Partial { start, end, size } => {
+            headers.push((
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end - 1, size),
+            ));
+            (206, start, end)
+        }
+        Span::Unsatisfiable(size) => {
+            headers.push(("Content-Range", format!("bytes */{}", size)));
+            headers.push(("Content-Length", "0".to_owned()));
+            return write_head(writer, 416, &headers).await;
+        }
+    };
+    headers.push(("Content-Length", (end - start).to_string()));
+    write_head(writer, status, &headers).await?;
+    if request.head_only {
+        return Ok(());
+    }
+
+    let mut offset = start;
+    while offset < end {
+        let data = fs
+            .read_file(ino, offset, CHUNK_SIZE.min(end - offset))
+            .await?;
+        if data.is_empty() {
+            // the file shrank meanwhile, the client sees a short body
+            break;
+        }
+        io::timeout(WRITE_TIMEOUT, writer.write_all(&data)).await?;
+        offset += data.len() as u64;
+    }
+    Ok(())
+}
+
+async fn respond(fs: &TiFs, stream: &TcpStream) -> Result<()> {
+    let request = match timeout(REQUEST_TIMEOUT, read_request(stream)).await {
+        Ok(request) => match request? {
+            Some(request) => request,
+            None => return Ok(()),
+        },
+        Err(_) => Err(408),
+    };
+    let mut writer = BufWriter::new(stream);
+    let request = match request {
+        Ok(request) => request,
+        Err(status) => {
+            write_error(&mut writer, status).await?;
+            return Ok(io::timeout(WRITE_TIMEOUT, writer.flush()).await?);
+        }
+    };
+    debug!("browse {}", request.path);
+
+    let attr = match fs.resolve_path(&request.path).await {
+        Ok(ino) => fs.read_inode(ino).await,
+        Err(err) => Err(err),
+    };
+    let result = match attr {
+        Ok(attr) if attr.kind == FileType::Directory => {
+            if request.path.ends_with('/') {
+                write_listing(fs, &mut writer, &request.path, attr.ino, request.head_only).await
+            } else {
+                // relative links of the listing need the trailing slash
+                let location = format!("{}/", request.path)
+                    .split('/')
+                    .map(encode_name)
+                    .collect::<Vec<_>>()
+                    .join("/");
+                write_head(
+                    &mut writer,
+                    301,
+                    &[("Location", location), ("Content-Length", "0".to_owned())],
+                )
+                .await
+            }
+        }
+        Ok(attr) if attr.kind == FileType::RegularFile => {
+            write_file(fs, &mut writer, attr.ino, attr.size, &request).await
+        }
+        Ok(_) => write_error(&mut writer, 403).await,
+        Err(err) => write_error(&mut writer, status_of(err)).await,
+    };
+    result?;
+    Ok(io::timeout(WRITE_TIMEOUT, writer.flush()).await?)
+}
+
+/// Bind the address of `-o http_browse`, before mounting, so an address in use fails the mount.
+pub async fn bind(addr: &str) -> Result<TcpListener> {
+    Ok(TcpListener::bind(addr).await?)
+}
+
+/// Serve the volume read-only over HTTP, see `-o http_browse`.
+///
+/// A `GET` of a directory lists its entries as HTML, a `GET` of a regular file sends its
+/// contents, or the single byte range asked by a `Range` header. Every connection serves one
+/// request, whose head must arrive within `REQUEST_TIMEOUT`, and every write of the response
+/// must be taken within `WRITE_TIMEOUT`. At most `MAX_CONNECTIONS` are served at once.
+/// Nothing is authenticated, the address should only be reachable by those allowed to read
+/// the whole volume.
+pub async fn serve_browser(fs: Arc<TiFs>, listener: TcpListener) -> Result<()> {
+    info!("browse the volume over http://{}", listener.local_addr()?);
+    let permits = Permits::new(MAX_CONNECTIONS);
+    let mut incoming = listener.incoming();
+    loop {
+        let permit = permits.acquire().await;
+        let stream = match incoming.next().await {
+            Some(stream) => stream,
+            None => break,
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                debug!("fail to accept a browser connection: {}", err);
+                continue;
+            }
+        };
+        let fs = fs.clone();
+        spawn(async move {
+            if let Err(err) = respond(&fs, &stream).await {
+                debug!("fail to answer a browser request: {}", err);
+            }
+            drop(permit);
+        });
+    }
+    Ok(())
+}
+
+/// A counting semaphore over a bounded channel, each message in it a connection served.
+struct Permits {
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+/// A connection served, until it is dropped.
+struct Permit(Receiver<()>);
+
+impl Permits {
+    fn new(capacity: usize) -> Self {
+        let (sender, receiver) = bounded(capacity);
+        Self { sender, receiver }
+    }
+
+    /// Wait until fewer than the capacity are served.
+    async fn acquire(&self) -> Permit {
+        // both ends are kept, so the channel is never closed
+        let _ = self.sender.send(()).await;
+        Permit(self.receiver.clone())
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let _ = self.0.try_recv();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range(None, 10), Span::Full(10));
+        assert_eq!(
+            parse_range(Some("bytes=2-4"), 10),
+            Span::Partial {
+                start: 2,
+                end: 5,
+                size: 10
+            }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=7-"), 10),
+            Span::Partial {
+                start: 7,
+                end: 10,
+                size: 10
+            }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=-3"), 10),
+            Span::Partial {
+                start: 7,
+                end: 10,
+                size: 10
+            }
+        );
+        assert_eq!(
+            parse_range(Some("bytes=5-100"), 10),
+            Span::Partial {
+                start: 5,
+                end: 10,
+                size: 10
+            }
+        );
+        assert_eq!(parse_range(Some("bytes=10-"), 10), Span::Unsatisfiable(10));
+        assert_eq!(parse_range(Some("bytes=-0"), 10), Span::Unsatisfiable(10));
+        assert_eq!(parse_range(Some("bytes=4-2"), 10), Span::Full(10));
+        assert_eq!(parse_range(Some("bytes=0-1,4-5"), 10), Span::Full(10));
+        assert_eq!(parse_range(Some("lines=1-2"), 10), Span::Full(10));
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(decode_path("/a%20b/c?x=1").as_deref(), Some("/a b/c"));
+        assert_eq!(decode_path("/%e4%b8%ad").as_deref(), Some("/中"));
+        assert_eq!(decode_path("/%zz"), None);
+        assert_eq!(decode_path("/%4"), None);
+        assert_eq!(encode_name("a b&<c>.txt"), "a%20b%26%3Cc%3E.txt");
+        assert_eq!(
+            decode_path(&encode_name("中 ?#%")).as_deref(),
+            Some("中 ?#%")
+        );
+        assert_eq!(
+            escape_html("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+}
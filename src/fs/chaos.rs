@@ -0,0 +1,147 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use async_std::task::sleep;
+use tracing::debug;
+
+use super::error::{FsError, Result};
+
+/// Fault injection for testing applications against a flaky network filesystem,
+/// configured by `-o chaos=latency:50ms,error:0.1%[,eagain:1%][,ops:read+write]`.
+///
+/// - `latency`: delay added to every selected operation, in `ms`, `us` or `s`.
+/// - `error`: probability of failing a selected operation with `EIO`.
+/// - `eagain`: probability of failing a selected operation with `EAGAIN`.
+/// - `ops`: `+` separated names of the operations to disturb, all of them by default.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Chaos {
+    pub latency: Duration,
+    pub eio: f64,
+    pub eagain: f64,
+    pub ops: Vec<String>,
+}
+
+static RNG_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// A cheap xorshift, faults do not need a good random source.
+fn random() -> f64 {
+    let mut x = RNG_STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d)
+            | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RNG_STATE.store(x, Ordering::Relaxed);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl Chaos {
+    pub fn is_selected(&self, op: &str) -> bool {
+        self.ops.is_empty() || self.ops.iter().any(|selected| selected == op)
+    }
+
+    /// Disturb the operation `op` before it is served.
+    pub async fn inject(&self, op: &str) -> Result<()> {
+        if !self.is_selected(op) {
+            return Ok(());
+        }
+
+        if self.latency > Duration::from_secs(0) {
+            sleep(self.latency).await;
+        }
+
+        let dice = random();
+        let errno = if dice < self.eio {
+            libc::EIO
+        } else if dice < self.eio + self.eagain {
+            libc::EAGAIN
+        } else {
+            return Ok(());
+        };
+        debug!("chaos injects errno({}) into {}", errno, op);
+        Err(FsError::Injected {
+            op: op.to_owned(),
+            errno,
+        })
+    }
+}
+
+fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let (num, unit) = value.split_at(
+        value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or_else(|| value.len()),
+    );
+    let num: u64 = num.parse()?;
+    match unit {
+        "" | "ms" => Ok(Duration::from_millis(num)),
+        "us" => Ok(Duration::from_micros(num)),
+        "s" => Ok(Duration::from_secs(num)),
+        _ => Err(anyhow!("unknown unit of duration: {}", value)),
+    }
+}
+
+fn parse_probability(value: &str) -> anyhow::Result<f64> {
+    let probability = if value.ends_with('%') {
+        value[..value.len() - 1].parse::<f64>()? / 100.
+    } else {
+        value.parse::<f64>()?
+    };
+    if !(0. ..=1.).contains(&probability) {
+        return Err(anyhow!("probability out of range: {}", value));
+    }
+    Ok(probability)
+}
+
+fn format_probability(probability: f64) -> String {
+    format!("{}%", probability * 100.)
+}
+
+impl FromStr for Chaos {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let mut chaos = Chaos::default();
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let mut splitter = item.splitn(2, ':');
+            let key = splitter.next().unwrap_or("");
+            let value = splitter
+                .next()
+                .ok_or_else(|| anyhow!("value of chaos item {} is not supplied", key))?;
+            match key {
+                "latency" => chaos.latency = parse_duration(value)?,
+                "error" => chaos.eio = parse_probability(value)?,
+                "eagain" => chaos.eagain = parse_probability(value)?,
+                "ops" => chaos.ops = value.split('+').map(ToOwned::to_owned).collect(),
+                _ => return Err(anyhow!("unknown chaos item: {}", key)),
+            }
+        }
+        if chaos.eio + chaos.eagain > 1. {
+            return Err(anyhow!("sum of error probabilities exceeds 100%"));
+        }
+        Ok(chaos)
+    }
+}
+
+impl Display for Chaos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut items = vec![format!("latency:{}us", self.latency.as_micros())];
+        if self.eio > 0. {
+            items.push(format!("error:{}", format_probability(self.eio)));
+        }
+        if self.eagain > 0. {
+            items.push(format!("eagain:{}", format_probability(self.eagain)));
+        }
+        if !self.ops.is_empty() {
+            items.push(format!("ops:{}", self.ops.join("+")));
+        }
+        write!(f, "{}", items.join(","))
+    }
+}
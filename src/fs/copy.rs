@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::Duration;
+
+use fuser::FileType;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use super::error::Result;
+use super::inode::Inode;
+use super::key::ScopedKey;
+use super::snapshot::SnapshotView;
+use super::tikv_fs::TiFs;
+
+/// Entries and bytes of file contents written to the destination in one transaction.
+const COPY_BATCH_ENTRIES: usize = 1 << 8;
+const COPY_BATCH_BYTES: u64 = 4 << 20;
+
+/// Blocks of a large file read by one scan, and written by one transaction.
+const COPY_SCAN_BLOCKS: u32 = 1 << 6;
+
+/// The copy reads the source at one timestamp, which is kept from GC until it finishes.
+const COPY_SAFEPOINT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, Serialize)]
+pub struct CopyStats {
+    pub dirs: u64,
+    pub files: u64,
+    pub symlinks: u64,
+    /// Entries linked to a file copied earlier.
+    pub hardlinks: u64,
+    /// Named pipes and devices.
+    pub specials: u64,
+    /// Bytes of the stored blocks and inline data, holes are not counted.
+    pub bytes: u64,
+}
+
+/// An entry to create in the destination, with the attributes and the data of the source.
+#[derive(Debug, Clone)]
+pub struct CopyEntry {
+    pub name: String,
+    pub source: Inode,
+    /// The copy of the file this entry is a hard link to, if it is copied by an earlier batch.
+    /// Links within a batch are found by the inode numbers of the sources.
+    pub link_to: Option<u64>,
+    /// Stored data as `(offset, data)`, trimmed to the size. Unless the file is large, its
+    /// data and attributes are written along with the entry.
+    pub data: Option<Vec<(u64, Vec<u8>)>>,
+}
+
+/// Copy the entries of the directory `root` of `src` into the directory `dest` of `dst`,
+/// which may be the same volume or one on another cluster.
+///
+/// The source is read at a single timestamp, so a copy into its own subtree does not recurse.
+/// Only stored blocks are copied, so holes stay holes, and owners, modes, ACLs and times are
+/// kept. Hard links within the subtree are kept too.
+pub async fn copy_tree(src: &TiFs, root: u64, dst: &TiFs, dest: u64) -> Result<CopyStats> {
+    dst.ensure_thawed().await?;
+    let ts = src.client.current_timestamp().await?.version();
+    let service = format!("copy-{}", ts);
+    src.register_safepoint(&service, ts, COPY_SAFEPOINT_TTL)
+        .await?;
+    let result = copy_at(src, ts, root, dst, dest).await;
+    if let Err(err) = src.remove_safepoint(&service).await {
+        warn!("fail to remove safepoint {}: {}", service, err);
+    }
+    result
+}
+
+/// State of a copy from a snapshot of the source into the destination.
+struct Copier<'a> {
+    view: SnapshotView,
+    block_size: u64,
+    dst: &'a TiFs,
+    /// Copies of the files with more than one link, by the inode numbers of the sources.
+    copied: HashMap<u64, u64>,
+    /// Directories to copy the entries of, with their copies and their sources.
+    pending: Vec<(u64, u64, Option<Inode>)>,
+    stats: CopyStats,
+}
+
+async fn copy_at(src: &TiFs, ts: u64, root: u64, dst: &TiFs, dest: u64) -> Result<CopyStats> {
+    let mut copier = Copier {
+        view: SnapshotView::at(&src.client, ts, src.block_size).with_hashed_names(src.hashed_names),
+        block_size: src.block_size,
+        dst,
+        copied: HashMap::new(),
+        pending: vec![(root, dest, None)],
+        stats: CopyStats::default(),
+    };
+    while let Some((from, to, dir)) = copier.pending.pop() {
+        copier.copy_dir(from, to).await?;
+        // copying the entries changed the times of the directory
+        if let Some(dir) = dir {
+            dst.copy_data(to, Vec::new(), Some(dir)).await?;
+        }
+    }
+    Ok(copier.stats)
+}
+
+impl Copier<'_> {
+    /// Copy the entries of the directory `from` into `to`, in batches.
+    async fn copy_dir(&mut self, from: u64, to: u64) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut bytes = 0;
+        for item in self.view.read_dir(from).await? {
+            if item.name == "." || item.name == ".." {
+                continue;
+            }
+            let source = self.view.read_inode(item.ino).await?;
+            let link_to = self.copied.get(&source.ino).copied();
+            let data = match (source.kind, link_to) {
+                (FileType::RegularFile, None) if source.size <= COPY_BATCH_BYTES => {
+                    let data = self.read_data(&source, 0, u32::MAX).await?;
+                    bytes += data_len(&data);
+                    Some(data)
+                }
+                _ => None,
+            };
+            entries.push(CopyEntry {
+                name: item.name,
+                source,
+                link_to,
+                data,
+            });
+            if entries.len() >= COPY_BATCH_ENTRIES || bytes >= COPY_BATCH_BYTES {
+                self.copy_entries(to, mem::take(&mut entries)).await?;
+                bytes = 0;
+            }
+        }
+        self.copy_entries(to, entries).await
+    }
+
+    /// Stored data of `source` from the block `first`, at most `limit` blocks, as
+    /// `(offset, data)` trimmed to its size.
+    async fn read_data(
+        &mut self,
+        source: &Inode,
+        first: u64,
+        limit: u32,
+    ) -> Result<Vec<(u64, Vec<u8>)>> {
+        if let Some(inlined) = &source.inline_data {
+            return Ok(vec![(0, inlined.clone())]);
+        }
+        let end = (source.size + self.block_size - 1) / self.block_size;
+        if first >= end {
+            return Ok(Vec::new());
+        }
+        let limit = (end - first).min(limit as u64) as u32;
+        let range = ScopedKey::block_range(source.ino, first..end);
+        let mut data = Vec::new();
+        for pair in self.view.scan(range, limit).await? {
+            let block = match ScopedKey::parse(pair.key().into())? {
+                ScopedKey::Block { ino: _, block } => block,
+                _ => continue,
+            };
+            let offset = block * self.block_size;
+            let mut value: Vec<u8> = pair.into_value();
+            value.truncate(source.size.saturating_sub(offset) as usize);
+            data.push((offset, value));
+        }
+        Ok(data)
+    }
+
+    /// Create the copies of `entries` in `to`, then copy the data of the large files among
+    /// them page by page.
+    async fn copy_entries(&mut self, to: u64, entries: Vec<CopyEntry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let inos = self.dst.copy_entries(to, entries.clone()).await?;
+        let mut large = Vec::new();
+        for (entry, ino) in entries.into_iter().zip(inos) {
+            let source = entry.source;
+            if entry.link_to.is_some() || self.copied.contains_key(&source.ino) {
+                self.stats.hardlinks += 1;
+                continue;
+            }
+            match source.kind {
+                FileType::Directory => {
+                    self.pending.push((source.ino, ino, Some(source)));
+                    self.stats.dirs += 1;
+                }
+                FileType::Symlink => self.stats.symlinks += 1,
+                FileType::RegularFile => {
+                    if source.nlink > 1 {
+                        self.copied.insert(source.ino, ino);
+                    }
+                    match entry.data {
+                        Some(data) => self.stats.bytes += data_len(&data),
+                        None => large.push((source, ino)),
+                    }
+                    self.stats.files += 1;
+                }
+                kind => {
+                    debug!("copied inode({}) of {:?} without data", source.ino, kind);
+                    self.stats.specials += 1;
+                }
+            }
+        }
+        for (source, ino) in large {
+            let mut next = 0;
+            loop {
+                let data = self.read_data(&source, next, COPY_SCAN_BLOCKS).await?;
+                self.stats.bytes += data_len(&data);
+                match data.last() {
+                    Some((offset, _)) if data.len() == COPY_SCAN_BLOCKS as usize => {
+                        next = offset / self.block_size + 1;
+                        self.dst.copy_data(ino, data, None).await?;
+                    }
+                    _ => {
+                        self.dst.copy_data(ino, data, Some(source)).await?;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn data_len(data: &[(u64, Vec<u8>)]) -> u64 {
+    data.iter().map(|(_, data)| data.len() as u64).sum()
+}
@@ -0,0 +1,69 @@
+use std::fs;
+
+use fuser::{FileAttr, FileType};
+use tracing::debug;
+
+/// The user and groups a request is made on behalf of.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups of the calling process.
+    pub groups: Vec<u32>,
+}
+
+/// Parse the `Groups:` line of `/proc/<pid>/status`.
+fn parse_groups(status: &str) -> Option<Vec<u32>> {
+    let line = status.lines().find(|line| line.starts_with("Groups:"))?;
+    line["Groups:".len()..]
+        .split_whitespace()
+        .map(|gid| gid.parse().ok())
+        .collect()
+}
+
+impl Credentials {
+    pub fn new(uid: u32, gid: u32, groups: Vec<u32>) -> Self {
+        Self { uid, gid, groups }
+    }
+
+    /// Credentials of the process `pid`, with its supplementary groups read from `/proc`.
+    /// Only the primary group is known if the process is gone or `/proc` is not available.
+    pub fn resolve(uid: u32, gid: u32, pid: u32) -> Self {
+        let groups = match fs::read_to_string(format!("/proc/{}/status", pid)) {
+            Ok(status) => parse_groups(&status).unwrap_or_default(),
+            Err(err) => {
+                debug!("fail to read supplementary groups of pid({}): {}", pid, err);
+                Vec::new()
+            }
+        };
+        Self::new(uid, gid, groups)
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// Whether the permission bits of `attr` grant `mask`, a combination of `R_OK`, `W_OK`
+    /// and `X_OK`. The class of the owner, group or others is picked as `access(2)` does.
+    pub fn permits(&self, attr: &FileAttr, mask: i32) -> bool {
+        let mask = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u16;
+        if self.is_root() {
+            // root may search any directory, but execute a file only if anyone may
+            return mask & libc::X_OK as u16 == 0
+                || attr.kind == FileType::Directory
+                || attr.perm & 0o111 != 0;
+        }
+        let bits = if self.uid == attr.uid {
+            attr.perm >> 6
+        } else if self.in_group(attr.gid) {
+            attr.perm >> 3
+        } else {
+            attr.perm
+        };
+        bits & mask == mask
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use super::error::{FsError, Result};
+
+/// FUSE operations which change the volume, refused by degraded mounts.
+const MUTATING_OPS: &[&str] = &[
+    "setattr",
+    "mknod",
+    "mkdir",
+    "unlink",
+    "rmdir",
+    "symlink",
+    "rename",
+    "link",
+    "write",
+    "flush",
+    "fsync",
+    "setxattr",
+    "removexattr",
+    "create",
+    "fallocate",
+    "copy_file_range",
+    // sets the flags of `chattr`
+    "ioctl",
+];
+
+/// Whether `op` changes the volume, so that it is refused by degraded mounts.
+fn is_mutating(op: &str) -> bool {
+    MUTATING_OPS.contains(&op)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// When the commits started to fail, none if the last one succeeded.
+    failing_since: Option<Instant>,
+    degraded: bool,
+}
+
+/// Outcomes of the commits of a mount, which degrades to read-only once its commits have
+/// failed for the whole `window`, e.g. when a region of the cluster loses its quorum.
+///
+/// Any successful commit ends the degradation, the registration of the mount every
+/// `HEARTBEAT_INTERVAL` keeps trying one while the mutating operations are refused.
+#[derive(Debug)]
+pub struct CommitHealth {
+    window: Duration,
+    state: Mutex<State>,
+}
+
+impl CommitHealth {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// How long a commit is waited for, and how long commits fail before the mount degrades.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.degraded {
+            info!("commits succeed again, mount is writable");
+        }
+        *state = State::default();
+    }
+
+    pub fn failed(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failing_since.get_or_insert_with(Instant::now);
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.degraded {
+            let window = self.window;
+            state.degraded = state
+                .failing_since
+                .map_or(false, |since| since.elapsed() >= window);
+            if state.degraded {
+                warn!("commits fail for {:?}, mount is read-only", window);
+            }
+        }
+        state.degraded
+    }
+
+    /// Refuse `op` if it is mutating and the mount is degraded.
+    pub fn check(&self, op: &'static str) -> Result<()> {
+        if is_mutating(op) && self.is_degraded() {
+            Err(FsError::Degraded {
+                op,
+                window: self.window,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use super::error::{FsError, Result};
+use super::file_handler::FileHandler;
+use super::flags::FS_APPEND_FL;
+use super::inode::Inode;
+
+/// How long a delegation lasts without being renewed by its holder.
+pub const LEASE: Duration = Duration::from_secs(10);
+
+/// Interval for holders to renew their delegations and to check for recalls,
+/// and for other mounts to wait for a recalled delegation.
+pub const RENEW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Buffered bytes of a file to flush without waiting for a recall or a close.
+pub const MAX_BUFFERED: usize = 4 << 20;
+
+/// Interval to flush the writes buffered by `-o write_buffer` to files kept open.
+pub const WRITE_BUFFER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Write delegation of a file, enabled by the `delegation` mount option.
+///
+/// A mount opening a file for writing while nobody else has it open is granted the delegation,
+/// it buffers the writes locally and flushes them in bulk. Opening the file on another mount
+/// recalls the delegation, the holder then flushes its buffer and falls back to write-through.
+/// A holder which stops renewing the lease loses its buffered writes once the lease expires.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Delegation {
+    /// Id of the holding mount.
+    pub holder: u64,
+    pub expires: SystemTime,
+    pub recalled: bool,
+}
+
+impl Delegation {
+    pub fn grant(holder: u64) -> Self {
+        Self {
+            holder,
+            expires: SystemTime::now() + LEASE,
+            recalled: false,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    /// Whether the lease is half gone.
+    pub fn needs_renewal(&self) -> bool {
+        self.expires <= SystemTime::now() + LEASE / 2
+    }
+
+    pub fn renew(&mut self) {
+        self.expires = SystemTime::now() + LEASE;
+    }
+}
+
+/// A handler found fit to write by `Txn::check_write`.
+///
+/// The write buffer of the file keeps it until the buffer is taken, so the writes buffered in
+/// between are checked against it rather than each in a transaction of its own. Seeks and
+/// changes of the inode on this mount take the buffer first, changes made by other mounts fail
+/// the flush.
+#[derive(Clone, Copy, Debug)]
+pub struct WriteCheck {
+    ino: u64,
+    cursor: u64,
+    append: bool,
+    /// Size of the file when it is checked.
+    size: u64,
+    append_only: bool,
+}
+
+impl WriteCheck {
+    pub fn new(handler: &FileHandler, inode: &Inode) -> Self {
+        Self {
+            ino: inode.ino,
+            cursor: handler.cursor,
+            append: handler.is_append(),
+            size: inode.size,
+            append_only: inode.fs_flags & FS_APPEND_FL != 0,
+        }
+    }
+
+    /// Where a write at `offset` starts, appends land past the end of the file and past the
+    /// writes buffered before it, which end at `end`.
+    pub fn start(&self, offset: i64, end: u64) -> Result<u64> {
+        let start = if self.append {
+            self.size.max(end) as i64
+        } else {
+            self.cursor as i64 + offset
+        };
+        if start < 0 {
+            return Err(FsError::InvalidOffset {
+                ino: self.ino,
+                offset: start,
+            });
+        }
+        if self.append_only && (start as u64) < self.size {
+            return Err(FsError::AppendOnly { ino: self.ino });
+        }
+        Ok(start as u64)
+    }
+}
+
+/// Writes to a delegated or buffered file as `(fh, offset, data)`, not yet in TiKV. The
+/// offsets are where the writes start in the file, see `WriteCheck::start`.
+///
+/// A write continuing the last one of the same handle is appended to it, so sequential small
+/// writes are flushed as a few large ones.
+#[derive(Debug, Default)]
+pub struct WriteBuffer {
+    writes: Vec<(u64, i64, Vec<u8>)>,
+    size: usize,
+    /// Handlers checked since the buffer is taken.
+    checked: HashMap<u64, WriteCheck>,
+}
+
+impl WriteBuffer {
+    pub fn push(&mut self, fh: u64, offset: i64, data: Bytes) {
+        self.size += data.len();
+        match self.writes.last_mut() {
+            Some((last_fh, last_offset, last))
+                if *last_fh == fh && *last_offset + last.len() as i64 == offset =>
+            {
+                last.extend_from_slice(&data)
+            }
+            _ => self.writes.push((fh, offset, data.to_vec())),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.size >= MAX_BUFFERED
+    }
+
+    /// End of the buffered writes.
+    pub fn end(&self) -> u64 {
+        self.writes
+            .iter()
+            .map(|(_, offset, data)| *offset as u64 + data.len() as u64)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn checked(&self, fh: u64) -> Option<WriteCheck> {
+        self.checked.get(&fh).copied()
+    }
+
+    pub fn check(&mut self, fh: u64, check: WriteCheck) {
+        self.checked.insert(fh, check);
+    }
+
+    /// Take the buffered writes to flush them, the handlers are checked again for the writes
+    /// buffered after.
+    pub fn take(&mut self) -> Vec<(u64, i64, Bytes)> {
+        self.size = 0;
+        self.checked.clear();
+        mem::take(&mut self.writes)
+            .into_iter()
+            .map(|(fh, offset, data)| (fh, offset, data.into()))
+            .collect()
+    }
+
+    /// Put back writes which fail to be flushed, before the ones buffered since.
+    pub fn restore(&mut self, writes: Vec<(u64, i64, Bytes)>) {
+        let writes = writes
+            .into_iter()
+            .map(|(fh, offset, data)| (fh, offset, data.to_vec()))
+            .collect();
+        let later = mem::replace(&mut self.writes, writes);
+        self.writes.extend(later);
+        self.size = self.writes.iter().map(|(_, _, data)| data.len()).sum();
+    }
+}
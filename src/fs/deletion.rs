@@ -0,0 +1,60 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{FsError, Result};
+use super::serialize::{deserialize, serialize, ENCODING};
+
+/// Interval of draining the deletion queue.
+pub const DELETION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Blocks deleted by one transaction while draining the deletion queue.
+pub const DELETION_BATCH_BLOCKS: u64 = 1 << 10;
+
+/// Blocks of a removed file which are not deleted yet.
+///
+/// Removing the last link of a file only queues its blocks, so unlinking a huge file takes one
+/// small transaction. `TiFs::serve_deletion` deletes them from the end in batches, shrinking
+/// the extent until it is empty.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeadExtent {
+    pub blocks: Range<u64>,
+    /// Generation of the removed inode, the number is released with it once it is empty.
+    #[serde(default)]
+    pub generation: u64,
+}
+
+impl DeadExtent {
+    pub fn new(blocks: Range<u64>, generation: u64) -> Self {
+        Self { blocks, generation }
+    }
+
+    /// Take the last `limit` blocks of the extent to delete them.
+    pub fn take_batch(&mut self, limit: u64) -> Range<u64> {
+        let start = self.blocks.end.saturating_sub(limit).max(self.blocks.start);
+        let batch = start..self.blocks.end;
+        self.blocks.end = start;
+        batch
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.start >= self.blocks.end
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "dead extent",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "dead extent",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// Names of the FUSE operations, as they are served by `AsyncFs`.
+pub const OPS: &[&str] = &[
+    "lookup",
+    "getattr",
+    "setattr",
+    "readlink",
+    "mknod",
+    "mkdir",
+    "unlink",
+    "rmdir",
+    "symlink",
+    "rename",
+    "link",
+    "open",
+    "read",
+    "write",
+    "flush",
+    "release",
+    "fsync",
+    "opendir",
+    "readdir",
+    "readdirplus",
+    "fsyncdir",
+    "statfs",
+    "setxattr",
+    "getxattr",
+    "listxattr",
+    "removexattr",
+    "access",
+    "create",
+    "getlk",
+    "setlk",
+    "bmap",
+    "fallocate",
+    "lseek",
+    "copy_file_range",
+    "ioctl",
+];
+
+/// Operations refused by a mount with `-o deny=unlink,rename`, e.g. for kiosks which should
+/// only read and append. They fail with EPERM before any transaction is started.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DeniedOps(Vec<&'static str>);
+
+impl DeniedOps {
+    pub fn is_op(name: &str) -> bool {
+        OPS.contains(&name)
+    }
+
+    pub fn contains(&self, op: &str) -> bool {
+        self.0.contains(&op)
+    }
+}
+
+impl FromStr for DeniedOps {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let mut ops = Vec::new();
+        for name in value.split(|c| c == ',' || c == ':') {
+            let op = OPS
+                .iter()
+                .find(|op| **op == name)
+                .ok_or_else(|| anyhow!("unknown operation: {}", name))?;
+            if !ops.contains(op) {
+                ops.push(*op);
+            }
+        }
+        Ok(DeniedOps(ops))
+    }
+}
+
+impl Display for DeniedOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(","))
+    }
+}
@@ -1,3 +1,8 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
 use super::error::{FsError, Result};
 use super::reply::DirItem;
 use super::serialize::{deserialize, serialize, ENCODING};
@@ -35,3 +40,105 @@ pub fn decode_item(bytes: &[u8]) -> Result<DirItem> {
         msg: err.to_string(),
     })
 }
+
+/// How a listing is kept across the `readdir` calls of an open directory, by
+/// `-o readdir=snapshot` or `-o readdir=relaxed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadDirIsolation {
+    /// The directory is read once when it is opened or rewound, so a listing is consistent
+    /// even if the directory is modified meanwhile.
+    Snapshot,
+    /// Every call reads the latest directory, so concurrent modifications may skip or repeat
+    /// entries. A call conflicting with a transaction serves the last listing of the handle
+    /// instead of waiting for it.
+    Relaxed,
+}
+
+impl Default for ReadDirIsolation {
+    fn default() -> Self {
+        Self::Snapshot
+    }
+}
+
+impl FromStr for ReadDirIsolation {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "snapshot" => Ok(Self::Snapshot),
+            "relaxed" => Ok(Self::Relaxed),
+            _ => Err(anyhow!("unknown readdir isolation: {}", value)),
+        }
+    }
+}
+
+impl Display for ReadDirIsolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Snapshot => write!(f, "snapshot"),
+            Self::Relaxed => write!(f, "relaxed"),
+        }
+    }
+}
+
+/// A directory opened by `opendir`, until `releasedir`.
+#[derive(Debug, Clone)]
+pub struct DirStream {
+    pub ino: u64,
+    /// The directory listed as `..`.
+    pub parent: u64,
+    /// The listing served to the following `readdir` calls, see `ReadDirIsolation`.
+    pub listing: Option<Directory>,
+}
+
+impl DirStream {
+    pub fn new(ino: u64, parent: u64) -> Self {
+        Self {
+            ino,
+            parent,
+            listing: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fuser::FileType;
+    use proptest::prelude::*;
+
+    fn item() -> impl Strategy<Value = DirItem> {
+        let typ = prop_oneof![
+            Just(FileType::Directory),
+            Just(FileType::RegularFile),
+            Just(FileType::Symlink),
+            Just(FileType::NamedPipe),
+        ];
+        (any::<u64>(), "\\PC{0,255}", typ).prop_map(|(ino, name, typ)| DirItem { ino, name, typ })
+    }
+
+    fn fields(dir: &[DirItem]) -> Vec<(u64, &str, FileType)> {
+        dir.iter()
+            .map(|item| (item.ino, item.name.as_str(), item.typ))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn directory_round_trip(dir in prop::collection::vec(item(), 0..64)) {
+            let decoded = decode(&encode(&dir).unwrap()).unwrap();
+            prop_assert_eq!(fields(&dir), fields(&decoded));
+        }
+
+        #[test]
+        fn item_round_trip(item in item()) {
+            let decoded = decode_item(&encode_item(&item).unwrap()).unwrap();
+            prop_assert_eq!(fields(&[item]), fields(&[decoded]));
+        }
+
+        #[test]
+        fn decode_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = decode(&bytes);
+            let _ = decode_item(&bytes);
+        }
+    }
+}
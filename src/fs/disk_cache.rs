@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use async_std::fs;
+use async_std::path::PathBuf;
+use async_std::prelude::*;
+use tracing::{debug, info, warn};
+
+use super::inode::Inode;
+use super::seal::{digest, hex};
+
+/// Bytes of the generation stored before the data of a cached block.
+const GENERATION_LEN: usize = 8 + 16 + 8;
+
+/// Bytes of the generation and the sha256 digest stored before the data of a cached block.
+const HEADER_LEN: usize = GENERATION_LEN + 32;
+
+/// The content of a file as of one modification, cached blocks of other generations are stale.
+///
+/// It is keyed on `Inode::data_version`, which clients cannot set back as they can the times;
+/// the modification time only tells apart versions of an inode restored from a backup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Generation {
+    version: u64,
+    mtime: u128,
+    size: u64,
+}
+
+impl Generation {
+    pub fn of(inode: &Inode) -> Self {
+        Self {
+            version: inode.data_version,
+            mtime: inode
+                .mtime
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0),
+            size: inode.size,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = self.version.to_le_bytes().to_vec();
+        data.extend_from_slice(&self.mtime.to_le_bytes());
+        data.extend_from_slice(&self.size.to_le_bytes());
+        data
+    }
+
+    fn decode(data: &[u8]) -> Self {
+        Self {
+            version: u64::from_le_bytes(data[..8].try_into().unwrap()),
+            mtime: u128::from_le_bytes(data[8..24].try_into().unwrap()),
+            size: u64::from_le_bytes(data[24..GENERATION_LEN].try_into().unwrap()),
+        }
+    }
+}
+
+/// Least recently used order of the cached blocks.
+#[derive(Debug, Default)]
+struct Lru {
+    tick: u64,
+    used: u64,
+    entries: HashMap<(u64, u64), (u64, u64)>,
+    order: BTreeMap<u64, (u64, u64)>,
+}
+
+impl Lru {
+    fn touch(&mut self, key: (u64, u64), len: u64) {
+        self.remove(key);
+        self.tick += 1;
+        self.entries.insert(key, (len, self.tick));
+        self.order.insert(self.tick, key);
+        self.used += len;
+    }
+
+    fn remove(&mut self, key: (u64, u64)) {
+        if let Some((len, tick)) = self.entries.remove(&key) {
+            self.order.remove(&tick);
+            self.used -= len;
+        }
+    }
+
+    fn pop_over(&mut self, capacity: u64) -> Option<(u64, u64)> {
+        if self.used <= capacity {
+            return None;
+        }
+        let key = *self.order.values().next()?;
+        self.remove(key);
+        Some(key)
+    }
+}
+
+/// Block data kept on a local disk across remounts, enabled by
+/// `-o cache_dir=/var/cache/tifs,cache_size=10G`.
+///
+/// Every block lives in `<cache_dir>/<volume>/<ino>/<block>` with the generation of the file it
+/// was read at and its sha256, so corrupted blocks and blocks of files modified since are dropped
+/// on read. Volumes sharing a `cache_dir` are kept apart by `volume_dir`, each within its own
+/// `cache_size`. Errors of the local disk are logged and served as misses.
+#[derive(Debug)]
+pub struct DiskCache {
+    dir: PathBuf,
+    capacity: u64,
+    block_size: u64,
+    lru: Mutex<Lru>,
+}
+
+impl DiskCache {
+    pub const DEFAULT_CAPACITY: u64 = 1 << 30;
+
+    /// Name of the directory of a volume in the cache. Keys are not prefixed, so every volume
+    /// has a cluster of its own and is told apart by its PD endpoints.
+    pub fn volume_dir(pd_endpoints: &[String]) -> String {
+        let mut endpoints = pd_endpoints.to_vec();
+        endpoints.sort();
+        endpoints.dedup();
+        format!("volume-{}", hex(&digest(endpoints.join(",").as_bytes())[..8]))
+    }
+
+    /// Open the cache of `volume` in `dir`, picking up the blocks cached by earlier mounts.
+    pub async fn open(
+        dir: impl Into<PathBuf>,
+        volume: &str,
+        capacity: u64,
+        block_size: u64,
+    ) -> io::Result<Self> {
+        let root = dir.into();
+        let dir = root.join(volume);
+        fs::create_dir_all(&dir).await?;
+
+        // blocks cached before volumes were told apart
+        let mut legacy = fs::read_dir(&root).await?;
+        while let Some(entry) = legacy.next().await {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().parse::<u64>().is_ok() {
+                fs::remove_dir_all(entry.path()).await?;
+            }
+        }
+
+        let mut blocks = Vec::new();
+        let mut inodes = fs::read_dir(&dir).await?;
+        while let Some(inode_dir) = inodes.next().await {
+            let inode_dir = inode_dir?;
+            let ino = match inode_dir.file_name().to_string_lossy().parse::<u64>() {
+                Ok(ino) => ino,
+                Err(_) => continue,
+            };
+            let mut entries = fs::read_dir(inode_dir.path()).await?;
+            while let Some(entry) = entries.next().await {
+                let entry = entry?;
+                let metadata = entry.metadata().await?;
+                match entry.file_name().to_string_lossy().parse::<u64>() {
+                    Ok(block) => blocks.push((
+                        metadata.modified().unwrap_or(UNIX_EPOCH),
+                        (ino, block),
+                        metadata.len(),
+                    )),
+                    // leftovers of interrupted writes
+                    Err(_) => fs::remove_file(entry.path()).await?,
+                }
+            }
+        }
+        blocks.sort();
+
+        let cache = Self {
+            dir,
+            capacity,
+            block_size,
+            lru: Mutex::new(Lru::default()),
+        };
+        {
+            let mut lru = cache.lru.lock().unwrap();
+            for (_, key, len) in blocks {
+                lru.touch(key, len);
+            }
+            info!(
+                "open disk cache in {:?}, {} blocks, {} bytes",
+                cache.dir,
+                lru.entries.len(),
+                lru.used
+            );
+        }
+        cache.evict().await;
+        Ok(cache)
+    }
+
+    fn path(&self, ino: u64, block: u64) -> PathBuf {
+        self.dir.join(ino.to_string()).join(block.to_string())
+    }
+
+    async fn evict(&self) {
+        loop {
+            let key = self.lru.lock().unwrap().pop_over(self.capacity);
+            let (ino, block) = match key {
+                Some(key) => key,
+                None => break,
+            };
+            if let Err(err) = fs::remove_file(self.path(ino, block)).await {
+                debug!("fail to evict block({}, {}): {}", ino, block, err);
+            }
+        }
+    }
+
+    async fn drop_block(&self, ino: u64, block: u64) {
+        self.lru.lock().unwrap().remove((ino, block));
+        let _ = fs::remove_file(self.path(ino, block)).await;
+    }
+
+    async fn get(&self, ino: u64, block: u64, generation: Generation) -> Option<Vec<u8>> {
+        let path = self.path(ino, block);
+        let mut value = match fs::read(&path).await {
+            Ok(value) => value,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("fail to read cached block({}, {}): {}", ino, block, err);
+                return None;
+            }
+        };
+        if value.len() < HEADER_LEN || Generation::decode(&value) != generation {
+            debug!("drop stale cached block({}, {})", ino, block);
+            self.drop_block(ino, block).await;
+            return None;
+        }
+        if value[GENERATION_LEN..HEADER_LEN] != digest(&value[HEADER_LEN..])[..] {
+            warn!("drop corrupted cached block({}, {})", ino, block);
+            self.drop_block(ino, block).await;
+            return None;
+        }
+        self.lru
+            .lock()
+            .unwrap()
+            .touch((ino, block), value.len() as u64);
+        Some(value.split_off(HEADER_LEN))
+    }
+
+    async fn put(&self, ino: u64, block: u64, generation: Generation, data: &[u8]) {
+        let mut value = generation.encode();
+        value.extend_from_slice(&digest(data));
+        value.extend_from_slice(data);
+
+        let path = self.path(ino, block);
+        let tmp = path.with_extension(format!("{}", self.lru.lock().unwrap().tick));
+        let result = async {
+            fs::create_dir_all(self.dir.join(ino.to_string())).await?;
+            fs::write(&tmp, &value).await?;
+            fs::rename(&tmp, &path).await
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                self.lru
+                    .lock()
+                    .unwrap()
+                    .touch((ino, block), value.len() as u64);
+                self.evict().await;
+            }
+            Err(err) => {
+                warn!("fail to cache block({}, {}): {}", ino, block, err);
+                let _ = fs::remove_file(&tmp).await;
+            }
+        }
+    }
+
+    /// Read `size` bytes from `start` of `inode` if all of the blocks are cached.
+    pub async fn read(&self, inode: &Inode, start: u64, size: u64) -> Option<Vec<u8>> {
+        if start >= inode.size {
+            return Some(Vec::new());
+        }
+        let size = size.min(inode.size - start);
+        let generation = Generation::of(inode);
+        let start_block = start / self.block_size;
+        let end_block = (start + size + self.block_size - 1) / self.block_size;
+
+        let mut data = Vec::with_capacity(((end_block - start_block) * self.block_size) as usize);
+        for block in start_block..end_block {
+            let mut value = self.get(inode.ino, block, generation).await?;
+            value.resize(self.block_size as usize, 0);
+            data.extend_from_slice(&value);
+        }
+        data.drain(..(start % self.block_size) as usize);
+        data.truncate(size as usize);
+        Some(data)
+    }
+
+    /// Cache the whole blocks in `data` read from `start` of `inode`.
+    pub async fn fill(&self, inode: &Inode, start: u64, data: &[u8]) {
+        let generation = Generation::of(inode);
+        let end = start + data.len() as u64;
+        let mut block = (start + self.block_size - 1) / self.block_size;
+        loop {
+            let block_start = block * self.block_size;
+            let block_end = (block_start + self.block_size).min(inode.size);
+            if block_start >= block_end || block_end > end {
+                break;
+            }
+            let slice = &data[(block_start - start) as usize..(block_end - start) as usize];
+            self.put(inode.ino, block, generation, slice).await;
+            block += 1;
+        }
+    }
+}
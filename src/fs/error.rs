@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 use tracing::error;
 
@@ -28,12 +30,21 @@ pub enum FsError {
     #[error("cannot find inode({inode})")]
     InodeNotFound { inode: u64 },
 
+    #[error("stale handle of inode({ino})")]
+    Stale { ino: u64 },
+
+    #[error("cannot resolve the path of inode({ino})")]
+    NoPath { ino: u64 },
+
     #[error("cannot find {ino}({fh})")]
     FhNotFound { ino: u64, fh: u64 },
 
     #[error("invalid offset({offset}) of ino({ino})")]
     InvalidOffset { ino: u64, offset: i64 },
 
+    #[error("unaligned direct io on ino({ino}): offset({offset}), size({size})")]
+    UnalignedDirectIo { ino: u64, offset: u64, size: u64 },
+
     #[error("unknown whence({whence})")]
     UnknownWhence { whence: i32 },
 
@@ -49,8 +60,9 @@ pub enum FsError {
     #[error("unknown file type")]
     UnknownFileType,
 
-    #[error("key error: {0}")]
-    KeyError(String),
+    /// Conflict or lock of a transaction, with the key if it is known.
+    #[error("key error: {msg}")]
+    KeyError { msg: String, key: Option<Vec<u8>> },
 
     #[error("excess max retry times: {0}")]
     RetryTimesExcess(u64),
@@ -64,13 +76,134 @@ pub enum FsError {
     #[error("invalid lock")]
     InvalidLock,
 
-    #[error("block size conflicts: origin({origin}) != new({new})")]
+    #[error("option `{name}` is turned off for the volume")]
+    VolumeOption { name: &'static str },
+
+    #[error("hashed_names cannot be changed once the volume has entries")]
+    HashedNamesChange,
+
+    #[error("operation {op} is denied by the mount")]
+    Denied { op: &'static str },
+
+    #[error("operation {op} is refused, commits have failed for {window:?}")]
+    Degraded { op: &'static str, window: Duration },
+
+    #[error("commit did not finish in {window:?}")]
+    CommitTimeout { window: Duration },
+
+    #[error("mounts {mounts:?} did not hold their writes in time, the volume is thawed")]
+    FreezeTimeout { mounts: Vec<u64> },
+
+    #[error("volume is frozen by freeze({id})")]
+    Frozen { id: u64 },
+
+    #[error(
+        "volume requires features({features:#x}) unknown to this client, mount it with `-o ro`"
+    )]
+    UnsupportedFeatures { features: u64 },
+
+    #[error(
+        "block size conflicts: origin({origin}) != new({new}), pass `-o blksize={}` or leave it out",
+        .origin >> 10
+    )]
     BlockSizeConflict { origin: u64, new: u64 },
 
     #[error("index not found")]
     IndexNotFound,
+
+    #[error("cannot find xattr({name}) of inode({ino})")]
+    XattrNotFound { ino: u64, name: String },
+
+    #[error("buffer of size({size}) is too small for {required} bytes")]
+    BufferTooSmall { size: u32, required: u32 },
+
+    #[error("xattr({name}) is not supported")]
+    UnsupportedXattr { name: String },
+
+    #[error("inode({ino}) is sealed")]
+    Sealed { ino: u64 },
+
+    #[error("sealed inode({ino}) does not match its Merkle tree")]
+    SealMismatch { ino: u64 },
+
+    #[error("invalid value of xattr({name})")]
+    InvalidXattrValue { name: String },
+
+    #[error("directory inode({ino}) cannot be moved into its own subtree")]
+    MoveIntoItself { ino: u64 },
+
+    #[error("unsupported fallocate mode({mode:#x}) on inode({ino})")]
+    FallocateMode { ino: u64, mode: i32 },
+
+    #[error("inode({ino}) is not a directory")]
+    NotDirectory { ino: u64 },
+
+    #[error("inode({ino}) is locked by WORM")]
+    WormLocked { ino: u64 },
+
+    #[error("inode({ino}) is a read only historical version")]
+    ReadOnlyVersion { ino: u64 },
+
+    #[error("inode({ino}) is a named pipe served by the kernel")]
+    NamedPipe { ino: u64 },
+
+    #[error("injected errno({errno}) into {op}")]
+    Injected { op: String, errno: i32 },
+
+    #[error("parent of batch operation refers to operation({index}) which is not before it")]
+    InvalidBatchParent { index: usize },
+
+    #[error("inode({ino}) in a sticky directory is owned by another user")]
+    Sticky { ino: u64 },
+
+    #[error("invalid backup: {0}")]
+    InvalidBackup(String),
+
+    #[error("invalid image of memory store: {0}")]
+    InvalidImage(String),
+
+    #[error("memory store is full: {used} of {capacity} bytes used")]
+    NoSpace { used: u64, capacity: u64 },
+
+    #[error("inode({ino}) is immutable")]
+    Immutable { ino: u64 },
+
+    #[error("inode({ino}) is append only")]
+    AppendOnly { ino: u64 },
+
+    #[error("unsupported inode flags({flags:#x}) on inode({ino})")]
+    UnsupportedFlags { ino: u64, flags: u32 },
+
+    #[error("only root may change the flags of inode({ino})")]
+    FlagsPermission { ino: u64 },
+
+    #[error("unsupported ioctl({cmd:#x}) on inode({ino})")]
+    UnsupportedIoctl { ino: u64, cmd: u32 },
+
+    #[error("access({mask:#o}) to inode({ino}) is denied")]
+    PermissionDenied { ino: u64, mask: i32 },
+
+    #[error("the directory of symlink inode({ino}) is not known to be inside the volume")]
+    UncontainedSymlink { ino: u64 },
+
+    #[error("unsupported rename flags({flags:#x})")]
+    RenameFlags { flags: u32 },
+
+    #[error("inode({ino}) is a directory")]
+    IsDirectory { ino: u64 },
+
+    #[error("too many symlinks met resolving {path:?}")]
+    SymlinkLoop { path: String },
+
+    #[error("writes buffered to inode({ino}) are lost with its delegation")]
+    DelegationLost { ino: u64 },
 }
 
+#[cfg(target_os = "linux")]
+const ENOATTR: libc::c_int = libc::ENODATA;
+#[cfg(not(target_os = "linux"))]
+const ENOATTR: libc::c_int = libc::ENOATTR;
+
 pub type Result<T> = std::result::Result<T, FsError>;
 
 impl FsError {
@@ -81,6 +214,20 @@ impl FsError {
     pub fn block_size_conflict(origin: u64, new: u64) -> Self {
         Self::BlockSizeConflict { origin, new }
     }
+
+    /// Whether the operation may succeed if it is tried again later, as it fails on TiKV
+    /// rather than on the state of the volume.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::KeyError { .. }
+                | Self::RetryTimesExcess(_)
+                | Self::UnknownError(_)
+                | Self::CommitTimeout { .. }
+                | Self::Degraded { .. }
+                | Self::Frozen { .. }
+        )
+    }
 }
 
 impl From<std::ffi::NulError> for FsError {
@@ -100,7 +247,10 @@ impl From<tikv_client::Error> for FsError {
         use tikv_client::Error::*;
 
         match err {
-            KeyError(err) => Self::KeyError(format!("{:?}", err)),
+            KeyError(err) => Self::KeyError {
+                key: err.conflict.as_ref().map(|conflict| conflict.key.clone()),
+                msg: format!("{:?}", err),
+            },
             _ => Self::UnknownError(err.to_string()),
         }
     }
@@ -116,16 +266,66 @@ impl Into<libc::c_int> for FsError {
             FileNotFound { file: _ } => libc::ENOENT,
             FileExist { file: _ } => libc::EEXIST,
             InodeNotFound { inode: _ } => libc::EFAULT,
+            Stale { ino: _ } => libc::ESTALE,
+            NoPath { ino: _ } => libc::ENOENT,
             FhNotFound { ino: _, fh: _ } => libc::EBADF,
             InvalidOffset { ino: _, offset: _ } => libc::EINVAL,
+            UnalignedDirectIo {
+                ino: _,
+                offset: _,
+                size: _,
+            } => libc::EINVAL,
             UnknownWhence { whence: _ } => libc::EINVAL,
             BlockNotFound { inode: _, block: _ } => libc::EINVAL,
             DirNotEmpty { dir: _ } => libc::ENOTEMPTY,
             UnknownFileType => libc::EINVAL,
-            KeyError(_) => libc::EAGAIN,
+            KeyError { msg: _, key: _ } => libc::EAGAIN,
             RetryTimesExcess(_) => libc::EAGAIN,
             InvalidStr => libc::EINVAL,
             BlockSizeConflict { origin: _, new: _ } => libc::EINVAL,
+            VolumeOption { name: _ } => libc::EINVAL,
+            HashedNamesChange => libc::EINVAL,
+            UnsupportedFeatures { features: _ } => libc::EROFS,
+            FreezeTimeout { mounts: _ } => libc::ETIMEDOUT,
+            Frozen { id: _ } => libc::EROFS,
+            Denied { op: _ } => libc::EPERM,
+            Degraded { op: _, window: _ } => libc::EROFS,
+            CommitTimeout { window: _ } => libc::EIO,
+            XattrNotFound { ino: _, name: _ } => ENOATTR,
+            BufferTooSmall {
+                size: _,
+                required: _,
+            } => libc::ERANGE,
+            UnsupportedXattr { name: _ } => libc::ENOTSUP,
+            Sealed { ino: _ } => libc::EROFS,
+            SealMismatch { ino: _ } => libc::EIO,
+            InvalidXattrValue { name: _ } => libc::EINVAL,
+            NotDirectory { ino: _ } => libc::ENOTDIR,
+            MoveIntoItself { ino: _ } => libc::EINVAL,
+            FallocateMode { ino: _, mode: _ } => libc::EOPNOTSUPP,
+            WormLocked { ino: _ } => libc::EPERM,
+            ReadOnlyVersion { ino: _ } => libc::EROFS,
+            NamedPipe { ino: _ } => libc::ESPIPE,
+            Injected { op: _, errno } => errno,
+            InvalidBatchParent { index: _ } => libc::EINVAL,
+            InvalidBackup(_) => libc::EINVAL,
+            Sticky { ino: _ } => libc::EPERM,
+            InvalidImage(_) => libc::EINVAL,
+            NoSpace {
+                used: _,
+                capacity: _,
+            } => libc::ENOSPC,
+            Immutable { ino: _ } => libc::EPERM,
+            AppendOnly { ino: _ } => libc::EPERM,
+            UnsupportedFlags { ino: _, flags: _ } => libc::EOPNOTSUPP,
+            FlagsPermission { ino: _ } => libc::EPERM,
+            UnsupportedIoctl { ino: _, cmd: _ } => libc::ENOTTY,
+            PermissionDenied { ino: _, mask: _ } => libc::EACCES,
+            UncontainedSymlink { ino: _ } => libc::EACCES,
+            RenameFlags { flags: _ } => libc::EINVAL,
+            IsDirectory { ino: _ } => libc::EISDIR,
+            SymlinkLoop { path: _ } => libc::ELOOP,
+            DelegationLost { ino: _ } => libc::EIO,
             _ => libc::EFAULT,
         }
     }
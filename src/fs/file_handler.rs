@@ -5,13 +5,57 @@ use super::serialize::{deserialize, serialize, ENCODING};
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy, Deserialize, Serialize)]
 pub struct FileHandler {
-    // TODO: add open flags
     pub cursor: u64,
+    /// `serde(default)` only fills this in for self-describing encodings, bincode handlers
+    /// written before it was added are decoded by `deserialize_legacy`.
+    #[serde(default)]
+    pub flags: i32,
+    /// The mount which opened the handler, zero for handlers opened before it was recorded.
+    #[serde(default)]
+    pub owner: u64,
 }
 
 impl FileHandler {
+    /// Alignment required for both offset and length of an `O_DIRECT` request.
+    pub const DIRECT_IO_ALIGNMENT: u64 = 512;
+
     pub const fn new(cursor: u64) -> Self {
-        Self { cursor }
+        Self {
+            cursor,
+            flags: 0,
+            owner: 0,
+        }
+    }
+
+    pub const fn with_flags(cursor: u64, flags: i32) -> Self {
+        Self {
+            cursor,
+            flags,
+            owner: 0,
+        }
+    }
+
+    pub const fn owned_by(self, owner: u64) -> Self {
+        Self { owner, ..self }
+    }
+
+    pub const fn is_direct(&self) -> bool {
+        self.flags & libc::O_DIRECT != 0
+    }
+
+    /// Writes of an `O_APPEND` handler go to the end of the file, wherever the kernel puts them.
+    pub const fn is_append(&self) -> bool {
+        self.flags & libc::O_APPEND != 0
+    }
+
+    pub fn check_direct_io(&self, ino: u64, offset: u64, size: u64) -> Result<()> {
+        if !self.is_direct() {
+            return Ok(());
+        }
+        if offset % Self::DIRECT_IO_ALIGNMENT != 0 || size % Self::DIRECT_IO_ALIGNMENT != 0 {
+            return Err(FsError::UnalignedDirectIo { ino, offset, size });
+        }
+        Ok(())
     }
 
     pub fn serialize(&self) -> Result<Vec<u8>> {
@@ -23,11 +67,44 @@ impl FileHandler {
     }
 
     pub fn deserialize(bytes: &[u8]) -> Result<Self> {
-        deserialize(bytes).map_err(|err| FsError::Serialize {
-            target: "file handler",
-            typ: ENCODING,
-            msg: err.to_string(),
-        })
+        deserialize(bytes)
+            .or_else(|err| Self::deserialize_legacy(bytes).ok_or(err))
+            .map_err(|err| FsError::Serialize {
+                target: "file handler",
+                typ: ENCODING,
+                msg: err.to_string(),
+            })
+    }
+
+    /// Bincode has no field names to default, so the older layouts are told apart by length:
+    /// a bare cursor, then a cursor with the open flags.
+    #[cfg(feature = "binc")]
+    fn deserialize_legacy(bytes: &[u8]) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct CursorOnly {
+            cursor: u64,
+        }
+
+        #[derive(Deserialize)]
+        struct CursorWithFlags {
+            cursor: u64,
+            flags: i32,
+        }
+
+        match bytes.len() {
+            8 => deserialize::<CursorOnly>(bytes)
+                .ok()
+                .map(|handler| Self::new(handler.cursor)),
+            12 => deserialize::<CursorWithFlags>(bytes)
+                .ok()
+                .map(|handler| Self::with_flags(handler.cursor, handler.flags)),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "binc"))]
+    fn deserialize_legacy(_bytes: &[u8]) -> Option<Self> {
+        None
     }
 }
 
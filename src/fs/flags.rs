@@ -0,0 +1,41 @@
+/// Requests of `lsattr` and `chattr` as Linux defines them, along with the ones of 32 bit
+/// callers. The kernel passes the flags as an int, or as a long the size of which the request
+/// tells.
+pub const FS_IOC_GETFLAGS: u32 = 0x8008_6601;
+pub const FS_IOC_SETFLAGS: u32 = 0x4008_6602;
+pub const FS_IOC32_GETFLAGS: u32 = 0x8004_6601;
+pub const FS_IOC32_SETFLAGS: u32 = 0x4004_6602;
+
+/// The inode cannot be written, truncated, linked, unlinked or have its attributes changed.
+pub const FS_IMMUTABLE_FL: u32 = 0x10;
+/// The inode can only be written at its end, and not unlinked or truncated.
+pub const FS_APPEND_FL: u32 = 0x20;
+
+/// Flags kept in `Inode::fs_flags`, setting others fails.
+pub const SUPPORTED_FLAGS: u32 = FS_IMMUTABLE_FL | FS_APPEND_FL;
+
+/// The flags passed to `FS_IOC_SETFLAGS`.
+pub fn decode(data: &[u8]) -> Option<u32> {
+    match data.len() {
+        4 => {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(data);
+            Some(u32::from_ne_bytes(bytes))
+        }
+        8 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(data);
+            Some(u64::from_ne_bytes(bytes) as u32)
+        }
+        _ => None,
+    }
+}
+
+/// The flags replied to `FS_IOC_GETFLAGS`, as a long if the caller takes one.
+pub fn encode(flags: u32, out_size: u32) -> Vec<u8> {
+    if out_size >= 8 {
+        (flags as u64).to_ne_bytes().to_vec()
+    } else {
+        flags.to_ne_bytes().to_vec()
+    }
+}
@@ -0,0 +1,27 @@
+use async_std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Gate of the commits writing the volume, closed while the volume is frozen, see
+/// `registry::Freeze`.
+///
+/// Every transaction which writes keys of the volume passes it before committing, whether it
+/// serves a FUSE operation or a background task. Transactions writing only the keys which
+/// coordinate the mounts pass freely, see `ScopedKey::is_coordination`, so mounts keep
+/// registering themselves and acknowledging the freeze.
+#[derive(Debug, Default)]
+pub struct WriteGate {
+    lock: RwLock<()>,
+}
+
+impl WriteGate {
+    /// Wait for the gate to open, the gate cannot be closed until the returned guard is
+    /// dropped.
+    pub async fn enter(&self) -> RwLockReadGuard<'_, ()> {
+        self.lock.read().await
+    }
+
+    /// Close the gate once the commits in progress finish, it opens again when the returned
+    /// guard is dropped.
+    pub async fn close(&self) -> RwLockWriteGuard<'_, ()> {
+        self.lock.write().await
+    }
+}
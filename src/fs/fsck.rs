@@ -0,0 +1,296 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+
+use std::mem;
+
+use fuser::{FileAttr, FileType};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+
+use super::error::{FsError, Result};
+use super::key::ROOT_INODE;
+use super::parallel;
+use super::reply::DirItem;
+use super::tikv_fs::TiFs;
+
+/// An inconsistency found by `check`, only mismatched types, open handles and orphans are
+/// fixed by `repair`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    /// An entry refers to an inode which does not exist.
+    Dangling { parent: u64, name: String, ino: u64 },
+    /// The type recorded in an entry differs from the type of its inode.
+    KindMismatch {
+        parent: u64,
+        name: String,
+        ino: u64,
+        entry: FileType,
+        inode: FileType,
+    },
+    /// The link count of a file differs from the number of entries referring to it.
+    LinkCount { ino: u64, nlink: u32, entries: u32 },
+    /// The count of open handles of an inode differs from its handlers held by live mounts,
+    /// or some of its handlers are left by mounts which are gone.
+    OpenHandles {
+        ino: u64,
+        recorded: u64,
+        live: u64,
+        stale: u64,
+    },
+    /// An unlinked inode is kept for the kernel of a mount which is gone.
+    Orphan { ino: u64, mount: u64 },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Dangling { parent, name, ino } => write!(
+                f,
+                "entry `{}` of dir({}) refers to missing inode({})",
+                name, parent, ino
+            ),
+            Problem::KindMismatch {
+                parent,
+                name,
+                ino,
+                entry,
+                inode,
+            } => write!(
+                f,
+                "entry `{}` of dir({}) is a {:?}, but inode({}) is a {:?}",
+                name, parent, entry, ino, inode
+            ),
+            Problem::LinkCount {
+                ino,
+                nlink,
+                entries,
+            } => write!(
+                f,
+                "inode({}) has {} links, but {} entries refer to it",
+                ino, nlink, entries
+            ),
+            Problem::OpenHandles {
+                ino,
+                recorded,
+                live,
+                stale,
+            } => write!(
+                f,
+                "inode({}) counts {} open handles, but live mounts hold {} and {} are left by gone mounts",
+                ino, recorded, live, stale
+            ),
+            Problem::Orphan { ino, mount } => write!(
+                f,
+                "unlinked inode({}) is kept for mount({}), which is gone",
+                ino, mount
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    pub dirs: u64,
+    pub files: u64,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// The entries of `dir` but `.` and `..`, with their inodes read.
+async fn read_listing(fs: &TiFs, dir: u64) -> Result<(u64, Vec<(DirItem, Result<FileAttr>)>)> {
+    let mut listing = Vec::new();
+    for item in fs.read_dir(dir).await? {
+        if item.name == "." || item.name == ".." {
+            continue;
+        }
+        let attr = fs.read_inode(item.ino).await;
+        listing.push((item, attr));
+    }
+    Ok((dir, listing))
+}
+
+/// Walk the whole tree from the root, checking that every entry refers to an existing inode
+/// of the same type and that link counts of files match the entries referring to them, then
+/// check the open handles and orphans of all inodes.
+///
+/// The tree is walked a level at a time, reading up to `parallelism` directories at once, and
+/// the inodes are scanned by `parallel::scan_inodes`. Directories are read in separate
+/// transactions, so run it on a volume which is not mounted to avoid reporting changes made
+/// meanwhile.
+pub async fn check(fs: &TiFs, parallelism: usize) -> Result<FsckReport> {
+    let parallelism = parallelism.max(1);
+    let mut report = FsckReport::default();
+    let mut visited = HashSet::new();
+    let mut links: HashMap<u64, (u32, u32)> = HashMap::new();
+    let mut pending = vec![ROOT_INODE];
+    fs.read_inode(ROOT_INODE).await?;
+
+    while !pending.is_empty() {
+        let dirs: Vec<u64> = mem::take(&mut pending)
+            .into_iter()
+            .filter(|dir| visited.insert(*dir))
+            .collect();
+        let mut listings: Vec<_> = stream::iter(dirs)
+            .map(|dir| read_listing(fs, dir))
+            .buffer_unordered(parallelism)
+            .try_collect()
+            .await?;
+        // the problems are reported in the same order whichever directory is read first
+        listings.sort_unstable_by_key(|(dir, _)| *dir);
+        for (dir, listing) in listings {
+            report.dirs += 1;
+            for (item, attr) in listing {
+                let attr = match attr {
+                    Err(FsError::InodeNotFound { inode }) => {
+                        report.problems.push(Problem::Dangling {
+                            parent: dir,
+                            name: item.name,
+                            ino: inode,
+                        });
+                        continue;
+                    }
+                    result => result?,
+                };
+                if attr.kind != item.typ {
+                    report.problems.push(Problem::KindMismatch {
+                        parent: dir,
+                        name: item.name.clone(),
+                        ino: item.ino,
+                        entry: item.typ,
+                        inode: attr.kind,
+                    });
+                }
+                if attr.kind == FileType::Directory {
+                    pending.push(attr.ino);
+                } else {
+                    links.entry(attr.ino).or_insert((attr.nlink, 0)).1 += 1;
+                }
+            }
+        }
+    }
+
+    report.files = links.len() as u64;
+    let mut counts: Vec<_> = links.into_iter().collect();
+    counts.sort_unstable_by_key(|(ino, _)| *ino);
+    for (ino, (nlink, entries)) in counts {
+        if nlink != entries {
+            report.problems.push(Problem::LinkCount {
+                ino,
+                nlink,
+                entries,
+            });
+        }
+    }
+
+    let mut opened = Vec::new();
+    let mut orphans = Vec::new();
+    parallel::scan_inodes(fs, parallelism, |inode| {
+        if inode.opened_fh != 0 {
+            opened.push((inode.ino, inode.opened_fh));
+        }
+        if inode.nlink == 0 && inode.held_by != 0 {
+            orphans.push((inode.ino, inode.held_by));
+        }
+    })
+    .await?;
+    opened.sort_unstable();
+    orphans.sort_unstable();
+    check_handles(fs, opened, parallelism, &mut report).await?;
+    check_orphans(fs, orphans, &mut report).await?;
+    Ok(report)
+}
+
+/// Compare the count of open handles of the inodes in `opened`, as `(ino, opened_fh)`, with
+/// the handlers of all inodes, taking the handlers of mounts missing in the clients registry
+/// as stale. The counts drift after crashes, and removed files are never deleted while they
+/// count open handles. The handlers are read by up to `parallelism` scans at once.
+pub async fn check_handles(
+    fs: &TiFs,
+    opened: Vec<(u64, u64)>,
+    parallelism: usize,
+    report: &mut FsckReport,
+) -> Result<()> {
+    let parallelism = parallelism.max(1);
+    let live_clients = fs.live_clients().await?;
+    // ino -> (recorded, live, stale)
+    let mut handles: HashMap<u64, (u64, u64, u64)> = HashMap::new();
+    for (ino, recorded) in opened {
+        handles.entry(ino).or_default().0 = recorded;
+    }
+    let ranges = parallel::inode_ranges(fs, 0..fs.next_inode().await?, parallelism).await;
+    let mut scans = stream::iter(ranges)
+        .map(|range| fs.read_handlers_of(range))
+        .buffer_unordered(parallelism);
+    while let Some(handlers) = scans.next().await {
+        for (ino, _, handler) in handlers? {
+            let counts = handles.entry(ino).or_default();
+            if live_clients.contains(&handler.owner) {
+                counts.1 += 1;
+            } else {
+                counts.2 += 1;
+            }
+        }
+    }
+
+    let mut handles: Vec<_> = handles.into_iter().collect();
+    handles.sort_unstable_by_key(|(ino, _)| *ino);
+    for (ino, (recorded, live, stale)) in handles {
+        if recorded != live || stale > 0 {
+            report.problems.push(Problem::OpenHandles {
+                ino,
+                recorded,
+                live,
+                stale,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Find the unlinked inodes of `orphans`, as `(ino, held_by)`, kept for the kernels of mounts
+/// which are gone, they are left by mounts which crashed before the kernel forgot them.
+pub async fn check_orphans(
+    fs: &TiFs,
+    orphans: Vec<(u64, u64)>,
+    report: &mut FsckReport,
+) -> Result<()> {
+    let live_clients = fs.live_clients().await?;
+    for (ino, mount) in orphans {
+        if !live_clients.contains(&mount) {
+            report.problems.push(Problem::Orphan { ino, mount });
+        }
+    }
+    Ok(())
+}
+
+/// Re-derive the types of the entries reported as `KindMismatch` from their inodes, recount
+/// the open handles reported as `OpenHandles` and remove the inodes reported as `Orphan`,
+/// returns the number of fixed problems. Other problems are left as they are.
+pub async fn repair(fs: &TiFs, report: &FsckReport) -> Result<u64> {
+    let mut fixed = 0;
+    for problem in &report.problems {
+        if let Problem::KindMismatch {
+            parent, name, ino, ..
+        } = problem
+        {
+            if fs.retype_dir_item(*parent, name.clone(), *ino).await? {
+                fixed += 1;
+            }
+        } else if let Problem::OpenHandles { ino, .. } = problem {
+            if fs.reconcile_handles(*ino).await?.is_some() {
+                fixed += 1;
+            }
+        } else if let Problem::Orphan { ino, .. } = problem {
+            if fs.reclaim(*ino).await? {
+                fixed += 1;
+            }
+        }
+    }
+    Ok(fixed)
+}
@@ -0,0 +1,65 @@
+use sha2::{Digest, Sha256};
+
+/// Extended attribute exposing the sha256 of the whole content of a regular file.
+///
+/// The kernel only passes namespaced attributes through, hence the `user.` prefix.
+pub const CONTENT_HASH_XATTR: &str = "user.tifs.sha256";
+
+/// Hash the content of a file from its blocks, which may be sparse.
+pub struct ContentHasher {
+    hasher: Sha256,
+    size: u64,
+    block_size: u64,
+    hashed: u64,
+}
+
+impl ContentHasher {
+    pub fn new(size: u64, block_size: u64) -> Self {
+        Self {
+            hasher: Sha256::new(),
+            size,
+            block_size,
+            hashed: 0,
+        }
+    }
+
+    fn fill_zero(&mut self, target: u64) {
+        let zeros = [0u8; 4096];
+        while self.hashed < target {
+            let len = (target - self.hashed).min(zeros.len() as u64);
+            self.hasher.update(&zeros[..len as usize]);
+            self.hashed += len;
+        }
+    }
+
+    /// Feed the inlined data of a small file.
+    pub fn update_inline(&mut self, data: &[u8]) {
+        let len = (data.len() as u64).min(self.size);
+        self.hasher.update(&data[..len as usize]);
+        self.hashed = len;
+    }
+
+    /// Feed a block, blocks must be fed in order and missing ones are regarded as holes.
+    pub fn update_block(&mut self, block: u64, data: &[u8]) {
+        let start = block * self.block_size;
+        if start >= self.size {
+            return;
+        }
+        self.fill_zero(start);
+        let len = (data.len() as u64)
+            .min(self.block_size)
+            .min(self.size - start);
+        self.hasher.update(&data[..len as usize]);
+        self.hashed += len;
+    }
+
+    /// Hex encoded digest.
+    pub fn finish(mut self) -> String {
+        self.fill_zero(self.size);
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
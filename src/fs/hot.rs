@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tikv_client::TimestampExt;
+
+use super::error::{FsError, Result};
+use super::key::ScopedKey;
+use super::parallel::{self, SCAN_PARALLELISM};
+use super::tikv_fs::TiFs;
+
+/// Blocks of a file and its changes found in the recent entries of the journal.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileUsage {
+    pub ino: u64,
+    pub blocks: u64,
+    /// Bytes of the stored blocks.
+    pub bytes: u64,
+    pub changes: u64,
+    pub last_change: Option<SystemTime>,
+}
+
+impl FileUsage {
+    fn new(ino: u64) -> Self {
+        Self {
+            ino,
+            blocks: 0,
+            bytes: 0,
+            changes: 0,
+            last_change: None,
+        }
+    }
+}
+
+/// The largest files by stored bytes and the hottest by recent changes, to decide what to
+/// tier or pin.
+#[derive(Debug, Default, Serialize)]
+pub struct HotReport {
+    /// Journal entries counted, none unless mounts write the journal with `-o journal`.
+    pub journal_entries: u64,
+    pub largest: Vec<FileUsage>,
+    pub hottest: Vec<FileUsage>,
+}
+
+/// Count the blocks of every inode at a single timestamp, scanning the regions holding them
+/// side by side, and the last `recent` entries of the journal, keeping the `top` files of each
+/// ranking.
+pub async fn report(fs: &TiFs, recent: u64, top: usize) -> Result<HotReport> {
+    let mut usage: HashMap<u64, FileUsage> = HashMap::new();
+    let ts = fs.client.current_timestamp().await?.version();
+    parallel::scan_at(
+        fs,
+        ts,
+        ScopedKey::blocks_range(),
+        SCAN_PARALLELISM,
+        |pair| match ScopedKey::parse(pair.key().into())? {
+            ScopedKey::Block { ino, block: _ } => Ok((ino, pair.value().len() as u64)),
+            _ => Err(FsError::InvalidScopedKey(pair.key().clone().into())),
+        },
+        |(ino, bytes)| {
+            let file = usage.entry(ino).or_insert_with(|| FileUsage::new(ino));
+            file.blocks += 1;
+            file.bytes += bytes;
+            Ok(())
+        },
+    )
+    .await?;
+
+    let mut report = HotReport::default();
+    let head = fs.journal_head().await?;
+    let mut from = head.saturating_sub(recent);
+    while from < head {
+        let entries = fs.read_journal(from, fs.scan_page).await?;
+        from = match entries.last() {
+            Some(last) => last.seq + 1,
+            None => break,
+        };
+        for entry in entries {
+            let ino = entry.op.ino();
+            let file = usage.entry(ino).or_insert_with(|| FileUsage::new(ino));
+            file.changes += 1;
+            file.last_change = Some(entry.time);
+            report.journal_entries += 1;
+        }
+    }
+
+    let mut files: Vec<FileUsage> = usage.into_iter().map(|(_, file)| file).collect();
+    files.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    report.largest = files
+        .iter()
+        .filter(|file| file.blocks > 0)
+        .take(top)
+        .cloned()
+        .collect();
+    files.sort_by(|a, b| {
+        b.changes
+            .cmp(&a.changes)
+            .then(b.last_change.cmp(&a.last_change))
+    });
+    report.hottest = files
+        .into_iter()
+        .filter(|file| file.changes > 0)
+        .take(top)
+        .collect();
+    Ok(report)
+}
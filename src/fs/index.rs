@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use super::error::{FsError, Result};
 use super::serialize::{deserialize, serialize, ENCODING};
@@ -29,3 +30,95 @@ impl Index {
         })
     }
 }
+
+/// Name in the index key of `name` on volumes with `hashed_names`, the hex of the first 8
+/// bytes of its sha256, so keys stay short however long names are.
+pub fn hashed_name(name: &str) -> String {
+    Sha256::digest(name.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Entries of a directory whose names share a hashed index key, with their real names.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct HashedIndex {
+    pub entries: Vec<(String, u64)>,
+}
+
+impl HashedIndex {
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, ino)| *ino)
+    }
+
+    pub fn insert(&mut self, name: &str, ino: u64) {
+        match self.entries.iter_mut().find(|(entry, _)| entry == name) {
+            Some(entry) => entry.1 = ino,
+            None => self.entries.push((name.to_owned(), ino)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(entry, _)| entry != name);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "hashed index",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "hashed index",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn index_round_trip(ino in any::<u64>()) {
+            let index = Index::new(ino);
+            prop_assert_eq!(index, Index::deserialize(&index.serialize().unwrap()).unwrap());
+        }
+
+        #[test]
+        fn hashed_index_round_trip(
+            entries in prop::collection::vec(("\\PC{0,255}", any::<u64>()), 0..16),
+        ) {
+            let mut index = HashedIndex::default();
+            for (name, ino) in &entries {
+                index.insert(name, *ino);
+            }
+            let decoded = HashedIndex::deserialize(&index.serialize().unwrap()).unwrap();
+            for (name, _) in &entries {
+                prop_assert_eq!(index.get(name), decoded.get(name));
+            }
+            prop_assert_eq!(index, decoded);
+        }
+
+        #[test]
+        fn hashed_names_are_short_hex(name in "\\PC*") {
+            let hashed = hashed_name(&name);
+            prop_assert_eq!(16, hashed.len());
+            prop_assert!(hashed.bytes().all(|byte| byte.is_ascii_hexdigit()));
+            prop_assert_eq!(hashed.clone(), hashed_name(&name));
+        }
+    }
+}
@@ -1,10 +1,17 @@
+use super::acl::Acl;
+use super::delegation::Delegation;
 use super::error::{FsError, Result};
+use super::flags::{FS_APPEND_FL, FS_IMMUTABLE_FL};
+use super::seal::Seal;
 use super::serialize::{deserialize, serialize, ENCODING};
-use fuser::FileAttr;
-use libc::F_UNLCK;
+use super::ttl::Ttl;
+use super::worm::Worm;
+use fuser::{FileAttr, FileType};
+use libc::{F_UNLCK, S_ISGID, S_ISUID, S_ISVTX};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::ops::{Deref, DerefMut};
+use std::time::SystemTime;
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct LockState {
@@ -19,6 +26,51 @@ pub struct Inode {
     pub inline_data: Option<Vec<u8>>,
     pub next_fh: u64,
     pub opened_fh: u64,
+    /// Hex encoded sha256 of the content, cleared whenever the data changes.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Set once the inode is sealed, see `Seal`.
+    #[serde(default)]
+    pub seal: Option<Seal>,
+    /// WORM policy of a directory.
+    #[serde(default)]
+    pub worm: Option<Worm>,
+    /// Time after which a file in a WORM directory is locked.
+    #[serde(default)]
+    pub worm_after: Option<SystemTime>,
+    /// Write delegation granted to a mount.
+    #[serde(default)]
+    pub delegation: Option<Delegation>,
+    /// Access ACL beyond the permission bits.
+    #[serde(default)]
+    pub acl: Option<Acl>,
+    /// Default ACL of a directory.
+    #[serde(default)]
+    pub default_acl: Option<Acl>,
+    /// TTL of the entries of a scratch directory.
+    #[serde(default)]
+    pub ttl: Option<Ttl>,
+    /// Bumped whenever the inode number is reused, so an inode number and generation never
+    /// name two files, as NFS file handles require.
+    #[serde(default)]
+    pub generation: u64,
+    /// Directory the inode is last linked into, 0 for inodes made before it is recorded.
+    #[serde(default)]
+    pub parent: u64,
+    /// Shards of the entries of a directory, 0 if they are kept in one block, see `DirShards`.
+    #[serde(default)]
+    pub dir_shards: u32,
+    /// Mount whose kernel still refers to the inode after its last link and handle are gone,
+    /// 0 if none, see `LookupCounts`.
+    #[serde(default)]
+    pub held_by: u64,
+    /// Flags set by `chattr`, see `flags::SUPPORTED_FLAGS`. `FileAttr::flags` are the ones
+    /// of `chflags` on macOS.
+    #[serde(default)]
+    pub fs_flags: u32,
+    /// Bumped whenever the data changes, unlike the times it cannot be set by clients.
+    #[serde(default)]
+    pub data_version: u64,
 }
 
 impl Inode {
@@ -26,11 +78,138 @@ impl Inode {
         self.blocks = (self.size + block_size - 1) / block_size;
     }
 
+    /// Record a change of the data, the content hash is stale and `data_version` moves on.
+    pub fn data_changed(&mut self) {
+        self.content_hash = None;
+        self.data_version += 1;
+    }
+
     pub fn set_size(&mut self, size: u64, block_size: u64) {
         self.size = size;
         self.update_blocks(block_size);
     }
 
+    /// Sealed inodes are read only.
+    pub fn ensure_unsealed(&self) -> Result<()> {
+        if self.seal.is_some() {
+            Err(FsError::Sealed { ino: self.ino })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sealed or immutable inodes and WORM files past their grace period cannot be modified
+    /// or deleted.
+    pub fn ensure_mutable(&self) -> Result<()> {
+        self.ensure_unsealed()?;
+        if self.fs_flags & FS_IMMUTABLE_FL != 0 {
+            return Err(FsError::Immutable { ino: self.ino });
+        }
+        match self.worm_after {
+            Some(time) if time <= SystemTime::now() => Err(FsError::WormLocked { ino: self.ino }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes to an append only inode must start at its end.
+    pub fn ensure_appendable(&self, start: u64) -> Result<()> {
+        if self.fs_flags & FS_APPEND_FL != 0 && start < self.size {
+            Err(FsError::AppendOnly { ino: self.ino })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Append only inodes cannot be unlinked, truncated, or change their owners and modes.
+    pub fn ensure_not_append_only(&self) -> Result<()> {
+        if self.fs_flags & FS_APPEND_FL != 0 {
+            Err(FsError::AppendOnly { ino: self.ino })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Inherit the group of a setgid directory, its default ACL and the TTL of a scratch
+    /// directory, as a file created in `dir`.
+    pub fn inherit(&mut self, dir: &Inode) {
+        let is_dir = self.kind == FileType::Directory;
+        if is_dir {
+            self.ttl = dir.ttl;
+        }
+        if dir.perm & S_ISGID as u16 != 0 {
+            self.gid = dir.gid;
+            if is_dir {
+                self.perm |= S_ISGID as u16;
+            }
+        }
+        if let Some(default_acl) = &dir.default_acl {
+            let acl = default_acl.inherit(self.perm);
+            self.perm = self.perm & !0o777 | acl.perm();
+            if !acl.is_minimal() {
+                self.acl = Some(acl);
+            }
+            if is_dir {
+                self.default_acl = Some(default_acl.clone());
+            }
+        }
+    }
+
+    /// In a sticky directory, only the owner of an entry, the owner of the directory, or root
+    /// may remove or rename the entry.
+    pub fn ensure_removable(&self, dir: &Inode, uid: u32) -> Result<()> {
+        if dir.perm & S_ISVTX as u16 != 0 && uid != 0 && uid != dir.uid && uid != self.uid {
+            Err(FsError::Sticky { ino: self.ino })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Clear the setuid bit, and the setgid bit if the group may execute the file, as a write
+    /// or a change of the owner does. Directories keep them.
+    pub fn kill_suid(&mut self) {
+        if self.kind == FileType::Directory {
+            return;
+        }
+        let mut perm = self.perm & !(S_ISUID as u16);
+        if perm & 0o010 != 0 {
+            perm &= !(S_ISGID as u16);
+        }
+        self.perm = perm;
+    }
+
+    /// Take the permissions, owner, times and ACLs of `source`, e.g. of a copied file.
+    pub fn copy_attrs(&mut self, source: &Inode) {
+        self.perm = source.perm;
+        self.uid = source.uid;
+        self.gid = source.gid;
+        self.atime = source.atime;
+        self.mtime = source.mtime;
+        self.crtime = source.crtime;
+        self.flags = source.flags;
+        self.acl = source.acl.clone();
+        self.default_acl = source.default_acl.clone();
+    }
+
+    /// Mask the mode of a file to create in this directory by `umask`, unless the directory
+    /// has a default ACL, which takes the place of the umask.
+    pub fn mask_mode(&self, mode: u32, umask: u32) -> u32 {
+        if self.default_acl.is_some() {
+            mode
+        } else {
+            mode & !umask
+        }
+    }
+
+    /// Update the permission bits, and the access ACL along with them. The kernel passes the
+    /// type of the file along, which is not kept.
+    pub fn chmod(&mut self, perm: u16) {
+        let perm = perm & 0o7777;
+        self.perm = perm;
+        if let Some(acl) = &mut self.acl {
+            acl.chmod(perm);
+        }
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>> {
         serialize(self).map_err(|err| FsError::Serialize {
             target: "inode",
@@ -40,11 +219,82 @@ impl Inode {
     }
 
     pub fn deserialize(bytes: &[u8]) -> Result<Self> {
-        deserialize(bytes).map_err(|err| FsError::Serialize {
-            target: "inode",
-            typ: ENCODING,
-            msg: err.to_string(),
-        })
+        deserialize(bytes)
+            .or_else(|err| Self::deserialize_legacy(bytes).ok_or(err))
+            .map_err(|err| FsError::Serialize {
+                target: "inode",
+                typ: ENCODING,
+                msg: err.to_string(),
+            })
+    }
+
+    /// Bincode has no field names to default, but the fields are only ever added at the end:
+    /// an inode written before some of them ends where they start, and they keep defaults.
+    #[cfg(feature = "binc")]
+    fn deserialize_legacy(bytes: &[u8]) -> Option<Self> {
+        let mut reader = LegacyReader(std::io::Cursor::new(bytes));
+        let mut inode = Inode::from(reader.field::<FileAttr>()?);
+        inode.lock_state = reader.field()?;
+        inode.inline_data = reader.field()?;
+        inode.next_fh = reader.field()?;
+        inode.opened_fh = reader.field()?;
+
+        macro_rules! added_fields {
+            ($($field:ident),*) => {
+                $(
+                    if reader.is_done() {
+                        return Some(inode);
+                    }
+                    inode.$field = reader.field()?;
+                )*
+            };
+        }
+        added_fields!(
+            content_hash,
+            seal,
+            worm,
+            worm_after,
+            delegation,
+            acl,
+            default_acl,
+            ttl,
+            generation,
+            parent,
+            dir_shards,
+            held_by,
+            fs_flags
+        );
+        // an inode with every field decodes without the legacy path
+        None
+    }
+
+    #[cfg(not(feature = "binc"))]
+    fn deserialize_legacy(_bytes: &[u8]) -> Option<Self> {
+        None
+    }
+}
+
+/// Reads the fields of a legacy bincode inode one by one, see `Inode::deserialize_legacy`.
+#[cfg(feature = "binc")]
+struct LegacyReader<'a>(std::io::Cursor<&'a [u8]>);
+
+#[cfg(feature = "binc")]
+impl LegacyReader<'_> {
+    fn field<T: serde::de::DeserializeOwned>(&mut self) -> Option<T> {
+        use bincode::Options;
+
+        // the limit keeps a corrupted length from allocating more than the inode holds
+        let limit = self.0.get_ref().len() as u64;
+        bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(limit)
+            .deserialize_from(&mut self.0)
+            .ok()
+    }
+
+    fn is_done(&self) -> bool {
+        self.0.position() == self.0.get_ref().len() as u64
     }
 }
 
@@ -56,6 +306,20 @@ impl From<FileAttr> for Inode {
             inline_data: None,
             next_fh: 0,
             opened_fh: 0,
+            content_hash: None,
+            seal: None,
+            worm: None,
+            worm_after: None,
+            delegation: None,
+            acl: None,
+            default_acl: None,
+            ttl: None,
+            generation: 0,
+            parent: 0,
+            dir_shards: 0,
+            held_by: 0,
+            fs_flags: 0,
+            data_version: 0,
         }
     }
 }
@@ -91,3 +355,165 @@ impl LockState {
         LockState { owner_set, lk_type }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::acl::AclEntry;
+    use proptest::prelude::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn time() -> impl Strategy<Value = SystemTime> {
+        (0..1u64 << 40, 0..1_000_000_000u32)
+            .prop_map(|(secs, nanos)| UNIX_EPOCH + Duration::new(secs, nanos))
+    }
+
+    fn kind() -> impl Strategy<Value = FileType> {
+        prop_oneof![
+            Just(FileType::NamedPipe),
+            Just(FileType::CharDevice),
+            Just(FileType::BlockDevice),
+            Just(FileType::Directory),
+            Just(FileType::RegularFile),
+            Just(FileType::Symlink),
+            Just(FileType::Socket),
+        ]
+    }
+
+    fn attr() -> impl Strategy<Value = FileAttr> {
+        (
+            (
+                any::<u64>(),
+                any::<u64>(),
+                any::<u64>(),
+                kind(),
+                any::<u16>(),
+            ),
+            (time(), time(), time(), time()),
+            (any::<u32>(), any::<u32>(), any::<u32>(), any::<u32>()),
+            (any::<u32>(), any::<u32>(), any::<u32>()),
+        )
+            .prop_map(
+                |(
+                    (ino, size, blocks, kind, perm),
+                    (atime, mtime, ctime, crtime),
+                    (nlink, uid, gid, rdev),
+                    (blksize, padding, flags),
+                )| FileAttr {
+                    ino,
+                    size,
+                    blocks,
+                    atime,
+                    mtime,
+                    ctime,
+                    crtime,
+                    kind,
+                    perm,
+                    nlink,
+                    uid,
+                    gid,
+                    rdev,
+                    blksize,
+                    padding,
+                    flags,
+                },
+            )
+    }
+
+    fn acl() -> impl Strategy<Value = Option<Acl>> {
+        prop::option::of(
+            prop::collection::vec(
+                (any::<u16>(), any::<u16>(), any::<u32>()).prop_map(|(tag, perm, id)| AclEntry {
+                    tag,
+                    perm,
+                    id,
+                }),
+                0..8,
+            )
+            .prop_map(Acl),
+        )
+    }
+
+    fn inode() -> impl Strategy<Value = Inode> {
+        (
+            attr(),
+            prop::option::of(prop::collection::vec(any::<u8>(), 0..256)),
+            (any::<u64>(), any::<u64>(), prop::option::of("[0-9a-f]{64}")),
+            (acl(), acl(), prop::option::of(time())),
+            (any::<u64>(), any::<u64>(), any::<u32>(), any::<u64>()),
+        )
+            .prop_map(
+                |(
+                    attr,
+                    inline_data,
+                    (next_fh, opened_fh, content_hash),
+                    (acl, default_acl, worm_after),
+                    (generation, parent, dir_shards, data_version),
+                )| {
+                    let mut inode = Inode::from(attr);
+                    inode.inline_data = inline_data;
+                    inode.next_fh = next_fh;
+                    inode.opened_fh = opened_fh;
+                    inode.content_hash = content_hash;
+                    inode.acl = acl;
+                    inode.default_acl = default_acl;
+                    inode.worm_after = worm_after;
+                    inode.generation = generation;
+                    inode.parent = parent;
+                    inode.dir_shards = dir_shards;
+                    inode.data_version = data_version;
+                    inode
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn inode_round_trip(inode in inode()) {
+            let bytes = inode.serialize().unwrap();
+            prop_assert_eq!(inode, Inode::deserialize(&bytes).unwrap());
+        }
+
+        #[test]
+        fn deserialize_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = Inode::deserialize(&bytes);
+        }
+
+        #[cfg(feature = "binc")]
+        #[test]
+        fn deserialize_legacy_layouts(inode in inode()) {
+            let mut expected = Inode::from(inode.file_attr);
+            expected.lock_state = inode.lock_state.clone();
+            expected.inline_data = inode.inline_data.clone();
+            expected.next_fh = inode.next_fh;
+            expected.opened_fh = inode.opened_fh;
+            // the layout before any field is added
+            let bytes = bincode::serialize(&(
+                &inode.file_attr,
+                &inode.lock_state,
+                &inode.inline_data,
+                inode.next_fh,
+                inode.opened_fh,
+            ))
+            .unwrap();
+            prop_assert_eq!(&expected, &Inode::deserialize(&bytes).unwrap());
+
+            // the layout of WORM directories, before delegations
+            expected.content_hash = inode.content_hash.clone();
+            expected.worm_after = inode.worm_after;
+            let bytes = bincode::serialize(&(
+                &inode.file_attr,
+                &inode.lock_state,
+                &inode.inline_data,
+                inode.next_fh,
+                inode.opened_fh,
+                &inode.content_hash,
+                &inode.seal,
+                &inode.worm,
+                inode.worm_after,
+            ))
+            .unwrap();
+            prop_assert_eq!(&expected, &Inode::deserialize(&bytes).unwrap());
+        }
+    }
+}
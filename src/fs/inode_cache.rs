@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tikv_client::Value;
+
+/// Inodes of a mount as they are stored, to spare the round trips of reading them again, see
+/// `-o inode_cache`.
+///
+/// A single operation reads the same inode several times, e.g. a write reads it to check the
+/// handle, to write and to save it, each in a transaction of its own. `Txn::read_inode` takes
+/// the inodes from here, and the inodes committed by the mount replace them. Changes of other
+/// mounts drop them once they show up in the journal, otherwise they expire after the ttl.
+///
+/// An inode taken from here is checked against the stored one when it is saved, so a stale
+/// entry fails the transaction, which is retried, instead of overwriting another change.
+#[derive(Debug)]
+pub struct InodeCache {
+    ttl: Duration,
+    inner: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Serialized inodes, with when and at which epoch they are cached.
+    inodes: HashMap<u64, (Value, Instant, u64)>,
+    /// Bumped by every change, inodes read before may be stale and are not cached.
+    epoch: u64,
+    /// Epoch of the last inode dropped for a change of another mount.
+    dropped: u64,
+}
+
+impl InodeCache {
+    /// Inodes kept at most, expired ones are dropped to make room.
+    const CAPACITY: usize = 1 << 14;
+
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// The current epoch, a transaction begun now fills and settles the cache with it.
+    pub fn epoch(&self) -> u64 {
+        self.inner.lock().unwrap().epoch
+    }
+
+    pub fn get(&self, ino: u64) -> Option<Value> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.inodes.get(&ino) {
+            Some((value, cached, _)) if cached.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                inner.inodes.remove(&ino);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache `ino` read by a transaction begun at `epoch`, unless anything changed since.
+    pub fn fill(&self, ino: u64, value: Value, epoch: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.epoch == epoch {
+            inner.insert(ino, value, self.ttl);
+        }
+    }
+
+    /// Replace the inodes saved, or drop the ones removed, by a transaction begun at `epoch`
+    /// once it commits. Inodes changed by others meanwhile are dropped, as the order of the
+    /// commits is not known.
+    pub fn settle(&self, saved: HashMap<u64, Option<Value>>, epoch: u64) {
+        if saved.is_empty() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.epoch += 1;
+        let dropped = inner.dropped > epoch;
+        for (ino, value) in saved {
+            let changed = matches!(inner.inodes.get(&ino), Some((_, _, at)) if *at > epoch);
+            match value {
+                Some(value) if !changed && !dropped => inner.insert(ino, value, self.ttl),
+                _ => {
+                    inner.inodes.remove(&ino);
+                }
+            }
+        }
+    }
+
+    /// Drop `ino`, changed by another mount or found stale.
+    pub fn invalidate(&self, ino: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.epoch += 1;
+        inner.dropped = inner.epoch;
+        inner.inodes.remove(&ino);
+    }
+}
+
+impl Entries {
+    fn insert(&mut self, ino: u64, value: Value, ttl: Duration) {
+        if self.inodes.len() >= InodeCache::CAPACITY && !self.inodes.contains_key(&ino) {
+            self.inodes
+                .retain(|_, (_, cached, _)| cached.elapsed() < ttl);
+            if self.inodes.len() >= InodeCache::CAPACITY {
+                return;
+            }
+        }
+        self.inodes.insert(ino, (value, Instant::now(), self.epoch));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+
+/// Duration of options like `revalidate=5s`, in seconds without a unit, or with the unit `ms`,
+/// `s` or `m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Interval(pub Duration);
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        let (num, unit) = value.split_at(
+            value
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or_else(|| value.len()),
+        );
+        let num: u64 = num.parse()?;
+        match unit {
+            "ms" => Ok(Interval(Duration::from_millis(num))),
+            "" | "s" => Ok(Interval(Duration::from_secs(num))),
+            "m" => num
+                .checked_mul(60)
+                .map(|secs| Interval(Duration::from_secs(secs)))
+                .ok_or_else(|| anyhow!("interval overflows: {}", value)),
+            _ => Err(anyhow!("unknown unit of interval: {}", value)),
+        }
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.subsec_millis() {
+            0 => write!(f, "{}s", self.0.as_secs()),
+            _ => write!(f, "{}ms", self.0.as_millis()),
+        }
+    }
+}
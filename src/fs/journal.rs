@@ -0,0 +1,148 @@
+use std::fmt::{self, Display};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{FsError, Result};
+use super::serialize::{deserialize, serialize, ENCODING};
+
+/// A mutation recorded in the change journal.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum JournalOp {
+    Create {
+        parent: u64,
+        name: String,
+        ino: u64,
+    },
+    Link {
+        ino: u64,
+        newparent: u64,
+        newname: String,
+    },
+    Write {
+        ino: u64,
+        offset: i64,
+        len: u64,
+    },
+    Unlink {
+        parent: u64,
+        name: String,
+        ino: u64,
+    },
+    Rename {
+        parent: u64,
+        name: String,
+        newparent: u64,
+        newname: String,
+        ino: u64,
+    },
+    /// Attributes of `ino` are changed, with its new size if it is truncated or extended.
+    SetAttr {
+        ino: u64,
+        size: Option<u64>,
+    },
+}
+
+/// An entry of the change journal, which is enabled by the `journal` mount option.
+///
+/// Entries are sequenced by a counter in the meta, they are shared by all mounts of the volume
+/// and can be tailed from any sequence number.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub time: SystemTime,
+    /// Id of the mount which made the change.
+    pub origin: u64,
+    pub op: JournalOp,
+}
+
+impl JournalEntry {
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "journal entry",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "journal entry",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
+
+impl JournalOp {
+    /// The inode changed by the operation.
+    pub fn ino(&self) -> u64 {
+        use JournalOp::*;
+
+        match self {
+            Create { ino, .. }
+            | Link { ino, .. }
+            | Write { ino, .. }
+            | Unlink { ino, .. }
+            | Rename { ino, .. }
+            | SetAttr { ino, .. } => *ino,
+        }
+    }
+}
+
+impl Display for JournalOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use JournalOp::*;
+
+        match self {
+            Create { parent, name, ino } => {
+                write!(f, "create parent={} name={:?} ino={}", parent, name, ino)
+            }
+            Link {
+                ino,
+                newparent,
+                newname,
+            } => write!(
+                f,
+                "link ino={} newparent={} newname={:?}",
+                ino, newparent, newname
+            ),
+            Write { ino, offset, len } => {
+                write!(f, "write ino={} offset={} len={}", ino, offset, len)
+            }
+            Unlink { parent, name, ino } => {
+                write!(f, "unlink parent={} name={:?} ino={}", parent, name, ino)
+            }
+            Rename {
+                parent,
+                name,
+                newparent,
+                newname,
+                ino,
+            } => write!(
+                f,
+                "rename parent={} name={:?} newparent={} newname={:?} ino={}",
+                parent, name, newparent, newname, ino
+            ),
+            SetAttr { ino, size: None } => write!(f, "setattr ino={}", ino),
+            SetAttr {
+                ino,
+                size: Some(size),
+            } => write!(f, "setattr ino={} size={}", ino, size),
+        }
+    }
+}
+
+impl Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "{} {}.{:03} {}",
+            self.seq,
+            time.as_secs(),
+            time.subsec_millis(),
+            self.op
+        )
+    }
+}
@@ -14,6 +14,15 @@ pub enum ScopedKey<'a> {
     Block { ino: u64, block: u64 },
     FileHandler { ino: u64, handler: u64 },
     FileIndex { parent: u64, name: &'a str },
+    Journal(u64),
+    SafePoint(&'a str),
+    Scratch(u64),
+    Deletion(u64),
+    Stats(u64),
+    FreeInode(u64),
+    Client(u64),
+    Freeze,
+    UsageRebase,
 }
 
 impl<'a> ScopedKey<'a> {
@@ -22,6 +31,15 @@ impl<'a> ScopedKey<'a> {
     const BLOCK: u8 = 2;
     const HANDLER: u8 = 3;
     const INDEX: u8 = 4;
+    const JOURNAL: u8 = 5;
+    const SAFEPOINT: u8 = 6;
+    const SCRATCH: u8 = 7;
+    const DELETION: u8 = 8;
+    const STATS: u8 = 9;
+    const FREE_INODE: u8 = 10;
+    const CLIENT: u8 = 11;
+    const FREEZE: u8 = 12;
+    const USAGE_REBASE: u8 = 13;
 
     pub const fn meta() -> Self {
         Self::Meta
@@ -47,15 +65,109 @@ impl<'a> ScopedKey<'a> {
         Self::FileIndex { parent, name }
     }
 
+    pub const fn journal(seq: u64) -> Self {
+        Self::Journal(seq)
+    }
+
+    pub fn safepoint(service: &'a str) -> Self {
+        Self::SafePoint(service)
+    }
+
+    pub const fn scratch(ino: u64) -> Self {
+        Self::Scratch(ino)
+    }
+
+    pub const fn deletion(ino: u64) -> Self {
+        Self::Deletion(ino)
+    }
+
+    pub const fn stats(mount_id: u64) -> Self {
+        Self::Stats(mount_id)
+    }
+
+    pub const fn free_inode(ino: u64) -> Self {
+        Self::FreeInode(ino)
+    }
+
+    pub const fn client(mount_id: u64) -> Self {
+        Self::Client(mount_id)
+    }
+
+    pub const fn freeze() -> Self {
+        Self::Freeze
+    }
+
+    pub const fn usage_rebase() -> Self {
+        Self::UsageRebase
+    }
+
     pub fn block_range(ino: u64, block_range: Range<u64>) -> Range<Key> {
         debug_assert_ne!(0, ino);
         Self::block(ino, block_range.start).into()..Self::block(ino, block_range.end).into()
     }
 
+    pub fn blocks_range() -> Range<Key> {
+        Key::from(vec![Self::BLOCK])..Key::from(vec![Self::BLOCK + 1])
+    }
+
+    pub fn handler_range(ino_range: Range<u64>) -> Range<Key> {
+        Self::handler(ino_range.start, 0).into()..Self::handler(ino_range.end, 0).into()
+    }
+
     pub fn inode_range(ino_range: Range<u64>) -> Range<Key> {
         Self::inode(ino_range.start).into()..Self::inode(ino_range.end).into()
     }
 
+    pub fn journal_range(seq_range: Range<u64>) -> Range<Key> {
+        Self::journal(seq_range.start).into()..Self::journal(seq_range.end).into()
+    }
+
+    pub fn index_range() -> Range<Key> {
+        Key::from(vec![Self::INDEX])..Key::from(vec![Self::INDEX + 1])
+    }
+
+    /// The index keys of the entries directly under `parent`.
+    pub fn entries_range(parent: u64) -> Range<Key> {
+        let end: Key = match parent.checked_add(1) {
+            Some(next) => Self::index(next, "").into(),
+            None => Key::from(vec![Self::INDEX + 1]),
+        };
+        Self::index(parent, "").into()..end
+    }
+
+    pub fn safepoint_range() -> Range<Key> {
+        Key::from(vec![Self::SAFEPOINT])..Key::from(vec![Self::SAFEPOINT + 1])
+    }
+
+    pub fn scratch_range() -> Range<Key> {
+        Key::from(vec![Self::SCRATCH])..Key::from(vec![Self::SCRATCH + 1])
+    }
+
+    pub fn deletion_range() -> Range<Key> {
+        Key::from(vec![Self::DELETION])..Key::from(vec![Self::DELETION + 1])
+    }
+
+    pub fn stats_range() -> Range<Key> {
+        Key::from(vec![Self::STATS])..Key::from(vec![Self::STATS + 1])
+    }
+
+    pub fn free_inode_range() -> Range<Key> {
+        Key::from(vec![Self::FREE_INODE])..Key::from(vec![Self::FREE_INODE + 1])
+    }
+
+    pub fn client_range() -> Range<Key> {
+        Key::from(vec![Self::CLIENT])..Key::from(vec![Self::CLIENT + 1])
+    }
+
+    /// Whether `key` coordinates the mounts instead of holding the volume, such keys are still
+    /// written while the volume is frozen.
+    pub fn is_coordination(key: &[u8]) -> bool {
+        matches!(
+            key.first(),
+            Some(&Self::SAFEPOINT) | Some(&Self::CLIENT) | Some(&Self::FREEZE)
+        )
+    }
+
     pub fn scope(&self) -> u8 {
         use ScopedKey::*;
 
@@ -65,6 +177,15 @@ impl<'a> ScopedKey<'a> {
             Block { ino: _, block: _ } => Self::BLOCK,
             FileHandler { ino: _, handler: _ } => Self::HANDLER,
             FileIndex { parent: _, name: _ } => Self::INDEX,
+            Journal(_) => Self::JOURNAL,
+            SafePoint(_) => Self::SAFEPOINT,
+            Scratch(_) => Self::SCRATCH,
+            Deletion(_) => Self::DELETION,
+            Stats(_) => Self::STATS,
+            FreeInode(_) => Self::FREE_INODE,
+            Client(_) => Self::CLIENT,
+            Freeze => Self::FREEZE,
+            UsageRebase => Self::USAGE_REBASE,
         }
     }
 
@@ -77,6 +198,15 @@ impl<'a> ScopedKey<'a> {
             Block { ino: _, block: _ } => size_of::<u64>() * 2,
             FileHandler { ino: _, handler: _ } => size_of::<u64>() * 2,
             FileIndex { parent: _, name } => size_of::<u64>() + name.len(),
+            Journal(_) => size_of::<u64>(),
+            SafePoint(service) => service.len(),
+            Scratch(_) => size_of::<u64>(),
+            Deletion(_) => size_of::<u64>(),
+            Stats(_) => size_of::<u64>(),
+            FreeInode(_) => size_of::<u64>(),
+            Client(_) => size_of::<u64>(),
+            Freeze => 0,
+            UsageRebase => 0,
         }
     }
 
@@ -109,6 +239,37 @@ impl<'a> ScopedKey<'a> {
                     std::str::from_utf8(&data[size_of::<u64>()..]).map_err(|_| invalid_key())?,
                 ))
             }
+            Self::JOURNAL => {
+                let seq = u64::from_be_bytes(*data.array_chunks().next().ok_or_else(invalid_key)?);
+                Ok(Self::journal(seq))
+            }
+            Self::SAFEPOINT => Ok(Self::safepoint(
+                std::str::from_utf8(data).map_err(|_| invalid_key())?,
+            )),
+            Self::SCRATCH => {
+                let ino = u64::from_be_bytes(*data.array_chunks().next().ok_or_else(invalid_key)?);
+                Ok(Self::scratch(ino))
+            }
+            Self::DELETION => {
+                let ino = u64::from_be_bytes(*data.array_chunks().next().ok_or_else(invalid_key)?);
+                Ok(Self::deletion(ino))
+            }
+            Self::STATS => {
+                let mount_id =
+                    u64::from_be_bytes(*data.array_chunks().next().ok_or_else(invalid_key)?);
+                Ok(Self::stats(mount_id))
+            }
+            Self::FREE_INODE => {
+                let ino = u64::from_be_bytes(*data.array_chunks().next().ok_or_else(invalid_key)?);
+                Ok(Self::free_inode(ino))
+            }
+            Self::CLIENT => {
+                let mount_id =
+                    u64::from_be_bytes(*data.array_chunks().next().ok_or_else(invalid_key)?);
+                Ok(Self::client(mount_id))
+            }
+            Self::FREEZE => Ok(Self::freeze()),
+            Self::USAGE_REBASE => Ok(Self::usage_rebase()),
             _ => Err(invalid_key()),
         }
     }
@@ -135,7 +296,112 @@ impl<'a> From<ScopedKey<'a>> for Key {
                 data.extend(parent.to_be_bytes().iter());
                 data.extend(name.as_bytes().iter());
             }
+            Journal(seq) => data.extend(seq.to_be_bytes().iter()),
+            SafePoint(service) => data.extend(service.as_bytes().iter()),
+            Scratch(ino) => data.extend(ino.to_be_bytes().iter()),
+            Deletion(ino) => data.extend(ino.to_be_bytes().iter()),
+            Stats(mount_id) => data.extend(mount_id.to_be_bytes().iter()),
+            FreeInode(ino) => data.extend(ino.to_be_bytes().iter()),
+            Client(mount_id) => data.extend(mount_id.to_be_bytes().iter()),
+            Freeze => (),
+            UsageRebase => (),
         }
         data.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn round_trip(key: ScopedKey) {
+        let encoded = Key::from(key);
+        let bytes: &[u8] = (&encoded).into();
+        assert_eq!(key.len(), bytes.len());
+        assert_eq!(key, ScopedKey::parse(bytes).unwrap());
+    }
+
+    fn contains(range: &Range<Key>, key: ScopedKey) -> bool {
+        range.contains(&Key::from(key))
+    }
+
+    proptest! {
+        #[test]
+        fn numbered_keys_round_trip(a in any::<u64>(), b in any::<u64>()) {
+            round_trip(ScopedKey::meta());
+            round_trip(ScopedKey::freeze());
+            round_trip(ScopedKey::usage_rebase());
+            round_trip(ScopedKey::inode(a));
+            round_trip(ScopedKey::block(a, b));
+            round_trip(ScopedKey::handler(a, b));
+            round_trip(ScopedKey::journal(a));
+            round_trip(ScopedKey::scratch(a));
+            round_trip(ScopedKey::deletion(a));
+            round_trip(ScopedKey::stats(a));
+            round_trip(ScopedKey::free_inode(a));
+            round_trip(ScopedKey::client(a));
+        }
+
+        #[test]
+        fn named_keys_round_trip(parent in any::<u64>(), name in "\\PC*") {
+            round_trip(ScopedKey::index(parent, &name));
+            round_trip(ScopedKey::safepoint(&name));
+        }
+
+        #[test]
+        fn parse_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..32)) {
+            // trailing bytes of fixed size keys are ignored, the rest of a key is its encoding
+            if let Ok(key) = ScopedKey::parse(&bytes) {
+                let encoded = Key::from(key);
+                let encoded: &[u8] = (&encoded).into();
+                prop_assert!(bytes.starts_with(encoded));
+            }
+        }
+
+        #[test]
+        fn block_keys_sort_by_inode_and_block(
+            a in (1..u64::MAX, any::<u64>()),
+            b in (1..u64::MAX, any::<u64>()),
+        ) {
+            prop_assert_eq!(
+                a.cmp(&b),
+                Key::from(ScopedKey::block(a.0, a.1)).cmp(&Key::from(ScopedKey::block(b.0, b.1)))
+            );
+        }
+
+        #[test]
+        fn block_range_holds_its_blocks_only(
+            ino in 1..u64::MAX,
+            start in any::<u64>(),
+            len in 0..1u64 << 16,
+            block in any::<u64>(),
+            other in 1..u64::MAX,
+        ) {
+            let end = start.saturating_add(len);
+            let range = ScopedKey::block_range(ino, start..end);
+            prop_assert_eq!(
+                (start..end).contains(&block),
+                contains(&range, ScopedKey::block(ino, block))
+            );
+            if other != ino {
+                prop_assert!(!contains(&range, ScopedKey::block(other, block)));
+            }
+            prop_assert!(contains(&ScopedKey::blocks_range(), ScopedKey::block(ino, block)));
+        }
+
+        #[test]
+        fn entries_range_holds_its_entries_only(
+            parent in any::<u64>(),
+            other in any::<u64>(),
+            name in "\\PC*",
+        ) {
+            let range = ScopedKey::entries_range(parent);
+            prop_assert!(contains(&range, ScopedKey::index(parent, &name)));
+            if other != parent {
+                prop_assert!(!contains(&range, ScopedKey::index(other, &name)));
+            }
+            prop_assert!(contains(&ScopedKey::index_range(), ScopedKey::index(parent, &name)));
+        }
+    }
+}
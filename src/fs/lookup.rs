@@ -0,0 +1,69 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Lookups of the kernel of a mount, and the unlinked inodes kept for it.
+///
+/// The kernel counts a reference to an inode for every entry replied to it, and drops them
+/// with `forget`. An inode whose last link and handle are gone while the kernel still refers
+/// to it is kept, with the mount recorded in `Inode::held_by`, and removed once the kernel
+/// forgets it, so that NFS re-exports and cached dentries can still reach it meanwhile.
+#[derive(Debug, Default)]
+pub struct LookupCounts {
+    mount_id: u64,
+    inner: Mutex<Lookups>,
+}
+
+#[derive(Debug, Default)]
+struct Lookups {
+    counts: HashMap<u64, u64>,
+    orphans: HashSet<u64>,
+}
+
+impl LookupCounts {
+    pub fn new(mount_id: u64) -> Self {
+        Self {
+            mount_id,
+            inner: Mutex::default(),
+        }
+    }
+
+    /// Count an entry of `ino` replied to the kernel.
+    pub fn add(&self, ino: u64) {
+        *self.inner.lock().unwrap().counts.entry(ino).or_default() += 1;
+    }
+
+    /// Keep the unlinked `ino` for the kernel if it refers to it, returns the id of the mount.
+    pub fn hold(&self, ino: u64) -> Option<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.counts.contains_key(&ino) {
+            inner.orphans.insert(ino);
+            Some(self.mount_id)
+        } else {
+            None
+        }
+    }
+
+    /// Drop `nlookup` references of `ino`, returns whether it is an unlinked inode kept for
+    /// the kernel, which may be removed now.
+    pub fn forget(&self, ino: u64, nlookup: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let count = match inner.counts.get_mut(&ino) {
+            Some(count) => count,
+            None => return false,
+        };
+        *count = count.saturating_sub(nlookup);
+        if *count > 0 {
+            return false;
+        }
+        inner.counts.remove(&ino);
+        inner.orphans.remove(&ino)
+    }
+
+    /// Drop every reference, as the kernel does when unmounting, returns the unlinked inodes
+    /// kept for it.
+    pub fn forget_all(&self) -> Vec<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.counts.clear();
+        inner.orphans.drain().collect()
+    }
+}
@@ -0,0 +1,571 @@
+use std::cmp::Ordering;
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::iter::Peekable;
+use std::ops::{Deref, DerefMut, RangeBounds};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use tikv_client::{Key, Value};
+use tracing::{debug, warn};
+
+use super::backup::{read_frame, write_frame};
+use super::error::{FsError, Result};
+use super::inode::Inode;
+use super::key::ScopedKey;
+
+const IMAGE_MAGIC: &[u8; 8] = b"TIFSMEM1";
+
+/// Interval of saving the memory store to its `-o store_file`.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval of writing the changes of the memory store back to TiKV with `-o write_back`.
+pub const WRITE_BACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Extended attribute of the root with the memory used by each kind of keys, see `MemUsage`.
+pub const MEM_USAGE_XATTR: &str = "user.tifs.mem_usage";
+
+fn entry_len(key: &Key, value: &[u8]) -> u64 {
+    (Into::<&'_ [u8]>::into(key).len() + value.len()) as u64
+}
+
+fn file_of(key: &Key) -> Option<u64> {
+    match ScopedKey::parse(key.into()) {
+        Ok(ScopedKey::Block { ino, block: _ }) => Some(ino),
+        _ => None,
+    }
+}
+
+fn inode_of(key: &Key) -> Option<u64> {
+    match ScopedKey::parse(key.into()) {
+        Ok(ScopedKey::Block { ino, block: _ }) | Ok(ScopedKey::Inode(ino)) => Some(ino),
+        _ => None,
+    }
+}
+
+/// Bytes of the keys and values in the memory store, by the kind of keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemUsage {
+    pub meta: u64,
+    pub inodes: u64,
+    pub blocks: u64,
+    pub handlers: u64,
+    pub indexes: u64,
+    pub journal: u64,
+    /// Safepoints, scratch directories and the deletion queue.
+    pub others: u64,
+}
+
+impl MemUsage {
+    pub fn total(&self) -> u64 {
+        self.meta
+            + self.inodes
+            + self.blocks
+            + self.handlers
+            + self.indexes
+            + self.journal
+            + self.others
+    }
+
+    fn of_key(&mut self, key: &Key) -> &mut u64 {
+        match ScopedKey::parse(key.into()) {
+            Ok(ScopedKey::Meta) => &mut self.meta,
+            Ok(ScopedKey::Inode(_)) => &mut self.inodes,
+            Ok(ScopedKey::Block { .. }) => &mut self.blocks,
+            Ok(ScopedKey::FileHandler { .. }) => &mut self.handlers,
+            Ok(ScopedKey::FileIndex { .. }) => &mut self.indexes,
+            Ok(ScopedKey::Journal(_)) => &mut self.journal,
+            _ => &mut self.others,
+        }
+    }
+}
+
+impl Display for MemUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "meta={} inodes={} blocks={} handlers={} indexes={} journal={} others={} total={}",
+            self.meta,
+            self.inodes,
+            self.blocks,
+            self.handlers,
+            self.indexes,
+            self.journal,
+            self.others,
+            self.total()
+        )
+    }
+}
+
+/// Write `entries` as an image to be loaded by `MemStore::load`.
+fn write_image<'a, W, I>(writer: &mut W, entries: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (&'a Key, &'a Value)>,
+{
+    writer.write_all(IMAGE_MAGIC)?;
+    for (key, value) in entries {
+        write_frame(writer, key.into())?;
+        write_frame(writer, value)?;
+    }
+    // keys are never empty
+    write_frame(writer, &[])?;
+    Ok(())
+}
+
+/// Save the image of `entries`, taken by `MemStore::snapshot`, to `path`. It is written to a
+/// temporary file first, so the last image is kept if saving is interrupted.
+pub fn save_image(entries: &[(Key, Value)], path: &Path) -> Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    write_image(&mut writer, entries.iter().map(|(key, value)| (key, value)))?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Least recently used order of the files with blocks in the store.
+#[derive(Debug, Default)]
+struct Lru {
+    tick: u64,
+    files: HashMap<u64, u64>,
+    order: BTreeMap<u64, u64>,
+}
+
+impl Lru {
+    fn touch(&mut self, ino: u64) {
+        self.remove(ino);
+        self.tick += 1;
+        self.files.insert(ino, self.tick);
+        self.order.insert(self.tick, ino);
+    }
+
+    fn remove(&mut self, ino: u64) {
+        if let Some(tick) = self.files.remove(&ino) {
+            self.order.remove(&tick);
+        }
+    }
+}
+
+/// The key-value store of a `mem_store` mount, bounded by `-o mem_size`.
+///
+/// Writes beyond the capacity fail with `ENOSPC`, unless the mount is a cache (`-o mem_evict`),
+/// in which case the least recently used files which are not open are emptied to make room.
+///
+/// Every entry carries the version it is written at, so `commit` can tell whether an entry
+/// read or written by a transaction has been changed by another one since.
+#[derive(Debug, Default)]
+pub struct MemStore {
+    entries: BTreeMap<Key, (u64, Value)>,
+    version: u64,
+    usage: MemUsage,
+    capacity: Option<u64>,
+    evict: bool,
+    lru: Lru,
+    /// Keys changed since they are last written back to TiKV, if the store is written back.
+    dirty: Option<BTreeSet<Key>>,
+}
+
+impl MemStore {
+    pub fn new(capacity: Option<u64>, evict: bool) -> Self {
+        Self {
+            capacity,
+            evict,
+            ..Default::default()
+        }
+    }
+
+    /// Bytes of the keys and values in the store.
+    pub fn used(&self) -> u64 {
+        self.usage.total()
+    }
+
+    pub fn usage(&self) -> MemUsage {
+        self.usage
+    }
+
+    pub fn capacity(&self) -> Option<u64> {
+        self.capacity
+    }
+
+    /// Track the changed keys from now on, to write them back by `take_dirty`.
+    pub fn enable_write_back(&mut self) {
+        self.dirty = Some(BTreeSet::new());
+    }
+
+    /// Take the changed keys with their values, `None` for the removed ones, up to about
+    /// `limit` bytes of values.
+    pub fn take_dirty(&mut self, limit: usize) -> Vec<(Key, Option<Value>)> {
+        let entries = &self.entries;
+        let dirty = match &mut self.dirty {
+            Some(dirty) => dirty,
+            None => return Vec::new(),
+        };
+        let mut bytes = 0;
+        let keys: Vec<Key> = dirty
+            .iter()
+            .take_while(|key| {
+                let within = bytes < limit;
+                bytes += entries.get(*key).map(|(_, v)| v.len()).unwrap_or(0);
+                within
+            })
+            .cloned()
+            .collect();
+        for key in &keys {
+            dirty.remove(key);
+        }
+        keys.into_iter()
+            .map(|key| {
+                let value = self.get(&key).cloned();
+                (key, value)
+            })
+            .collect()
+    }
+
+    /// Mark keys changed again after failing to write them back.
+    pub fn mark_dirty<I: IntoIterator<Item = Key>>(&mut self, keys: I) {
+        if let Some(dirty) = &mut self.dirty {
+            dirty.extend(keys);
+        }
+    }
+
+    /// Version of the last write, to tell whether the store has changed.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.entries.get(key).map(|(_, value)| value)
+    }
+
+    pub fn range<R: RangeBounds<Key>>(&self, range: R) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries
+            .range(range)
+            .map(|(key, (_, value))| (key, value))
+    }
+
+    fn version_of(&self, key: &Key) -> Option<u64> {
+        self.entries.get(key).map(|(version, _)| *version)
+    }
+
+    /// Mark the file `ino` as used, so it is evicted after the others.
+    pub fn touch(&mut self, ino: u64) {
+        if self.lru.files.contains_key(&ino) {
+            self.lru.touch(ino);
+        }
+    }
+
+    pub fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        let len = entry_len(&key, &value);
+        let writing: HashSet<u64> = inode_of(&key).into_iter().collect();
+        self.reserve(len.saturating_sub(self.len_of(&key)), &writing)?;
+        self.put(key, value);
+        Ok(())
+    }
+
+    /// Apply the writes of a transaction at once. It fails without changing anything if an
+    /// entry it depends on has been changed since, or if there is no room for the writes.
+    pub fn commit(&mut self, writes: LocalWrites) -> Result<()> {
+        let bases = writes.bases.into_inner().unwrap();
+        for key in writes.values.keys() {
+            match bases.get(key) {
+                Some(base) if *base != self.version_of(key) => {
+                    return Err(FsError::KeyError {
+                        msg: format!("write conflict on {:?}", key),
+                        key: Some(key.clone().into()),
+                    });
+                }
+                _ => (),
+            }
+        }
+        let (added, released) =
+            writes
+                .values
+                .iter()
+                .fold((0, 0), |(added, released), (key, value)| {
+                    (
+                        added + value.as_ref().map(|v| entry_len(key, v)).unwrap_or(0),
+                        released + self.len_of(key),
+                    )
+                });
+        let writing: HashSet<u64> = writes.values.keys().filter_map(inode_of).collect();
+        self.reserve(added.saturating_sub(released), &writing)?;
+        for (key, value) in writes.values {
+            match value {
+                Some(value) => self.put(key, value),
+                None => {
+                    self.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the entries as an image to be loaded by `load`.
+    pub fn dump<W: Write>(&self, writer: &mut W) -> Result<()> {
+        write_image(writer, self.range(..))
+    }
+
+    /// Copy of the entries, to be saved by `save_image` once the store is released.
+    pub fn snapshot(&self) -> Vec<(Key, Value)> {
+        self.range(..)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Load the entries of an image written by `dump`, within the capacity of the store.
+    pub fn load<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != IMAGE_MAGIC {
+            return Err(FsError::InvalidImage("not a tifs image".to_owned()));
+        }
+        loop {
+            let key = read_frame(reader)?;
+            if key.is_empty() {
+                return Ok(());
+            }
+            let value = read_frame(reader)?;
+            self.insert(key.into(), value)?;
+        }
+    }
+
+    /// Load the image at `path` saved by `save_image`, if there is one.
+    pub fn open(&mut self, path: &Path) -> Result<bool> {
+        match File::open(path) {
+            Ok(file) => {
+                self.load(&mut BufReader::new(file))?;
+                Ok(true)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn len_of(&self, key: &Key) -> u64 {
+        self.get(key)
+            .map(|value| entry_len(key, value))
+            .unwrap_or(0)
+    }
+
+    /// Insert an entry at a new version, without checking the capacity.
+    fn put(&mut self, key: Key, value: Value) {
+        if let Some(ino) = file_of(&key) {
+            self.lru.touch(ino);
+        }
+        self.version += 1;
+        if let Some(dirty) = &mut self.dirty {
+            dirty.insert(key.clone());
+        }
+        let len = entry_len(&key, &value);
+        if let Some((_, old)) = self.entries.insert(key.clone(), (self.version, value)) {
+            *self.usage.of_key(&key) -= entry_len(&key, &old);
+        }
+        *self.usage.of_key(&key) += len;
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let (_, value) = self.entries.remove(key)?;
+        if let Some(dirty) = &mut self.dirty {
+            dirty.insert(key.clone());
+        }
+        *self.usage.of_key(key) -= entry_len(key, &value);
+        Some(value)
+    }
+
+    /// Make room for `len` more bytes, without evicting the files being written.
+    fn reserve(&mut self, len: u64, writing: &HashSet<u64>) -> Result<()> {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return Ok(()),
+        };
+        while self.used() + len > capacity {
+            let victim = if self.evict {
+                self.victim(writing)
+            } else {
+                None
+            };
+            match victim {
+                Some(ino) => self.evict_file(ino)?,
+                None => {
+                    return Err(FsError::NoSpace {
+                        used: self.used(),
+                        capacity,
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The least recently used file which can be evicted.
+    fn victim(&self, writing: &HashSet<u64>) -> Option<u64> {
+        self.lru
+            .order
+            .values()
+            .copied()
+            .filter(|ino| !writing.contains(ino))
+            .find(|ino| match self.get(&Key::from(ScopedKey::inode(*ino))) {
+                Some(value) => match Inode::deserialize(value) {
+                    Ok(inode) => inode.opened_fh == 0 && inode.seal.is_none(),
+                    Err(_) => false,
+                },
+                // blocks left by a removed file
+                None => true,
+            })
+    }
+
+    /// Drop the blocks of the file `ino` and truncate it.
+    fn evict_file(&mut self, ino: u64) -> Result<()> {
+        let blocks: Vec<Key> = self
+            .entries
+            .range(ScopedKey::block_range(ino, 0..std::u64::MAX))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &blocks {
+            self.remove(key);
+        }
+        self.lru.remove(ino);
+        let key = Key::from(ScopedKey::inode(ino));
+        if let Some(value) = self.get(&key) {
+            let mut inode = Inode::deserialize(value)?;
+            inode.size = 0;
+            inode.blocks = 0;
+            inode.data_changed();
+            let value = inode.serialize()?;
+            self.put(key, value);
+        }
+        warn!("evicted {} blocks of inode({})", blocks.len(), ino);
+        debug!("memory usage: {}", self.usage);
+        Ok(())
+    }
+}
+
+/// Writes buffered by a `LocalTxn` until it commits.
+#[derive(Debug, Default)]
+pub struct LocalWrites {
+    /// New values of the written entries, `None` for the removed ones.
+    values: BTreeMap<Key, Option<Value>>,
+    /// Versions of the entries when the transaction first reads or writes them, `None` for
+    /// absent ones.
+    bases: Mutex<HashMap<Key, Option<u64>>>,
+}
+
+impl LocalWrites {
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.values.keys()
+    }
+}
+
+/// The store as seen by a transaction, with its own writes on top.
+pub struct LocalView<'a, W> {
+    store: MutexGuard<'a, MemStore>,
+    writes: W,
+}
+
+impl<'a, W> LocalView<'a, W>
+where
+    W: Deref<Target = LocalWrites>,
+{
+    pub fn new(store: MutexGuard<'a, MemStore>, writes: W) -> Self {
+        Self { store, writes }
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        match self.writes.values.get(key) {
+            Some(value) => value.as_ref(),
+            None => {
+                let version = self.store.version_of(key);
+                let mut bases = self.writes.bases.lock().unwrap();
+                bases.entry(key.clone()).or_insert(version);
+                self.store.get(key)
+            }
+        }
+    }
+
+    pub fn range<R>(&self, range: R) -> Merged<'_>
+    where
+        R: RangeBounds<Key> + Clone,
+    {
+        Merged {
+            store: self.store.entries.range(range.clone()).peekable(),
+            writes: self.writes.values.range(range).peekable(),
+            bases: &self.writes.bases,
+        }
+    }
+
+    pub fn touch(&mut self, ino: u64) {
+        self.store.touch(ino)
+    }
+}
+
+impl<'a, W> LocalView<'a, W>
+where
+    W: DerefMut<Target = LocalWrites>,
+{
+    pub fn insert(&mut self, key: Key, value: Value) -> Result<()> {
+        self.write(key, Some(value));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Key) {
+        self.write(key.clone(), None);
+    }
+
+    pub fn extend<I: IntoIterator<Item = (Key, Value)>>(&mut self, pairs: I) -> Result<()> {
+        for (key, value) in pairs {
+            self.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, key: Key, value: Option<Value>) {
+        let version = self.store.version_of(&key);
+        let bases = self.writes.bases.get_mut().unwrap();
+        bases.entry(key.clone()).or_insert(version);
+        self.writes.values.insert(key, value);
+    }
+}
+
+/// Entries of a range of the store merged with the writes of a transaction.
+pub struct Merged<'a> {
+    store: Peekable<btree_map::Range<'a, Key, (u64, Value)>>,
+    writes: Peekable<btree_map::Range<'a, Key, Option<Value>>>,
+    bases: &'a Mutex<HashMap<Key, Option<u64>>>,
+}
+
+impl<'a> Iterator for Merged<'a> {
+    type Item = (&'a Key, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.store.peek(), self.writes.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((stored, _)), Some((written, _))) => stored.cmp(written),
+            };
+            if order == Ordering::Less {
+                let (key, (version, value)) = self.store.next()?;
+                let mut bases = self.bases.lock().unwrap();
+                bases.entry(key.clone()).or_insert(Some(*version));
+                return Some((key, value));
+            }
+            if order == Ordering::Equal {
+                self.store.next();
+            }
+            if let Some((key, Some(value))) = self.writes.next() {
+                return Some((key, value));
+            }
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use std::fmt::{self, Display};
+use std::ops::Range;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
 use super::error::{FsError, Result};
@@ -8,6 +13,14 @@ use super::serialize::{deserialize, serialize, ENCODING};
 pub struct Meta {
     pub inode_next: u64,
     pub block_size: u64,
+    /// Sequence number of the next entry of the change journal.
+    #[serde(default)]
+    pub journal_next: u64,
+    #[serde(default)]
+    pub config: VolumeConfig,
+    /// Features of the volume a client must understand to mount it read-write, see `feature`.
+    #[serde(default)]
+    pub features: u64,
 }
 
 impl Meta {
@@ -15,9 +28,17 @@ impl Meta {
         Self {
             inode_next: ROOT_INODE,
             block_size,
+            journal_next: 0,
+            config: VolumeConfig::UNSET,
+            features: 0,
         }
     }
 
+    /// Features of the volume unknown to this client.
+    pub fn unsupported_features(&self) -> u64 {
+        self.features & !feature::SUPPORTED
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>> {
         serialize(self).map_err(|err| FsError::Serialize {
             target: "meta",
@@ -34,3 +55,139 @@ impl Meta {
         })
     }
 }
+
+/// Bits of `Meta::features`.
+///
+/// A client ignoring a feature it does not know could write data the clients using it do
+/// not expect, so it refuses to mount a volume with unknown features read-write. A feature is
+/// added to a volume when it is first used, older volumes stay mountable by older clients.
+pub mod feature {
+    /// Options of `VolumeConfig`, which every mount must apply.
+    pub const VOLUME_CONFIG: u64 = 1 << 0;
+    /// Index keys holding hashes of the names, see `HashedIndex`.
+    pub const HASHED_NAMES: u64 = 1 << 1;
+
+    /// All features this client understands.
+    pub const SUPPORTED: u64 = VOLUME_CONFIG | HASHED_NAMES;
+}
+
+/// Options applied to every mount of a volume, set by `tifs mkfs` or `tifs admin config`.
+///
+/// Options which should not differ between mounts live here: mounts without `journal` leave
+/// holes in the journal others are notified by, and numbers of files removed by mounts without
+/// `inode_reuse` are never released. An unset option is left to the `-o` options of each mount,
+/// except `hashed_names`: entries are keyed alike by every mount, so it is only set by `mkfs`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct VolumeConfig {
+    #[serde(default)]
+    pub journal: Option<bool>,
+    #[serde(default)]
+    pub inode_reuse: Option<bool>,
+    #[serde(default)]
+    pub no_inline: Option<bool>,
+    #[serde(default)]
+    pub hashed_names: Option<bool>,
+}
+
+impl VolumeConfig {
+    pub const UNSET: Self = Self {
+        journal: None,
+        inode_reuse: None,
+        no_inline: None,
+        hashed_names: None,
+    };
+
+    pub fn settings(&self) -> [(&'static str, Option<bool>); 4] {
+        [
+            ("journal", self.journal),
+            ("inode_reuse", self.inode_reuse),
+            ("no_inline", self.no_inline),
+            ("hashed_names", self.hashed_names),
+        ]
+    }
+
+    fn setting_mut(&mut self, name: &str) -> Option<&mut Option<bool>> {
+        match name {
+            "journal" => Some(&mut self.journal),
+            "inode_reuse" => Some(&mut self.inode_reuse),
+            "no_inline" => Some(&mut self.no_inline),
+            "hashed_names" => Some(&mut self.hashed_names),
+            _ => None,
+        }
+    }
+
+    /// Apply a setting of the form `name=on`, `name=off` or `name=unset`.
+    pub fn set(&mut self, setting: &str) -> anyhow::Result<()> {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        let value = match parts.next() {
+            Some("on") => Some(true),
+            Some("off") => Some(false),
+            Some("unset") => None,
+            _ => return Err(anyhow!("expect {}=on|off|unset", name)),
+        };
+        *self
+            .setting_mut(name)
+            .ok_or_else(|| anyhow!("unknown volume option: {}", name))? = value;
+        Ok(())
+    }
+
+    /// Whether the option `name` is on for a mount passing it or not, a mount cannot turn on
+    /// an option which is off for the volume.
+    pub fn resolve(&self, name: &'static str, local: bool) -> Result<bool> {
+        match self.settings().iter().find(|(setting, _)| *setting == name) {
+            Some((_, Some(false))) if local => Err(FsError::VolumeOption { name }),
+            Some((_, Some(value))) => Ok(*value),
+            _ => Ok(local),
+        }
+    }
+}
+
+impl Display for VolumeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.settings().iter().enumerate() {
+            let value = match value {
+                Some(true) => "on",
+                Some(false) => "off",
+                None => "unset",
+            };
+            write!(f, "{}{}={}", if i > 0 { " " } else { "" }, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Inode numbers reserved from `Meta::inode_next` by a mount.
+///
+/// Taking the numbers of new inodes from the pool keeps the meta out of most creates, so
+/// creates under different parents do not conflict with each other and commit concurrently.
+/// Numbers left in the pool when the mount goes away are never used.
+#[derive(Debug, Default)]
+pub struct InodePool {
+    reserved: Mutex<Vec<Range<u64>>>,
+}
+
+impl InodePool {
+    /// Inode numbers reserved at once when the pool runs out.
+    pub const BATCH: u64 = 1 << 10;
+
+    pub fn take(&self) -> Option<u64> {
+        let mut reserved = self.reserved.lock().unwrap();
+        loop {
+            let range = reserved.last_mut()?;
+            match range.next() {
+                Some(ino) => return Some(ino),
+                None => {
+                    reserved.pop();
+                }
+            }
+        }
+    }
+
+    /// Add numbers reserved by a committed transaction.
+    pub fn refill(&self, range: Range<u64>) {
+        if range.start < range.end {
+            self.reserved.lock().unwrap().push(range);
+        }
+    }
+}
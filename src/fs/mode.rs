@@ -1,41 +1,82 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::anyhow;
 use fuser::FileType;
 
+/// Permission bits given in octal like `root_mode=0755`, including the sticky, setuid and
+/// setgid bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode(pub u16);
+
+impl FileMode {
+    /// Mode of the root directory unless it is given by `root_mode` or `mkfs --mode`.
+    pub const DEFAULT_ROOT: Self = FileMode(0o777);
+}
+
+impl FromStr for FileMode {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let mode = u16::from_str_radix(value.trim(), 8)?;
+        if mode > 0o7777 {
+            return Err(anyhow!("mode out of range: {}", value));
+        }
+        Ok(FileMode(mode))
+    }
+}
+
+impl Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+/// Permission bits of `mode`, without the file type, the setuid and the setgid bits.
 pub const fn as_file_perm(mode: u32) -> u16 {
-    (mode & !(libc::S_ISUID | libc::S_ISGID) as u32) as _
+    (mode & 0o7777 & !(libc::S_ISUID | libc::S_ISGID) as u32) as _
 }
 
+/// Kind of file of `mode`, a regular file if it has no type like `mknod(2)` takes it, or
+/// `None` if the type is unknown.
 #[cfg(target_os = "freebsd")]
-pub fn as_file_kind(mode: u32) -> FileType {
+pub fn as_file_kind(mode: u32) -> Option<FileType> {
     use FileType::*;
 
     match mode as u16 & libc::S_IFMT {
-        libc::S_IFREG => RegularFile,
-        libc::S_IFLNK => Symlink,
-        libc::S_IFDIR => Directory,
-        libc::S_IFIFO => NamedPipe,
-        libc::S_IFBLK => BlockDevice,
-        libc::S_IFCHR => CharDevice,
-        libc::S_IFSOCK => Socket,
-        _ => unimplemented!("{}", mode),
+        0 | libc::S_IFREG => Some(RegularFile),
+        libc::S_IFLNK => Some(Symlink),
+        libc::S_IFDIR => Some(Directory),
+        libc::S_IFIFO => Some(NamedPipe),
+        libc::S_IFBLK => Some(BlockDevice),
+        libc::S_IFCHR => Some(CharDevice),
+        libc::S_IFSOCK => Some(Socket),
+        _ => None,
     }
 }
 
+/// Kind of file of `mode`, a regular file if it has no type like `mknod(2)` takes it, or
+/// `None` if the type is unknown.
 #[cfg(target_os = "linux")]
-pub fn as_file_kind(mode: u32) -> FileType {
+pub fn as_file_kind(mode: u32) -> Option<FileType> {
     use FileType::*;
 
     match mode & libc::S_IFMT as u32 {
-        libc::S_IFREG => RegularFile,
-        libc::S_IFLNK => Symlink,
-        libc::S_IFDIR => Directory,
-        libc::S_IFIFO => NamedPipe,
-        libc::S_IFBLK => BlockDevice,
-        libc::S_IFCHR => CharDevice,
-        libc::S_IFSOCK => Socket,
-        _ => unimplemented!("{}", mode),
+        0 | libc::S_IFREG => Some(RegularFile),
+        libc::S_IFLNK => Some(Symlink),
+        libc::S_IFDIR => Some(Directory),
+        libc::S_IFIFO => Some(NamedPipe),
+        libc::S_IFBLK => Some(BlockDevice),
+        libc::S_IFCHR => Some(CharDevice),
+        libc::S_IFSOCK => Some(Socket),
+        _ => None,
     }
 }
 
+/// Whether files of `kind` are devices, the only ones with a `rdev`.
+pub fn is_device(kind: FileType) -> bool {
+    matches!(kind, FileType::BlockDevice | FileType::CharDevice)
+}
+
 #[cfg(target_os = "freebsd")]
 pub fn make_mode(tpy: FileType, perm: u16) -> u32 {
     use FileType::*;
@@ -69,3 +110,38 @@ pub fn make_mode(tpy: FileType, perm: u16) -> u32 {
 
     kind | perm as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn kind() -> impl Strategy<Value = FileType> {
+        prop_oneof![
+            Just(FileType::NamedPipe),
+            Just(FileType::CharDevice),
+            Just(FileType::BlockDevice),
+            Just(FileType::Directory),
+            Just(FileType::RegularFile),
+            Just(FileType::Symlink),
+            Just(FileType::Socket),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn mode_round_trip(kind in kind(), perm in 0..0o7777u16) {
+            let mode = make_mode(kind, perm);
+            prop_assert_eq!(as_file_kind(mode), Some(kind));
+            prop_assert_eq!(
+                as_file_perm(mode),
+                perm & !(libc::S_ISUID | libc::S_ISGID) as u16
+            );
+        }
+
+        #[test]
+        fn mode_without_type_is_regular(perm in 0..0o7777u32) {
+            prop_assert_eq!(as_file_kind(perm), Some(FileType::RegularFile));
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::ffi::OsStr;
+use std::io;
+use std::time::Duration;
+
+use fuser::Notifier;
+use tracing::{debug, info};
+
+use super::error::Result;
+use super::journal::{JournalEntry, JournalOp};
+use super::tikv_fs::TiFs;
+
+/// Interval to poll the journal for changes of other mounts.
+pub const NOTIFY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The kernel answers `ENOENT` for inodes or entries it does not cache, which is expected.
+fn check(result: io::Result<()>, target: &str) {
+    if let Err(err) = result {
+        debug!("fail to invalidate {}: {}", target, err);
+    }
+}
+
+fn inval_inode(notifier: &Notifier, ino: u64, offset: i64, len: i64) {
+    check(
+        notifier.inval_inode(ino, offset, len),
+        &format!("inode({})", ino),
+    );
+}
+
+fn inval_entry(notifier: &Notifier, parent: u64, name: &str) {
+    check(
+        notifier.inval_entry(parent, OsStr::new(name)),
+        &format!("entry({}, {})", parent, name),
+    );
+}
+
+/// Drop what the kernel caches about the change of `entry`.
+pub fn invalidate(notifier: &Notifier, entry: &JournalEntry) {
+    use JournalOp::*;
+
+    match &entry.op {
+        Create { parent, name, .. } => {
+            inval_entry(notifier, *parent, name);
+            inval_inode(notifier, *parent, 0, 0);
+        }
+        Link {
+            ino,
+            newparent,
+            newname,
+        } => {
+            inval_entry(notifier, *newparent, newname);
+            inval_inode(notifier, *newparent, 0, 0);
+            inval_inode(notifier, *ino, -1, 0);
+        }
+        Write { ino, offset, len } => inval_inode(notifier, *ino, *offset, *len as i64),
+        Unlink { parent, name, ino } => {
+            inval_entry(notifier, *parent, name);
+            inval_inode(notifier, *parent, 0, 0);
+            inval_inode(notifier, *ino, -1, 0);
+        }
+        Rename {
+            parent,
+            name,
+            newparent,
+            newname,
+            ..
+        } => {
+            inval_entry(notifier, *parent, name);
+            inval_entry(notifier, *newparent, newname);
+            inval_inode(notifier, *parent, 0, 0);
+            inval_inode(notifier, *newparent, 0, 0);
+        }
+        // the cached data is dropped as well if the size changes, e.g. by a truncate
+        SetAttr { ino, size: None } => inval_inode(notifier, *ino, -1, 0),
+        SetAttr { ino, size: Some(_) } => inval_inode(notifier, *ino, 0, 0),
+    }
+}
+
+/// Tail the journal from its current head and invalidate the kernel caches of this mount
+/// for every change made by other mounts, approximating inotify across hosts. The inode cache
+/// of the mount drops the changed inodes too.
+///
+/// Only mounts with the `journal` option record their changes.
+pub async fn notify_changes(fs: &TiFs, notifier: Notifier) -> Result<()> {
+    let head = fs.journal_head().await?;
+    info!("notify changes of other mounts from journal seq {}", head);
+    fs.tail_journal(head, NOTIFY_INTERVAL, |entry| {
+        if entry.origin != fs.mount_id {
+            debug!("invalidate remote change: {}", entry);
+            invalidate(&notifier, &entry);
+            fs.invalidate_cached(&entry.op);
+        }
+        true
+    })
+    .await
+}
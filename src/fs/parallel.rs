@@ -0,0 +1,243 @@
+use std::ops::Range;
+
+use futures::stream::{self, StreamExt};
+use tikv_client::{Key, KvPair};
+use tracing::debug;
+
+use super::backup::scan_all;
+use super::error::{FsError, Result};
+use super::inode::Inode;
+use super::key::{ScopedKey, ROOT_INODE};
+use super::region;
+use super::snapshot::SnapshotView;
+use super::tikv_fs::TiFs;
+
+/// Ranges a parallel scan is split into per scan run at once, so a range on a slow region
+/// does not hold the others back.
+const RANGES_PER_SCAN: usize = 4;
+
+/// Scans run at once by the scans of the whole volume which take no `--parallel`.
+pub const SCAN_PARALLELISM: usize = 4;
+
+/// Split `range` into at most `parts` contiguous ranges of nearly the same length.
+pub fn split(range: Range<u64>, parts: usize) -> Vec<Range<u64>> {
+    let len = range.end.saturating_sub(range.start);
+    let parts = (parts.max(1) as u64).min(len);
+    let mut ranges = Vec::with_capacity(parts as usize);
+    let mut start = range.start;
+    for part in 0..parts {
+        let end = start + len / parts + if part < len % parts { 1 } else { 0 };
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+/// Split `range` at `bounds`, then each part into nearly even ones, so there are about `parts`
+/// ranges and none crosses a bound.
+pub fn split_at(range: Range<u64>, bounds: &[u64], parts: usize) -> Vec<Range<u64>> {
+    let mut cuts = Vec::with_capacity(bounds.len() + 1);
+    let mut start = range.start;
+    for &bound in bounds {
+        if start < bound && bound < range.end {
+            cuts.push(start..bound);
+            start = bound;
+        }
+    }
+    if start < range.end {
+        cuts.push(start..range.end);
+    }
+    let each = (parts.max(1) + cuts.len().max(1) - 1) / cuts.len().max(1);
+    cuts.into_iter().flat_map(|cut| split(cut, each)).collect()
+}
+
+/// The regions holding the keys of `range` split at their boundaries, or `range` whole if PD
+/// cannot tell.
+pub async fn key_ranges(fs: &TiFs, range: Range<Key>) -> Vec<Range<Key>> {
+    let bounds = match region::boundaries(&fs.pd_endpoints, range.clone()).await {
+        Ok(bounds) => bounds,
+        Err(err) => {
+            debug!("fail to ask pd for the regions, scan at once: {}", err);
+            Vec::new()
+        }
+    };
+    let mut ranges = Vec::with_capacity(bounds.len() + 1);
+    let mut start = range.start;
+    for bound in bounds {
+        ranges.push(start..bound.clone());
+        start = bound;
+    }
+    ranges.push(start..range.end);
+    ranges
+}
+
+/// The numbers of the inodes in `inos` the regions holding them start at, in order.
+pub async fn inode_bounds(fs: &TiFs, inos: Range<u64>) -> Vec<u64> {
+    let mut bounds: Vec<u64> = key_ranges(fs, ScopedKey::inode_range(inos.clone()))
+        .await
+        .into_iter()
+        .skip(1)
+        .map(|range| first_inode_from(&range.start))
+        .filter(|ino| inos.start < *ino && *ino < inos.end)
+        .collect();
+    bounds.dedup();
+    bounds
+}
+
+/// The first inode keyed at `key` or after it, `key` lies among the keys of the inodes.
+fn first_inode_from(key: &Key) -> u64 {
+    let key: &[u8] = key.into();
+    let mut bytes = [0; 8];
+    let number = key.get(1..).unwrap_or_default();
+    let len = number.len().min(bytes.len());
+    bytes[..len].copy_from_slice(&number[..len]);
+    let ino = u64::from_be_bytes(bytes);
+    let at = Key::from(ScopedKey::inode(ino));
+    let at: &[u8] = (&at).into();
+    if at < key {
+        ino.saturating_add(1)
+    } else {
+        ino
+    }
+}
+
+/// Split the numbers of the inodes in `inos` on the regions holding them, see `split_at`. The
+/// numbers key the handlers of the inodes too, which are split the same way.
+pub async fn inode_ranges(fs: &TiFs, inos: Range<u64>, parts: usize) -> Vec<Range<u64>> {
+    let bounds = inode_bounds(fs, inos.clone()).await;
+    split_at(inos, &bounds, parts)
+}
+
+/// Scan the keys of `range` at the timestamp `ts`, a region per scan with up to `parallelism`
+/// of them at once, and pass what `map` makes of each pair to `visit` in the order of the keys.
+///
+/// Each region is mapped in full before it is visited, so `map` should keep little of values
+/// larger than metadata.
+pub async fn scan_at<T, M, F>(
+    fs: &TiFs,
+    ts: u64,
+    range: Range<Key>,
+    parallelism: usize,
+    map: M,
+    mut visit: F,
+) -> Result<()>
+where
+    M: Fn(KvPair) -> Result<T>,
+    F: FnMut(T) -> Result<()>,
+{
+    let map = &map;
+    let mut scans = stream::iter(key_ranges(fs, range).await)
+        .map(|range| async move {
+            let mut view = SnapshotView::at(&fs.client, ts, fs.block_size);
+            let mut mapped = Vec::new();
+            scan_all(&mut view, range, fs.scan_page, |pair| {
+                mapped.push(map(pair)?);
+                Ok(())
+            })
+            .await?;
+            Ok::<_, FsError>(mapped)
+        })
+        .buffered(parallelism.max(1));
+    while let Some(mapped) = scans.next().await {
+        for item in mapped? {
+            visit(item)?;
+        }
+    }
+    Ok(())
+}
+
+/// Scan every inode of the volume, running up to `parallelism` scans at once, and pass them
+/// to `visit`, in no particular order.
+///
+/// The inodes are keyed by their numbers, so a range of numbers is a range of keys. The
+/// numbers given so far are split on the regions holding them, and further into ranges
+/// scanned in transactions of their own, which the regions serve side by side instead of one
+/// after another, so the inodes seen are not taken at a single timestamp.
+pub async fn scan_inodes<F>(fs: &TiFs, parallelism: usize, mut visit: F) -> Result<()>
+where
+    F: FnMut(Inode),
+{
+    let parallelism = parallelism.max(1);
+    let ranges = inode_ranges(
+        fs,
+        ROOT_INODE..fs.next_inode().await?,
+        parallelism * RANGES_PER_SCAN,
+    )
+    .await;
+    let mut scans = stream::iter(ranges)
+        .map(|range| fs.scan_inodes(range))
+        .buffer_unordered(parallelism);
+    while let Some(inodes) = scans.next().await {
+        for inode in inodes? {
+            visit(inode);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn split_ranges() {
+        assert_eq!(split(1..11, 3), vec![1..5, 5..8, 8..11]);
+        assert_eq!(split(1..3, 4), vec![1..2, 2..3]);
+        assert_eq!(split(5..5, 4), Vec::<Range<u64>>::new());
+        assert_eq!(split(0..4, 0), vec![0..4]);
+    }
+
+    #[test]
+    fn split_ranges_at_bounds() {
+        assert_eq!(split_at(1..11, &[], 3), split(1..11, 3));
+        assert_eq!(split_at(1..11, &[0, 4, 11], 2), vec![1..4, 4..11]);
+        assert_eq!(split_at(1..11, &[4], 4), vec![1..3, 3..4, 4..8, 8..11]);
+    }
+
+    #[test]
+    fn first_inodes_from_keys() {
+        let inode = |ino| Key::from(ScopedKey::inode(ino));
+        assert_eq!(first_inode_from(&inode(42)), 42);
+        let mut after: Vec<u8> = inode(42).into();
+        after.push(0);
+        assert_eq!(first_inode_from(&Key::from(after)), 43);
+        let mut short: Vec<u8> = inode(42 << 8).into();
+        short.truncate(8);
+        assert_eq!(first_inode_from(&Key::from(short)), 42 << 8);
+    }
+
+    proptest! {
+        #[test]
+        fn split_covers_range(start in 0..1u64 << 40, len in 0..1u64 << 20, parts in 0..64usize) {
+            let ranges = split(start..start + len, parts);
+            prop_assert!(ranges.len() <= parts.max(1));
+            let mut next = start;
+            for range in ranges {
+                prop_assert_eq!(range.start, next);
+                prop_assert!(range.end > range.start);
+                next = range.end;
+            }
+            prop_assert_eq!(next, start + len);
+        }
+
+        #[test]
+        fn split_at_cuts_at_bounds(
+            len in 0..1u64 << 20,
+            mut bounds in prop::collection::vec(0..1u64 << 20, 0..8),
+            parts in 0..64usize,
+        ) {
+            bounds.sort_unstable();
+            let ranges = split_at(0..len, &bounds, parts);
+            let mut next = 0;
+            for range in &ranges {
+                prop_assert_eq!(range.start, next);
+                prop_assert!(range.end > range.start);
+                let crossed = bounds.iter().any(|bound| range.start < *bound && *bound < range.end);
+                prop_assert!(!crossed);
+                next = range.end;
+            }
+            prop_assert_eq!(next, len);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::time::Duration;
+
+use fuser::FileAttr;
+
+use super::error::FsError;
+use super::key::ScopedKey;
+
+/// Extended attribute to pin the metadata of a directory subtree into the cache of the mount,
+/// the value is `meta` or `data` to warm the disk cache with the file data as well.
+pub const PIN_XATTR: &str = "user.tifs.pin";
+
+/// Interval of refreshing the pinned subtrees.
+pub const PIN_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What of a pinned subtree is kept in the cache.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PinLevel {
+    /// Attributes and entries, so `stat` and path walks are served without a transaction.
+    Meta,
+    /// Metadata, and the blocks of files in the disk cache of `-o cache_dir`.
+    Data,
+}
+
+impl FromStr for PinLevel {
+    type Err = FsError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim_end_matches('\0').trim() {
+            "" | "meta" => Ok(PinLevel::Meta),
+            "data" => Ok(PinLevel::Data),
+            _ => Err(FsError::InvalidXattrValue {
+                name: PIN_XATTR.to_owned(),
+            }),
+        }
+    }
+}
+
+impl Display for PinLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinLevel::Meta => f.write_str("meta"),
+            PinLevel::Data => f.write_str("data"),
+        }
+    }
+}
+
+/// Parse a prefetch list given by `-o prefetch`, with a directory to pin on each line like
+/// `src/project data`. Paths are relative to the root of the volume, the level is `meta` if
+/// it is omitted, and empty lines and lines starting with `#` are skipped.
+pub fn parse_prefetch_list(list: &str) -> Result<Vec<(String, PinLevel)>, FsError> {
+    let mut dirs = Vec::new();
+    for line in list.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let path = fields.next().unwrap_or_default();
+        let level = fields.next().unwrap_or_default().parse()?;
+        if fields.next().is_some() {
+            return Err(FsError::UnknownError(format!(
+                "invalid line of prefetch list: {}",
+                line
+            )));
+        }
+        dirs.push((path.to_owned(), level));
+    }
+    Ok(dirs)
+}
+
+/// Inodes and directories written by a transaction, whose cached metadata is stale once it
+/// commits.
+#[derive(Debug, Default)]
+pub struct Touched {
+    inodes: HashSet<u64>,
+    dirs: HashSet<u64>,
+}
+
+impl Touched {
+    pub fn record(&mut self, key: &[u8]) {
+        match ScopedKey::parse(key) {
+            Ok(ScopedKey::Inode(ino)) => {
+                self.inodes.insert(ino);
+            }
+            Ok(ScopedKey::FileIndex { parent, name: _ }) => {
+                self.dirs.insert(parent);
+            }
+            _ => (),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inodes.is_empty() && self.dirs.is_empty()
+    }
+}
+
+/// Metadata of the pinned subtrees of a mount, refreshed by `TiFs::serve_pinning`.
+///
+/// Pins live as long as the mount, they are not shared with other mounts. Writes of this
+/// mount drop what they touch at once, changes of other mounts show up on the next refresh.
+#[derive(Debug, Default)]
+pub struct PinCache {
+    pins: HashMap<u64, PinLevel>,
+    /// Attributes and generations of the inodes.
+    attrs: HashMap<u64, (FileAttr, u64)>,
+    entries: HashMap<u64, HashMap<String, u64>>,
+    /// Inodes and directories touched while a refresh is walking the subtrees.
+    stale: Touched,
+}
+
+impl PinCache {
+    pub fn pin(&mut self, ino: u64, level: PinLevel) {
+        self.pins.insert(ino, level);
+    }
+
+    /// Returns false if `ino` is not pinned.
+    pub fn unpin(&mut self, ino: u64) -> bool {
+        if self.pins.remove(&ino).is_none() {
+            return false;
+        }
+        if self.pins.is_empty() {
+            self.attrs.clear();
+            self.entries.clear();
+        }
+        true
+    }
+
+    pub fn level(&self, ino: u64) -> Option<PinLevel> {
+        self.pins.get(&ino).copied()
+    }
+
+    pub fn pins(&self) -> Vec<(u64, PinLevel)> {
+        self.pins
+            .iter()
+            .map(|(ino, level)| (*ino, *level))
+            .collect()
+    }
+
+    pub fn attr(&self, ino: u64) -> Option<FileAttr> {
+        self.attrs.get(&ino).map(|(attr, _)| *attr)
+    }
+
+    /// Attributes and generation of an entry.
+    pub fn lookup(&self, parent: u64, name: &str) -> Option<(FileAttr, u64)> {
+        let ino = self.entries.get(&parent)?.get(name)?;
+        self.attrs.get(ino).copied()
+    }
+
+    pub fn invalidate(&mut self, touched: Touched) {
+        if self.pins.is_empty() {
+            return;
+        }
+        for ino in &touched.inodes {
+            self.attrs.remove(ino);
+        }
+        for dir in &touched.dirs {
+            self.entries.remove(dir);
+        }
+        self.stale.inodes.extend(touched.inodes);
+        self.stale.dirs.extend(touched.dirs);
+    }
+
+    /// Start walking the pinned subtrees, anything touched from now on is not taken from it.
+    pub fn begin_refresh(&mut self) {
+        self.stale = Touched::default();
+    }
+
+    /// Replace the cache by the result of a walk started by `begin_refresh`.
+    pub fn finish_refresh(
+        &mut self,
+        mut attrs: HashMap<u64, (FileAttr, u64)>,
+        mut entries: HashMap<u64, HashMap<String, u64>>,
+    ) {
+        let stale = std::mem::take(&mut self.stale);
+        for ino in &stale.inodes {
+            attrs.remove(ino);
+        }
+        for dir in &stale.dirs {
+            entries.remove(dir);
+        }
+        self.attrs = attrs;
+        self.entries = entries;
+    }
+}
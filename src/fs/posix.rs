@@ -0,0 +1,51 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// How closely a mount follows POSIX where compliance costs throughput, by `-o posix=strict`
+/// or `-o posix=relaxed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PosixMode {
+    /// Access times are batched, see `AtimeBatch`, the kernel caches attributes and checks
+    /// permissions against the cached modes, and the inode of a sharded directory is left
+    /// alone when its entries change, so creations in it do not conflict.
+    Relaxed,
+    /// Every read saves the access time in its transaction, attributes are never cached
+    /// so permissions are checked against the latest modes, changes of attributes keep the
+    /// modification time unless it is set, the times of sharded directories follow their
+    /// entries, and writes or changes of the owner clear the setuid and setgid bits.
+    Strict,
+}
+
+impl PosixMode {
+    pub fn is_strict(self) -> bool {
+        self == Self::Strict
+    }
+}
+
+impl Default for PosixMode {
+    fn default() -> Self {
+        Self::Relaxed
+    }
+}
+
+impl FromStr for PosixMode {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "strict" => Ok(Self::Strict),
+            "relaxed" => Ok(Self::Relaxed),
+            _ => Err(anyhow!("unknown posix mode: {}", value)),
+        }
+    }
+}
+
+impl Display for PosixMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Strict => write!(f, "strict"),
+            Self::Relaxed => write!(f, "relaxed"),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::fmt::Write as _;
+use std::ops::Range;
+use std::time::Duration;
+
+use async_std::io;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use serde::Deserialize;
+use tikv_client::Key;
+
+use super::error::{FsError, Result};
+
+/// Time PD is given to answer a query of the regions.
+const PD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Regions asked of PD by one query.
+const REGIONS_PER_QUERY: usize = 1 << 10;
+
+/// Bytes of a key held by each group of its memcomparable encoding, see `encode_key`.
+const ENC_GROUP_SIZE: usize = 8;
+const ENC_MARKER: u8 = 0xff;
+
+#[derive(Deserialize)]
+struct Regions {
+    #[serde(default)]
+    regions: Vec<RegionInfo>,
+}
+
+/// A region as PD reports it, its keys are the encoded ones in upper case hex.
+#[derive(Deserialize)]
+struct RegionInfo {
+    start_key: String,
+    end_key: String,
+}
+
+/// Encode `key` the way TiKV keys the data of transactions and PD bounds its regions: every 8
+/// bytes followed by a marker, the last group padded with zeros and marked by the padding.
+pub fn encode_key(key: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity((key.len() / ENC_GROUP_SIZE + 1) * (ENC_GROUP_SIZE + 1));
+    let mut groups = key.chunks_exact(ENC_GROUP_SIZE);
+    for group in &mut groups {
+        encoded.extend_from_slice(group);
+        encoded.push(ENC_MARKER);
+    }
+    let rest = groups.remainder();
+    let pad = ENC_GROUP_SIZE - rest.len();
+    encoded.extend_from_slice(rest);
+    encoded.resize(encoded.len() + pad, 0);
+    encoded.push(ENC_MARKER - pad as u8);
+    encoded
+}
+
+/// Decode a key encoded by `encode_key`. Region boundaries may be cut short or carry a
+/// timestamp after the key, what is cut is kept as it is and what follows the key is ignored.
+pub fn decode_key(encoded: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(encoded.len());
+    for group in encoded.chunks(ENC_GROUP_SIZE + 1) {
+        if group.len() <= ENC_GROUP_SIZE {
+            key.extend_from_slice(group);
+            break;
+        }
+        let pad = (ENC_MARKER - group[ENC_GROUP_SIZE]) as usize;
+        key.extend_from_slice(&group[..ENC_GROUP_SIZE - pad.min(ENC_GROUP_SIZE)]);
+        if pad > 0 {
+            break;
+        }
+    }
+    key
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let invalid = || FsError::UnknownError(format!("invalid region key from pd: {}", hex));
+    if hex.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| {
+            let byte = hex.get(at..at + 2).ok_or_else(invalid)?;
+            u8::from_str_radix(byte, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+/// Percent-encode every byte of `bytes` for a query string.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len() * 3);
+    for byte in bytes {
+        let _ = write!(escaped, "%{:02X}", byte);
+    }
+    escaped
+}
+
+/// The regions overlapping the encoded keys `from..to`, up to `REGIONS_PER_QUERY` of them,
+/// asked of PD at `endpoint` over its HTTP API.
+async fn query(endpoint: &str, from: &[u8], to: &[u8]) -> Result<Vec<RegionInfo>> {
+    let host = endpoint.trim_start_matches("http://").trim_end_matches('/');
+    let request = format!(
+        "GET /pd/api/v1/regions/keys?key={}&end_key={}&limit={} HTTP/1.0\r\nHost: {}\r\n\r\n",
+        escape(from),
+        escape(to),
+        REGIONS_PER_QUERY,
+        host
+    );
+    let response = io::timeout(PD_TIMEOUT, async {
+        let mut stream = TcpStream::connect(host).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok(response)
+    })
+    .await?;
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| FsError::UnknownError("truncated response from pd".into()))?;
+    let status = String::from_utf8_lossy(&response[..head_end]);
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(FsError::UnknownError(format!(
+            "pd refuses to list the regions: {}",
+            status.lines().next().unwrap_or_default()
+        )));
+    }
+    let regions: Regions =
+        serde_json::from_slice(&response[head_end + 4..]).map_err(|err| FsError::Serialize {
+            target: "regions",
+            typ: "json",
+            msg: err.to_string(),
+        })?;
+    Ok(regions.regions)
+}
+
+async fn boundaries_of(endpoint: &str, range: &Range<Key>) -> Result<Vec<Key>> {
+    let start: &[u8] = (&range.start).into();
+    let end: &[u8] = (&range.end).into();
+    let to = encode_key(end);
+    let mut from = encode_key(start);
+    let mut keys = Vec::new();
+    loop {
+        let regions = query(endpoint, &from, &to).await?;
+        let full = regions.len() == REGIONS_PER_QUERY;
+        let mut next = None;
+        for region in regions {
+            let key = decode_key(&decode_hex(&region.start_key)?);
+            if start < key.as_slice() && key.as_slice() < end {
+                keys.push(Key::from(key));
+            }
+            next = Some(decode_hex(&region.end_key)?);
+        }
+        match next {
+            // the last region reaches the end of the keys if its end is empty
+            Some(next) if full && !next.is_empty() && next < to => from = next,
+            _ => return Ok(keys),
+        }
+    }
+}
+
+/// The keys the regions holding `range` start at inside it, in order, asked of the first of
+/// `endpoints` which answers.
+pub async fn boundaries(endpoints: &[String], range: Range<Key>) -> Result<Vec<Key>> {
+    let mut last_err = FsError::UnknownError("no pd endpoint".into());
+    for endpoint in endpoints {
+        match boundaries_of(endpoint, &range).await {
+            Ok(keys) => return Ok(keys),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn encode_keys() {
+        assert_eq!(encode_key(b""), vec![0, 0, 0, 0, 0, 0, 0, 0, 0xf7]);
+        assert_eq!(encode_key(&[1, 2, 3]), vec![1, 2, 3, 0, 0, 0, 0, 0, 0xfa]);
+        assert_eq!(
+            encode_key(&[1; 8]),
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0xf7]
+        );
+        assert_eq!(decode_hex("01Ff").unwrap(), vec![1, 0xff]);
+        assert!(decode_hex("0").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn decode_encoded_keys(
+            key in prop::collection::vec(any::<u8>(), 0..32),
+            other in prop::collection::vec(any::<u8>(), 0..32),
+            ts in any::<u64>(),
+        ) {
+            let mut encoded = encode_key(&key);
+            prop_assert_eq!(key.cmp(&other), encoded.cmp(&encode_key(&other)));
+            encoded.extend_from_slice(&ts.to_be_bytes());
+            prop_assert_eq!(decode_key(&encoded), key);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{FsError, Result};
+use super::serialize::{deserialize, serialize, ENCODING};
+
+/// How long a mount is taken as alive without registering again.
+pub const CLIENT_TTL: Duration = Duration::from_secs(90);
+
+/// How often a mount registers itself, well within `CLIENT_TTL`.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An entry of the clients registry, a mount is alive as long as its entry is not expired.
+///
+/// File handlers record the mount opening them, so the handlers left by a crashed mount can be
+/// told apart from the ones of live mounts, see `fsck::check_handles`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Client {
+    pub mount_id: u64,
+    pub expires: SystemTime,
+    /// Id of the `Freeze` this mount has stopped writing for, 0 if it is not frozen.
+    #[serde(default)]
+    pub frozen: u64,
+}
+
+impl Client {
+    pub fn new(mount_id: u64, frozen: u64) -> Self {
+        Self {
+            mount_id,
+            expires: SystemTime::now() + CLIENT_TTL,
+            frozen,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "client",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "client",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
+
+/// How often a mount checks whether the volume is frozen.
+pub const FREEZE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A freeze of the volume, set by `tifs admin freeze` and removed by `tifs admin thaw`.
+///
+/// Every mount polls the freeze, waits for its commits writing the volume in progress to finish
+/// and holds the new ones until the freeze is removed or expires, then records the freeze in its
+/// entry of the clients registry. The volume is frozen once all live mounts record it.
+/// Every commit writing the volume reads the freeze too and waits while it holds.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Freeze {
+    pub id: u64,
+    pub expires: SystemTime,
+}
+
+impl Freeze {
+    pub fn new(timeout: Duration) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: now
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+                .max(1),
+            expires: now + timeout,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "freeze",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "freeze",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
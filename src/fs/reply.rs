@@ -6,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, error, trace};
 
 use super::error::Result;
+use super::inode::Inode;
 
 pub fn get_time() -> Duration {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
@@ -28,6 +29,19 @@ impl Entry {
     }
 }
 
+impl Entry {
+    /// Let the kernel revalidate the entry and its attributes once `ttl` passes.
+    pub fn cached_for(self, ttl: Duration) -> Self {
+        Self { time: ttl, ..self }
+    }
+}
+
+impl From<Inode> for Entry {
+    fn from(inode: Inode) -> Self {
+        Self::new(inode.file_attr, inode.generation)
+    }
+}
+
 #[derive(Debug)]
 pub struct Open {
     pub fh: u64,
@@ -51,6 +65,11 @@ impl Attr {
             attr,
         }
     }
+
+    /// Let the kernel fetch the attributes again once `ttl` passes.
+    pub fn cached_for(self, ttl: Duration) -> Self {
+        Self { time: ttl, ..self }
+    }
 }
 
 #[derive(Debug)]
@@ -177,6 +196,11 @@ impl Create {
             flags,
         }
     }
+
+    /// Let the kernel revalidate the entry and its attributes once `ttl` passes.
+    pub fn cached_for(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
 }
 
 #[derive(Debug)]
@@ -234,6 +258,18 @@ impl Lseek {
     }
 }
 
+#[derive(Debug)]
+pub struct Ioctl {
+    result: i32,
+    data: Vec<u8>,
+}
+
+impl Ioctl {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { result: 0, data }
+    }
+}
+
 pub trait FsReply<T: Debug>: Sized {
     fn reply_ok(self, item: T);
     fn reply_err(self, err: libc::c_int);
@@ -409,6 +445,15 @@ impl FsReply<Lseek> for ReplyLseek {
     }
 }
 
+impl FsReply<Ioctl> for ReplyIoctl {
+    fn reply_ok(self, item: Ioctl) {
+        self.ioctl(item.result, item.data.as_slice())
+    }
+    fn reply_err(self, err: libc::c_int) {
+        self.error(err);
+    }
+}
+
 impl FsReply<()> for ReplyEmpty {
     fn reply_ok(self, _: ()) {
         self.ok();
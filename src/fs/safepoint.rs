@@ -0,0 +1,54 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::{FsError, Result};
+use super::serialize::{deserialize, serialize, ENCODING};
+
+/// How long a safepoint is kept without being registered again.
+pub const SAFEPOINT_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// MVCC versions younger than this are kept by `tifs-admin gc` without any safepoint.
+pub const DEFAULT_GC_LIFE_TIME: Duration = Duration::from_secs(10 * 60);
+
+/// A service safepoint, `TiFs::gc` never advances the GC safepoint of TiKV beyond it.
+///
+/// Snapshot mounts and version reads register one for the oldest timestamp they read at,
+/// backups for the timestamp they are taken at. BR registers its service safepoint in PD,
+/// which is not seen by `TiFs::gc`, so register one for BR by `tifs-admin safepoint set`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SafePoint {
+    pub service: String,
+    pub timestamp: u64,
+    pub expires: SystemTime,
+}
+
+impl SafePoint {
+    pub fn new(service: String, timestamp: u64, ttl: Duration) -> Self {
+        Self {
+            service,
+            timestamp,
+            expires: SystemTime::now() + ttl,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "safepoint",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "safepoint",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
@@ -0,0 +1,135 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::block::empty_block;
+use super::error::{FsError, Result};
+
+/// Extended attribute to seal a subtree (set) or to verify and get its Merkle root (get).
+pub const SEAL_XATTR: &str = "user.tifs.seal";
+
+/// How long ago the earlier versions of a corrupted block of a sealed file are read, from the
+/// latest one, until one matches its leaf or the versions are collected by GC.
+pub const REPAIR_PROBES: [Duration; 8] = [
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(600),
+    Duration::from_secs(3600),
+    Duration::from_secs(6 * 3600),
+    Duration::from_secs(24 * 3600),
+    Duration::from_secs(7 * 24 * 3600),
+];
+
+/// The Merkle tree node of a sealed inode.
+///
+/// A sealed file keeps the hashes of its blocks as leaves, the root of a file is derived from
+/// its size and leaves, and the root of a directory from the names and roots of its entries.
+/// Reads check every block against its leaf, and getting `SEAL_XATTR` of a sealed directory
+/// re-derives the whole subtree to check it against the stored root.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Seal {
+    pub root: Vec<u8>,
+    pub leaves: Vec<Vec<u8>>,
+}
+
+pub fn digest(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+fn merkle_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    if leaves.is_empty() {
+        return digest(&[]);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().to_vec()
+                }
+                [single] => single.clone(),
+                _ => unreachable!("chunks of 2 have one or two items"),
+            })
+            .collect();
+    }
+    level.pop().unwrap()
+}
+
+pub fn hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl Seal {
+    pub fn file(size: u64, leaves: Vec<Vec<u8>>) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(&size.to_le_bytes());
+        hasher.update(&merkle_root(&leaves));
+        Self {
+            root: hasher.finalize().to_vec(),
+            leaves,
+        }
+    }
+
+    /// Seal a directory from the names and roots of its entries, in any order.
+    pub fn dir(mut entries: Vec<(String, Vec<u8>)>) -> Self {
+        entries.sort();
+        let mut hasher = Sha256::new();
+        for (name, root) in entries {
+            hasher.update(name.as_bytes());
+            hasher.update(&[0]);
+            hasher.update(&root);
+        }
+        Self {
+            root: hasher.finalize().to_vec(),
+            leaves: Vec::new(),
+        }
+    }
+
+    pub fn verify_block(&self, ino: u64, block: u64, data: &[u8]) -> Result<()> {
+        match self.leaves.get(block as usize) {
+            Some(leaf) if *leaf == digest(data) => Ok(()),
+            _ => Err(FsError::SealMismatch { ino }),
+        }
+    }
+
+    /// Verify the blocks in `range`, the missing ones are regarded as holes.
+    pub fn verify_blocks<'a, I>(
+        &self,
+        ino: u64,
+        range: Range<u64>,
+        block_size: u64,
+        blocks: I,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = (u64, &'a [u8])>,
+    {
+        let empty = empty_block(block_size);
+        let mut next_block = range.start;
+        for (block, data) in blocks {
+            for hole in next_block..block {
+                self.verify_block(ino, hole, &empty)?;
+            }
+            self.verify_block(ino, block, data)?;
+            next_block = block + 1;
+        }
+        for hole in next_block..range.end {
+            self.verify_block(ino, hole, &empty)?;
+        }
+        Ok(())
+    }
+
+    pub fn verify_root(&self, ino: u64, expected: &Seal) -> Result<()> {
+        if self.root == expected.root {
+            Ok(())
+        } else {
+            Err(FsError::SealMismatch { ino })
+        }
+    }
+}
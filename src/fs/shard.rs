@@ -0,0 +1,51 @@
+use std::str::FromStr;
+
+use super::dir::Directory;
+use super::error::FsError;
+
+/// Extended attribute to spread the entries of a directory over shards, the value is the
+/// number of shards, 0 to merge them back.
+pub const SHARDS_XATTR: &str = "user.tifs.shards";
+
+/// Most shards of a directory, they are kept in its blocks from 0 on.
+pub const MAX_DIR_SHARDS: u32 = 1 << 8;
+
+/// Number of shards of a directory.
+///
+/// Entries are placed by the hash of their names, and a change of entries only writes their
+/// shards, not the directory inode. Creates of different names in a sharded directory mostly
+/// touch different keys, so they commit concurrently instead of conflicting on the listing;
+/// in return the size and times of the directory are not updated by them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DirShards(pub u32);
+
+impl DirShards {
+    /// Shard of the entry `name`, by FNV-1a which is stable across builds.
+    pub fn of(&self, name: &str) -> u64 {
+        let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        hash % self.0.max(1) as u64
+    }
+
+    /// Split a listing into its shards, keeping the order of entries in each of them.
+    pub fn split(&self, dir: &Directory) -> Vec<Directory> {
+        let mut shards = vec![Directory::new(); self.0.max(1) as usize];
+        for item in dir {
+            shards[self.of(&item.name) as usize].push(item.clone());
+        }
+        shards
+    }
+}
+
+impl FromStr for DirShards {
+    type Err = FsError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim_end_matches('\0').trim().parse() {
+            Ok(shards) if shards <= MAX_DIR_SHARDS => Ok(DirShards(shards)),
+            _ => Err(FsError::InvalidXattrValue {
+                name: SHARDS_XATTR.to_owned(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+const UNITS: [(char, u32); 4] = [('K', 10), ('M', 20), ('G', 30), ('T', 40)];
+
+/// Size in bytes of options like `cache_size=10G`, with an optional binary unit `K`, `M`, `G`
+/// or `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let upper = value.trim().to_ascii_uppercase();
+        let digits = upper.trim_end_matches(|c: char| c == 'B' || c == 'I');
+        let (num, shift) = match digits.chars().last() {
+            Some(last) if last.is_ascii_alphabetic() => {
+                let shift = UNITS
+                    .iter()
+                    .find(|(unit, _)| *unit == last)
+                    .map(|(_, shift)| *shift)
+                    .ok_or_else(|| anyhow!("unknown unit of size: {}", value))?;
+                (&digits[..digits.len() - 1], shift)
+            }
+            _ => (digits, 0),
+        };
+        let num: u64 = num.parse()?;
+        num.checked_mul(1 << shift)
+            .map(ByteSize)
+            .ok_or_else(|| anyhow!("size overflows: {}", value))
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (unit, shift) in UNITS.iter().rev() {
+            if self.0 != 0 && self.0 % (1 << shift) == 0 {
+                return write!(f, "{}{}", self.0 >> shift, unit);
+            }
+        }
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Block size of `-o blksize`, in KiB.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BlockSize(pub u64);
+
+impl BlockSize {
+    /// Smallest and largest block sizes in KiB, blocks larger than TiKV handles well as
+    /// single values are refused.
+    pub const MIN: u64 = 4;
+    pub const MAX: u64 = 4 << 10;
+
+    pub fn bytes(&self) -> u64 {
+        self.0 << 10
+    }
+}
+
+impl FromStr for BlockSize {
+    type Err = anyhow::Error;
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let size: u64 = value.trim().parse()?;
+        if size < Self::MIN || size > Self::MAX {
+            return Err(anyhow!(
+                "block size of {} KiB is out of range, expect {} to {}",
+                size,
+                Self::MIN,
+                Self::MAX
+            ));
+        }
+        Ok(BlockSize(size))
+    }
+}
+
+/// Formatted as the number given to `-o blksize`.
+impl fmt::Debug for BlockSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for BlockSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tikv_client::{
+    Key, KvPair, Snapshot, Timestamp, TimestampExt, TransactionClient, TransactionOptions, Value,
+};
+use tracing::debug;
+
+use super::block::empty_block;
+use super::dir::Directory;
+use super::error::{FsError, Result};
+use super::index::{hashed_name, HashedIndex, Index};
+use super::inode::Inode;
+use super::key::ScopedKey;
+use super::shard::MAX_DIR_SHARDS;
+use super::stats::OpStats;
+
+/// Bits of the logical part of a TiKV timestamp.
+const PHYSICAL_SHIFT_BITS: u64 = 18;
+
+/// Inode numbers from here on are handed out to historical versions, which are read only.
+pub const VIRTUAL_INODE_BASE: u64 = 1 << 62;
+
+/// Virtual directory in the root, `.@snapshots/<time>/...` is the volume at `<time>`.
+pub const SNAPSHOTS_DIR: &str = ".@snapshots";
+
+pub const SNAPSHOTS_INODE: u64 = VIRTUAL_INODE_BASE;
+
+/// Unix timestamps in seconds are below this, TiKV timestamps are above.
+const MAX_UNIX_SECONDS: u64 = 1 << 40;
+
+/// The earliest TiKV timestamp of `time`.
+pub fn timestamp_of(time: SystemTime) -> u64 {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    millis << PHYSICAL_SHIFT_BITS
+}
+
+pub fn time_of(ts: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ts >> PHYSICAL_SHIFT_BITS)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn parse_fields(value: &str, separator: char) -> Option<Vec<i64>> {
+    value
+        .split(separator)
+        .map(|field| field.parse().ok())
+        .collect()
+}
+
+/// Parse a TiKV timestamp, unix seconds, or UTC time like `2021-02-01` or `2021-02-01T08:30:00`.
+pub fn parse_timestamp(value: &str) -> Option<u64> {
+    if let Ok(ts) = value.parse::<u64>() {
+        return Some(if ts < MAX_UNIX_SECONDS {
+            timestamp_of(UNIX_EPOCH + Duration::from_secs(ts))
+        } else {
+            ts
+        });
+    }
+
+    let mut parts = value.splitn(2, 'T');
+    let date = parse_fields(parts.next()?, '-')?;
+    let time = match parts.next() {
+        Some(time) => parse_fields(time, ':')?,
+        None => vec![0, 0, 0],
+    };
+    match (&date[..], &time[..]) {
+        ([year, month, day], [hour, minute, second])
+            if (1..=12).contains(month)
+                && (1..=31).contains(day)
+                && (0..24).contains(hour)
+                && (0..60).contains(minute)
+                && (0..60).contains(second) =>
+        {
+            let secs =
+                days_from_civil(*year, *month, *day) * 86400 + hour * 3600 + minute * 60 + second;
+            if secs < 0 {
+                return None;
+            }
+            Some(timestamp_of(UNIX_EPOCH + Duration::from_secs(secs as u64)))
+        }
+        _ => None,
+    }
+}
+
+/// Split `file@<time>` into the file name and the timestamp, see `parse_timestamp`.
+pub fn parse_versioned_name(name: &str) -> Option<(&str, u64)> {
+    let at = name.rfind('@')?;
+    let (base, ts) = (&name[..at], &name[at + 1..]);
+    if base.is_empty() {
+        return None;
+    }
+    parse_timestamp(ts).map(|ts| (base, ts))
+}
+
+/// Read-only view of the filesystem at a TiKV MVCC timestamp.
+///
+/// Versions older than the GC safepoint of the cluster are collected, reading them fails.
+pub struct SnapshotView {
+    snapshot: Snapshot,
+    ts: u64,
+    block_size: u64,
+    /// Whether index keys hold hashes of the names, see `HashedIndex`.
+    hashed_names: bool,
+}
+
+impl SnapshotView {
+    pub fn at(client: &TransactionClient, ts: u64, block_size: u64) -> Self {
+        Self {
+            snapshot: client.snapshot(
+                Timestamp::from_version(ts),
+                TransactionOptions::new_optimistic(),
+            ),
+            ts,
+            block_size,
+            hashed_names: false,
+        }
+    }
+
+    pub fn with_hashed_names(mut self, hashed: bool) -> Self {
+        self.hashed_names = hashed;
+        self
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.ts
+    }
+
+    async fn get(&mut self, key: impl Into<Key>) -> Result<Option<Value>> {
+        let value = self.snapshot.get(key).await?;
+        OpStats::update(|stats| {
+            stats.gets += 1;
+            stats.read_bytes += value.as_ref().map(|v| v.len() as u64).unwrap_or(0);
+        });
+        Ok(value)
+    }
+
+    pub async fn scan(&mut self, range: Range<Key>, limit: u32) -> Result<Vec<KvPair>> {
+        let pairs: Vec<KvPair> = self.snapshot.scan(range, limit).await?.collect();
+        OpStats::update(|stats| {
+            stats.scans += 1;
+            stats.read_bytes += pairs
+                .iter()
+                .map(|pair| pair.value().len() as u64)
+                .sum::<u64>();
+        });
+        Ok(pairs)
+    }
+
+    pub async fn read_inode(&mut self, ino: u64) -> Result<Inode> {
+        let value = self
+            .get(ScopedKey::inode(ino))
+            .await?
+            .ok_or_else(|| FsError::InodeNotFound { inode: ino })?;
+        Inode::deserialize(&value)
+    }
+
+    pub async fn lookup(&mut self, parent: u64, name: &str) -> Result<u64> {
+        let not_found = || FsError::FileNotFound {
+            file: name.to_owned(),
+        };
+        if self.hashed_names {
+            let value = self
+                .get(ScopedKey::index(parent, &hashed_name(name)))
+                .await?
+                .ok_or_else(not_found)?;
+            return HashedIndex::deserialize(&value)?
+                .get(name)
+                .ok_or_else(not_found);
+        }
+        let value = self
+            .get(ScopedKey::index(parent, name))
+            .await?
+            .ok_or_else(not_found)?;
+        Ok(Index::deserialize(&value)?.ino)
+    }
+
+    pub async fn read_block(&mut self, ino: u64, block: u64) -> Result<Option<Value>> {
+        self.get(ScopedKey::block(ino, block)).await
+    }
+
+    /// The entries of a directory, merged from its shards if it is sharded.
+    pub async fn read_dir(&mut self, ino: u64) -> Result<Directory> {
+        let pairs = self
+            .scan(
+                ScopedKey::block_range(ino, 0..MAX_DIR_SHARDS as u64),
+                MAX_DIR_SHARDS,
+            )
+            .await?;
+        if pairs.is_empty() {
+            return Err(FsError::BlockNotFound {
+                inode: ino,
+                block: 0,
+            });
+        }
+        let mut dir = Directory::new();
+        for pair in pairs {
+            dir.extend(super::dir::decode(pair.value())?);
+        }
+        Ok(dir)
+    }
+
+    pub async fn read_data(&mut self, ino: u64, start: u64, size: u64) -> Result<Vec<u8>> {
+        let inode = self.read_inode(ino).await?;
+        if start >= inode.size {
+            return Ok(Vec::new());
+        }
+        let size = size.min(inode.size - start);
+
+        let mut data = if let Some(inlined) = &inode.inline_data {
+            inlined.get(start as usize..).unwrap_or_default().to_vec()
+        } else {
+            let start_block = start / self.block_size;
+            let end_block = (start + size + self.block_size - 1) / self.block_size;
+            let mut data =
+                Vec::with_capacity(((end_block - start_block) * self.block_size) as usize);
+            for block in start_block..end_block {
+                let value = self
+                    .get(ScopedKey::block(ino, block))
+                    .await?
+                    .unwrap_or_else(|| empty_block(self.block_size));
+                data.extend_from_slice(&value);
+            }
+            data.drain(..(start % self.block_size) as usize);
+            data
+        };
+        data.resize(size as usize, 0);
+        Ok(data)
+    }
+}
+
+#[derive(Debug)]
+pub struct Version {
+    /// Timestamp to read this version at, e.g. by `file@<timestamp>`.
+    pub timestamp: u64,
+    pub inode: Inode,
+}
+
+/// Versions of `ino` from the latest one, each earlier one is read just before the next one
+/// was modified. It stops at the creation of the file or at the GC safepoint.
+pub async fn versions(
+    client: &TransactionClient,
+    ino: u64,
+    block_size: u64,
+    limit: usize,
+) -> Result<Vec<Version>> {
+    let mut ts = client.current_timestamp().await?.version();
+    let mut versions = Vec::new();
+    while versions.len() < limit {
+        let inode = match SnapshotView::at(client, ts, block_size)
+            .read_inode(ino)
+            .await
+        {
+            Ok(inode) => inode,
+            Err(FsError::InodeNotFound { inode: _ }) => break,
+            Err(err) if !versions.is_empty() => {
+                debug!("stop reading versions of inode({}) at {}: {}", ino, ts, err);
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        let next = timestamp_of(inode.mtime).saturating_sub(1);
+        versions.push(Version {
+            timestamp: ts,
+            inode,
+        });
+        if next == 0 || next >= ts {
+            break;
+        }
+        ts = next;
+    }
+    Ok(versions)
+}
+
+/// Inode numbers handed out to historical versions of inodes.
+#[derive(Debug, Default)]
+pub struct VirtualInodes {
+    next: u64,
+    versions: HashMap<u64, (u64, u64)>,
+    inodes: HashMap<(u64, u64), u64>,
+}
+
+impl VirtualInodes {
+    pub fn is_virtual(ino: u64) -> bool {
+        ino >= VIRTUAL_INODE_BASE
+    }
+
+    pub fn get_or_insert(&mut self, ino: u64, ts: u64) -> u64 {
+        if let Some(virtual_ino) = self.inodes.get(&(ino, ts)) {
+            return *virtual_ino;
+        }
+        let virtual_ino = SNAPSHOTS_INODE + 1 + self.next;
+        self.next += 1;
+        self.inodes.insert((ino, ts), virtual_ino);
+        self.versions.insert(virtual_ino, (ino, ts));
+        virtual_ino
+    }
+
+    /// The inode and timestamp behind a virtual inode.
+    pub fn resolve(&self, virtual_ino: u64) -> Option<(u64, u64)> {
+        self.versions.get(&virtual_ino).copied()
+    }
+}
@@ -0,0 +1,320 @@
+use std::cell::RefCell;
+use std::time::{Duration, SystemTime};
+
+use async_std::task_local;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, Span};
+
+use super::error::{FsError, Result};
+use super::key::ScopedKey;
+use super::serialize::{deserialize, serialize, ENCODING};
+
+/// Interval of adding the counters of a mount into the statistics of the volume.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Row of the volume statistics holding the usage counted by `TiFs::rebase_usage`, and the
+/// rows of the mounts gone folded into it by `TiFs::fold_stats`. No mount has the id 0.
+pub const USAGE_BASE_ROW: u64 = 0;
+
+/// Rows of the volume statistics after `USAGE_BASE_ROW` the transactions count the files and
+/// blocks they change in, picked by the inode changed, so they seldom conflict on one.
+pub const USAGE_SHARDS: u64 = 64;
+
+/// The row a transaction changing `ino` counts its usage in.
+pub const fn usage_row(ino: u64) -> u64 {
+    USAGE_BASE_ROW + 1 + ino % USAGE_SHARDS
+}
+
+/// Whether the row `id` of the volume statistics holds usage rather than the counters of a
+/// mount. Mount ids are taken from the clock, no mount takes one of these.
+pub const fn is_usage_row(id: u64) -> bool {
+    id <= USAGE_BASE_ROW + USAGE_SHARDS
+}
+
+/// TiKV round trips issued on behalf of a single FUSE operation.
+///
+/// Every operation is served in its own task (see `spawn_reply`), so the counters are kept in a
+/// task local and recorded into the span of the operation once it finishes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OpStats {
+    pub gets: u64,
+    pub puts: u64,
+    pub deletes: u64,
+    pub scans: u64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    pub retries: u64,
+    /// Retries by the class of the conflicting key, see `KeyClass`.
+    pub meta_retries: u64,
+    pub dir_retries: u64,
+    pub inode_retries: u64,
+    pub block_retries: u64,
+}
+
+/// Class of the key a transaction conflicts on, to tell which hotspot retries come from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyClass {
+    /// The volume meta, e.g. the next inode number.
+    Meta,
+    /// Entries of directories.
+    Dir,
+    Inode,
+    /// Blocks of files, and the listings of directories.
+    Block,
+    /// Other keys, or conflicts of which the key is not known.
+    Other,
+}
+
+impl KeyClass {
+    pub fn of(key: Option<&[u8]>) -> Self {
+        match key.map(ScopedKey::parse) {
+            Some(Ok(ScopedKey::Meta)) => KeyClass::Meta,
+            Some(Ok(ScopedKey::FileIndex { parent: _, name: _ })) => KeyClass::Dir,
+            Some(Ok(ScopedKey::Inode(_))) => KeyClass::Inode,
+            Some(Ok(ScopedKey::Block { ino: _, block: _ })) => KeyClass::Block,
+            _ => KeyClass::Other,
+        }
+    }
+
+    /// Log a failed optimistic commit with the decoded key it conflicts on, and classify it.
+    pub fn of_conflict(msg: &str, key: Option<&[u8]>) -> Self {
+        let class = Self::of(key);
+        match key.map(|raw| (raw, ScopedKey::parse(raw))) {
+            Some((_, Ok(scoped))) => {
+                debug!(?class, key = ?scoped, "transaction conflict: {}", msg)
+            }
+            Some((raw, Err(_))) => debug!(?class, ?raw, "transaction conflict: {}", msg),
+            None => debug!(?class, "transaction conflict on an unknown key: {}", msg),
+        }
+        class
+    }
+}
+
+task_local! {
+    static OP_STATS: RefCell<OpStats> = RefCell::new(OpStats::default());
+}
+
+impl OpStats {
+    pub fn update<F>(f: F)
+    where
+        F: FnOnce(&mut OpStats),
+    {
+        // outside of a task (e.g. in a plain thread) there is nothing to account to.
+        let _ = OP_STATS.try_with(|stats| f(&mut stats.borrow_mut()));
+    }
+
+    pub fn take() -> Self {
+        OP_STATS
+            .try_with(|stats| stats.replace(Default::default()))
+            .unwrap_or_default()
+    }
+
+    /// Count a retry on a conflict of a key of `class`.
+    pub fn retry(&mut self, class: KeyClass) {
+        self.retries += 1;
+        match class {
+            KeyClass::Meta => self.meta_retries += 1,
+            KeyClass::Dir => self.dir_retries += 1,
+            KeyClass::Inode => self.inode_retries += 1,
+            KeyClass::Block => self.block_retries += 1,
+            KeyClass::Other => (),
+        }
+    }
+
+    pub fn record(&self, span: &Span) {
+        span.record("gets", &self.gets);
+        span.record("puts", &self.puts);
+        span.record("deletes", &self.deletes);
+        span.record("scans", &self.scans);
+        span.record("read_bytes", &self.read_bytes);
+        span.record("written_bytes", &self.written_bytes);
+        span.record("retries", &self.retries);
+        span.record("meta_retries", &self.meta_retries);
+        span.record("dir_retries", &self.dir_retries);
+        span.record("inode_retries", &self.inode_retries);
+        span.record("block_retries", &self.block_retries);
+    }
+}
+
+/// Files and blocks of the inodes saved and removed by a transaction, see `VolumeStats::files`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UsageDelta {
+    pub files: i64,
+    pub blocks: i64,
+    /// Row the delta is counted in, see `usage_row`.
+    pub row: u64,
+}
+
+impl UsageDelta {
+    /// Count the inode `ino` replaced, with the blocks it has stored before and after, if any.
+    pub fn change(&mut self, ino: u64, before: Option<u64>, after: Option<u64>) {
+        let files = after.is_some() as i64 - before.is_some() as i64;
+        let blocks = after.unwrap_or(0) as i64 - before.unwrap_or(0) as i64;
+        if files != 0 || blocks != 0 {
+            self.files += files;
+            self.blocks += blocks;
+            self.row = usage_row(ino);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files == 0 && self.blocks == 0
+    }
+}
+
+/// Cumulative statistics of a volume, to follow its growth over time.
+///
+/// Each mount adds its counters to a row of its own (see `TiFs::serve_stats`), so mounts never
+/// conflict on them; the statistics of the volume are the rows merged by `merge`. The files and
+/// blocks are counted in the usage rows instead, by the transactions changing them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VolumeStats {
+    /// FUSE operations served.
+    pub ops: u64,
+    pub gets: u64,
+    pub puts: u64,
+    pub deletes: u64,
+    pub scans: u64,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    pub retries: u64,
+    #[serde(default)]
+    pub meta_retries: u64,
+    #[serde(default)]
+    pub dir_retries: u64,
+    #[serde(default)]
+    pub inode_retries: u64,
+    #[serde(default)]
+    pub block_retries: u64,
+    /// Files and blocks created less removed, all rows sum up to the usage of the volume.
+    /// Changes made by raw writes are not counted, until the usage is counted again by
+    /// `tifs admin info --exact`; restores count it once they finish.
+    #[serde(default)]
+    pub files: i64,
+    #[serde(default)]
+    pub blocks: i64,
+    /// Most files and blocks of the volume seen when the counters are added.
+    pub files_high: u64,
+    pub blocks_high: u64,
+    /// When the counters are first and last added.
+    pub since: Option<SystemTime>,
+    pub updated: Option<SystemTime>,
+}
+
+impl VolumeStats {
+    /// Count an operation served with the round trips of `stats`.
+    pub fn account(&mut self, stats: &OpStats) {
+        self.ops += 1;
+        self.gets += stats.gets;
+        self.puts += stats.puts;
+        self.deletes += stats.deletes;
+        self.scans += stats.scans;
+        self.read_bytes += stats.read_bytes;
+        self.written_bytes += stats.written_bytes;
+        self.retries += stats.retries;
+        self.meta_retries += stats.meta_retries;
+        self.dir_retries += stats.dir_retries;
+        self.inode_retries += stats.inode_retries;
+        self.block_retries += stats.block_retries;
+    }
+
+    /// Count the files and blocks created or removed by a transaction.
+    pub fn add_usage(&mut self, delta: UsageDelta) {
+        self.files += delta.files;
+        self.blocks += delta.blocks;
+    }
+
+    /// Raise the high-water marks to the current usage of the volume.
+    pub fn mark_usage(&mut self, files: u64, blocks: u64, now: SystemTime) {
+        self.files_high = self.files_high.max(files);
+        self.blocks_high = self.blocks_high.max(blocks);
+        self.updated = Some(now);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.ops += other.ops;
+        self.gets += other.gets;
+        self.puts += other.puts;
+        self.deletes += other.deletes;
+        self.scans += other.scans;
+        self.read_bytes += other.read_bytes;
+        self.written_bytes += other.written_bytes;
+        self.retries += other.retries;
+        self.meta_retries += other.meta_retries;
+        self.dir_retries += other.dir_retries;
+        self.inode_retries += other.inode_retries;
+        self.block_retries += other.block_retries;
+        self.files += other.files;
+        self.blocks += other.blocks;
+        self.files_high = self.files_high.max(other.files_high);
+        self.blocks_high = self.blocks_high.max(other.blocks_high);
+        self.since = match (self.since, other.since) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.updated = self.updated.max(other.updated);
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "volume stats",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "volume stats",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
+
+/// Progress of `TiFs::rebase_usage`, which counts the inodes in batches of its own transactions
+/// and keeps the counts so far under `ScopedKey::usage_rebase`, so a rebase stopped halfway is
+/// resumed rather than started over.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UsageRebase {
+    /// The inode the next batch starts from.
+    pub next: u64,
+    pub files: u64,
+    pub blocks: u64,
+    /// When the last batch is counted, a cursor left longer than `STATS_INTERVAL` ago is
+    /// started over as the volume has changed too much since.
+    pub updated: SystemTime,
+}
+
+impl UsageRebase {
+    pub fn new(next: u64, now: SystemTime) -> Self {
+        Self {
+            next,
+            files: 0,
+            blocks: 0,
+            updated: now,
+        }
+    }
+
+    /// Whether the rebase is still worth resuming at `now`.
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        now.duration_since(self.updated)
+            .map_or(true, |idle| idle < STATS_INTERVAL)
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        serialize(self).map_err(|err| FsError::Serialize {
+            target: "usage rebase",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        deserialize(bytes).map_err(|err| FsError::Serialize {
+            target: "usage rebase",
+            typ: ENCODING,
+            msg: err.to_string(),
+        })
+    }
+}
@@ -0,0 +1,134 @@
+/// Directories walked up from a symlink to the root before giving up on a loop of parents.
+pub const MAX_CONTAIN_DEPTH: usize = 1 << 12;
+
+/// Symlinks followed by the resolution of one path before it fails with `ELOOP`, like
+/// `MAXSYMLINKS` of Linux.
+pub const MAX_FOLLOWS: usize = 40;
+
+/// How `TiFs::resolve_path_with` treats the symlinks met on a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Follow {
+    /// A symlink in the middle of a path fails it with `ELOOP`, like `RESOLVE_NO_SYMLINKS`, and
+    /// one at its end is resolved to itself.
+    Never,
+    /// Symlinks are followed, targets taken from the root of the volume when absolute, up to
+    /// the given number of them, after which the resolution fails with `ELOOP`.
+    Within(usize),
+}
+
+impl Default for Follow {
+    fn default() -> Self {
+        Self::Within(MAX_FOLLOWS)
+    }
+}
+
+/// The names of `path`, in reverse order to be popped one by one.
+pub fn pending_names(path: &[u8]) -> Vec<Vec<u8>> {
+    path.split(|c| *c == b'/')
+        .filter(|name| !name.is_empty() && *name != b".")
+        .rev()
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+/// Whether `target` may lead out of the volume, as an absolute path or by climbing with `..`.
+pub fn escapes(target: &[u8]) -> bool {
+    target.starts_with(b"/") || target.split(|c| *c == b'/').any(|name| name == b"..")
+}
+
+/// Rewrite `target` of a symlink whose directory is `depth` levels below the root of the
+/// volume into a relative path that stays inside the volume, see `-o contain_symlinks`.
+///
+/// Absolute targets are taken from the root of the volume, and `..` at the root stays there,
+/// like in a chroot. The path is resolved lexically, a symlink met on the way is contained
+/// by itself when it is read.
+pub fn contain(target: &[u8], depth: usize) -> Vec<u8> {
+    let absolute = target.starts_with(b"/");
+    // levels climbed from the directory of the symlink, the names below, and the level reached
+    let mut ups = if absolute { depth } else { 0 };
+    let mut names: Vec<&[u8]> = Vec::new();
+    let mut level = if absolute { 0 } else { depth };
+    for name in target.split(|c| *c == b'/') {
+        match name {
+            b"" | b"." => (),
+            b".." => {
+                if names.pop().is_some() {
+                    level -= 1;
+                } else if level > 0 {
+                    ups += 1;
+                    level -= 1;
+                }
+            }
+            name => {
+                names.push(name);
+                level += 1;
+            }
+        }
+    }
+
+    let trailing = target.ends_with(b"/") && !names.is_empty();
+    let mut contained = Vec::with_capacity(target.len() + 3 * ups);
+    for _ in 0..ups {
+        contained.extend_from_slice(b"../");
+    }
+    for name in names {
+        contained.extend_from_slice(name);
+        contained.push(b'/');
+    }
+    if contained.is_empty() {
+        return b".".to_vec();
+    }
+    if !trailing {
+        contained.pop();
+    }
+    contained
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contained(target: &str, depth: usize) -> String {
+        String::from_utf8(contain(target.as_bytes(), depth)).unwrap()
+    }
+
+    #[test]
+    fn absolute_targets() {
+        assert_eq!(contained("/etc/passwd", 0), "etc/passwd");
+        assert_eq!(contained("/etc/passwd", 2), "../../etc/passwd");
+        assert_eq!(contained("/", 2), "../..");
+        assert_eq!(contained("/", 0), ".");
+        assert_eq!(contained("/../../etc", 1), "../etc");
+        assert_eq!(contained("//a/./b/../c/", 1), "../a/c/");
+    }
+
+    #[test]
+    fn relative_targets() {
+        assert_eq!(contained("../../../../etc", 2), "../../etc");
+        assert_eq!(contained("a/../../b", 1), "../b");
+        assert_eq!(contained("../x/../../y", 3), "../../y");
+        assert_eq!(contained("a/b/../..", 0), ".");
+    }
+
+    #[test]
+    fn pending() {
+        let names = |path: &str| -> Vec<String> {
+            pending_names(path.as_bytes())
+                .into_iter()
+                .map(|name| String::from_utf8(name).unwrap())
+                .collect()
+        };
+        assert_eq!(names("/a/./b//../c/"), vec!["c", "..", "b", "a"]);
+        assert_eq!(names("."), Vec::<String>::new());
+        assert_eq!(names(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn escaping() {
+        assert!(escapes(b"/etc"));
+        assert!(escapes(b"a/../.."));
+        assert!(escapes(b".."));
+        assert!(!escapes(b"a/b"));
+        assert!(!escapes(b"..a/b.."));
+    }
+}
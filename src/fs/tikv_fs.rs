@@ -1,31 +1,84 @@
-use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Debug};
 use std::future::Future;
 use std::matches;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::anyhow;
-use async_std::task::sleep;
+use async_std::future::timeout;
+use async_std::sync::{Mutex as AsyncMutex, RwLockWriteGuard};
+use async_std::task::{sleep, spawn_blocking};
 use async_trait::async_trait;
 use bytes::Bytes;
 use bytestring::ByteString;
 use fuser::consts::FOPEN_DIRECT_IO;
 use fuser::*;
-use libc::{F_RDLCK, F_UNLCK, F_WRLCK, O_DIRECT, SEEK_CUR, SEEK_END, SEEK_SET};
-use tikv_client::{Config, Key, TransactionClient, Value};
+use libc::{
+    F_RDLCK, F_UNLCK, F_WRLCK, O_ACCMODE, O_DIRECT, O_EXCL, O_RDONLY, O_TRUNC, RENAME_EXCHANGE,
+    RENAME_NOREPLACE, SEEK_CUR, SEEK_END, SEEK_SET, W_OK,
+};
+use tikv_client::{Config, Key, KvPair, Timestamp, TimestampExt, TransactionClient};
 use tracing::{debug, error, info, instrument, trace, warn};
 
-use super::dir::Directory;
+use super::acl::{Acl, ACL_ACCESS_XATTR, ACL_DEFAULT_XATTR};
+use super::atime::{AtimeBatch, ATIME_FLUSH_INTERVAL};
+use super::batch::{resolve_parent, Batch, BatchOp};
+use super::block::empty_block;
+use super::chaos::Chaos;
+use super::copy::CopyEntry;
+use super::credentials::Credentials;
+use super::degrade::CommitHealth;
+use super::delegation::{Delegation, WriteBuffer, RENEW_INTERVAL, WRITE_BUFFER_INTERVAL};
+use super::deletion::{DELETION_BATCH_BLOCKS, DELETION_INTERVAL};
+use super::deny::DeniedOps;
+use super::dir::{DirStream, Directory, ReadDirIsolation};
+use super::disk_cache::DiskCache;
 use super::error::{FsError, Result};
+use super::file_handler::FileHandler;
+use super::flags::{
+    self, FS_IOC32_GETFLAGS, FS_IOC32_SETFLAGS, FS_IOC_GETFLAGS, FS_IOC_SETFLAGS, SUPPORTED_FLAGS,
+};
+use super::freeze::WriteGate;
+use super::hash::CONTENT_HASH_XATTR;
 use super::inode::Inode;
+use super::inode_cache::InodeCache;
+use super::journal::{JournalEntry, JournalOp};
 use super::key::{ScopedKey, ROOT_INODE};
-use super::mode::make_mode;
+use super::lookup::LookupCounts;
+#[cfg(feature = "mem_store")]
+use super::mem_store::MEM_USAGE_XATTR;
+use super::mem_store::{save_image, MemStore, SAVE_INTERVAL, WRITE_BACK_INTERVAL};
+use super::meta::{feature, InodePool, Meta, VolumeConfig};
+use super::mode::{make_mode, FileMode};
+use super::parallel;
+use super::pin::{
+    parse_prefetch_list, PinCache, PinLevel, Touched, PIN_REFRESH_INTERVAL, PIN_XATTR,
+};
+use super::posix::PosixMode;
+use super::registry::{Client, Freeze, FREEZE_POLL_INTERVAL, HEARTBEAT_INTERVAL};
 use super::reply::get_time;
-use super::reply::{Attr, Create, Data, Dir, DirItem, Entry, Lseek, Open, StatFs, Write};
+use super::reply::{
+    Attr, Create, Data, Dir, DirItem, Entry, Ioctl, Lseek, Open, StatFs, Write, Xattr,
+};
+use super::safepoint::{SafePoint, SAFEPOINT_TTL};
+use super::seal::{Seal, REPAIR_PROBES, SEAL_XATTR};
+use super::shard::{DirShards, SHARDS_XATTR};
+use super::snapshot::{
+    parse_timestamp, parse_versioned_name, time_of, timestamp_of, SnapshotView, VirtualInodes,
+    SNAPSHOTS_DIR, SNAPSHOTS_INODE,
+};
+use super::stats::{KeyClass, OpStats, VolumeStats, STATS_INTERVAL, USAGE_BASE_ROW};
+use super::symlink::{self, Follow, MAX_CONTAIN_DEPTH};
 use super::transaction::{LocalTxn, Txn};
+use super::ttl::{Ttl, SWEEP_INTERVAL, TTL_XATTR};
+use super::worm::{Worm, WORM_XATTR};
 use super::{async_fs::AsyncFileSystem, reply::Lock};
+use crate::systemd::notify_or_warn;
 use crate::MountOption;
 
 pub struct TiFs {
@@ -34,15 +87,109 @@ pub struct TiFs {
     pub client: TransactionClient,
     pub direct_io: bool,
     pub block_size: u64,
-    entry_map: Arc<Mutex<BTreeMap<Key, Value>>>,
+    pub chaos: Option<Chaos>,
+    pub journal: bool,
+    pub disk_cache: Option<DiskCache>,
+    pub delegation: bool,
+    /// Write buffers of the files this mount holds the delegations of.
+    delegated: Mutex<HashMap<u64, WriteBuffer>>,
+    /// Whether writes are buffered until their files are flushed, see `-o write_buffer`.
+    pub write_buffer: bool,
+    /// Write buffers of the files written with `write_buffer`.
+    buffered: Mutex<HashMap<u64, WriteBuffer>>,
+    /// Errors which drop the writes buffered to a file, reported once by its next `flush` or
+    /// `fsync`.
+    lost_writes: Mutex<HashMap<u64, FsError>>,
+    /// Flushes of the writes buffered to a file in progress, one at a time so they commit in
+    /// the order the writes are made, see `flush_lock`.
+    flushing: Mutex<HashMap<u64, Arc<AsyncMutex<()>>>>,
+    /// The safepoint registered for snapshot and version reads, and when it is registered.
+    held_safepoint: Mutex<Option<(u64, SystemTime)>>,
+    /// Random id of this mount, to tell changes of other mounts in the journal.
+    pub mount_id: u64,
+    pub readdir_isolation: ReadDirIsolation,
+    /// Listings of the open directories, by their handles.
+    dir_streams: Mutex<HashMap<u64, DirStream>>,
+    next_dir_handle: AtomicU64,
+    versions: Mutex<VirtualInodes>,
+    entry_map: Arc<Mutex<MemStore>>,
+    /// Image the memory store is loaded from and saved to.
+    pub store_file: Option<PathBuf>,
+    /// Version of the memory store when it is last saved, locked while saving so images are
+    /// written one at a time.
+    saved_version: AsyncMutex<u64>,
+    /// Whether the changes of the memory store are written back to TiKV.
+    pub write_back: bool,
+    /// Held while writing back, so an older value of a key is never committed after a newer
+    /// one taken by another write-back.
+    write_back_lock: AsyncMutex<()>,
+    /// Owner and mode of the root directory if the volume is initialized by this mount.
+    pub root_uid: Option<u32>,
+    pub root_gid: Option<u32>,
+    pub root_mode: Option<FileMode>,
+    /// Metadata of the subtrees pinned by `PIN_XATTR`.
+    pins: Mutex<PinCache>,
+    /// Inode numbers reserved by this mount for new inodes.
+    inode_pool: Arc<InodePool>,
+    /// Access times recorded by reads and not saved yet.
+    atimes: Arc<AtimeBatch>,
+    /// References of the kernel to the inodes, see `LookupCounts`.
+    lookups: Arc<LookupCounts>,
+    /// Inodes read and saved by this mount, see `-o inode_cache`.
+    inode_cache: Option<Arc<InodeCache>>,
+    /// Whether the numbers of removed inodes are taken again for new inodes.
+    pub inode_reuse: bool,
+    /// Counters of this mount not added to the statistics of the volume yet.
+    stats: Mutex<VolumeStats>,
+    /// Retries on conflicts after which an operation is logged as contended.
+    pub retry_warn: u64,
+    /// Whether named pipes are only recorded in metadata and their data stays in the kernel.
+    pub fifo_passthrough: bool,
+    /// Whether the data of every file is kept in blocks, never inline in its inode.
+    pub no_inline: bool,
+    /// Whether symlinks are read as paths which stay inside the volume, see `symlink::contain`.
+    pub contain_symlinks: bool,
+    /// Whether index keys hold hashes of the names, taken from the volume, see `HashedIndex`.
+    pub hashed_names: bool,
+    /// Whether the kernel fetches the attributes of regular files on every use.
+    pub eof_revalidate: bool,
+    /// How long the kernel caches entries and attributes, forever if it is not set.
+    pub revalidate: Option<Duration>,
+    /// Operations refused by the mount.
+    pub denied: DeniedOps,
+    /// How closely the mount follows POSIX, see `-o posix`.
+    pub posix: PosixMode,
+    /// Outcomes of the commits if the mount degrades to read-only when they keep failing.
+    commit_health: Option<CommitHealth>,
+    /// Whether the data written to a file is committed to TiKV before closing it returns.
+    pub flush_on_close: bool,
+    /// Keys fetched by one scan, see `-o scan_page`.
+    pub scan_page: u32,
+    /// Gate of the commits writing the volume, closed while the volume is frozen.
+    write_gate: WriteGate,
+    /// Id of the freeze this mount holds its commits for, 0 if it is not frozen.
+    frozen: AtomicU64,
 }
 
-type BoxedFuture<'a, T> = Pin<Box<dyn 'a + Send + Future<Output = Result<T>>>>;
+pub(crate) type BoxedFuture<'a, T> = Pin<Box<dyn 'a + Send + Future<Output = Result<T>>>>;
 
 impl TiFs {
-    pub const SCAN_LIMIT: u32 = 1 << 10;
+    /// Bytes of values written back to TiKV by one transaction.
+    const WRITE_BACK_BATCH_BYTES: usize = 4 << 20;
+    /// Blocks read by one transaction while warming the disk cache for pinned data.
+    const WARM_CHUNK_BLOCKS: u64 = 1 << 6;
+    /// Inodes whose access times are saved by one transaction.
+    const ATIME_FLUSH_BATCH: usize = 1 << 8;
+    /// Inodes changed by one transaction of `chown_tree`.
+    const CHOWN_BATCH: usize = 1 << 8;
+    /// File handle of named pipes opened by mounts with `fifo_passthrough`, never allocated by `Txn::open`.
+    const PIPE_FH: u64 = u64::MAX;
     pub const DEFAULT_BLOCK_SIZE: u64 = 1 << 16;
     pub const MAX_NAME_LEN: u32 = 1 << 8;
+    pub const DEFAULT_RETRY_WARN: u64 = 1 << 4;
+    pub const DEFAULT_SCAN_PAGE: u32 = 1 << 10;
+    /// Directories walked up by `path_of` before it gives up on a loop.
+    const MAX_PATH_DEPTH: usize = 1 << 12;
 
     #[instrument]
     pub async fn construct<S>(
@@ -57,7 +204,111 @@ impl TiFs {
             .await
             .map_err(|err| anyhow!("{}", err))?;
         info!("connected to pd endpoints: {:?}", pd_endpoints);
-        Ok(TiFs {
+        let requested_block_size = options.iter().find_map(|option| {
+            if let MountOption::BlkSize(size) = option {
+                Some(size.bytes())
+            } else {
+                None
+            }
+        });
+        let block_size = match (
+            requested_block_size,
+            Self::volume_block_size(&client).await?,
+        ) {
+            (Some(requested), Some(origin)) if requested != origin => {
+                return Err(FsError::block_size_conflict(origin, requested).into());
+            }
+            (requested, origin) => requested.or(origin).unwrap_or(Self::DEFAULT_BLOCK_SIZE),
+        };
+        let disk_cache = match options.iter().find_map(|option| {
+            if let MountOption::CacheDir(dir) = option {
+                Some(dir)
+            } else {
+                None
+            }
+        }) {
+            Some(dir) => {
+                let capacity = options
+                    .iter()
+                    .find_map(|option| {
+                        if let MountOption::CacheSize(size) = option {
+                            Some(size.0)
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap_or(DiskCache::DEFAULT_CAPACITY);
+                let endpoints: Vec<String> =
+                    pd_endpoints.clone().into_iter().map(Into::into).collect();
+                let volume = DiskCache::volume_dir(&endpoints);
+                Some(DiskCache::open(dir, &volume, capacity, block_size).await?)
+            }
+            None => None,
+        };
+        let mem_evict = options
+            .iter()
+            .find(|option| matches!(option, MountOption::MemEvict))
+            .is_some();
+        let mut store = MemStore::new(
+            options.iter().find_map(|option| {
+                if let MountOption::MemSize(size) = option {
+                    Some(size.0)
+                } else {
+                    None
+                }
+            }),
+            mem_evict,
+        );
+        let store_file = options.iter().find_map(|option| {
+            if let MountOption::StoreFile(path) = option {
+                Some(PathBuf::from(path))
+            } else {
+                None
+            }
+        });
+        if let Some(path) = &store_file {
+            if cfg!(feature = "kv_store") {
+                warn!("ignore store_file({:?}) of a volume on tikv", path);
+            } else if store.open(path)? {
+                info!("loaded memory store from {:?}: {}", path, store.usage());
+            }
+        }
+        let scan_page = options
+            .iter()
+            .find_map(|option| {
+                if let MountOption::ScanPage(page) = option {
+                    Some(page.get())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(Self::DEFAULT_SCAN_PAGE);
+        let mut write_back = options
+            .iter()
+            .find(|option| matches!(option, MountOption::WriteBack))
+            .is_some();
+        if write_back && cfg!(feature = "kv_store") {
+            warn!("ignore write_back of a volume on tikv");
+            write_back = false;
+        }
+        if write_back && mem_evict {
+            // evicting truncates files, which would be written back over their data on tikv
+            return Err(anyhow!("mem_evict cannot be used with write_back"));
+        }
+        if write_back {
+            if store.get(&ScopedKey::meta().into()).is_none() {
+                let pairs = Self::pull_store(&client, block_size, scan_page, &mut store).await?;
+                info!("loaded {} pairs of memory store from tikv", pairs);
+            }
+            store.enable_write_back();
+        }
+        let saved_version = AsyncMutex::new(store.version());
+        let mount_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+            ^ (std::process::id() as u64).rotate_left(32);
+        let mut fs = TiFs {
             client,
             pd_endpoints: pd_endpoints.clone().into_iter().map(Into::into).collect(),
             config: cfg,
@@ -65,29 +316,384 @@ impl TiFs {
                 .iter()
                 .find(|option| matches!(option, MountOption::DirectIO))
                 .is_some(),
-            block_size: options
+            block_size,
+            chaos: options.iter().find_map(|option| {
+                if let MountOption::Chaos(chaos) = option {
+                    Some(chaos.clone())
+                } else {
+                    None
+                }
+            }),
+            journal: options
+                .iter()
+                .find(|option| matches!(option, MountOption::Journal))
+                .is_some(),
+            mount_id,
+            disk_cache,
+            delegation: options
+                .iter()
+                .find(|option| matches!(option, MountOption::Delegation))
+                .is_some(),
+            delegated: Mutex::new(HashMap::new()),
+            write_buffer: options
+                .iter()
+                .find(|option| matches!(option, MountOption::WriteBuffer))
+                .is_some(),
+            buffered: Mutex::new(HashMap::new()),
+            lost_writes: Mutex::new(HashMap::new()),
+            flushing: Mutex::new(HashMap::new()),
+            held_safepoint: Mutex::new(None),
+            readdir_isolation: options
+                .iter()
+                .find_map(|option| {
+                    if let MountOption::ReadDir(isolation) = option {
+                        Some(*isolation)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default(),
+            dir_streams: Mutex::new(HashMap::new()),
+            next_dir_handle: AtomicU64::new(1),
+            versions: Mutex::new(VirtualInodes::default()),
+            entry_map: Arc::new(Mutex::new(store)),
+            store_file,
+            saved_version,
+            write_back,
+            write_back_lock: AsyncMutex::new(()),
+            root_uid: options.iter().find_map(|option| {
+                if let MountOption::RootUid(uid) = option {
+                    Some(*uid)
+                } else {
+                    None
+                }
+            }),
+            root_gid: options.iter().find_map(|option| {
+                if let MountOption::RootGid(gid) = option {
+                    Some(*gid)
+                } else {
+                    None
+                }
+            }),
+            root_mode: options.iter().find_map(|option| {
+                if let MountOption::RootMode(mode) = option {
+                    Some(*mode)
+                } else {
+                    None
+                }
+            }),
+            pins: Mutex::new(PinCache::default()),
+            inode_pool: Arc::new(InodePool::default()),
+            atimes: Arc::new(AtimeBatch::default()),
+            lookups: Arc::new(LookupCounts::new(mount_id)),
+            inode_cache: options.iter().find_map(|option| {
+                if let MountOption::InodeCache(ttl) = option {
+                    Some(Arc::new(InodeCache::new(ttl.0)))
+                } else {
+                    None
+                }
+            }),
+            inode_reuse: options
+                .iter()
+                .find(|option| matches!(option, MountOption::InodeReuse))
+                .is_some(),
+            stats: Mutex::new(VolumeStats::default()),
+            retry_warn: options
+                .iter()
+                .find_map(|option| {
+                    if let MountOption::RetryWarn(retries) = option {
+                        Some(*retries)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(Self::DEFAULT_RETRY_WARN),
+            fifo_passthrough: options
+                .iter()
+                .find(|option| matches!(option, MountOption::FifoPassthrough))
+                .is_some(),
+            no_inline: options
+                .iter()
+                .find(|option| matches!(option, MountOption::NoInline))
+                .is_some(),
+            contain_symlinks: options
+                .iter()
+                .find(|option| matches!(option, MountOption::ContainSymlinks))
+                .is_some(),
+            hashed_names: options
+                .iter()
+                .find(|option| matches!(option, MountOption::HashedNames))
+                .is_some(),
+            eof_revalidate: options
+                .iter()
+                .find(|option| matches!(option, MountOption::EofRevalidate))
+                .is_some(),
+            revalidate: options.iter().find_map(|option| {
+                if let MountOption::Revalidate(interval) = option {
+                    Some(interval.0)
+                } else {
+                    None
+                }
+            }),
+            denied: options
+                .iter()
+                .find_map(|option| {
+                    if let MountOption::Deny(ops) = option {
+                        Some(ops.clone())
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default(),
+            posix: options
                 .iter()
                 .find_map(|option| {
-                    if let MountOption::BlkSize(size) = option {
-                        Some(size << 10)
+                    if let MountOption::Posix(posix) = option {
+                        Some(*posix)
                     } else {
                         None
                     }
                 })
-                .unwrap_or(Self::DEFAULT_BLOCK_SIZE),
-            entry_map: Arc::new(Mutex::new(BTreeMap::new())),
+                .unwrap_or_default(),
+            commit_health: options.iter().find_map(|option| {
+                if let MountOption::DegradeAfter(interval) = option {
+                    Some(CommitHealth::new(interval.0))
+                } else {
+                    None
+                }
+            }),
+            flush_on_close: options
+                .iter()
+                .find(|option| matches!(option, MountOption::FlushOnClose))
+                .is_some(),
+            scan_page,
+            write_gate: WriteGate::default(),
+            frozen: AtomicU64::new(0),
+        };
+        let read_only = options.iter().any(|option| *option == MountOption::RO);
+        fs.check_features(read_only).await?;
+        fs.apply_volume_config().await?;
+        Ok(fs)
+    }
+
+    /// Refuse to mount a volume read-write if it requires features unknown to this client.
+    async fn check_features(&self, read_only: bool) -> Result<()> {
+        let features = self
+            .spin_no_delay_local(move |_, txn| {
+                Box::pin(async move {
+                    Ok(txn
+                        .read_meta()
+                        .await?
+                        .map(|meta| meta.unsupported_features())
+                        .unwrap_or_default())
+                })
+            })
+            .await?;
+        if features == 0 {
+            Ok(())
+        } else if read_only {
+            warn!(
+                "volume requires features({:#x}) unknown to this client, mounted read-only",
+                features
+            );
+            Ok(())
+        } else {
+            Err(FsError::UnsupportedFeatures { features })
+        }
+    }
+
+    /// Turn on the options the volume turns on, and check that no option the volume turns
+    /// off is passed, see `VolumeConfig`.
+    async fn apply_volume_config(&mut self) -> Result<()> {
+        let config = self.volume_config().await?;
+        self.journal = config.resolve("journal", self.journal)?;
+        self.inode_reuse = config.resolve("inode_reuse", self.inode_reuse)?;
+        self.no_inline = config.resolve("no_inline", self.no_inline)?;
+        // entries must be keyed alike by every mount, `-o hashed_names` only initializes a volume
+        self.hashed_names = match config.hashed_names {
+            Some(hashed) => hashed,
+            None if self.hashed_names && self.has_meta().await? => {
+                return Err(FsError::VolumeOption {
+                    name: "hashed_names",
+                })
+            }
+            None => self.hashed_names,
+        };
+        Ok(())
+    }
+
+    /// The options of the volume, all unset before it is initialized.
+    pub async fn volume_config(&self) -> Result<VolumeConfig> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                Ok(txn
+                    .read_meta()
+                    .await?
+                    .map(|meta| meta.config)
+                    .unwrap_or_default())
+            })
+        })
+        .await
+    }
+
+    /// Replace the options of the volume, which take effect for mounts made afterwards.
+    pub async fn set_volume_config(&self, config: VolumeConfig) -> Result<()> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let mut meta = txn
+                    .read_meta()
+                    .await?
+                    .unwrap_or_else(|| Meta::new(txn.block_size()));
+                if config.hashed_names != meta.config.hashed_names && txn.has_index().await? {
+                    return Err(FsError::HashedNamesChange);
+                }
+                meta.config = config;
+                if config != VolumeConfig::UNSET {
+                    meta.features |= feature::VOLUME_CONFIG;
+                }
+                if config.hashed_names == Some(true) {
+                    meta.features |= feature::HASHED_NAMES;
+                }
+                txn.save_meta(&meta).await
+            })
         })
+        .await
     }
 
     async fn process_txn<F, T>(&self, txn: &mut Txn, f: F) -> Result<T>
     where
         T: 'static + Send,
         F: for<'a> FnOnce(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
+    {
+        let result = match f(self, txn).await {
+            Ok(v) => txn.save_usage().await.map(|_| v),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(v) => {
+                let _writing = if txn.writes_volume() {
+                    let gate = self.write_gate.enter().await;
+                    // the freeze is kept in the volume, so commits of mounts which have not
+                    // polled it yet and of admin commands are held as well
+                    if let Err(err) = txn.ensure_not_frozen().await {
+                        txn.rollback().await?;
+                        return Err(err);
+                    }
+                    Some(gate)
+                } else {
+                    None
+                };
+                self.commit_txn(txn).await?;
+                trace!("transaction committed");
+                self.invalidate_pinned(txn.take_touched());
+                if let Some(reserved) = txn.take_reserved() {
+                    self.inode_pool.refill(reserved);
+                }
+                self.atimes.settle(&txn.take_saved_atimes());
+                txn.settle_inode_cache();
+                Ok(v)
+            }
+            Err(e) => {
+                txn.rollback().await?;
+                debug!("transaction rollbacked");
+                Err(e)
+            }
+        }
+    }
+
+    /// Commit `txn`, recording the outcome if the mount degrades to read-only. A commit not
+    /// finished within the window is given up, TiKV commits or rolls it back once its locks
+    /// are resolved.
+    async fn commit_txn(&self, txn: &mut Txn) -> Result<()> {
+        let health = match &self.commit_health {
+            Some(health) => health,
+            None => {
+                txn.commit().await?;
+                return Ok(());
+            }
+        };
+        let window = health.window();
+        let result = match timeout(window, txn.commit()).await {
+            Ok(result) => result.map(|_| ()).map_err(FsError::from),
+            Err(_) => Err(FsError::CommitTimeout { window }),
+        };
+        match &result {
+            Ok(()) | Err(FsError::KeyError { .. }) => health.succeeded(),
+            Err(_) => health.failed(),
+        }
+        result
+    }
+
+    async fn with_optimistic<F, T>(&self, f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnOnce(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
+    {
+        let mut txn = Txn::begin_optimistic(&self.client, self.block_size)
+            .await?
+            .with_inode_pool(self.inode_pool.clone())
+            .with_inode_reuse(self.inode_reuse)
+            .with_inline_data(!self.no_inline)
+            .with_hashed_names(self.hashed_names)
+            .with_atime_batch(self.atimes.clone())
+            .with_posix(self.posix)
+            .with_scan_page(self.scan_page)
+            .with_lookups(self.lookups.clone())
+            .with_inode_cache(self.inode_cache.clone());
+        self.process_txn(&mut txn, f).await
+    }
+
+    pub(crate) async fn spin<F, T>(&self, delay: Option<Duration>, mut f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnMut(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
+    {
+        loop {
+            match self.with_optimistic(&mut f).await {
+                Ok(v) => break Ok(v),
+                Err(FsError::KeyError { msg, key }) => {
+                    let class = KeyClass::of_conflict(&msg, key.as_deref());
+                    OpStats::update(|stats| stats.retry(class));
+                    if let Some(time) = delay {
+                        sleep(time).await;
+                    }
+                }
+                // held until the volume is thawed or the freeze expires
+                Err(FsError::Frozen { id }) => {
+                    trace!("commit held by freeze({})", id);
+                    sleep(FREEZE_POLL_INTERVAL).await;
+                }
+                Err(err) => break Err(err),
+            }
+        }
+    }
+
+    async fn process_txn_local<F, T>(&self, txn: &mut LocalTxn, f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnOnce(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
     {
         match f(self, txn).await {
             Ok(v) => {
+                let _writing = if txn.writes_volume() {
+                    let gate = self.write_gate.enter().await;
+                    // the freeze is kept in the volume, so commits of mounts which have not
+                    // polled it yet and of admin commands are held as well
+                    if let Err(err) = txn.ensure_not_frozen().await {
+                        txn.rollback().await?;
+                        return Err(err);
+                    }
+                    Some(gate)
+                } else {
+                    None
+                };
                 txn.commit().await?;
                 trace!("transaction committed");
+                self.invalidate_pinned(txn.take_touched());
+                if let Some(reserved) = txn.take_reserved() {
+                    self.inode_pool.refill(reserved);
+                }
+                self.atimes.settle(&txn.take_saved_atimes());
                 Ok(v)
             }
             Err(e) => {
@@ -98,158 +704,2169 @@ impl TiFs {
         }
     }
 
-    async fn with_optimistic<F, T>(&self, f: F) -> Result<T>
-    where
-        T: 'static + Send,
-        F: for<'a> FnOnce(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
-    {
-        let mut txn = Txn::begin_optimistic(&self.client, self.block_size).await?;
-        self.process_txn(&mut txn, f).await
+    /// Drop the inodes changed by `op` of another mount from the inode cache.
+    pub fn invalidate_cached(&self, op: &JournalOp) {
+        let cache = match &self.inode_cache {
+            Some(cache) => cache,
+            None => return,
+        };
+        cache.invalidate(op.ino());
+        match op {
+            JournalOp::Create { parent, .. } | JournalOp::Unlink { parent, .. } => {
+                cache.invalidate(*parent)
+            }
+            JournalOp::Link { newparent, .. } => cache.invalidate(*newparent),
+            JournalOp::Rename {
+                parent, newparent, ..
+            } => {
+                cache.invalidate(*parent);
+                cache.invalidate(*newparent);
+            }
+            JournalOp::Write { .. } | JournalOp::SetAttr { .. } => (),
+        }
+    }
+
+    fn invalidate_pinned(&self, touched: Touched) {
+        if !touched.is_empty() {
+            self.pins.lock().unwrap().invalidate(touched);
+        }
+    }
+
+    async fn with_optimistic_local<F, T>(&self, f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnOnce(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
+    {
+        let mut local_txn = LocalTxn::begin_optimistic(self.entry_map.clone(), self.block_size)
+            .await?
+            .with_inode_pool(self.inode_pool.clone())
+            .with_inode_reuse(self.inode_reuse)
+            .with_inline_data(!self.no_inline)
+            .with_hashed_names(self.hashed_names)
+            .with_atime_batch(self.atimes.clone())
+            .with_posix(self.posix)
+            .with_lookups(self.lookups.clone());
+        self.process_txn_local(&mut local_txn, f).await
+    }
+
+    async fn spin_local<F, T>(&self, delay: Option<Duration>, mut f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnMut(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
+    {
+        loop {
+            match self.with_optimistic_local(&mut f).await {
+                Ok(v) => break Ok(v),
+                Err(FsError::KeyError { msg, key }) => {
+                    let class = KeyClass::of_conflict(&msg, key.as_deref());
+                    OpStats::update(|stats| stats.retry(class));
+                    if let Some(time) = delay {
+                        sleep(time).await;
+                    }
+                }
+                // held until the volume is thawed or the freeze expires
+                Err(FsError::Frozen { id }) => {
+                    trace!("commit held by freeze({})", id);
+                    sleep(FREEZE_POLL_INTERVAL).await;
+                }
+                Err(err) => break Err(err),
+            }
+        }
+    }
+
+    #[cfg(feature = "kv_store")]
+    async fn spin_no_delay_local<F, T>(&self, f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnMut(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
+    {
+        self.spin(None, f).await
+    }
+
+    #[cfg(feature = "mem_store")]
+    async fn spin_no_delay_local<F, T>(&self, f: F) -> Result<T>
+    where
+        T: 'static + Send,
+        F: for<'a> FnMut(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
+    {
+        self.spin_local(None, f).await
+    }
+
+    pub(crate) async fn read_dir(&self, ino: u64) -> Result<Directory> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_dir(ino)))
+            .await
+    }
+
+    /// Change the owner and mode of the root directory of an initialized volume, returns its
+    /// new attributes.
+    pub async fn set_root_attr(
+        &self,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        mode: Option<FileMode>,
+    ) -> Result<FileAttr> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let mut inode = txn.read_inode(ROOT_INODE).await?;
+                if let Some(uid) = uid {
+                    inode.uid = uid;
+                }
+                if let Some(gid) = gid {
+                    inode.gid = gid;
+                }
+                if let Some(mode) = mode {
+                    inode.perm = mode.0;
+                }
+                inode.ctime = SystemTime::now();
+                txn.save_inode(&inode).await?;
+                Ok(inode.file_attr)
+            })
+        })
+        .await
+    }
+
+    /// Change the owner, group or mode of `ino` and, if `recursive`, of everything under it,
+    /// batching inodes into transactions instead of serving a setattr per file. Returns the
+    /// number of inodes changed.
+    pub async fn chown_tree(
+        &self,
+        ino: u64,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        mode: Option<FileMode>,
+        recursive: bool,
+    ) -> Result<u64> {
+        self.ensure_thawed().await?;
+        let mut changed = 0;
+        let mut pending = vec![ino];
+        let mut dirs = Vec::new();
+        loop {
+            if pending.is_empty() {
+                match dirs.pop() {
+                    Some(dir) => {
+                        pending.extend(self.read_dir(dir).await?.into_iter().map(|item| item.ino))
+                    }
+                    None => return Ok(changed),
+                }
+                continue;
+            }
+            let inos = pending.split_off(pending.len().saturating_sub(Self::CHOWN_BATCH));
+            let (count, subdirs) = self.chown_inodes(inos, uid, gid, mode).await?;
+            changed += count;
+            if recursive {
+                dirs.extend(subdirs);
+            }
+        }
+    }
+
+    /// Change the inodes in one transaction, returns how many are changed and which of them
+    /// are directories. Inodes removed in the meantime are skipped.
+    async fn chown_inodes(
+        &self,
+        inos: Vec<u64>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        mode: Option<FileMode>,
+    ) -> Result<(u64, Vec<u64>)> {
+        self.spin_no_delay_local(move |fs, txn| {
+            let inos = inos.clone();
+            Box::pin(async move {
+                let mut changed = 0;
+                let mut dirs = Vec::new();
+                for ino in inos {
+                    let mut inode = match txn.read_inode(ino).await {
+                        Ok(inode) => inode,
+                        Err(FsError::InodeNotFound { inode: _ }) => continue,
+                        Err(err) => return Err(err),
+                    };
+                    if let Some(uid) = uid {
+                        inode.uid = uid;
+                    }
+                    if let Some(gid) = gid {
+                        inode.gid = gid;
+                    }
+                    if fs.posix.is_strict() && (uid.is_some() || gid.is_some()) {
+                        inode.kill_suid();
+                    }
+                    if let Some(mode) = mode {
+                        inode.perm = mode.0;
+                    }
+                    inode.ctime = SystemTime::now();
+                    txn.save_inode(&inode).await?;
+                    if fs.journal {
+                        txn.append_journal(fs.mount_id, JournalOp::SetAttr { ino, size: None })
+                            .await?;
+                    }
+                    if inode.kind == FileType::Directory {
+                        dirs.push(ino);
+                    }
+                    changed += 1;
+                }
+                Ok((changed, dirs))
+            })
+        })
+        .await
+    }
+
+    /// Move a file or a whole directory tree to `newparent`, replacing the entry named `newname`
+    /// if it exists, as tooling would by `rename`.
+    pub async fn move_subtree(
+        &self,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+    ) -> Result<()> {
+        Self::check_file_name(name)?;
+        Self::check_file_name(newname)?;
+        self.move_entry(0, parent, name.into(), newparent, newname.into(), false)
+            .await
+    }
+
+    /// Move the entry `name` of `parent` to `newname` of `newparent` in one transaction.
+    ///
+    /// Every key of a file but its index is keyed by its own inode number: the inode, blocks
+    /// and handlers. The index is keyed by the inode number of the parent and the name. So a
+    /// move writes the index keys of the old and the new entry, the two parent directories
+    /// and the inode of the moved file, however large the tree below a moved directory is;
+    /// its descendants keep their keys and their parent pointers. Only the ancestors of
+    /// `newparent` are read, to refuse moving a directory into its own subtree.
+    async fn move_entry(
+        &self,
+        uid: u32,
+        parent: u64,
+        raw_name: ByteString,
+        newparent: u64,
+        new_raw_name: ByteString,
+        noreplace: bool,
+    ) -> Result<()> {
+        self.spin_no_delay_local(move |fs, txn| {
+            let name = raw_name.clone();
+            let new_name = new_raw_name.clone();
+            Box::pin(async move {
+                if noreplace && txn.get_index(newparent, new_name.clone()).await?.is_some() {
+                    return Err(FsError::FileExist {
+                        file: new_name.to_string(),
+                    });
+                }
+                let inode = txn
+                    .rename(parent, name.clone(), newparent, new_name.clone(), uid)
+                    .await?;
+                if fs.journal {
+                    txn.append_journal(
+                        fs.mount_id,
+                        JournalOp::Rename {
+                            parent,
+                            name: name.to_string(),
+                            newparent,
+                            newname: new_name.to_string(),
+                            ino: inode.ino,
+                        },
+                    )
+                    .await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Swap the entries `name` of `parent` and `newname` of `newparent` in one transaction, see
+    /// `Txn::exchange`. The journal records it as two moves, of each entry to the other.
+    async fn exchange_entries(
+        &self,
+        uid: u32,
+        parent: u64,
+        raw_name: ByteString,
+        newparent: u64,
+        new_raw_name: ByteString,
+    ) -> Result<()> {
+        self.spin_no_delay_local(move |fs, txn| {
+            let name = raw_name.clone();
+            let new_name = new_raw_name.clone();
+            Box::pin(async move {
+                let (inode, newinode) = txn
+                    .exchange(parent, name.clone(), newparent, new_name.clone(), uid)
+                    .await?;
+                if fs.journal {
+                    txn.append_journal(
+                        fs.mount_id,
+                        JournalOp::Rename {
+                            parent,
+                            name: name.to_string(),
+                            newparent,
+                            newname: new_name.to_string(),
+                            ino: inode.ino,
+                        },
+                    )
+                    .await?;
+                    txn.append_journal(
+                        fs.mount_id,
+                        JournalOp::Rename {
+                            parent: newparent,
+                            name: new_name.to_string(),
+                            newparent: parent,
+                            newname: name.to_string(),
+                            ino: newinode.ino,
+                        },
+                    )
+                    .await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Set the type of a directory entry to the type of its inode, see `fsck::repair`.
+    pub async fn retype_dir_item(&self, parent: u64, name: String, ino: u64) -> Result<bool> {
+        self.spin_no_delay_local(move |_, txn| {
+            let name = name.clone();
+            Box::pin(async move { txn.retype_dir_item(parent, &name, ino).await })
+        })
+        .await
+    }
+
+    /// Read a directory in a single transaction, without retrying on conflicts.
+    #[cfg(feature = "kv_store")]
+    async fn try_read_dir(&self, ino: u64) -> Result<Directory> {
+        self.with_optimistic(move |_, txn| Box::pin(txn.read_dir(ino)))
+            .await
+    }
+
+    #[cfg(feature = "mem_store")]
+    async fn try_read_dir(&self, ino: u64) -> Result<Directory> {
+        self.read_dir(ino).await
+    }
+
+    /// Set or remove the access or default ACL by the name of its xattr. Setting the access ACL
+    /// updates the permission bits, and a default ACL can only be set on directories.
+    async fn set_acl(&self, ino: u64, name: ByteString, acl: Option<Acl>) -> Result<()> {
+        self.spin_no_delay_local(move |_, txn| {
+            let name = name.clone();
+            let acl = acl.clone();
+            Box::pin(async move {
+                let mut inode = txn.read_inode(ino).await?;
+                inode.ensure_mutable()?;
+                if name == ACL_ACCESS_XATTR {
+                    if let Some(acl) = &acl {
+                        inode.perm = inode.perm & !0o777 | acl.perm();
+                    }
+                    inode.acl = acl.filter(|acl| !acl.is_minimal());
+                } else {
+                    if inode.kind != FileType::Directory {
+                        return Err(FsError::NotDirectory { ino });
+                    }
+                    inode.default_acl = acl;
+                }
+                inode.ctime = SystemTime::now();
+                txn.save_inode(&inode).await
+            })
+        })
+        .await
+    }
+
+    /// The listing of an open directory, see `ReadDirIsolation`.
+    async fn list_dir(&self, ino: u64, fh: u64, rewind: bool) -> Result<Directory> {
+        let last = self
+            .dir_streams
+            .lock()
+            .unwrap()
+            .get(&fh)
+            .filter(|stream| stream.ino == ino)
+            .and_then(|stream| stream.listing.clone());
+        let directory = match (self.readdir_isolation, last) {
+            (ReadDirIsolation::Snapshot, Some(directory)) if !rewind => return Ok(directory),
+            (ReadDirIsolation::Relaxed, Some(directory)) => match self.try_read_dir(ino).await {
+                Err(FsError::KeyError { msg, key: _ }) => {
+                    debug!("serve the last listing of dir({}): {}", ino, msg);
+                    return Ok(directory);
+                }
+                result => result?,
+            },
+            _ => self.read_dir(ino).await?,
+        };
+        match self.dir_streams.lock().unwrap().get_mut(&fh) {
+            Some(stream) if stream.ino == ino => stream.listing = Some(directory.clone()),
+            _ => (),
+        }
+        Ok(directory)
+    }
+
+    pub(crate) async fn read_inode(&self, ino: u64) -> Result<FileAttr> {
+        let ino = self
+            .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+            .await?;
+        Ok(ino.file_attr)
+    }
+
+    async fn setlkw(&self, ino: u64, lock_owner: u64, typ: i32) -> Result<bool> {
+        loop {
+            let res = self
+                .spin_no_delay_local(move |_, txn| {
+                    Box::pin(async move {
+                        let mut inode = txn.read_inode(ino).await?;
+                        match typ {
+                            F_WRLCK => {
+                                if inode.lock_state.owner_set.len() > 1 {
+                                    return Ok(false);
+                                }
+                                if inode.lock_state.owner_set.is_empty() {
+                                    inode.lock_state.lk_type = F_WRLCK;
+                                    inode.lock_state.owner_set.insert(lock_owner);
+                                    txn.save_inode(&inode).await?;
+                                    return Ok(true);
+                                }
+                                if inode.lock_state.owner_set.get(&lock_owner) == Some(&lock_owner)
+                                {
+                                    inode.lock_state.lk_type = F_WRLCK;
+                                    txn.save_inode(&inode).await?;
+                                    return Ok(true);
+                                }
+                                Err(FsError::InvalidLock)
+                            }
+                            F_RDLCK => {
+                                if inode.lock_state.lk_type == F_WRLCK {
+                                    return Ok(false);
+                                } else {
+                                    inode.lock_state.lk_type = F_RDLCK;
+                                    inode.lock_state.owner_set.insert(lock_owner);
+                                    txn.save_inode(&inode).await?;
+                                    return Ok(true);
+                                }
+                            }
+                            _ => return Err(FsError::InvalidLock),
+                        }
+                    })
+                })
+                .await?;
+            if res {
+                break;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Check the metadata of the volume and make the root directory if it doesn't exist.
+    pub async fn init_volume(&self, gid: u32, uid: u32, mode: FileMode) -> Result<()> {
+        self.spin_no_delay_local(move |fs, txn| {
+            Box::pin(async move {
+                info!("initializing tifs on {:?} ...", &fs.pd_endpoints);
+                if let Some(meta) = txn.read_meta().await? {
+                    if meta.block_size != txn.block_size() {
+                        let err = FsError::block_size_conflict(meta.block_size, txn.block_size());
+                        error!("{}", err);
+                        return Err(err);
+                    }
+                }
+
+                let root_inode = txn.read_inode(ROOT_INODE).await;
+                if let Err(FsError::InodeNotFound { inode: _ }) = root_inode {
+                    let attr = txn
+                        .mkdir(
+                            0,
+                            Default::default(),
+                            make_mode(FileType::Directory, mode.0),
+                            gid,
+                            uid,
+                        )
+                        .await?;
+                    debug!("make root directory {:?}", &attr);
+                    Ok(())
+                } else {
+                    root_inode.map(|_| ())
+                }
+            })
+        })
+        .await
+    }
+
+    /// Resolve a path inside the volume, relative to its root, into an inode number, following
+    /// symlinks, see `Follow`.
+    pub async fn resolve_path(&self, path: &str) -> Result<u64> {
+        self.resolve_path_with(path, Follow::default()).await
+    }
+
+    /// Resolve a path inside the volume, relative to its root, into an inode number, with the
+    /// symlinks on the way followed as `follow` tells. `..` goes back to the directory the
+    /// resolution came from, and stays at the root of the volume.
+    pub async fn resolve_path_with(&self, path: &str, follow: Follow) -> Result<u64> {
+        let path = path.to_owned();
+        self.spin_no_delay_local(move |_, txn| {
+            let path = path.clone();
+            Box::pin(async move {
+                let mut pending = symlink::pending_names(path.as_bytes());
+                // the directories walked into, for `..`
+                let mut dirs = vec![ROOT_INODE];
+                let mut follows = 0;
+                while let Some(name) = pending.pop() {
+                    if name == b".." {
+                        if dirs.len() > 1 {
+                            dirs.pop();
+                        }
+                        continue;
+                    }
+                    let dir = *dirs.last().unwrap();
+                    let name = String::from_utf8(name).map_err(|_| FsError::InvalidStr)?;
+                    let ino = txn.lookup(dir, name.into()).await?;
+                    let inode = txn.read_inode(ino).await?;
+                    if inode.kind == FileType::Symlink {
+                        let max = match follow {
+                            Follow::Never if pending.is_empty() => {
+                                dirs.push(ino);
+                                continue;
+                            }
+                            Follow::Never => 0,
+                            Follow::Within(max) => max,
+                        };
+                        if follows == max {
+                            return Err(FsError::SymlinkLoop { path });
+                        }
+                        follows += 1;
+                        let target = txn.read_link(ino).await?;
+                        if target.starts_with(b"/") {
+                            dirs.truncate(1);
+                        }
+                        pending.extend(symlink::pending_names(&target));
+                        continue;
+                    }
+                    if !pending.is_empty() && inode.kind != FileType::Directory {
+                        return Err(FsError::NotDirectory { ino });
+                    }
+                    dirs.push(ino);
+                }
+                Ok(*dirs.last().unwrap())
+            })
+        })
+        .await
+    }
+
+    /// Look up every component of `path` under `parent` in one transaction, returning the
+    /// entries of all of them, like the lookups of the kernel walking it. Empty components and
+    /// `.` are skipped, `..` is not supported.
+    pub async fn lookup_path(&self, parent: u64, path: &str) -> Result<Vec<Entry>> {
+        let names: Vec<&str> = path
+            .split('/')
+            .filter(|name| !name.is_empty() && *name != ".")
+            .collect();
+        if names.contains(&"..") {
+            return Err(FsError::InvalidStr);
+        }
+        let names: Vec<ByteString> = names.into_iter().map(Into::into).collect();
+        let inodes = self
+            .spin_no_delay_local(move |_, txn| Box::pin(txn.lookup_path(parent, names.clone())))
+            .await?;
+        Ok(inodes.into_iter().map(Entry::from).collect())
+    }
+
+    /// Whether the kernel must not cache `attr`, see `-o eof_revalidate`. The kernel fetches
+    /// attributes it does not cache before a read beyond the size it knows, so appends of other
+    /// mounts are read instead of ending at a stale EOF.
+    fn revalidates(&self, attr: &FileAttr) -> bool {
+        self.eof_revalidate && attr.kind == FileType::RegularFile
+    }
+
+    /// How long the kernel caches `attr`, forever if it is not bounded by `eof_revalidate`,
+    /// `revalidate` or a strict `posix`.
+    fn reply_ttl(&self, attr: &FileAttr) -> Option<Duration> {
+        if self.revalidates(attr) || self.posix.is_strict() {
+            Some(Duration::default())
+        } else {
+            self.revalidate
+        }
+    }
+
+    fn revalidated_entry(&self, entry: Entry) -> Entry {
+        match self.reply_ttl(&entry.stat) {
+            Some(ttl) => entry.cached_for(ttl),
+            None => entry,
+        }
+    }
+
+    /// The entry `name` of `parent`, also of the virtual directories of snapshots and
+    /// versions.
+    async fn lookup_entry(&self, parent: u64, name: ByteString) -> Result<Entry> {
+        if name == "." || name == ".." {
+            return self.lookup_export(parent, name == "..").await;
+        }
+        if parent == ROOT_INODE && name == SNAPSHOTS_DIR {
+            return Ok(Entry::new(self.snapshots_attr().await?, 0));
+        }
+        if parent == SNAPSHOTS_INODE {
+            let ts = parse_timestamp(&name).ok_or_else(|| FsError::FileNotFound {
+                file: name.to_string(),
+            })?;
+            self.hold_safepoint(ts).await;
+            let root = SnapshotView::at(&self.client, ts, self.block_size)
+                .read_inode(ROOT_INODE)
+                .await?;
+            return Ok(Entry::new(self.version_attr(root, ts), 0));
+        }
+        if let Some(version) = self.resolve_version(parent) {
+            let (parent, ts) = version?;
+            return self.lookup_version(parent, &name, ts).await;
+        }
+
+        let pinned = self.pins.lock().unwrap().lookup(parent, &name);
+        if let Some((attr, generation)) = pinned.filter(|(attr, _)| !self.revalidates(attr)) {
+            return Ok(self.revalidated_entry(Entry::new(attr, generation)));
+        }
+        let versioned = parse_versioned_name(&name).map(|(base, ts)| (base.to_owned(), ts));
+        let result = self
+            .spin_no_delay_local(move |fs, txn| {
+                let name = name.clone();
+                Box::pin(async move {
+                    let ino = txn.lookup(parent, name).await?;
+                    Ok(fs.revalidated_entry(Entry::from(txn.read_inode(ino).await?)))
+                })
+            })
+            .await;
+        match (result, versioned) {
+            (Err(FsError::FileNotFound { file: _ }), Some((base, ts))) => {
+                self.lookup_version(parent, &base, ts).await
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Count the reference the kernel takes to the inode of an entry replied to it.
+    fn counted(&self, entry: Entry) -> Entry {
+        self.lookups.add(entry.stat.ino);
+        entry
+    }
+
+    fn revalidated_attr(&self, attr: Attr) -> Attr {
+        match self.reply_ttl(&attr.attr) {
+            Some(ttl) => attr.cached_for(ttl),
+            None => attr,
+        }
+    }
+
+    /// Path of an inode relative to the root of the volume, resolved by the parent pointers.
+    /// A file with hard links resolves to the name it is last linked by.
+    pub async fn path_of(&self, ino: u64) -> Result<String> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let mut names = Vec::new();
+                let mut current = ino;
+                while current != ROOT_INODE {
+                    let inode = txn.read_inode(current).await?;
+                    if inode.parent < ROOT_INODE || names.len() > Self::MAX_PATH_DEPTH {
+                        return Err(FsError::NoPath { ino });
+                    }
+                    let name = txn
+                        .read_dir(inode.parent)
+                        .await?
+                        .into_iter()
+                        .find(|item| item.ino == current)
+                        .ok_or(FsError::NoPath { ino })?
+                        .name;
+                    names.push(name);
+                    current = inode.parent;
+                }
+                names.reverse();
+                Ok(format!("/{}", names.join("/")))
+            })
+        })
+        .await
+    }
+
+    /// Serve the lookups of `.` and `..` by which the kernel finds inodes of file handles it
+    /// does not hold anymore, see `FUSE_EXPORT_SUPPORT`.
+    async fn lookup_export(&self, ino: u64, parent: bool) -> Result<Entry> {
+        if ino == SNAPSHOTS_INODE || self.resolve_version(ino).is_some() {
+            // virtual inodes live as long as the mount, they are looked up by name again
+            return Err(FsError::Stale { ino });
+        }
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let inode = match txn.read_inode(ino).await {
+                    Err(FsError::InodeNotFound { inode: _ }) => return Err(FsError::Stale { ino }),
+                    result => result?,
+                };
+                if !parent || ino == ROOT_INODE {
+                    return Ok(Entry::from(inode));
+                }
+                if inode.parent < ROOT_INODE {
+                    return Err(FsError::Stale { ino });
+                }
+                match txn.read_inode(inode.parent).await {
+                    Err(FsError::InodeNotFound { inode: _ }) => Err(FsError::Stale { ino }),
+                    result => Ok(Entry::from(result?)),
+                }
+            })
+        })
+        .await
+    }
+
+    /// Flush the buffered writes of a delegated file after the flushes of it in progress, and
+    /// return the delegation if `release`. The writes are dropped if they cannot be committed
+    /// for good, see `lost_writes`.
+    async fn flush_delegated(&self, ino: u64, release: bool) -> Result<()> {
+        let lock = self.flush_lock(ino);
+        let result = {
+            let _flushing = lock.lock().await;
+            self.commit_delegated(ino, release).await
+        };
+        self.unlock_flush(ino, lock);
+        result
+    }
+
+    async fn commit_delegated(&self, ino: u64, release: bool) -> Result<()> {
+        loop {
+            let writes = match self.delegated.lock().unwrap().get_mut(&ino) {
+                Some(buffer) => buffer.take(),
+                None => return Ok(()),
+            };
+            if writes.is_empty() && !release {
+                return Ok(());
+            }
+
+            let pending = writes.clone();
+            let result = self
+                .spin_no_delay_local(move |fs, txn| {
+                    let pending = pending.clone();
+                    Box::pin(async move {
+                        for (_, offset, data) in pending {
+                            let len = txn.write_data(ino, offset as u64, data).await?;
+                            if fs.journal {
+                                txn.append_journal(
+                                    fs.mount_id,
+                                    JournalOp::Write {
+                                        ino,
+                                        offset,
+                                        len: len as u64,
+                                    },
+                                )
+                                .await?;
+                            }
+                        }
+                        if release {
+                            let mut inode = match txn.read_inode(ino).await {
+                                Ok(inode) => inode,
+                                // a removed file has no delegation to return
+                                Err(FsError::InodeNotFound { inode: _ }) => return Ok(()),
+                                Err(err) => return Err(err),
+                            };
+                            if matches!(&inode.delegation, Some(delegation) if delegation.holder == fs.mount_id)
+                            {
+                                inode.delegation = None;
+                                txn.save_inode(&inode).await?;
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .await;
+
+            let mut delegated = self.delegated.lock().unwrap();
+            match result {
+                // trying again cannot help, the delegation is still returned without them
+                Err(err) if !err.is_transient() && !writes.is_empty() => {
+                    warn!(
+                        "drop delegated writes of inode({}) failing for good: {}",
+                        ino, err
+                    );
+                    if let Some(buffer) = delegated.get_mut(&ino) {
+                        buffer.take();
+                    }
+                    self.lost_writes.lock().unwrap().insert(ino, err);
+                    if !release {
+                        return Ok(());
+                    }
+                }
+                // no other flush of `ino` commits meanwhile, so they go back before newer writes
+                Err(err) => {
+                    if let Some(buffer) = delegated.get_mut(&ino) {
+                        buffer.restore(writes);
+                    }
+                    return Err(err);
+                }
+                Ok(()) if !release => return Ok(()),
+                // writes may be buffered while the delegation is returned
+                Ok(()) => match delegated.get(&ino) {
+                    Some(buffer) if !buffer.is_empty() => continue,
+                    _ => {
+                        delegated.remove(&ino);
+                        debug!("return delegation of inode({})", ino);
+                        return Ok(());
+                    }
+                },
+            }
+        }
+    }
+
+    /// The lock of the flushes of `ino`, held from taking the buffered writes until they are
+    /// committed or put back, so a flush never lands before an older one.
+    fn flush_lock(&self, ino: u64) -> Arc<AsyncMutex<()>> {
+        self.flushing
+            .lock()
+            .unwrap()
+            .entry(ino)
+            .or_default()
+            .clone()
+    }
+
+    /// Give back the lock of the flushes of `ino`, forgetting it if nobody else waits on it.
+    fn unlock_flush(&self, ino: u64, lock: Arc<AsyncMutex<()>>) {
+        drop(lock);
+        let mut flushing = self.flushing.lock().unwrap();
+        // the locks are only shared under `flushing`, so nobody can take it again
+        if matches!(flushing.get(&ino), Some(lock) if Arc::strong_count(lock) == 1) {
+            flushing.remove(&ino);
+        }
+    }
+
+    /// Buffer a write to `ino` in `buffers`, checked by the handler check kept by the buffer
+    /// or by a new one. Returns whether the buffer is full, or `None` without a buffer of
+    /// `ino` if not `create`.
+    async fn buffer_write(
+        &self,
+        buffers: &Mutex<HashMap<u64, WriteBuffer>>,
+        create: bool,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: Bytes,
+    ) -> Result<Option<bool>> {
+        let checked = match buffers.lock().unwrap().get(&ino) {
+            Some(buffer) => buffer.checked(fh),
+            None if create => None,
+            None => return Ok(None),
+        };
+        let check = match checked {
+            Some(check) => check,
+            None => {
+                // appends are resolved against the size of the file once the flushes in
+                // progress are committed
+                let lock = self.flush_lock(ino);
+                let result = {
+                    let _flushing = lock.lock().await;
+                    self.spin_no_delay_local(move |_, txn| Box::pin(txn.check_write(ino, fh)))
+                        .await
+                };
+                self.unlock_flush(ino, lock);
+                result?
+            }
+        };
+        // a frozen volume holds the writes to be buffered like the others
+        drop(self.write_gate.enter().await);
+
+        let mut buffers = buffers.lock().unwrap();
+        if !create && !buffers.contains_key(&ino) {
+            return Ok(None);
+        }
+        let buffer = buffers.entry(ino).or_default();
+        let start = check.start(offset, buffer.end())?;
+        buffer.check(fh, check);
+        buffer.push(fh, start as i64, data);
+        Ok(Some(buffer.is_full()))
+    }
+
+    /// Commit the writes buffered for `ino` by `write_buffer` in one transaction, after the
+    /// flushes of `ino` in progress. The writes are dropped if they cannot be committed for
+    /// good, see `lost_writes`.
+    async fn flush_buffered(&self, ino: u64) -> Result<()> {
+        let lock = self.flush_lock(ino);
+        let result = {
+            let _flushing = lock.lock().await;
+            self.commit_buffered(ino).await
+        };
+        self.unlock_flush(ino, lock);
+        result
+    }
+
+    async fn commit_buffered(&self, ino: u64) -> Result<()> {
+        let writes = match self.buffered.lock().unwrap().get_mut(&ino) {
+            Some(buffer) => buffer.take(),
+            None => return Ok(()),
+        };
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let pending = writes.clone();
+        let result = self
+            .spin_no_delay_local(move |fs, txn| {
+                let pending = pending.clone();
+                Box::pin(async move {
+                    for (_, offset, data) in pending {
+                        let len = txn.write_data(ino, offset as u64, data).await?;
+                        if fs.journal {
+                            txn.append_journal(
+                                fs.mount_id,
+                                JournalOp::Write {
+                                    ino,
+                                    offset,
+                                    len: len as u64,
+                                },
+                            )
+                            .await?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .await;
+
+        let mut buffered = self.buffered.lock().unwrap();
+        match result {
+            // trying again cannot help, like a removed file or one made immutable since
+            Err(err) if !err.is_transient() => {
+                warn!(
+                    "drop buffered writes of inode({}) failing for good: {}",
+                    ino, err
+                );
+                buffered.remove(&ino);
+                self.lost_writes.lock().unwrap().insert(ino, err);
+                Ok(())
+            }
+            // no other flush of `ino` commits meanwhile, the writes go back before the newer ones
+            Err(err) => {
+                if let Some(buffer) = buffered.get_mut(&ino) {
+                    buffer.restore(writes);
+                }
+                Err(err)
+            }
+            Ok(()) => {
+                if matches!(buffered.get(&ino), Some(buffer) if buffer.is_empty()) {
+                    buffered.remove(&ino);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush the writes buffered for `ino` by `write_buffer` and by its delegation, and return
+    /// the delegation if `release`.
+    async fn flush_writes(&self, ino: u64, release: bool) -> Result<()> {
+        self.flush_buffered(ino).await?;
+        self.flush_delegated(ino, release).await
+    }
+
+    /// Fail with the error which dropped the writes buffered to `ino`, once.
+    fn report_lost_writes(&self, ino: u64) -> Result<()> {
+        match self.lost_writes.lock().unwrap().remove(&ino) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Flush the writes buffered by `write_buffer` to every file.
+    async fn flush_write_buffers(&self) {
+        let inos: Vec<u64> = self.buffered.lock().unwrap().keys().copied().collect();
+        for ino in inos {
+            if let Err(err) = self.flush_buffered(ino).await {
+                warn!("fail to flush buffered writes of inode({}): {}", ino, err);
+            }
+        }
+    }
+
+    /// Flush the writes buffered by `write_buffer` periodically, so other mounts see the
+    /// writes to files kept open. It should run as long as the mount.
+    pub async fn serve_write_buffers(&self) {
+        loop {
+            sleep(WRITE_BUFFER_INTERVAL).await;
+            self.flush_write_buffers().await;
+        }
+    }
+
+    /// Renew the delegation of `ino`, or flush and return it once it is recalled.
+    async fn renew_delegation(&self, ino: u64) -> Result<()> {
+        let recalled = self
+            .spin_no_delay_local(move |fs, txn| {
+                Box::pin(async move {
+                    let mut inode = match txn.read_inode(ino).await {
+                        Ok(inode) => inode,
+                        Err(FsError::InodeNotFound { inode: _ }) => return Ok(None),
+                        Err(err) => return Err(err),
+                    };
+                    match &mut inode.delegation {
+                        Some(delegation) if delegation.holder == fs.mount_id => {
+                            if delegation.recalled {
+                                return Ok(Some(true));
+                            }
+                            if delegation.needs_renewal() {
+                                delegation.renew();
+                                txn.save_inode(&inode).await?;
+                            }
+                            Ok(Some(false))
+                        }
+                        _ => Ok(None),
+                    }
+                })
+            })
+            .await?;
+
+        match recalled {
+            Some(true) => {
+                debug!("delegation of inode({}) is recalled", ino);
+                self.flush_delegated(ino, true).await
+            }
+            Some(false) => Ok(()),
+            None => {
+                if let Some(mut buffer) = self.delegated.lock().unwrap().remove(&ino) {
+                    let lost = buffer.take();
+                    if !lost.is_empty() {
+                        warn!(
+                            "lose {} buffered writes of inode({}) with its delegation",
+                            lost.len(),
+                            ino
+                        );
+                        self.lost_writes
+                            .lock()
+                            .unwrap()
+                            .insert(ino, FsError::DelegationLost { ino });
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Save the access times recorded by reads, see `AtimeBatch`.
+    pub async fn flush_atimes(&self) -> Result<()> {
+        for chunk in self.atimes.inos().chunks(Self::ATIME_FLUSH_BATCH) {
+            let inos = chunk.to_vec();
+            self.spin_no_delay_local(move |fs, txn| {
+                let inos = inos.clone();
+                Box::pin(async move {
+                    for ino in inos {
+                        match txn.read_inode(ino).await {
+                            Ok(inode) => txn.save_inode(&inode).await?,
+                            Err(FsError::InodeNotFound { inode: _ }) => fs.atimes.discard(ino),
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Save the access times periodically, it should run as long as the mount.
+    pub async fn serve_atimes(&self) {
+        loop {
+            sleep(ATIME_FLUSH_INTERVAL).await;
+            if let Err(err) = self.flush_atimes().await {
+                warn!("fail to save access times: {}", err);
+            }
+        }
+    }
+
+    /// Keep the delegations held by this mount, it should run as long as the mount.
+    pub async fn serve_delegations(&self) {
+        loop {
+            sleep(RENEW_INTERVAL).await;
+            let inos: Vec<u64> = self.delegated.lock().unwrap().keys().copied().collect();
+            for ino in inos {
+                if let Err(err) = self.renew_delegation(ino).await {
+                    warn!("fail to renew delegation of inode({}): {}", ino, err);
+                }
+            }
+        }
+    }
+
+    /// Unlink the expired entries of the scratch directories, see `Ttl`. Returns the number of
+    /// removed entries.
+    pub async fn expire_scratch(&self) -> Result<u64> {
+        let mut removed = 0;
+        let mut from = 0;
+        let page = self.scan_page;
+        loop {
+            let dirs = self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.scratch_dirs(from, page)))
+                .await?;
+            for dir in &dirs {
+                removed += self.expire_dir(*dir).await?;
+            }
+            match dirs.last() {
+                Some(last) if dirs.len() == page as usize => from = last + 1,
+                _ => return Ok(removed),
+            }
+        }
+    }
+
+    async fn expire_dir(&self, dir: u64) -> Result<u64> {
+        let inode = match self
+            .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(dir)))
+            .await
+        {
+            Ok(inode) => inode,
+            Err(FsError::InodeNotFound { inode: _ }) => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let ttl = match inode.ttl {
+            Some(ttl) => ttl,
+            None => return Ok(0),
+        };
+        let mut removed = 0;
+        for item in self.read_dir(dir).await? {
+            let name: ByteString = item.name.into();
+            let result = self
+                .spin_no_delay_local(move |fs, txn| {
+                    let name = name.clone();
+                    Box::pin(async move {
+                        let ino = txn.lookup(dir, name.clone()).await?;
+                        let inode = txn.read_inode(ino).await?;
+                        if !ttl.is_expired(inode.mtime) {
+                            return Ok(false);
+                        }
+                        if inode.kind == FileType::Directory {
+                            txn.rmdir(dir, name.clone(), 0).await?;
+                        } else {
+                            txn.unlink(dir, name.clone(), 0).await?;
+                        }
+                        if fs.journal {
+                            txn.append_journal(
+                                fs.mount_id,
+                                JournalOp::Unlink {
+                                    parent: dir,
+                                    name: name.to_string(),
+                                    ino,
+                                },
+                            )
+                            .await?;
+                        }
+                        Ok(true)
+                    })
+                })
+                .await;
+            match result {
+                Ok(true) => removed += 1,
+                Ok(false) => (),
+                Err(err @ FsError::FileNotFound { .. })
+                | Err(err @ FsError::DirNotEmpty { .. })
+                | Err(err @ FsError::WormLocked { .. })
+                | Err(err @ FsError::Sealed { .. }) => {
+                    debug!("skip expiring an entry of dir({}): {}", dir, err)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Save the memory store to its `store_file` if it has changed since it is last saved.
+    pub async fn save_store(&self) -> Result<()> {
+        let path = match &self.store_file {
+            Some(path) if cfg!(feature = "mem_store") => path,
+            _ => return Ok(()),
+        };
+        let mut saved_version = self.saved_version.lock().await;
+        let (version, entries) = {
+            let store = self.entry_map.lock().unwrap();
+            let version = store.version();
+            if *saved_version == version {
+                return Ok(());
+            }
+            (version, store.snapshot())
+        };
+        // the image is written without holding the store, so operations go on meanwhile
+        let target = path.clone();
+        spawn_blocking(move || save_image(&entries, &target)).await?;
+        *saved_version = version;
+        debug!("saved memory store to {:?} at version {}", path, version);
+        Ok(())
+    }
+
+    /// Block size recorded in the meta of the volume on TiKV, if it is initialized.
+    async fn volume_block_size(client: &TransactionClient) -> Result<Option<u64>> {
+        let mut txn = Txn::begin_optimistic(client, Self::DEFAULT_BLOCK_SIZE).await?;
+        let meta = txn.read_meta().await?;
+        txn.commit().await?;
+        Ok(meta.map(|meta| meta.block_size))
+    }
+
+    /// Load the whole volume on TiKV into an empty memory store.
+    async fn pull_store(
+        client: &TransactionClient,
+        block_size: u64,
+        page: u32,
+        store: &mut MemStore,
+    ) -> Result<u64> {
+        let mut txn = Txn::begin_optimistic(client, block_size).await?;
+        let mut start = Key::from(Vec::new());
+        let mut count = 0;
+        loop {
+            let pairs: Vec<KvPair> = txn.scan(start.clone().., page).await?.collect();
+            let last = match pairs.last() {
+                Some(last) => last.key().clone(),
+                None => break,
+            };
+            count += pairs.len() as u64;
+            for pair in pairs {
+                store.insert(pair.key().clone(), pair.value().clone())?;
+            }
+            let mut next: Vec<u8> = last.into();
+            next.push(0);
+            start = next.into();
+        }
+        txn.commit().await?;
+        Ok(count)
+    }
+
+    /// Write the changes of the memory store back to TiKV, returns the number of written keys.
+    pub async fn write_back_store(&self) -> Result<usize> {
+        let _writing = self.write_back_lock.lock().await;
+        let mut written = 0;
+        while self.write_back {
+            let pairs = self
+                .entry_map
+                .lock()
+                .unwrap()
+                .take_dirty(Self::WRITE_BACK_BATCH_BYTES);
+            if pairs.is_empty() {
+                break;
+            }
+            let result = self
+                .spin(None, |_, txn| {
+                    let pairs = pairs.clone();
+                    Box::pin(async move {
+                        for (key, value) in pairs {
+                            match value {
+                                Some(value) => txn.put(key, value).await?,
+                                None => txn.delete(key).await?,
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .await;
+            if let Err(err) = result {
+                let keys = pairs.into_iter().map(|(key, _)| key);
+                self.entry_map.lock().unwrap().mark_dirty(keys);
+                return Err(err);
+            }
+            written += pairs.len();
+        }
+        Ok(written)
+    }
+
+    /// Write the changes back to TiKV before a close returns with `flush_on_close`, buffered
+    /// writes of delegated files are flushed on every close.
+    async fn write_back_on_close(&self) -> Result<()> {
+        if self.flush_on_close && self.write_back {
+            self.write_back_store().await?;
+        }
+        Ok(())
+    }
+
+    /// Write the changes back to TiKV periodically, it should run as long as the mount.
+    pub async fn serve_write_back(&self) {
+        loop {
+            sleep(WRITE_BACK_INTERVAL).await;
+            match self.write_back_store().await {
+                Ok(written) if written > 0 => trace!("wrote back {} keys to tikv", written),
+                Ok(_) => (),
+                Err(err) => warn!("fail to write back memory store: {}", err),
+            }
+        }
+    }
+
+    /// Save the memory store periodically, it should run as long as the mount.
+    pub async fn serve_saving(&self) {
+        loop {
+            sleep(SAVE_INTERVAL).await;
+            if let Err(err) = self.save_store().await {
+                warn!("fail to save memory store: {}", err);
+            }
+        }
+    }
+
+    /// Sweep the scratch directories periodically, it should run as long as the mount.
+    pub async fn serve_expiry(&self) {
+        loop {
+            sleep(SWEEP_INTERVAL).await;
+            match self.expire_scratch().await {
+                Ok(removed) if removed > 0 => info!("expired {} scratch entries", removed),
+                Ok(_) => (),
+                Err(err) => warn!("fail to expire scratch entries: {}", err),
+            }
+        }
+    }
+
+    /// Delete the blocks of removed files queued in the deletion queue, see `DeadExtent`.
+    /// Returns the number of deleted blocks.
+    pub async fn drain_deletions(&self) -> Result<u64> {
+        let mut deleted = 0;
+        let page = self.scan_page;
+        loop {
+            let inos = self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.dead_inodes(page)))
+                .await?;
+            if inos.is_empty() {
+                return Ok(deleted);
+            }
+            for ino in inos {
+                loop {
+                    let batch = self
+                        .spin_no_delay_local(move |_, txn| {
+                            Box::pin(txn.delete_dead_blocks(ino, DELETION_BATCH_BLOCKS))
+                        })
+                        .await?;
+                    if batch == 0 {
+                        break;
+                    }
+                    deleted += batch;
+                }
+            }
+        }
+    }
+
+    /// Drain the deletion queue periodically, it should run as long as the mount.
+    pub async fn serve_deletion(&self) {
+        loop {
+            sleep(DELETION_INTERVAL).await;
+            match self.drain_deletions().await {
+                Ok(deleted) if deleted > 0 => debug!("deleted {} blocks of removed files", deleted),
+                Ok(_) => (),
+                Err(err) => warn!("fail to drain the deletion queue: {}", err),
+            }
+        }
+    }
+
+    /// Add the counters of this mount to its row of the volume statistics, raising the
+    /// high-water marks to the current usage of the volume.
+    pub async fn persist_stats(&self) -> Result<VolumeStats> {
+        let usage = self.statfs(ROOT_INODE).await?;
+        let mut delta = std::mem::take(&mut *self.stats.lock().unwrap());
+        let now = SystemTime::now();
+        delta.mark_usage(usage.files, usage.blocks, now);
+        let mount_id = self.mount_id;
+        let result = self
+            .spin_no_delay_local(move |_, txn| {
+                Box::pin(async move {
+                    let mut stats = txn
+                        .read_volume_stats(mount_id)
+                        .await?
+                        .unwrap_or(VolumeStats {
+                            since: Some(now),
+                            ..Default::default()
+                        });
+                    stats.merge(&delta);
+                    txn.save_volume_stats(mount_id, &stats).await?;
+                    Ok(stats)
+                })
+            })
+            .await;
+        if result.is_err() {
+            // keep the counters for the next try.
+            self.stats.lock().unwrap().merge(&delta);
+        }
+        result
+    }
+
+    /// Fold the rows of the volume statistics of the mounts gone into the usage base row.
+    /// Returns the rows folded.
+    pub async fn fold_stats(&self) -> Result<usize> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let live = txn.live_clients().await?;
+                txn.fold_stats(&live).await
+            })
+        })
+        .await
+    }
+
+    /// Count the usage of a volume made by a version not counting it in the transactions,
+    /// whose usage base row is absent. Returns whether it is counted now.
+    pub async fn ensure_usage_base(&self) -> Result<bool> {
+        let counted = self
+            .spin_no_delay_local(move |_, txn| {
+                Box::pin(async move { txn.read_volume_stats(USAGE_BASE_ROW).await })
+            })
+            .await?
+            .is_some();
+        if counted {
+            return Ok(false);
+        }
+        let (files, blocks) = self.rebase_usage().await?;
+        info!("usage of the volume counted: {} files, {} blocks", files, blocks);
+        Ok(true)
+    }
+
+    /// Persist the counters of this mount periodically and fold the rows of the mounts gone,
+    /// it should run as long as the mount.
+    pub async fn serve_stats(&self) {
+        let mut based = false;
+        loop {
+            if !based {
+                match self.ensure_usage_base().await {
+                    Ok(_) => based = true,
+                    Err(err) => warn!("fail to count the usage of the volume: {}", err),
+                }
+            }
+            sleep(STATS_INTERVAL).await;
+            if let Err(err) = self.persist_stats().await {
+                warn!("fail to persist volume stats: {}", err);
+            }
+            if let Err(err) = self.fold_stats().await {
+                warn!("fail to fold volume stats: {}", err);
+            }
+        }
+    }
+
+    /// Register this mount as alive in the clients registry.
+    pub async fn register_client(&self) -> Result<()> {
+        let client = Client::new(self.mount_id, self.frozen.load(Ordering::Acquire));
+        self.spin_no_delay_local(move |_, txn| {
+            let client = client.clone();
+            Box::pin(async move { txn.save_client(&client).await })
+        })
+        .await
+    }
+
+    /// Keep this mount registered, it should run as long as the mount.
+    pub async fn serve_clients(&self) {
+        loop {
+            if let Err(err) = self.register_client().await {
+                warn!("fail to register mount({}): {}", self.mount_id, err);
+            }
+            sleep(HEARTBEAT_INTERVAL).await;
+        }
+    }
+
+    /// Hold the commits writing the volume while it is frozen, it should run as long as the mount.
+    pub async fn serve_freeze(&self) {
+        let mut held: Option<(Freeze, RwLockWriteGuard<'_, ()>)> = None;
+        loop {
+            let freeze = match self.read_freeze().await {
+                Ok(freeze) => freeze,
+                Err(err) => {
+                    warn!("fail to check the freeze of volume: {}", err);
+                    held.as_ref().map(|(freeze, _)| freeze.clone())
+                }
+            }
+            .filter(|freeze| !freeze.is_expired());
+            let id = freeze.as_ref().map(|freeze| freeze.id).unwrap_or_default();
+            if id != self.frozen.load(Ordering::Acquire) {
+                held = None;
+                match freeze {
+                    Some(freeze) => {
+                        let gate = self.write_gate.close().await;
+                        info!("volume is frozen by freeze({})", freeze.id);
+                        held = Some((freeze, gate));
+                    }
+                    None => info!("volume is thawed"),
+                }
+                self.frozen.store(id, Ordering::Release);
+                if let Err(err) = self.register_client().await {
+                    warn!("fail to register mount({}): {}", self.mount_id, err);
+                }
+            }
+            sleep(FREEZE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// The freeze of the volume, if it is set.
+    pub async fn read_freeze(&self) -> Result<Option<Freeze>> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_freeze()))
+            .await
+    }
+
+    /// Freeze the volume for `timeout` and wait up to `wait` until every live mount holds its
+    /// commits, the freeze is removed if some mounts do not in time.
+    pub async fn freeze_volume(&self, timeout: Duration, wait: Duration) -> Result<Freeze> {
+        let freeze = Freeze::new(timeout);
+        let saved = freeze.clone();
+        self.spin_no_delay_local(move |_, txn| {
+            let freeze = saved.clone();
+            Box::pin(async move { txn.save_freeze(&freeze).await })
+        })
+        .await?;
+        let deadline = SystemTime::now() + wait;
+        loop {
+            let mounts = self.unfrozen_clients(freeze.id).await?;
+            if mounts.is_empty() {
+                return Ok(freeze);
+            }
+            if SystemTime::now() >= deadline {
+                self.thaw_volume().await?;
+                return Err(FsError::FreezeTimeout { mounts });
+            }
+            sleep(FREEZE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Refuse to start a command writing the volume at length while it is frozen, rather than
+    /// holding each of its commits until the volume is thawed.
+    pub async fn ensure_thawed(&self) -> Result<()> {
+        match self.read_freeze().await? {
+            Some(freeze) if !freeze.is_expired() => Err(FsError::Frozen { id: freeze.id }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove the freeze of the volume, mounts go on with their commits within
+    /// `FREEZE_POLL_INTERVAL`.
+    pub async fn thaw_volume(&self) -> Result<()> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.delete_freeze()))
+            .await
+    }
+
+    /// Live mounts which have not recorded the freeze `id`.
+    async fn unfrozen_clients(&self, id: u64) -> Result<Vec<u64>> {
+        let clients = self
+            .spin_no_delay_local(move |_, txn| Box::pin(txn.read_clients()))
+            .await?;
+        Ok(clients
+            .into_iter()
+            .filter(|client| !client.is_expired() && client.frozen != id)
+            .map(|client| client.mount_id)
+            .collect())
+    }
+
+    /// Mounts registered as alive, see `fsck::check_handles`.
+    pub async fn live_clients(&self) -> Result<HashSet<u64>> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.live_clients()))
+            .await
+    }
+
+    /// The file handlers of the inodes numbered in `inos`, as `(ino, fh, handler)`.
+    pub async fn read_handlers_of(&self, inos: Range<u64>) -> Result<Vec<(u64, u64, FileHandler)>> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_handlers(inos.clone())))
+            .await
+    }
+
+    /// The inodes numbered in `inos`, scanned in one transaction, see `parallel::scan_inodes`.
+    pub async fn scan_inodes(&self, inos: Range<u64>) -> Result<Vec<Inode>> {
+        let page = self.scan_page;
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.scan_inodes(inos.clone(), page)))
+            .await
+    }
+
+    /// The number given to the next inode, every inode is numbered below it.
+    pub async fn next_inode(&self) -> Result<u64> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                Ok(txn
+                    .read_meta()
+                    .await?
+                    .map(|meta| meta.inode_next)
+                    .unwrap_or(ROOT_INODE))
+            })
+        })
+        .await
+    }
+
+    /// Remove `ino` if its last link and handle are gone and the kernel of this mount does
+    /// not refer to it anymore. Returns whether it is unlinked.
+    pub async fn reclaim(&self, ino: u64) -> Result<bool> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let inode = match txn.read_inode(ino).await {
+                    Err(FsError::InodeNotFound { inode: _ }) => return Ok(false),
+                    result => result?,
+                };
+                if inode.nlink != 0 || inode.opened_fh != 0 {
+                    return Ok(false);
+                }
+                txn.save_inode(&inode).await?;
+                Ok(true)
+            })
+        })
+        .await
+    }
+
+    /// Drop the handlers of `ino` left by mounts which are not alive and recount its open
+    /// handlers, see `fsck::repair`. Returns the count recorded before if it is changed.
+    pub async fn reconcile_handles(&self, ino: u64) -> Result<Option<u64>> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                let live = txn.live_clients().await?;
+                txn.reconcile_handles(ino, &live).await
+            })
+        })
+        .await
+    }
+
+    /// The statistics of the volume, and the number of mounts which have added to them.
+    pub async fn volume_stats(&self) -> Result<(usize, VolumeStats)> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.volume_stats()))
+            .await
+    }
+
+    /// Count the files and blocks of the volume by scanning all inodes, and rebase the counters
+    /// statfs reports on them. Returns the files and blocks counted.
+    ///
+    /// The inodes are counted `scan_page` at a time and a region at most, each batch in a
+    /// transaction of its own which keeps the counts so far in a cursor, so no transaction
+    /// grows with the volume and a rebase stopped halfway is resumed by the next one.
+    pub async fn rebase_usage(&self) -> Result<(u64, u64)> {
+        let page = self.scan_page;
+        let bounds = parallel::inode_bounds(self, ROOT_INODE..self.next_inode().await?).await;
+        loop {
+            let counted = self
+                .spin_no_delay_local(|_, txn| {
+                    let bounds = bounds.clone();
+                    Box::pin(async move { txn.rebase_usage_batch(page, &bounds).await })
+                })
+                .await?;
+            if let Some(counted) = counted {
+                return Ok(counted);
+            }
+        }
+    }
+
+    /// Ping the watchdog of systemd as long as the volume can be read, it should run as long
+    /// as the mount.
+    pub async fn serve_watchdog(&self, interval: Duration) {
+        loop {
+            sleep(interval).await;
+            match self.has_meta().await {
+                Ok(_) => notify_or_warn("WATCHDOG=1"),
+                Err(err) => warn!("skip pinging the watchdog of systemd: {}", err),
+            }
+        }
+    }
+
+    /// Pin a directory subtree, which is cached by the next `refresh_pinned`.
+    pub async fn pin_dir(&self, ino: u64, level: PinLevel) -> Result<()> {
+        if self.read_inode(ino).await?.kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        if level == PinLevel::Data && self.disk_cache.is_none() {
+            warn!("pin only the metadata of dir({}) without cache_dir", ino);
+        }
+        self.pins.lock().unwrap().pin(ino, level);
+        info!("pinned dir({}) with {}", ino, level);
+        Ok(())
+    }
+
+    /// Pin the directories of a prefetch list, see `parse_prefetch_list`, and read them into
+    /// the cache at once. Directories which cannot be pinned are skipped.
+    pub async fn prefetch(&self, list: &str) -> Result<usize> {
+        let mut pinned = 0;
+        for (path, level) in parse_prefetch_list(list)? {
+            let result = match self.resolve_path(&path).await {
+                Ok(ino) => self.pin_dir(ino, level).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => pinned += 1,
+                Err(err) => warn!("skip prefetching {}: {}", path, err),
+            }
+        }
+        self.refresh_pinned().await?;
+        Ok(pinned)
+    }
+
+    /// Walk the pinned subtrees and replace the cached metadata by what they hold now,
+    /// warming the disk cache with the files of the subtrees pinned with data.
+    pub async fn refresh_pinned(&self) -> Result<()> {
+        let mut pending = self.pins.lock().unwrap().pins();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        // walk the subtrees pinned with data first, so nested pins do not cut them short
+        pending.sort_by_key(|(_, level)| *level == PinLevel::Data);
+        self.pins.lock().unwrap().begin_refresh();
+
+        let mut attrs = HashMap::new();
+        let mut entries = HashMap::new();
+        while let Some((dir, level)) = pending.pop() {
+            if entries.contains_key(&dir) {
+                continue;
+            }
+            match self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(dir)))
+                .await
+            {
+                Ok(inode) => attrs.insert(dir, (inode.file_attr, inode.generation)),
+                Err(FsError::InodeNotFound { inode: _ }) => continue,
+                Err(err) => return Err(err),
+            };
+            let mut names = HashMap::new();
+            for item in self.read_dir(dir).await? {
+                if item.name == "." || item.name == ".." {
+                    continue;
+                }
+                let ino = item.ino;
+                let inode = match self
+                    .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                    .await
+                {
+                    Ok(inode) => inode,
+                    Err(FsError::InodeNotFound { inode: _ }) => continue,
+                    Err(err) => return Err(err),
+                };
+                if inode.kind == FileType::Directory {
+                    pending.push((ino, level));
+                } else if level == PinLevel::Data {
+                    self.warm_cache(&inode).await?;
+                }
+                attrs.insert(ino, (inode.file_attr, inode.generation));
+                names.insert(item.name, ino);
+            }
+            entries.insert(dir, names);
+        }
+        self.pins.lock().unwrap().finish_refresh(attrs, entries);
+        Ok(())
+    }
+
+    /// Fill the disk cache with the blocks of a file which it does not hold yet.
+    async fn warm_cache(&self, inode: &Inode) -> Result<()> {
+        let cache = match &self.disk_cache {
+            Some(cache) => cache,
+            None => return Ok(()),
+        };
+        if inode.kind != FileType::RegularFile || inode.inline_data.is_some() {
+            return Ok(());
+        }
+        let ino = inode.ino;
+        let chunk = self.block_size * Self::WARM_CHUNK_BLOCKS;
+        let mut start = 0;
+        while start < inode.size {
+            let size = chunk.min(inode.size - start);
+            if cache.read(inode, start, size).await.is_none() {
+                let data = self
+                    .spin_no_delay_local(move |_, txn| {
+                        Box::pin(txn.read_data(ino, start, Some(size)))
+                    })
+                    .await?;
+                cache.fill(inode, start, &data).await;
+            }
+            start += size;
+        }
+        Ok(())
+    }
+
+    /// Refresh the pinned subtrees periodically, it should run as long as the mount.
+    pub async fn serve_pinning(&self) {
+        loop {
+            sleep(PIN_REFRESH_INTERVAL).await;
+            if let Err(err) = self.refresh_pinned().await {
+                warn!("fail to refresh pinned directories: {}", err);
+            }
+        }
+    }
+
+    /// Read `size` bytes of a file from `start`, without opening it.
+    pub async fn read_file(&self, ino: u64, start: u64, size: u64) -> Result<Vec<u8>> {
+        self.flush_writes(ino, false).await?;
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_data(ino, start, Some(size))))
+            .await
+    }
+
+    /// Apply `batch` and commit once, see `Batch`.
+    pub async fn batch(&self, batch: Batch) -> Result<Vec<FileAttr>> {
+        for name in batch.ops.iter().flat_map(BatchOp::names) {
+            Self::check_file_name(name)?;
+        }
+        let inodes = self
+            .spin_no_delay_local(move |fs, txn| {
+                let batch = batch.clone();
+                Box::pin(async move {
+                    let inodes = txn.apply_batch(&batch).await?;
+                    if fs.journal {
+                        for (op, inode) in batch.ops.iter().zip(&inodes) {
+                            let ino = inode.ino;
+                            let op = match op {
+                                BatchOp::Mkdir { parent, name, .. }
+                                | BatchOp::Create { parent, name, .. }
+                                | BatchOp::Symlink { parent, name, .. } => JournalOp::Create {
+                                    parent: resolve_parent(*parent, &inodes)?,
+                                    name: name.to_string(),
+                                    ino,
+                                },
+                                BatchOp::Link { parent, name, .. } => JournalOp::Link {
+                                    ino,
+                                    newparent: resolve_parent(*parent, &inodes)?,
+                                    newname: name.to_string(),
+                                },
+                                BatchOp::Write { offset, data, .. } => JournalOp::Write {
+                                    ino,
+                                    offset: *offset as i64,
+                                    len: data.len() as u64,
+                                },
+                                BatchOp::Rename {
+                                    parent,
+                                    name,
+                                    newparent,
+                                    newname,
+                                } => JournalOp::Rename {
+                                    parent: resolve_parent(*parent, &inodes)?,
+                                    name: name.to_string(),
+                                    newparent: resolve_parent(*newparent, &inodes)?,
+                                    newname: newname.to_string(),
+                                    ino,
+                                },
+                                BatchOp::Unlink { parent, name } => JournalOp::Unlink {
+                                    parent: resolve_parent(*parent, &inodes)?,
+                                    name: name.to_string(),
+                                    ino,
+                                },
+                            };
+                            txn.append_journal(fs.mount_id, op).await?;
+                        }
+                    }
+                    Ok(inodes)
+                })
+            })
+            .await?;
+        Ok(inodes.into_iter().map(|inode| inode.file_attr).collect())
+    }
+
+    /// Create the copies of `entries` in the directory `parent` in one transaction, see
+    /// `copy::copy_tree`. Returns the inode numbers of the copies.
+    pub async fn copy_entries(&self, parent: u64, entries: Vec<CopyEntry>) -> Result<Vec<u64>> {
+        for entry in &entries {
+            Self::check_file_name(&entry.name)?;
+        }
+        self.spin_no_delay_local(move |fs, txn| {
+            let entries = entries.clone();
+            Box::pin(async move {
+                let mut linked: HashMap<u64, u64> = HashMap::new();
+                let mut inos = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    let source = &entry.source;
+                    let name: ByteString = entry.name.clone().into();
+                    if let Some(ino) = entry.link_to.or_else(|| linked.get(&source.ino).copied()) {
+                        txn.link(ino, parent, name).await?;
+                        if fs.journal {
+                            let op = JournalOp::Link {
+                                ino,
+                                newparent: parent,
+                                newname: entry.name.clone(),
+                            };
+                            txn.append_journal(fs.mount_id, op).await?;
+                        }
+                        inos.push(ino);
+                        continue;
+                    }
+                    let mut inode = match source.kind {
+                        FileType::Directory => {
+                            txn.mkdir(parent, name, source.perm as u32, source.gid, source.uid)
+                                .await?
+                        }
+                        kind => {
+                            let mode = make_mode(kind, source.perm);
+                            txn.make_inode(parent, name, mode, source.gid, source.uid, source.rdev)
+                                .await?
+                        }
+                    };
+                    if source.kind == FileType::Symlink {
+                        let target = source.inline_data.clone().unwrap_or_default();
+                        txn.write_link(&mut inode, target.into()).await?;
+                    }
+                    let complete = entry.data.is_some();
+                    for (offset, data) in entry.data.into_iter().flatten() {
+                        txn.write_data(inode.ino, offset, data.into()).await?;
+                    }
+                    let mut inode = txn.read_inode(inode.ino).await?;
+                    if complete && inode.size < source.size {
+                        txn.truncate(&mut inode, source.size).await?;
+                    }
+                    inode.copy_attrs(source);
+                    txn.save_inode(&inode).await?;
+                    if source.nlink > 1 {
+                        linked.insert(source.ino, inode.ino);
+                    }
+                    if fs.journal {
+                        let op = JournalOp::Create {
+                            parent,
+                            name: entry.name.clone(),
+                            ino: inode.ino,
+                        };
+                        txn.append_journal(fs.mount_id, op).await?;
+                    }
+                    inos.push(inode.ino);
+                }
+                Ok(inos)
+            })
+        })
+        .await
+    }
+
+    /// Write the data copied into `ino` by `copy::copy_tree` as `(offset, data)`, then take the
+    /// size and attributes of `source` if the copy is complete.
+    pub async fn copy_data(
+        &self,
+        ino: u64,
+        data: Vec<(u64, Vec<u8>)>,
+        source: Option<Inode>,
+    ) -> Result<()> {
+        self.spin_no_delay_local(move |fs, txn| {
+            let data = data.clone();
+            let source = source.clone();
+            Box::pin(async move {
+                for (offset, data) in data {
+                    txn.write_data(ino, offset, data.into()).await?;
+                }
+                let mut inode = txn.read_inode(ino).await?;
+                if let Some(source) = &source {
+                    if inode.kind == FileType::RegularFile && inode.size < source.size {
+                        txn.truncate(&mut inode, source.size).await?;
+                    }
+                    inode.copy_attrs(source);
+                    txn.save_inode(&inode).await?;
+                }
+                if fs.journal {
+                    let size = Some(inode.size);
+                    txn.append_journal(fs.mount_id, JournalOp::SetAttr { ino, size })
+                        .await?;
+                }
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    /// Keep the MVCC versions at `ts` from the GC by `TiFs::gc`, see `SafePoint`.
+    pub async fn register_safepoint(&self, service: &str, ts: u64, ttl: Duration) -> Result<()> {
+        let safepoint = SafePoint::new(service.to_owned(), ts, ttl);
+        self.spin_no_delay_local(move |_, txn| {
+            let safepoint = safepoint.clone();
+            Box::pin(async move { txn.save_safepoint(&safepoint).await })
+        })
+        .await
+    }
+
+    pub async fn remove_safepoint(&self, service: &str) -> Result<()> {
+        let service = service.to_owned();
+        self.spin_no_delay_local(move |_, txn| {
+            let service = service.clone();
+            Box::pin(async move { txn.delete_safepoint(&service).await })
+        })
+        .await
+    }
+
+    pub async fn safepoints(&self) -> Result<Vec<SafePoint>> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_safepoints()))
+            .await
+    }
+
+    /// Advance the GC safepoint of TiKV to `life_time` ago, but not beyond any registered
+    /// safepoint, and remove the expired ones. Returns the new GC safepoint.
+    pub async fn gc(&self, life_time: Duration) -> Result<u64> {
+        let now = self.client.current_timestamp().await?.version();
+        let mut gc_safepoint = timestamp_of(time_of(now) - life_time);
+        for safepoint in self.safepoints().await? {
+            if safepoint.is_expired() {
+                debug!("remove expired safepoint {:?}", safepoint);
+                self.remove_safepoint(&safepoint.service).await?;
+            } else {
+                gc_safepoint = gc_safepoint.min(safepoint.timestamp);
+            }
+        }
+        info!("advance gc safepoint to {}", gc_safepoint);
+        self.client
+            .gc(Timestamp::from_version(gc_safepoint))
+            .await?;
+        Ok(gc_safepoint)
+    }
+
+    /// Restore the blocks of a sealed file in `blocks` which do not match their leaves from
+    /// earlier MVCC versions, see `REPAIR_PROBES`. Returns the number of restored blocks.
+    pub async fn repair_bitrot(&self, ino: u64, blocks: Range<u64>) -> Result<u64> {
+        let now = self.client.current_timestamp().await?.version();
+        let mut current = SnapshotView::at(&self.client, now, self.block_size);
+        let inode = current.read_inode(ino).await?;
+        let seal = match inode.seal {
+            Some(seal) if inode.inline_data.is_none() => seal,
+            _ => return Ok(0),
+        };
+        let mut restored = Vec::new();
+        for block in blocks.start..blocks.end.min(seal.leaves.len() as u64) {
+            let data = current
+                .read_block(ino, block)
+                .await?
+                .unwrap_or_else(|| empty_block(self.block_size));
+            if seal.verify_block(ino, block, &data).is_ok() {
+                continue;
+            }
+            warn!("block({}, {}) does not match its seal", ino, block);
+            match self.find_intact_block(ino, block, &seal, now).await? {
+                Some(data) => restored.push((ScopedKey::block(ino, block).into(), data)),
+                None => error!("no intact version of block({}, {}) is left", ino, block),
+            }
+        }
+        let count = restored.len() as u64;
+        if count > 0 {
+            self.put_pairs(restored).await?;
+            info!("restored {} blocks of inode({}) from history", count, ino);
+        }
+        Ok(count)
+    }
+
+    /// The latest earlier version of a block which matches its leaf, if it is not collected.
+    async fn find_intact_block(
+        &self,
+        ino: u64,
+        block: u64,
+        seal: &Seal,
+        now: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        for ago in REPAIR_PROBES.iter() {
+            let ts = timestamp_of(time_of(now) - *ago);
+            let data = match SnapshotView::at(&self.client, ts, self.block_size)
+                .read_block(ino, block)
+                .await
+            {
+                Ok(data) => data.unwrap_or_else(|| empty_block(self.block_size)),
+                Err(err) => {
+                    debug!("stop probing block({}, {}) at {}: {}", ino, block, ts, err);
+                    return Ok(None);
+                }
+            };
+            if seal.verify_block(ino, block, &data).is_ok() {
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn read_data(&self, ino: u64, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>> {
+        self.spin_no_delay_local(move |fs, txn| {
+            Box::pin(async move {
+                match &fs.disk_cache {
+                    Some(cache) => txn.read_cached(ino, fh, offset, size, cache).await,
+                    None => txn.read(ino, fh, offset, size).await,
+                }
+            })
+        })
+        .await
+    }
+
+    /// Whether the volume is initialized, by a mount or a restore.
+    pub async fn has_meta(&self) -> Result<bool> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move { Ok(txn.read_meta().await?.is_some()) })
+        })
+        .await
+    }
+
+    /// Write raw pairs in one transaction, see `backup::restore`.
+    pub async fn put_pairs(&self, pairs: Vec<(Key, Vec<u8>)>) -> Result<()> {
+        self.spin_no_delay_local(move |_, txn| {
+            let pairs = pairs.clone();
+            Box::pin(async move { txn.put_pairs(pairs).await })
+        })
+        .await
     }
 
-    async fn spin<F, T>(&self, delay: Option<Duration>, mut f: F) -> Result<T>
-    where
-        T: 'static + Send,
-        F: for<'a> FnMut(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
-    {
-        loop {
-            match self.with_optimistic(&mut f).await {
-                Ok(v) => break Ok(v),
-                Err(FsError::KeyError(err)) => {
-                    trace!("spin because of a key error({})", err);
-                    if let Some(time) = delay {
-                        sleep(time).await;
-                    }
-                }
-                Err(err) => break Err(err),
-            }
-        }
+    fn safepoint_service(&self) -> String {
+        format!("mount-{:016x}", self.mount_id)
     }
 
-    async fn process_txn_local<F, T>(&self, txn: &mut LocalTxn, f: F) -> Result<T>
-    where
-        T: 'static + Send,
-        F: for<'a> FnOnce(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
-    {
-        match f(self, txn).await {
-            Ok(v) => {
-                // txn.commit().await?;
-                trace!("transaction committed");
-                Ok(v)
+    /// Register the safepoint of this mount for a read at `ts`, if it is not held yet.
+    async fn hold_safepoint(&self, ts: u64) {
+        let now = SystemTime::now();
+        let register = {
+            let mut held = self.held_safepoint.lock().unwrap();
+            match *held {
+                Some((held_ts, time)) if held_ts <= ts && time + SAFEPOINT_TTL / 2 > now => None,
+                Some((held_ts, time)) if time + SAFEPOINT_TTL > now => {
+                    *held = Some((held_ts.min(ts), now));
+                    Some(held_ts.min(ts))
+                }
+                _ => {
+                    *held = Some((ts, now));
+                    Some(ts)
+                }
             }
-            Err(e) => {
-                // txn.rollback().await?;
-                debug!("transaction rollbacked");
-                Err(e)
+        };
+        if let Some(ts) = register {
+            let service = self.safepoint_service();
+            if let Err(err) = self.register_safepoint(&service, ts, SAFEPOINT_TTL).await {
+                warn!("fail to register safepoint {} at {}: {}", service, ts, err);
             }
         }
     }
 
-    async fn with_optimistic_local<F, T>(&self, f: F) -> Result<T>
-    where
-        T: 'static + Send,
-        F: for<'a> FnOnce(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
-    {
-        let mut local_txn =
-            LocalTxn::begin_optimistic(self.entry_map.clone(), self.block_size).await?;
-        self.process_txn_local(&mut local_txn, f).await
+    /// Journal entries from sequence number `from` on, at most `limit` ones.
+    pub async fn read_journal(&self, from: u64, limit: u32) -> Result<Vec<JournalEntry>> {
+        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_journal(from, limit)))
+            .await
     }
 
-    async fn spin_local<F, T>(&self, delay: Option<Duration>, mut f: F) -> Result<T>
+    /// Sequence number of the next entry appended to the journal.
+    pub async fn journal_head(&self) -> Result<u64> {
+        self.spin_no_delay_local(move |_, txn| {
+            Box::pin(async move {
+                Ok(txn
+                    .read_meta()
+                    .await?
+                    .map(|meta| meta.journal_next)
+                    .unwrap_or(0))
+            })
+        })
+        .await
+    }
+
+    /// Tail the journal from sequence number `from`, polling every `interval`,
+    /// until `f` returns false.
+    pub async fn tail_journal<F>(&self, mut from: u64, interval: Duration, mut f: F) -> Result<()>
     where
-        T: 'static + Send,
-        F: for<'a> FnMut(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
+        F: FnMut(JournalEntry) -> bool,
     {
         loop {
-            match self.with_optimistic_local(&mut f).await {
-                Ok(v) => break Ok(v),
-                Err(FsError::KeyError(err)) => {
-                    trace!("spin because of a key error({})", err);
-                    if let Some(time) = delay {
-                        sleep(time).await;
-                    }
+            let entries = self.read_journal(from, self.scan_page).await?;
+            if entries.is_empty() {
+                sleep(interval).await;
+                continue;
+            }
+            for entry in entries {
+                from = entry.seq + 1;
+                if !f(entry) {
+                    return Ok(());
                 }
-                Err(err) => break Err(err),
             }
         }
     }
 
-    #[cfg(feature = "kv_store")]
-    async fn spin_no_delay_local<F, T>(&self, f: F) -> Result<T>
-    where
-        T: 'static + Send,
-        F: for<'a> FnMut(&'a TiFs, &'a mut Txn) -> BoxedFuture<'a, T>,
-    {
-        self.spin(None, f).await
+    /// The inode and timestamp behind a virtual inode of a historical version.
+    fn resolve_version(&self, ino: u64) -> Option<Result<(u64, u64)>> {
+        if !VirtualInodes::is_virtual(ino) {
+            return None;
+        }
+        Some(
+            self.versions
+                .lock()
+                .unwrap()
+                .resolve(ino)
+                .ok_or(FsError::InodeNotFound { inode: ino }),
+        )
     }
 
-    #[cfg(feature = "mem_store")]
-    async fn spin_no_delay_local<F, T>(&self, f: F) -> Result<T>
-    where
-        T: 'static + Send,
-        F: for<'a> FnMut(&'a TiFs, &'a mut LocalTxn) -> BoxedFuture<'a, T>,
-    {
-        self.spin_local(None, f).await
+    fn version_attr(&self, inode: Inode, ts: u64) -> FileAttr {
+        let mut attr: FileAttr = inode.into();
+        attr.ino = self.versions.lock().unwrap().get_or_insert(attr.ino, ts);
+        attr.perm &= !0o222;
+        attr
     }
 
-    async fn read_dir(&self, ino: u64) -> Result<Directory> {
-        self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_dir(ino)))
-            .await
+    async fn snapshots_attr(&self) -> Result<FileAttr> {
+        let mut attr = self.read_inode(ROOT_INODE).await?;
+        attr.ino = SNAPSHOTS_INODE;
+        attr.perm = 0o555;
+        Ok(attr)
     }
 
-    async fn read_inode(&self, ino: u64) -> Result<FileAttr> {
-        let ino = self
-            .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
-            .await?;
-        Ok(ino.file_attr)
+    async fn lookup_version(&self, parent: u64, name: &str, ts: u64) -> Result<Entry> {
+        self.hold_safepoint(ts).await;
+        let mut view = SnapshotView::at(&self.client, ts, self.block_size)
+            .with_hashed_names(self.hashed_names);
+        let ino = view.lookup(parent, name).await?;
+        let inode = view.read_inode(ino).await?;
+        Ok(Entry::new(self.version_attr(inode, ts), 0))
     }
 
-    async fn setlkw(&self, ino: u64, lock_owner: u64, typ: i32) -> Result<bool> {
-        loop {
-            let res = self
-                .spin_no_delay_local(move |_, txn| {
-                    Box::pin(async move {
-                        let mut inode = txn.read_inode(ino).await?;
-                        match typ {
-                            F_WRLCK => {
-                                if inode.lock_state.owner_set.len() > 1 {
-                                    return Ok(false);
-                                }
-                                if inode.lock_state.owner_set.is_empty() {
-                                    inode.lock_state.lk_type = F_WRLCK;
-                                    inode.lock_state.owner_set.insert(lock_owner);
-                                    txn.save_inode(&inode).await?;
-                                    return Ok(true);
-                                }
-                                if inode.lock_state.owner_set.get(&lock_owner) == Some(&lock_owner)
-                                {
-                                    inode.lock_state.lk_type = F_WRLCK;
-                                    txn.save_inode(&inode).await?;
-                                    return Ok(true);
-                                }
-                                Err(FsError::InvalidLock)
-                            }
-                            F_RDLCK => {
-                                if inode.lock_state.lk_type == F_WRLCK {
-                                    return Ok(false);
-                                } else {
-                                    inode.lock_state.lk_type = F_RDLCK;
-                                    inode.lock_state.owner_set.insert(lock_owner);
-                                    txn.save_inode(&inode).await?;
-                                    return Ok(true);
-                                }
-                            }
-                            _ => return Err(FsError::InvalidLock),
-                        }
-                    })
-                })
-                .await?;
-            if res {
-                break;
-            }
+    fn reply_xattr(data: Vec<u8>, size: u32) -> Result<Xattr> {
+        if size == 0 {
+            Ok(Xattr::size(data.len() as u32))
+        } else if (data.len() as u32) <= size {
+            Ok(Xattr::data(data))
+        } else {
+            Err(FsError::BufferTooSmall {
+                size,
+                required: data.len() as u32,
+            })
         }
-
-        Ok(true)
     }
 
     fn check_file_name(name: &str) -> Result<()> {
@@ -279,55 +2896,134 @@ impl AsyncFileSystem for TiFs {
         config
             .add_capabilities(fuser::consts::FUSE_FLOCK_LOCKS)
             .expect("kernel config failed to add cap_fuse FUSE_CAP_FLOCK_LOCKS");
+        // the umask is applied by `mkdir` and `mknod`, unless the parent has a default ACL
+        if let Err(unsupported) =
+            config.add_capabilities(fuser::consts::FUSE_POSIX_ACL | fuser::consts::FUSE_DONT_MASK)
+        {
+            warn!("kernel does not support posix acl: {:#x}", unsupported);
+        }
+        // `O_TRUNC` is applied by `open` in the same transaction, not by a `setattr` before it
+        if let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_ATOMIC_O_TRUNC) {
+            warn!("kernel does not support atomic O_TRUNC: {:#x}", unsupported);
+        }
+        // file handles of `name_to_handle_at` stay valid after the kernel forgets the inodes
+        if let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_EXPORT_SUPPORT) {
+            warn!("kernel does not support export: {:#x}", unsupported);
+        }
+        // cached data of a file is dropped once its refetched mtime or size changes
+        if self.revalidate.is_some() {
+            if let Err(unsupported) = config.add_capabilities(fuser::consts::FUSE_AUTO_INVAL_DATA) {
+                warn!(
+                    "kernel does not support auto invalidation: {:#x}",
+                    unsupported
+                );
+            }
+        }
 
-        self.spin_no_delay_local(move |fs, txn| {
-            Box::pin(async move {
-                info!("initializing tifs on {:?} ...", &fs.pd_endpoints);
-                if let Some(meta) = txn.read_meta().await? {
-                    if meta.block_size != txn.block_size() {
-                        let err = FsError::block_size_conflict(meta.block_size, txn.block_size());
-                        error!("{}", err);
-                        return Err(err);
-                    }
-                }
-
-                let root_inode = txn.read_inode(ROOT_INODE).await;
-                if let Err(FsError::InodeNotFound { inode: _ }) = root_inode {
-                    let attr = txn
-                        .mkdir(
-                            0,
-                            Default::default(),
-                            make_mode(FileType::Directory, 0o777),
-                            gid,
-                            uid,
-                        )
-                        .await?;
-                    debug!("make root directory {:?}", &attr);
-                    Ok(())
-                } else {
-                    root_inode.map(|_| ())
-                }
-            })
-        })
+        self.init_volume(
+            self.root_gid.unwrap_or(gid),
+            self.root_uid.unwrap_or(uid),
+            self.root_mode.unwrap_or(FileMode::DEFAULT_ROOT),
+        )
         .await
     }
 
+    async fn destroy(&self) {
+        let held = self.held_safepoint.lock().unwrap().take();
+        if held.is_some() {
+            let service = self.safepoint_service();
+            if let Err(err) = self.remove_safepoint(&service).await {
+                warn!("fail to remove safepoint {}: {}", service, err);
+            }
+        }
+        self.flush_write_buffers().await;
+        for ino in self.lookups.forget_all() {
+            if let Err(err) = self.reclaim(ino).await {
+                warn!("fail to remove unlinked inode({}): {}", ino, err);
+            }
+        }
+        if let Err(err) = self.flush_atimes().await {
+            warn!("fail to save access times: {}", err);
+        }
+        if let Err(err) = self.persist_stats().await {
+            warn!("fail to persist volume stats: {}", err);
+        }
+        let mount_id = self.mount_id;
+        if let Err(err) = self
+            .spin_no_delay_local(move |_, txn| Box::pin(txn.delete_client(mount_id)))
+            .await
+        {
+            warn!("fail to unregister mount({}): {}", mount_id, err);
+        }
+        if let Err(err) = self.save_store().await {
+            error!("fail to save memory store: {}", err);
+        }
+        if let Err(err) = self.write_back_store().await {
+            error!("fail to write back memory store: {}", err);
+        }
+    }
+
+    fn chaos(&self) -> Option<&Chaos> {
+        self.chaos.as_ref()
+    }
+
+    fn denies(&self, op: &str) -> bool {
+        self.denied.contains(op)
+    }
+
+    fn commit_health(&self) -> Option<&CommitHealth> {
+        self.commit_health.as_ref()
+    }
+
+    fn account(&self, op: &str, stats: &OpStats) {
+        if stats.retries >= self.retry_warn {
+            warn!(
+                "{} retried {} times on conflicts: meta {}, dir {}, inode {}, block {}",
+                op,
+                stats.retries,
+                stats.meta_retries,
+                stats.dir_retries,
+                stats.inode_retries,
+                stats.block_retries,
+            );
+        }
+        self.stats.lock().unwrap().account(stats);
+    }
+
     #[tracing::instrument]
     async fn lookup(&self, parent: u64, name: ByteString) -> Result<Entry> {
         Self::check_file_name(&name)?;
-        self.spin_no_delay_local(move |_, txn| {
-            let name = name.clone();
-            Box::pin(async move {
-                let ino = txn.lookup(parent, name).await?;
-                Ok(Entry::new(txn.read_inode(ino).await?.into(), 0))
-            })
-        })
-        .await
+        let entry = self.lookup_entry(parent, name).await?;
+        Ok(self.counted(entry))
+    }
+
+    #[tracing::instrument]
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        if self.lookups.forget(ino, nlookup) {
+            if let Err(err) = self.reclaim(ino).await {
+                warn!("fail to remove unlinked inode({}): {}", ino, err);
+            }
+        }
     }
 
     #[tracing::instrument]
     async fn getattr(&self, ino: u64) -> Result<Attr> {
-        Ok(Attr::new(self.read_inode(ino).await?))
+        if ino == SNAPSHOTS_INODE {
+            return Ok(Attr::new(self.snapshots_attr().await?));
+        }
+        if let Some(version) = self.resolve_version(ino) {
+            let (ino, ts) = version?;
+            let inode = SnapshotView::at(&self.client, ts, self.block_size)
+                .read_inode(ino)
+                .await?;
+            return Ok(Attr::new(self.version_attr(inode, ts)));
+        }
+        self.flush_writes(ino, false).await?;
+        let pinned = self.pins.lock().unwrap().attr(ino);
+        if let Some(attr) = pinned.filter(|attr| !self.revalidates(attr)) {
+            return Ok(self.revalidated_attr(Attr::new(attr)));
+        }
+        Ok(self.revalidated_attr(Attr::new(self.read_inode(ino).await?)))
     }
 
     #[tracing::instrument]
@@ -347,17 +3043,32 @@ impl AsyncFileSystem for TiFs {
         bkuptime: Option<SystemTime>,
         flags: Option<u32>,
     ) -> Result<Attr> {
-        self.spin_no_delay_local(move |_, txn| {
+        if VirtualInodes::is_virtual(ino) {
+            return Err(FsError::ReadOnlyVersion { ino });
+        }
+        self.flush_writes(ino, false).await?;
+        self.spin_no_delay_local(move |fs, txn| {
             Box::pin(async move {
                 // TODO: how to deal with fh, chgtime, bkuptime?
                 let mut attr = txn.read_inode(ino).await?;
-                attr.perm = match mode {
-                    Some(m) => m as _,
-                    None => attr.perm,
-                };
+                attr.ensure_mutable()?;
+                if mode.is_some() || uid.is_some() || gid.is_some() || size.is_some() {
+                    attr.ensure_not_append_only()?;
+                }
+                if let Some(mode) = mode {
+                    attr.chmod(mode as _);
+                }
                 attr.uid = uid.unwrap_or(attr.uid);
                 attr.gid = gid.unwrap_or(attr.gid);
-                attr.set_size(size.unwrap_or(attr.size), txn.block_size());
+                if fs.posix.is_strict() && mode.is_none() && (uid.is_some() || gid.is_some()) {
+                    attr.kill_suid();
+                }
+                // Named pipes keep a size of zero like on other file systems, truncating them is a no-op.
+                let pipe = fs.fifo_passthrough && attr.kind == FileType::NamedPipe;
+                let size = size.filter(|_| !pipe);
+                if let Some(size) = size {
+                    txn.truncate(&mut attr, size).await?;
+                }
                 attr.atime = match atime {
                     None => attr.atime,
                     Some(TimeOrNow::SpecificTime(t)) => t,
@@ -365,28 +3076,69 @@ impl AsyncFileSystem for TiFs {
                 };
                 attr.mtime = match mtime {
                     Some(TimeOrNow::SpecificTime(t)) => t,
+                    // only a change of the size modifies the data
+                    None if fs.posix.is_strict() && size.is_none() => attr.mtime,
                     Some(TimeOrNow::Now) | None => SystemTime::now(),
                 };
                 attr.ctime = ctime.unwrap_or(SystemTime::now());
                 attr.crtime = crtime.unwrap_or(attr.crtime);
                 attr.flags = flags.unwrap_or(attr.flags);
                 txn.save_inode(&attr).await?;
-                Ok(Attr {
+                if fs.journal {
+                    txn.append_journal(fs.mount_id, JournalOp::SetAttr { ino, size })
+                        .await?;
+                }
+                Ok(fs.revalidated_attr(Attr {
                     time: get_time(),
                     attr: attr.into(),
-                })
+                }))
             })
         })
         .await
     }
 
     #[tracing::instrument]
-    async fn readdir(&self, ino: u64, _fh: u64, mut offset: i64) -> Result<Dir> {
+    async fn opendir(&self, ino: u64, _flags: i32) -> Result<Open> {
+        let parent = if ino == SNAPSHOTS_INODE || self.resolve_version(ino).is_some() {
+            ROOT_INODE
+        } else {
+            let inode = self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                .await?;
+            if inode.kind != FileType::Directory {
+                return Err(FsError::NotDirectory { ino });
+            }
+            // the root and directories made before parent pointers list the root as `..`
+            inode.parent.max(ROOT_INODE)
+        };
+        let fh = self.next_dir_handle.fetch_add(1, Ordering::Relaxed);
+        self.dir_streams
+            .lock()
+            .unwrap()
+            .insert(fh, DirStream::new(ino, parent));
+        Ok(Open::new(fh, 0))
+    }
+
+    async fn releasedir(&self, _ino: u64, fh: u64, _flags: i32) -> Result<()> {
+        self.dir_streams.lock().unwrap().remove(&fh);
+        Ok(())
+    }
+
+    async fn readdir(&self, ino: u64, fh: u64, mut offset: i64) -> Result<Dir> {
         let mut dir = Dir::offset(offset as usize);
+        let rewind = offset == 0;
+        let parent = self
+            .dir_streams
+            .lock()
+            .unwrap()
+            .get(&fh)
+            .filter(|stream| stream.ino == ino)
+            .map(|stream| stream.parent)
+            .unwrap_or(ROOT_INODE);
 
         if offset == 0 {
             dir.push(DirItem {
-                ino: ROOT_INODE,
+                ino: parent,
                 name: "..".to_string(),
                 typ: FileType::Directory,
             });
@@ -402,23 +3154,119 @@ impl AsyncFileSystem for TiFs {
 
         offset -= 2.min(offset);
 
-        let directory = self.read_dir(ino).await?;
+        let directory = if ino == SNAPSHOTS_INODE {
+            Directory::new()
+        } else if let Some(version) = self.resolve_version(ino) {
+            let (ino, ts) = version?;
+            let mut directory = SnapshotView::at(&self.client, ts, self.block_size)
+                .read_dir(ino)
+                .await?;
+            let mut versions = self.versions.lock().unwrap();
+            for item in directory.iter_mut() {
+                item.ino = versions.get_or_insert(item.ino, ts);
+            }
+            directory
+        } else {
+            self.list_dir(ino, fh, rewind).await?
+        };
         for (item) in directory.into_iter().skip(offset as usize) {
             dir.push(item)
         }
-        debug!("read directory {:?}", &dir);
-        Ok(dir)
-    }
-
-    #[tracing::instrument]
-    async fn open(&self, ino: u64, flags: i32) -> Result<Open> {
-        // TODO: deal with flags
-        let fh = self
-            .spin_no_delay_local(move |_, txn| Box::pin(txn.open(ino)))
-            .await?;
+        debug!("read directory {:?}", &dir);
+        Ok(dir)
+    }
+
+    #[tracing::instrument]
+    async fn open(&self, ino: u64, flags: i32) -> Result<Open> {
+        if let Some(version) = self.resolve_version(ino) {
+            version?;
+            if flags & O_ACCMODE != O_RDONLY {
+                return Err(FsError::ReadOnlyVersion { ino });
+            }
+            return Ok(Open::new(0, 0));
+        }
+
+        let write = flags & O_ACCMODE != O_RDONLY;
+        let truncate = flags & O_TRUNC != 0;
+        if truncate {
+            // buffered writes of other handlers land before the truncation, not after it
+            self.flush_writes(ino, false).await?;
+        }
+        let (fh, delegated) = loop {
+            let opened = self
+                .spin_no_delay_local(move |fs, txn| {
+                    Box::pin(async move {
+                        let mut inode = txn.read_inode(ino).await?;
+                        if fs.fifo_passthrough && inode.kind == FileType::NamedPipe {
+                            return Ok(Some((Self::PIPE_FH, false)));
+                        }
+                        match &mut inode.delegation {
+                            Some(delegation)
+                                if delegation.holder != fs.mount_id && !delegation.is_expired() =>
+                            {
+                                if !delegation.recalled {
+                                    delegation.recalled = true;
+                                    txn.save_inode(&inode).await?;
+                                }
+                                return Ok(None);
+                            }
+                            Some(delegation) if delegation.holder != fs.mount_id => {
+                                warn!(
+                                    "delegation of inode({}) held by mount({}) expires",
+                                    ino, delegation.holder
+                                );
+                                inode.delegation = None;
+                                txn.save_inode(&inode).await?;
+                            }
+                            _ => {}
+                        }
+
+                        // with `FUSE_ATOMIC_O_TRUNC` the kernel leaves `O_TRUNC` to the open
+                        if truncate && inode.kind == FileType::RegularFile {
+                            inode.ensure_mutable()?;
+                            inode.ensure_not_append_only()?;
+                            txn.truncate(&mut inode, 0).await?;
+                            let now = SystemTime::now();
+                            inode.mtime = now;
+                            inode.ctime = now;
+                            txn.save_inode(&inode).await?;
+                            if fs.journal {
+                                txn.append_journal(
+                                    fs.mount_id,
+                                    JournalOp::SetAttr { ino, size: Some(0) },
+                                )
+                                .await?;
+                            }
+                        }
+
+                        let fh = txn.open(ino, flags, fs.mount_id).await?;
+                        let mut inode = txn.read_inode(ino).await?;
+                        if inode.delegation.is_some() {
+                            return Ok(Some((fh, true)));
+                        }
+                        if fs.delegation && write && inode.opened_fh == 1 {
+                            inode.delegation = Some(Delegation::grant(fs.mount_id));
+                            txn.save_inode(&inode).await?;
+                            return Ok(Some((fh, true)));
+                        }
+                        Ok(Some((fh, false)))
+                    })
+                })
+                .await?;
+            match opened {
+                Some(opened) => break opened,
+                None => {
+                    debug!("wait for the recalled delegation of inode({})", ino);
+                    sleep(RENEW_INTERVAL).await;
+                }
+            }
+        };
+        if delegated {
+            self.delegated.lock().unwrap().entry(ino).or_default();
+        }
 
         let mut open_flags = 0;
-        if self.direct_io || flags | O_DIRECT != 0 {
+        if self.direct_io || flags & O_DIRECT != 0 {
             open_flags |= FOPEN_DIRECT_IO;
         }
 
@@ -435,9 +3283,37 @@ impl AsyncFileSystem for TiFs {
         _flags: i32,
         _lock_owner: Option<u64>,
     ) -> Result<Data> {
-        let data = self
-            .spin_no_delay_local(move |_, txn| Box::pin(txn.read(ino, fh, offset, size)))
-            .await?;
+        if let Some(version) = self.resolve_version(ino) {
+            let (ino, ts) = version?;
+            if offset < 0 {
+                return Err(FsError::InvalidOffset { ino, offset });
+            }
+            let data = SnapshotView::at(&self.client, ts, self.block_size)
+                .read_data(ino, offset as u64, size as u64)
+                .await?;
+            return Ok(Data::new(data));
+        }
+        if fh == Self::PIPE_FH {
+            return Err(FsError::NamedPipe { ino });
+        }
+
+        self.flush_writes(ino, false).await?;
+        let data = match self.read_data(ino, fh, offset, size).await {
+            Err(FsError::SealMismatch { ino: _ }) if offset >= 0 => {
+                let start = offset as u64;
+                let blocks = start / self.block_size
+                    ..(start + size as u64 + self.block_size - 1) / self.block_size;
+                match self.repair_bitrot(ino, blocks).await {
+                    Ok(restored) if restored > 0 => self.read_data(ino, fh, offset, size).await?,
+                    Ok(_) => return Err(FsError::SealMismatch { ino }),
+                    Err(err) => {
+                        warn!("fail to repair blocks of inode({}): {}", ino, err);
+                        return Err(FsError::SealMismatch { ino });
+                    }
+                }
+            }
+            result => result?,
+        };
         Ok(Data::new(data))
     }
 
@@ -449,12 +3325,59 @@ impl AsyncFileSystem for TiFs {
         offset: i64,
         data: Vec<u8>,
         _write_flags: u32,
-        _flags: i32,
+        flags: i32,
         _lock_owner: Option<u64>,
     ) -> Result<Write> {
+        if VirtualInodes::is_virtual(ino) {
+            return Err(FsError::ReadOnlyVersion { ino });
+        }
+        if fh == Self::PIPE_FH {
+            return Err(FsError::NamedPipe { ino });
+        }
         let data: Bytes = data.into();
+        let buffered = self
+            .buffer_write(&self.delegated, false, ino, fh, offset, data.clone())
+            .await?;
+        if let Some(full) = buffered {
+            if full {
+                self.flush_delegated(ino, false).await?;
+            }
+            return Ok(Write::new(data.len() as u32));
+        }
+        // direct writes go through, so their alignment is checked when they are made
+        if self.write_buffer && !self.direct_io && flags & O_DIRECT == 0 {
+            let full = self
+                .buffer_write(&self.buffered, true, ino, fh, offset, data.clone())
+                .await?;
+            if full == Some(true) {
+                self.flush_buffered(ino).await?;
+            }
+            return Ok(Write::new(data.len() as u32));
+        }
+        if self.write_buffer {
+            // the writes buffered by other handlers land before this one
+            self.flush_buffered(ino).await?;
+        }
+
         let len = self
-            .spin_no_delay_local(move |_, txn| Box::pin(txn.write(ino, fh, offset, data.clone())))
+            .spin_no_delay_local(move |fs, txn| {
+                let data = data.clone();
+                Box::pin(async move {
+                    let len = txn.write(ino, fh, offset, data).await?;
+                    if fs.journal {
+                        txn.append_journal(
+                            fs.mount_id,
+                            JournalOp::Write {
+                                ino,
+                                offset,
+                                len: len as u64,
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(len)
+                })
+            })
             .await?;
         Ok(Write::new(len as u32))
     }
@@ -468,22 +3391,55 @@ impl AsyncFileSystem for TiFs {
         mode: u32,
         gid: u32,
         uid: u32,
-        _umask: u32,
+        umask: u32,
     ) -> Result<Entry> {
         Self::check_file_name(&name)?;
         let attr = self
-            .spin_no_delay_local(move |_, txn| {
-                Box::pin(txn.mkdir(parent, name.clone(), mode, gid, uid))
+            .spin_no_delay_local(move |fs, txn| {
+                let name = name.clone();
+                Box::pin(async move {
+                    let mode = txn.read_inode(parent).await?.mask_mode(mode, umask);
+                    let attr = txn.mkdir(parent, name.clone(), mode, gid, uid).await?;
+                    if fs.journal {
+                        txn.append_journal(
+                            fs.mount_id,
+                            JournalOp::Create {
+                                parent,
+                                name: name.to_string(),
+                                ino: attr.ino,
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(attr)
+                })
             })
             .await?;
-        Ok(Entry::new(attr.into(), 0))
+        Ok(self.counted(self.revalidated_entry(Entry::from(attr))))
     }
 
     #[tracing::instrument]
-    async fn rmdir(&self, parent: u64, raw_name: ByteString) -> Result<()> {
+    async fn rmdir(&self, uid: u32, parent: u64, raw_name: ByteString) -> Result<()> {
         Self::check_file_name(&raw_name)?;
-        self.spin_no_delay_local(move |_, txn| Box::pin(txn.rmdir(parent, raw_name.clone())))
-            .await
+        self.spin_no_delay_local(move |fs, txn| {
+            let name = raw_name.clone();
+            Box::pin(async move {
+                if fs.journal {
+                    let ino = txn.lookup(parent, name.clone()).await?;
+                    txn.append_journal(
+                        fs.mount_id,
+                        JournalOp::Unlink {
+                            parent,
+                            name: name.to_string(),
+                            ino,
+                        },
+                    )
+                    .await?;
+                }
+                txn.rmdir(parent, name, uid).await
+            })
+        })
+        .await
     }
 
     #[tracing::instrument]
@@ -494,21 +3450,55 @@ impl AsyncFileSystem for TiFs {
         mode: u32,
         gid: u32,
         uid: u32,
-        _umask: u32,
+        umask: u32,
         rdev: u32,
     ) -> Result<Entry> {
         Self::check_file_name(&name)?;
         let attr = self
-            .spin_no_delay_local(move |_, txn| {
-                Box::pin(txn.make_inode(parent, name.clone(), mode, gid, uid, rdev))
+            .spin_no_delay_local(move |fs, txn| {
+                let name = name.clone();
+                Box::pin(async move {
+                    let mode = txn.read_inode(parent).await?.mask_mode(mode, umask);
+                    let attr = txn
+                        .make_inode(parent, name.clone(), mode, gid, uid, rdev)
+                        .await?;
+                    if fs.journal {
+                        txn.append_journal(
+                            fs.mount_id,
+                            JournalOp::Create {
+                                parent,
+                                name: name.to_string(),
+                                ino: attr.ino,
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(attr)
+                })
             })
             .await?;
-        Ok(Entry::new(attr.into(), 0))
+        Ok(self.counted(self.revalidated_entry(Entry::from(attr))))
     }
 
     #[tracing::instrument]
-    async fn access(&self, ino: u64, mask: i32) -> Result<()> {
-        Ok(())
+    async fn access(&self, cred: Credentials, ino: u64, mask: i32) -> Result<()> {
+        let attr = if ino == SNAPSHOTS_INODE || self.resolve_version(ino).is_some() {
+            self.getattr(ino).await?.attr
+        } else {
+            let inode = self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                .await?;
+            // like `access(2)` on an immutable file, whatever its mode
+            if mask & W_OK != 0 {
+                inode.ensure_mutable()?;
+            }
+            inode.file_attr
+        };
+        if cred.permits(&attr, mask) {
+            Ok(())
+        } else {
+            Err(FsError::PermissionDenied { ino, mask })
+        }
     }
 
     async fn create(
@@ -522,17 +3512,39 @@ impl AsyncFileSystem for TiFs {
         flags: i32,
     ) -> Result<Create> {
         Self::check_file_name(&name)?;
-        let entry = self.mknod(parent, name, mode, gid, uid, umask, 0).await?;
+        let (mut entry, existing) = match self
+            .mknod(parent, name.clone(), mode, gid, uid, umask, 0)
+            .await
+        {
+            // made by another mount since the kernel looked it up, opened as it is unless
+            // `O_EXCL` asks for a new file
+            Err(FsError::FileExist { .. }) if flags & O_EXCL == 0 => {
+                let entry = self.lookup_entry(parent, name).await?;
+                if entry.stat.kind == FileType::Directory {
+                    return Err(FsError::IsDirectory {
+                        ino: entry.stat.ino,
+                    });
+                }
+                (self.counted(entry), true)
+            }
+            result => (result?, false),
+        };
         let open = self.open(entry.stat.ino, flags).await?;
-        Ok(Create::new(
-            entry.stat,
-            entry.generation,
-            open.fh,
-            open.flags,
-        ))
+        if existing && flags & O_TRUNC != 0 {
+            entry.stat = self.read_inode(entry.stat.ino).await?;
+        }
+        let create = Create::new(entry.stat, entry.generation, open.fh, open.flags);
+        match self.reply_ttl(&create.attr) {
+            Some(ttl) => Ok(create.cached_for(ttl)),
+            None => Ok(create),
+        }
     }
 
     async fn lseek(&self, ino: u64, fh: u64, offset: i64, whence: i32) -> Result<Lseek> {
+        if fh == Self::PIPE_FH {
+            return Err(FsError::NamedPipe { ino });
+        }
+        self.flush_writes(ino, false).await?;
         self.spin_no_delay_local(move |_, txn| {
             Box::pin(async move {
                 let mut file_handler = txn.read_fh(ino, fh).await?;
@@ -567,44 +3579,97 @@ impl AsyncFileSystem for TiFs {
         _lock_owner: Option<u64>,
         _flush: bool,
     ) -> Result<()> {
+        if VirtualInodes::is_virtual(ino) || fh == Self::PIPE_FH {
+            return Ok(());
+        }
+        self.flush_writes(ino, true).await?;
         self.spin_no_delay_local(move |_, txn| Box::pin(txn.close(ino, fh)))
-            .await
+            .await?;
+        self.write_back_on_close().await
+    }
+
+    async fn flush(&self, ino: u64, _fh: u64, _lock_owner: u64) -> Result<()> {
+        self.flush_writes(ino, false).await?;
+        self.report_lost_writes(ino)?;
+        self.write_back_on_close().await
+    }
+
+    async fn fsync(&self, ino: u64, _fh: u64, _datasync: bool) -> Result<()> {
+        self.flush_writes(ino, false).await?;
+        self.report_lost_writes(ino)
     }
 
     /// Create a hard link.
     async fn link(&self, ino: u64, newparent: u64, newname: ByteString) -> Result<Entry> {
         Self::check_file_name(&newname)?;
         let inode = self
-            .spin_no_delay_local(move |_, txn| Box::pin(txn.link(ino, newparent, newname.clone())))
+            .spin_no_delay_local(move |fs, txn| {
+                let newname = newname.clone();
+                Box::pin(async move {
+                    let inode = txn.link(ino, newparent, newname.clone()).await?;
+                    if fs.journal {
+                        txn.append_journal(
+                            fs.mount_id,
+                            JournalOp::Link {
+                                ino,
+                                newparent,
+                                newname: newname.to_string(),
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(inode)
+                })
+            })
             .await?;
-        Ok(Entry::new(inode.into(), 0))
+        Ok(self.counted(self.revalidated_entry(Entry::from(inode))))
     }
 
-    async fn unlink(&self, parent: u64, raw_name: ByteString) -> Result<()> {
-        self.spin_no_delay_local(move |_, txn| Box::pin(txn.unlink(parent, raw_name.clone())))
-            .await
+    async fn unlink(&self, uid: u32, parent: u64, raw_name: ByteString) -> Result<()> {
+        self.spin_no_delay_local(move |fs, txn| {
+            let name = raw_name.clone();
+            Box::pin(async move {
+                if fs.journal {
+                    let ino = txn.lookup(parent, name.clone()).await?;
+                    txn.append_journal(
+                        fs.mount_id,
+                        JournalOp::Unlink {
+                            parent,
+                            name: name.to_string(),
+                            ino,
+                        },
+                    )
+                    .await?;
+                }
+                txn.unlink(parent, name, uid).await
+            })
+        })
+        .await
     }
 
     async fn rename(
         &self,
+        uid: u32,
         parent: u64,
         raw_name: ByteString,
         newparent: u64,
         new_raw_name: ByteString,
-        _flags: u32,
+        flags: u32,
     ) -> Result<()> {
         Self::check_file_name(&raw_name)?;
         Self::check_file_name(&new_raw_name)?;
-        self.spin_no_delay_local(move |_, txn| {
-            let name = raw_name.clone();
-            let new_name = new_raw_name.clone();
-            Box::pin(async move {
-                let ino = txn.lookup(parent, name.clone()).await?;
-                txn.link(ino, newparent, new_name).await?;
-                txn.unlink(parent, name).await
-            })
-        })
-        .await
+        let known = RENAME_NOREPLACE | RENAME_EXCHANGE;
+        if flags & !known != 0 || flags == known {
+            return Err(FsError::RenameFlags { flags });
+        }
+        if flags & RENAME_EXCHANGE != 0 {
+            return self
+                .exchange_entries(uid, parent, raw_name, newparent, new_raw_name)
+                .await;
+        }
+        let noreplace = flags & RENAME_NOREPLACE != 0;
+        self.move_entry(uid, parent, raw_name, newparent, new_raw_name, noreplace)
+            .await
     }
 
     #[tracing::instrument]
@@ -617,35 +3682,172 @@ impl AsyncFileSystem for TiFs {
         link: ByteString,
     ) -> Result<Entry> {
         Self::check_file_name(&name)?;
-        self.spin_no_delay_local(move |_, txn| {
-            let name = name.clone();
-            let link = link.clone();
-            Box::pin(async move {
-                let mut attr = txn
-                    .make_inode(
-                        parent,
-                        name,
-                        make_mode(FileType::Symlink, 0o777),
-                        gid,
-                        uid,
-                        0,
-                    )
-                    .await?;
+        let entry = self
+            .spin_no_delay_local(move |fs, txn| {
+                let name = name.clone();
+                let link = link.clone();
+                Box::pin(async move {
+                    let mut attr = txn
+                        .make_inode(
+                            parent,
+                            name.clone(),
+                            make_mode(FileType::Symlink, 0o777),
+                            gid,
+                            uid,
+                            0,
+                        )
+                        .await?;
 
-                txn.write_link(&mut attr, link.into_bytes()).await?;
-                Ok(Entry::new(attr.into(), 0))
+                    txn.write_link(&mut attr, link.into_bytes()).await?;
+                    if fs.journal {
+                        txn.append_journal(
+                            fs.mount_id,
+                            JournalOp::Create {
+                                parent,
+                                name: name.to_string(),
+                                ino: attr.ino,
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(fs.revalidated_entry(Entry::from(attr)))
+                })
             })
-        })
-        .await
+            .await?;
+        Ok(self.counted(entry))
     }
 
     async fn readlink(&self, ino: u64) -> Result<Data> {
-        self.spin_local(None, move |_, txn| {
-            Box::pin(async move { Ok(Data::new(txn.read_link(ino).await?)) })
+        if let Some(version) = self.resolve_version(ino) {
+            let (ino, ts) = version?;
+            let inode = SnapshotView::at(&self.client, ts, self.block_size)
+                .read_inode(ino)
+                .await?;
+            return Ok(Data::new(inode.inline_data.unwrap_or_default()));
+        }
+        self.spin_no_delay_local(move |fs, txn| {
+            Box::pin(async move {
+                let target = txn.read_link(ino).await?;
+                if !fs.contain_symlinks || !symlink::escapes(&target) {
+                    return Ok(Data::new(target));
+                }
+                let mut depth = 0;
+                let mut ancestor = txn.read_inode(ino).await?.parent;
+                while ancestor != ROOT_INODE {
+                    // inodes made before parent pointers are recorded point to 0
+                    if ancestor == 0 || depth == MAX_CONTAIN_DEPTH {
+                        return Err(FsError::UncontainedSymlink { ino });
+                    }
+                    ancestor = txn.read_inode(ancestor).await?.parent;
+                    depth += 1;
+                }
+                Ok(Data::new(symlink::contain(&target, depth)))
+            })
         })
         .await
     }
 
+    /// Copy between files within TiKV, the data never goes through the kernel and whole
+    /// blocks are not even decoded, see `Txn::copy_range`.
+    #[tracing::instrument]
+    async fn copy_file_range(
+        &self,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+    ) -> Result<Write> {
+        if VirtualInodes::is_virtual(ino_out) {
+            return Err(FsError::ReadOnlyVersion { ino: ino_out });
+        }
+        // historical versions are read by the kernel and written as usual
+        if VirtualInodes::is_virtual(ino_in) {
+            return Err(FsError::unimplemented());
+        }
+        if fh_in == Self::PIPE_FH {
+            return Err(FsError::NamedPipe { ino: ino_in });
+        }
+        if fh_out == Self::PIPE_FH {
+            return Err(FsError::NamedPipe { ino: ino_out });
+        }
+        self.flush_writes(ino_in, false).await?;
+        self.flush_writes(ino_out, false).await?;
+        let len = self
+            .spin_no_delay_local(move |fs, txn| {
+                Box::pin(async move {
+                    let len = txn
+                        .copy_range(ino_in, fh_in, offset_in, ino_out, fh_out, offset_out, len)
+                        .await?;
+                    if fs.journal && len > 0 {
+                        txn.append_journal(
+                            fs.mount_id,
+                            JournalOp::Write {
+                                ino: ino_out,
+                                offset: offset_out,
+                                len: len as u64,
+                            },
+                        )
+                        .await?;
+                    }
+                    Ok(len)
+                })
+            })
+            .await?;
+        Ok(Write::new(len as u32))
+    }
+
+    #[tracing::instrument(skip(in_data))]
+    async fn ioctl(
+        &self,
+        uid: u32,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: Vec<u8>,
+        out_size: u32,
+    ) -> Result<Ioctl> {
+        match cmd {
+            FS_IOC_GETFLAGS | FS_IOC32_GETFLAGS => {
+                let flags = self
+                    .spin_no_delay_local(move |_, txn| {
+                        Box::pin(async move { Ok(txn.read_inode(ino).await?.fs_flags) })
+                    })
+                    .await?;
+                Ok(Ioctl::new(flags::encode(flags, out_size)))
+            }
+            FS_IOC_SETFLAGS | FS_IOC32_SETFLAGS => {
+                let flags =
+                    flags::decode(&in_data).ok_or(FsError::UnsupportedIoctl { ino, cmd })?;
+                if flags & !SUPPORTED_FLAGS != 0 {
+                    return Err(FsError::UnsupportedFlags { ino, flags });
+                }
+                self.spin_no_delay_local(move |_, txn| {
+                    Box::pin(async move {
+                        let mut inode = txn.read_inode(ino).await?;
+                        if inode.fs_flags == flags {
+                            return Ok(());
+                        }
+                        // like CAP_LINUX_IMMUTABLE, only root may set or clear them
+                        if uid != 0 {
+                            return Err(FsError::FlagsPermission { ino });
+                        }
+                        inode.fs_flags = flags;
+                        inode.ctime = SystemTime::now();
+                        txn.save_inode(&inode).await
+                    })
+                })
+                .await?;
+                Ok(Ioctl::new(Vec::new()))
+            }
+            _ => Err(FsError::UnsupportedIoctl { ino, cmd }),
+        }
+    }
+
     #[tracing::instrument]
     async fn fallocate(
         &self,
@@ -653,16 +3855,232 @@ impl AsyncFileSystem for TiFs {
         fh: u64,
         offset: i64,
         length: i64,
-        _mode: i32,
+        mode: i32,
     ) -> Result<()> {
-        self.spin_no_delay_local(move |_, txn| {
+        self.flush_writes(ino, false).await?;
+        self.spin_no_delay_local(move |fs, txn| {
             Box::pin(async move {
                 let mut inode = txn.read_inode(ino).await?;
-                txn.fallocate(&mut inode, offset, length).await
+                if fs.fifo_passthrough && inode.kind == FileType::NamedPipe {
+                    return Err(FsError::NamedPipe { ino });
+                }
+                txn.fallocate(&mut inode, offset, length, mode).await?;
+                if fs.journal {
+                    let size = Some(inode.size);
+                    txn.append_journal(fs.mount_id, JournalOp::SetAttr { ino, size })
+                        .await?;
+                }
+                Ok(())
             })
         })
-        .await?;
-        Ok(())
+        .await
+    }
+
+    #[tracing::instrument(skip(value))]
+    async fn setxattr(
+        &self,
+        ino: u64,
+        name: ByteString,
+        value: Vec<u8>,
+        _flags: i32,
+        _position: u32,
+    ) -> Result<()> {
+        match &*name {
+            SEAL_XATTR => {
+                let root = self
+                    .spin_no_delay_local(move |_, txn| Box::pin(txn.seal(ino)))
+                    .await?;
+                info!("sealed inode({}) with Merkle root {}", ino, root);
+                Ok(())
+            }
+            WORM_XATTR => {
+                let worm: Worm = String::from_utf8_lossy(&value).parse()?;
+                self.spin_no_delay_local(move |_, txn| Box::pin(txn.set_worm(ino, worm)))
+                    .await
+            }
+            TTL_XATTR => {
+                let ttl: Ttl = String::from_utf8_lossy(&value).parse()?;
+                self.spin_no_delay_local(move |_, txn| Box::pin(txn.set_ttl(ino, Some(ttl))))
+                    .await
+            }
+            SHARDS_XATTR => {
+                let shards: DirShards = String::from_utf8_lossy(&value).parse()?;
+                self.spin_no_delay_local(move |_, txn| Box::pin(txn.set_dir_shards(ino, shards)))
+                    .await
+            }
+            PIN_XATTR => {
+                let level: PinLevel = String::from_utf8_lossy(&value).parse()?;
+                self.pin_dir(ino, level).await?;
+                self.refresh_pinned().await
+            }
+            ACL_ACCESS_XATTR | ACL_DEFAULT_XATTR => {
+                let acl = Acl::decode(&name, &value)?;
+                self.set_acl(ino, name, Some(acl)).await
+            }
+            _ => Err(FsError::UnsupportedXattr {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    #[tracing::instrument]
+    async fn getxattr(&self, ino: u64, name: ByteString, size: u32) -> Result<Xattr> {
+        let not_found = || FsError::XattrNotFound {
+            ino,
+            name: name.to_string(),
+        };
+        let value = match &*name {
+            SEAL_XATTR => {
+                self.spin_no_delay_local(move |_, txn| {
+                    Box::pin(async move {
+                        if txn.read_inode(ino).await?.seal.is_none() {
+                            return Err(FsError::XattrNotFound {
+                                ino,
+                                name: SEAL_XATTR.to_owned(),
+                            });
+                        }
+                        txn.verify_seal(ino).await
+                    })
+                })
+                .await?
+            }
+            WORM_XATTR => self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                .await?
+                .worm
+                .map(|worm| worm.grace.to_string())
+                .ok_or_else(not_found)?,
+            TTL_XATTR => self
+                .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                .await?
+                .ttl
+                .map(|ttl| ttl.secs.to_string())
+                .ok_or_else(not_found)?,
+            SHARDS_XATTR => Some(
+                self.spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                    .await?
+                    .dir_shards,
+            )
+            .filter(|shards| *shards > 0)
+            .map(|shards| shards.to_string())
+            .ok_or_else(not_found)?,
+            PIN_XATTR => self
+                .pins
+                .lock()
+                .unwrap()
+                .level(ino)
+                .map(|level| level.to_string())
+                .ok_or_else(not_found)?,
+            CONTENT_HASH_XATTR => {
+                self.spin_no_delay_local(move |_, txn| {
+                    Box::pin(async move {
+                        if txn.read_inode(ino).await?.kind != FileType::RegularFile {
+                            return Err(FsError::XattrNotFound {
+                                ino,
+                                name: CONTENT_HASH_XATTR.to_owned(),
+                            });
+                        }
+                        txn.content_hash(ino).await
+                    })
+                })
+                .await?
+            }
+            #[cfg(feature = "mem_store")]
+            MEM_USAGE_XATTR if ino == ROOT_INODE => {
+                self.entry_map.lock().unwrap().usage().to_string()
+            }
+            ACL_ACCESS_XATTR | ACL_DEFAULT_XATTR => {
+                let inode = self
+                    .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+                    .await?;
+                let acl = if name == ACL_ACCESS_XATTR {
+                    inode.acl
+                } else {
+                    inode.default_acl
+                };
+                return Self::reply_xattr(acl.ok_or_else(not_found)?.encode(), size);
+            }
+            _ => return Err(not_found()),
+        };
+        Self::reply_xattr(value.into_bytes(), size)
+    }
+
+    #[tracing::instrument]
+    async fn removexattr(&self, ino: u64, name: ByteString) -> Result<()> {
+        match &*name {
+            ACL_ACCESS_XATTR | ACL_DEFAULT_XATTR => self.set_acl(ino, name, None).await,
+            TTL_XATTR => {
+                self.spin_no_delay_local(move |_, txn| Box::pin(txn.set_ttl(ino, None)))
+                    .await
+            }
+            SHARDS_XATTR => {
+                self.spin_no_delay_local(move |_, txn| {
+                    Box::pin(txn.set_dir_shards(ino, DirShards(0)))
+                })
+                .await
+            }
+            PIN_XATTR => {
+                if !self.pins.lock().unwrap().unpin(ino) {
+                    return Err(FsError::XattrNotFound {
+                        ino,
+                        name: PIN_XATTR.to_owned(),
+                    });
+                }
+                info!("unpinned dir({})", ino);
+                Ok(())
+            }
+            _ => Err(FsError::UnsupportedXattr {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    #[tracing::instrument]
+    async fn listxattr(&self, ino: u64, size: u32) -> Result<Xattr> {
+        let inode = self
+            .spin_no_delay_local(move |_, txn| Box::pin(txn.read_inode(ino)))
+            .await?;
+        let mut names = Vec::new();
+        if inode.kind == FileType::RegularFile {
+            names.extend_from_slice(CONTENT_HASH_XATTR.as_bytes());
+            names.push(0);
+        }
+        if inode.seal.is_some() {
+            names.extend_from_slice(SEAL_XATTR.as_bytes());
+            names.push(0);
+        }
+        if inode.worm.is_some() {
+            names.extend_from_slice(WORM_XATTR.as_bytes());
+            names.push(0);
+        }
+        if inode.ttl.is_some() {
+            names.extend_from_slice(TTL_XATTR.as_bytes());
+            names.push(0);
+        }
+        if inode.dir_shards > 0 {
+            names.extend_from_slice(SHARDS_XATTR.as_bytes());
+            names.push(0);
+        }
+        if self.pins.lock().unwrap().level(ino).is_some() {
+            names.extend_from_slice(PIN_XATTR.as_bytes());
+            names.push(0);
+        }
+        if inode.acl.is_some() {
+            names.extend_from_slice(ACL_ACCESS_XATTR.as_bytes());
+            names.push(0);
+        }
+        if inode.default_acl.is_some() {
+            names.extend_from_slice(ACL_DEFAULT_XATTR.as_bytes());
+            names.push(0);
+        }
+        #[cfg(feature = "mem_store")]
+        {
+            if ino == ROOT_INODE {
+                names.extend_from_slice(MEM_USAGE_XATTR.as_bytes());
+                names.push(0);
+            }
+        }
+        Self::reply_xattr(names, size)
     }
 
     // TODO: Find an api to calculate total and available space on tikv.
@@ -670,7 +4088,7 @@ impl AsyncFileSystem for TiFs {
     async fn statfs(&self, _ino: u64) -> Result<StatFs> {
         let bsize = self.block_size as u32;
         let namelen = Self::MAX_NAME_LEN;
-        let (ffree, blocks, files) = self
+        let (ffree, counted) = self
             .spin_no_delay_local(move |_, txn| {
                 Box::pin(async move {
                     let next_inode = txn
@@ -678,20 +4096,14 @@ impl AsyncFileSystem for TiFs {
                         .await?
                         .map(|meta| meta.inode_next)
                         .unwrap_or(ROOT_INODE);
-                    let (b, f) = txn
-                        .scan(
-                            ScopedKey::inode_range(ROOT_INODE..next_inode),
-                            (next_inode - ROOT_INODE) as u32,
-                        )
-                        .await?
-                        .map(|pair| Inode::deserialize(pair.value()))
-                        .try_fold((0, 0), |(blocks, files), inode| {
-                            Ok::<_, FsError>((blocks + inode?.blocks, files + 1))
-                        })?;
-                    Ok((std::u64::MAX - next_inode, b, f))
+                    let (_, counted) = txn.volume_stats().await?;
+                    Ok((std::u64::MAX - next_inode, counted))
                 })
             })
             .await?;
+        // the usage is counted by the transactions rather than scanned
+        let files = counted.files.max(0) as u64;
+        let blocks = counted.blocks.max(0) as u64;
         Ok(StatFs::new(
             blocks,
             std::u64::MAX,
@@ -710,7 +4122,7 @@ impl AsyncFileSystem for TiFs {
         let bsize = self.block_size as u32;
         let namelen = Self::MAX_NAME_LEN;
 
-        let (ffree, blocks, files) = self
+        let (ffree, blocks, files, usage) = self
             .spin_no_delay_local(move |_, txn| {
                 Box::pin(async move {
                     let next_inode = txn
@@ -726,19 +4138,21 @@ impl AsyncFileSystem for TiFs {
                             Ok::<_, FsError>((blocks + inode?.blocks, files + 1))
                         },
                     )?;
-                    Ok((std::u64::MAX - next_inode, b, f))
+                    debug!("memory usage: {}", local.usage());
+                    let usage = local
+                        .capacity()
+                        .map(|capacity| (capacity, capacity.saturating_sub(local.used())));
+                    Ok((std::u64::MAX - next_inode, b, f, usage))
                 })
             })
             .await?;
+        // a bounded store reports its capacity and the bytes left, in blocks
+        let (blocks, bfree) = match usage {
+            Some((capacity, free)) => (capacity / self.block_size, free / self.block_size),
+            None => (blocks, std::u64::MAX),
+        };
         Ok(StatFs::new(
-            blocks,
-            std::u64::MAX,
-            std::u64::MAX,
-            files,
-            ffree,
-            bsize,
-            namelen,
-            0,
+            blocks, bfree, bfree, files, ffree, bsize, namelen, 0,
         ))
     }
 
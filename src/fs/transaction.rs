@@ -1,36 +1,157 @@
-use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
 use bytes::Bytes;
 use bytestring::ByteString;
 use fuser::{FileAttr, FileType};
-use tikv_client::{Key, Transaction, TransactionClient, Value};
-use tracing::{debug, debug_span, trace};
+use tikv_client::{
+    BoundRange, Key, KvPair, Result as TiKvResult, Transaction, TransactionClient, Value,
+};
+use tracing::{debug, debug_span, trace, warn};
 use tracing_attributes::instrument;
 use tracing_libatrace::InstrumentExt;
 
+use super::atime::AtimeBatch;
+use super::batch::{resolve, resolve_parent, Batch, BatchOp};
 use super::block::empty_block;
+use super::delegation::WriteCheck;
+use super::deletion::DeadExtent;
 use super::dir::Directory;
+use super::disk_cache::DiskCache;
 use super::error::{FsError, Result};
 use super::file_handler::FileHandler;
-use super::index::Index;
+use super::hash::ContentHasher;
+use super::index::{hashed_name, HashedIndex, Index};
 use super::inode::Inode;
+use super::inode_cache::InodeCache;
+use super::journal::{JournalEntry, JournalOp};
 use super::key::{ScopedKey, ROOT_INODE};
-use super::meta::Meta;
-use super::mode::{as_file_kind, as_file_perm, make_mode};
+use super::lookup::LookupCounts;
+use super::mem_store::{LocalView, LocalWrites, MemStore};
+use super::meta::{InodePool, Meta};
+use super::mode::{as_file_kind, as_file_perm, is_device, make_mode};
+use super::pin::Touched;
+use super::posix::PosixMode;
+use super::registry::{Client, Freeze};
 use super::reply::DirItem;
+use super::safepoint::SafePoint;
+use super::seal::{digest, hex, Seal};
+use super::shard::{DirShards, MAX_DIR_SHARDS};
+use super::stats::{is_usage_row, OpStats, UsageDelta, UsageRebase, VolumeStats, USAGE_BASE_ROW};
+use super::ttl::Ttl;
+use super::worm::Worm;
+
+/// Block index of a key from scanning a block range.
+fn block_of(key: &Key) -> u64 {
+    if let Ok(ScopedKey::Block { ino: _, block }) = ScopedKey::parse(key.into()) {
+        block
+    } else {
+        unreachable!("the keys from scanning should be always valid block keys")
+    }
+}
+
+/// Targets of symlinks are paths, at most `PATH_MAX` bytes with the terminating nul.
+fn check_link_target(target: &[u8]) -> Result<()> {
+    if target.len() < libc::PATH_MAX as usize {
+        Ok(())
+    } else {
+        Err(FsError::NameTooLong {
+            file: String::from_utf8_lossy(target).into_owned(),
+        })
+    }
+}
+
+/// Last generation of a released inode number, kept as the value of its free inode key.
+fn released_generation(value: &[u8]) -> u64 {
+    value
+        .array_chunks()
+        .next()
+        .copied()
+        .map(u64::from_be_bytes)
+        .unwrap_or_default()
+}
+
+/// Blocks fetched by one scan while hashing the content of a file.
+const HASH_SCAN_LIMIT: u32 = 1 << 8;
+
+/// Safepoints are registered by few services.
+const SAFEPOINT_SCAN_LIMIT: u32 = 1 << 10;
+
+/// Ancestors walked up by `rename` before it gives up on a loop.
+const MAX_RENAME_DEPTH: usize = 1 << 12;
+
+/// Blocks copied by one `copy_range`, callers of `copy_file_range` go on after short copies.
+const COPY_RANGE_BLOCKS: u64 = 1 << 8;
+
+/// Modes of `fallocate` as Linux defines them, other platforms do not pass them.
+const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+const FALLOC_FL_PUNCH_HOLE: i32 = 0x02;
+const FALLOC_FL_ZERO_RANGE: i32 = 0x10;
 
 pub struct Txn {
     txn: Transaction,
     block_size: u64,
+    touched: Touched,
+    inode_pool: Option<Arc<InodePool>>,
+    /// Inode numbers reserved by this transaction, moved into the pool once it commits.
+    reserved: Option<Range<u64>>,
+    /// Whether the numbers of removed inodes are released and taken again.
+    reuse_inodes: bool,
+    /// Whether small files are kept in their inodes.
+    inline: bool,
+    /// Whether index keys hold hashes of the names, see `HashedIndex`.
+    hashed_names: bool,
+    /// Access times recorded by reads instead of saving the inodes.
+    atimes: Option<Arc<AtimeBatch>>,
+    /// Access times of the batch saved by this transaction, settled once it commits.
+    saved_atimes: Vec<(u64, SystemTime)>,
+    /// How closely POSIX is followed, see `PosixMode`.
+    posix: PosixMode,
+    /// Blocks fetched by one scan of a read, see `-o scan_page`.
+    scan_page: u32,
+    /// Inodes the kernel refers to, kept after their last link is gone.
+    lookups: Option<Arc<LookupCounts>>,
+    /// Files and blocks saved and removed, added to the volume statistics once it commits.
+    usage: UsageDelta,
+    /// Inodes cached by the mount, see `-o inode_cache`.
+    inode_cache: Option<Arc<InodeCache>>,
+    /// Epoch of the inode cache when the transaction begins.
+    cache_epoch: u64,
+    /// Inodes taken from the inode cache, checked against the stored ones when they are saved.
+    cache_served: Mutex<HashMap<u64, Value>>,
+    /// Inodes saved or removed, settled in the inode cache once it commits.
+    cache_saved: HashMap<u64, Option<Value>>,
+    /// Values fetched ahead by `prefetch` and not written since, `None` if the key is absent.
+    prefetched: Mutex<HashMap<Key, Option<Value>>>,
+    /// Whether keys of the volume are written, beyond those coordinating the mounts.
+    writes_volume: bool,
 }
 
 pub struct LocalTxn {
-    pub(super) entry_map: Arc<Mutex<BTreeMap<Key, Value>>>,
+    pub(super) entry_map: Arc<Mutex<MemStore>>,
     block_size: u64,
+    writes: LocalWrites,
+    touched: Touched,
+    inode_pool: Option<Arc<InodePool>>,
+    /// Inode numbers reserved by this transaction, moved into the pool once it commits.
+    reserved: Option<Range<u64>>,
+    /// Whether the numbers of removed inodes are released and taken again.
+    reuse_inodes: bool,
+    /// Whether small files are kept in their inodes.
+    inline: bool,
+    /// Whether index keys hold hashes of the names, see `HashedIndex`.
+    hashed_names: bool,
+    /// Access times recorded by reads instead of saving the inodes.
+    atimes: Option<Arc<AtimeBatch>>,
+    /// Access times of the batch saved by this transaction, settled once it commits.
+    saved_atimes: Vec<(u64, SystemTime)>,
+    /// How closely POSIX is followed, see `PosixMode`.
+    posix: PosixMode,
+    /// Inodes the kernel refers to, kept after their last link is gone.
+    lookups: Option<Arc<LookupCounts>>,
 }
 
 impl Txn {
@@ -44,6 +165,161 @@ impl Txn {
         self.block_size
     }
 
+    /// Take the numbers of new inodes from `pool`, see `InodePool`.
+    pub fn with_inode_pool(mut self, pool: Arc<InodePool>) -> Self {
+        self.inode_pool = Some(pool);
+        self
+    }
+
+    /// Release the numbers of removed inodes and take the lowest released number for new
+    /// inodes, see `-o inode_reuse`.
+    pub fn with_inode_reuse(mut self, reuse: bool) -> Self {
+        self.reuse_inodes = reuse;
+        self
+    }
+
+    /// Keep the data of every file in blocks, moving inline data out of the inodes written,
+    /// see `-o no_inline`. Symlink targets stay in their inodes.
+    pub fn with_inline_data(mut self, inline: bool) -> Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Key the entries of directories by hashes of their names, see `hashed_names`.
+    pub fn with_hashed_names(mut self, hashed: bool) -> Self {
+        self.hashed_names = hashed;
+        self
+    }
+
+    /// Record the access times of reads in `batch` instead of saving the inodes, see
+    /// `AtimeBatch`.
+    pub fn with_atime_batch(mut self, batch: Arc<AtimeBatch>) -> Self {
+        self.atimes = Some(batch);
+        self
+    }
+
+    /// Follow POSIX as closely as `posix` asks, see `-o posix`. A strict transaction saves
+    /// access times itself instead of recording them in the atime batch.
+    pub fn with_posix(mut self, posix: PosixMode) -> Self {
+        self.posix = posix;
+        self
+    }
+
+    /// Keep the inodes referred to by the kernel of the mount after their last link and
+    /// handle are gone, see `LookupCounts`.
+    pub fn with_lookups(mut self, lookups: Arc<LookupCounts>) -> Self {
+        self.lookups = Some(lookups);
+        self
+    }
+
+    /// Take the inodes read from the inode cache of the mount and settle the inodes saved
+    /// in it, see `InodeCache`.
+    pub fn with_inode_cache(mut self, cache: Option<Arc<InodeCache>>) -> Self {
+        self.cache_epoch = cache.as_ref().map_or(0, |cache| cache.epoch());
+        self.inode_cache = cache;
+        self
+    }
+
+    /// Replace the inodes saved by this transaction in the inode cache, once it commits.
+    pub fn settle_inode_cache(&mut self) {
+        if let Some(cache) = &self.inode_cache {
+            cache.settle(std::mem::take(&mut self.cache_saved), self.cache_epoch);
+        }
+    }
+
+    /// Scan the blocks of reads `page` at a time, which bounds the size of the responses of
+    /// TiKV, see `-o scan_page`.
+    pub fn with_scan_page(mut self, page: u32) -> Self {
+        self.scan_page = page;
+        self
+    }
+
+    /// The inode numbers reserved but not used by this transaction.
+    pub fn take_reserved(&mut self) -> Option<Range<u64>> {
+        self.reserved.take()
+    }
+
+    /// The access times of the batch saved by this transaction, see `AtimeBatch::settle`.
+    pub fn take_saved_atimes(&mut self) -> Vec<(u64, SystemTime)> {
+        std::mem::take(&mut self.saved_atimes)
+    }
+
+    /// Count the files and blocks saved and removed by this transaction in a usage row, in the
+    /// transaction itself so the counters never drift from the inodes, see `VolumeStats::files`.
+    pub async fn save_usage(&mut self) -> Result<()> {
+        let delta = std::mem::take(&mut self.usage);
+        if delta.is_empty() {
+            return Ok(());
+        }
+        let mut stats = self
+            .read_volume_stats(delta.row)
+            .await?
+            .unwrap_or_default();
+        stats.add_usage(delta);
+        self.save_volume_stats(delta.row, &stats).await
+    }
+
+    /// Record the access time of a read, in the atime batch if there is one.
+    async fn touch_atime(&mut self, inode: &mut Inode) -> Result<()> {
+        inode.atime = SystemTime::now();
+        if let Some(batch) = self.atimes.as_ref().filter(|_| !self.posix.is_strict()) {
+            batch.touch(inode.ino, inode.atime);
+            return Ok(());
+        }
+        self.save_inode(inode).await
+    }
+
+    /// Apply the access time recorded in the atime batch to an inode read.
+    fn apply_pending_atime(&self, inode: &mut Inode) {
+        if let Some(atime) = self
+            .atimes
+            .as_ref()
+            .and_then(|batch| batch.pending(inode.ino))
+        {
+            inode.atime = inode.atime.max(atime);
+        }
+    }
+
+    /// Remember that the access time of the batch is saved with `inode`.
+    fn save_pending_atime(&mut self, inode: &Inode) {
+        let pending = self
+            .atimes
+            .as_ref()
+            .and_then(|batch| batch.pending(inode.ino));
+        if pending.is_some() {
+            self.saved_atimes.push((inode.ino, inode.atime));
+        }
+    }
+
+    /// Number and generation of a new inode, the lowest released number if inodes are reused,
+    /// or from the numbers reserved by the mount if there are any. Otherwise it is taken from
+    /// the meta, with a batch reserved for the pool.
+    async fn next_ino(&mut self) -> Result<(u64, u64)> {
+        if self.reuse_inodes {
+            if let Some((ino, generation)) = self.take_free_ino().await? {
+                return Ok((ino, generation + 1));
+            }
+        }
+        if let Some(ino) = self.reserved.as_mut().and_then(Iterator::next) {
+            return Ok((ino, 0));
+        }
+        if let Some(ino) = self.inode_pool.as_ref().and_then(|pool| pool.take()) {
+            return Ok((ino, 0));
+        }
+        let mut meta = self
+            .read_meta()
+            .await?
+            .unwrap_or_else(|| Meta::new(self.block_size));
+        let ino = meta.inode_next;
+        meta.inode_next += 1;
+        if self.inode_pool.is_some() {
+            self.reserved = Some(meta.inode_next..meta.inode_next + InodePool::BATCH);
+            meta.inode_next += InodePool::BATCH;
+        }
+        self.save_meta(&meta).await?;
+        Ok((ino, 0))
+    }
+
     pub async fn begin_optimistic(client: &TransactionClient, block_size: u64) -> Result<Self> {
         Ok(Txn {
             txn: client
@@ -51,14 +327,156 @@ impl Txn {
                 .instrument(debug_span!("begin_optimistic"))
                 .await?,
             block_size,
+            touched: Touched::default(),
+            inode_pool: None,
+            reserved: None,
+            reuse_inodes: false,
+            inline: true,
+            hashed_names: false,
+            atimes: None,
+            saved_atimes: Vec::new(),
+            posix: PosixMode::default(),
+            scan_page: u32::MAX,
+            lookups: None,
+            usage: UsageDelta::default(),
+            inode_cache: None,
+            cache_epoch: 0,
+            cache_served: Mutex::default(),
+            cache_saved: HashMap::new(),
+            prefetched: Mutex::default(),
+            writes_volume: false,
         })
     }
 
+    pub async fn get(&self, key: impl Into<Key>) -> TiKvResult<Option<Value>> {
+        let key = key.into();
+        if let Some(value) = self.prefetched.lock().unwrap().get(&key) {
+            return Ok(value.clone());
+        }
+        let value = self.txn.get(key).await?;
+        OpStats::update(|stats| {
+            stats.gets += 1;
+            stats.read_bytes += value.as_ref().map(Vec::len).unwrap_or(0) as u64;
+        });
+        Ok(value)
+    }
+
+    /// Get the existing ones of `keys` in one request.
+    pub async fn batch_get(
+        &self,
+        keys: impl IntoIterator<Item = impl Into<Key>>,
+    ) -> TiKvResult<impl Iterator<Item = KvPair>> {
+        let pairs: Vec<KvPair> = self.txn.batch_get(keys).await?.collect();
+        OpStats::update(|stats| {
+            stats.gets += 1;
+            stats.read_bytes += pairs
+                .iter()
+                .map(|pair| pair.value().len() as u64)
+                .sum::<u64>();
+        });
+        Ok(pairs.into_iter())
+    }
+
+    pub async fn scan(
+        &self,
+        range: impl Into<BoundRange>,
+        limit: u32,
+    ) -> TiKvResult<impl Iterator<Item = KvPair>> {
+        let pairs: Vec<KvPair> = self.txn.scan(range, limit).await?.collect();
+        OpStats::update(|stats| {
+            stats.scans += 1;
+            stats.read_bytes += pairs
+                .iter()
+                .map(|pair| pair.value().len() as u64)
+                .sum::<u64>();
+        });
+        Ok(pairs.into_iter())
+    }
+
+    /// The stored blocks of `ino` in `blocks`, scanned `scan_page` blocks at a time.
+    pub async fn scan_blocks(&self, ino: u64, blocks: Range<u64>) -> Result<Vec<KvPair>> {
+        let mut pairs = Vec::new();
+        let mut next = blocks.start;
+        while next < blocks.end {
+            let page = (blocks.end - next).min(self.scan_page as u64);
+            let range = ScopedKey::block_range(ino, next..next + page);
+            pairs.extend(self.scan(range, page as u32).await?);
+            next += page;
+        }
+        Ok(pairs)
+    }
+
+    pub async fn put(&mut self, key: impl Into<Key>, value: impl Into<Value>) -> TiKvResult<()> {
+        let key = key.into();
+        let value = value.into();
+        OpStats::update(|stats| {
+            stats.puts += 1;
+            stats.written_bytes += value.len() as u64;
+        });
+        self.touched.record((&key).into());
+        self.prefetched.get_mut().unwrap().remove(&key);
+        self.writes_volume |= !ScopedKey::is_coordination((&key).into());
+        self.txn.put(key, value).await
+    }
+
+    pub async fn delete(&mut self, key: impl Into<Key>) -> TiKvResult<()> {
+        let key = key.into();
+        OpStats::update(|stats| stats.deletes += 1);
+        self.touched.record((&key).into());
+        self.prefetched.get_mut().unwrap().remove(&key);
+        self.writes_volume |= !ScopedKey::is_coordination((&key).into());
+        self.txn.delete(key).await
+    }
+
+    /// Whether the transaction writes keys of the volume, so its commit is held by freezes.
+    pub fn writes_volume(&self) -> bool {
+        self.writes_volume
+    }
+
+    /// Fetch `keys` in one request, for the gets of them which follow.
+    async fn prefetch(&self, mut keys: Vec<Key>) -> TiKvResult<()> {
+        keys.sort();
+        keys.dedup();
+        let mut fetched: HashMap<Key, Option<Value>> =
+            keys.iter().map(|key| (key.clone(), None)).collect();
+        for pair in self.batch_get(keys).await? {
+            let key = pair.key().clone();
+            fetched.insert(key, Some(pair.into_value()));
+        }
+        self.prefetched.lock().unwrap().extend(fetched);
+        Ok(())
+    }
+
+    /// Fetch the handler `fh` of `ino` along with the inode, unless it is in the inode cache,
+    /// as the data operations read both.
+    async fn prefetch_handle(&self, ino: u64, fh: u64) -> Result<()> {
+        let handler = Key::from(ScopedKey::handler(ino, fh));
+        if self.prefetched.lock().unwrap().contains_key(&handler) {
+            return Ok(());
+        }
+        let mut keys = vec![handler];
+        let cached = self
+            .inode_cache
+            .as_ref()
+            .and_then(|cache| cache.get(ino))
+            .is_some();
+        if !cached {
+            keys.push(Key::from(ScopedKey::inode(ino)));
+        }
+        Ok(self.prefetch(keys).await?)
+    }
+
+    /// The inodes and directories written so far, see `PinCache::invalidate`.
+    pub fn take_touched(&mut self) -> Touched {
+        std::mem::take(&mut self.touched)
+    }
+
     #[instrument]
-    pub async fn open(&mut self, ino: u64) -> Result<u64> {
+    pub async fn open(&mut self, ino: u64, flags: i32, owner: u64) -> Result<u64> {
         let mut inode = self.read_inode(ino).await?;
         let fh = inode.next_fh;
-        self.save_fh(ino, fh, &FileHandler::default()).await?;
+        self.save_fh(ino, fh, &FileHandler::with_flags(0, flags).owned_by(owner))
+            .await?;
         inode.next_fh += 1;
         inode.opened_fh += 1;
         self.save_inode(&inode).await?;
@@ -67,11 +485,18 @@ impl Txn {
 
     #[instrument]
     pub async fn close(&mut self, ino: u64, fh: u64) -> Result<()> {
+        self.prefetch_handle(ino, fh).await?;
         self.read_fh(ino, fh).await?;
         self.delete(ScopedKey::handler(ino, fh)).await?;
 
         let mut inode = self.read_inode(ino).await?;
-        inode.opened_fh -= 1;
+        if inode.opened_fh == 0 {
+            warn!(
+                "no handler of inode({}) is counted open when closing {}",
+                ino, fh
+            );
+        }
+        inode.opened_fh = inode.opened_fh.saturating_sub(1);
         self.save_inode(&inode).await
     }
 
@@ -93,6 +518,7 @@ impl Txn {
 
     #[instrument]
     pub async fn read(&mut self, ino: u64, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>> {
+        self.prefetch_handle(ino, fh).await?;
         let handler = self.read_fh(ino, fh).await?;
         let start = handler.cursor as i64 + offset;
         if start < 0 {
@@ -101,23 +527,172 @@ impl Txn {
                 offset: start,
             });
         }
+        handler.check_direct_io(ino, start as u64, size as u64)?;
         self.read_data(ino, start as u64, Some(size as u64)).await
     }
 
+    /// Read like `read`, serving whole blocks from and filling them into `cache`.
+    #[instrument(skip(cache))]
+    pub async fn read_cached(
+        &mut self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        cache: &DiskCache,
+    ) -> Result<Vec<u8>> {
+        self.prefetch_handle(ino, fh).await?;
+        let inode = self.read_inode(ino).await?;
+        if inode.inline_data.is_some() {
+            return self.read(ino, fh, offset, size).await;
+        }
+        let handler = self.read_fh(ino, fh).await?;
+        let start = handler.cursor as i64 + offset;
+        if start >= 0 {
+            handler.check_direct_io(ino, start as u64, size as u64)?;
+            if let Some(data) = cache.read(&inode, start as u64, size as u64).await {
+                trace!("read inode({}) from disk cache", ino);
+                return Ok(data);
+            }
+        }
+        let data = self.read(ino, fh, offset, size).await?;
+        cache.fill(&inode, start as u64, &data).await;
+        Ok(data)
+    }
+
     #[instrument(skip(data))]
     pub async fn write(&mut self, ino: u64, fh: u64, offset: i64, data: Bytes) -> Result<usize> {
+        self.prefetch_handle(ino, fh).await?;
         let handler = self.read_fh(ino, fh).await?;
-        let start = handler.cursor as i64 + offset;
+        // the size known to the kernel may be stale, another mount may have appended since
+        let start = if handler.is_append() {
+            self.read_inode(ino).await?.size as i64
+        } else {
+            handler.cursor as i64 + offset
+        };
         if start < 0 {
             return Err(FsError::InvalidOffset {
                 ino: ino,
                 offset: start,
             });
         }
+        handler.check_direct_io(ino, start as u64, data.len() as u64)?;
 
         self.write_data(ino, start as u64, data).await
     }
 
+    /// Check that the handler `fh` of `ino` may write, for the writes to be buffered until the
+    /// handler or the inode changes, see `WriteCheck`.
+    #[instrument]
+    pub async fn check_write(&mut self, ino: u64, fh: u64) -> Result<WriteCheck> {
+        self.prefetch_handle(ino, fh).await?;
+        let handler = self.read_fh(ino, fh).await?;
+        let inode = self.read_inode(ino).await?;
+        inode.ensure_mutable()?;
+        Ok(WriteCheck::new(&handler, &inode))
+    }
+
+    /// Copy `len` bytes of `ino_in` at `offset_in` to `ino_out` at `offset_out`, relative to
+    /// the cursors of their handlers like `read` and `write`. Whole blocks at aligned offsets
+    /// are copied as they are stored, holes included, the rest is read and written. Returns
+    /// the copied bytes, fewer than `len` at the end of the source or beyond
+    /// `COPY_RANGE_BLOCKS`.
+    #[instrument]
+    pub async fn copy_range(
+        &mut self,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+    ) -> Result<usize> {
+        self.prefetch(vec![
+            ScopedKey::handler(ino_in, fh_in).into(),
+            ScopedKey::handler(ino_out, fh_out).into(),
+            ScopedKey::inode(ino_in).into(),
+            ScopedKey::inode(ino_out).into(),
+        ])
+        .await?;
+        let start_in = self.read_fh(ino_in, fh_in).await?.cursor as i64 + offset_in;
+        if start_in < 0 {
+            return Err(FsError::InvalidOffset {
+                ino: ino_in,
+                offset: start_in,
+            });
+        }
+        let start_out = self.read_fh(ino_out, fh_out).await?.cursor as i64 + offset_out;
+        if start_out < 0 {
+            return Err(FsError::InvalidOffset {
+                ino: ino_out,
+                offset: start_out,
+            });
+        }
+        let (start_in, start_out) = (start_in as u64, start_out as u64);
+        let source = self.read_inode(ino_in).await?;
+        let len = len
+            .min(source.size.saturating_sub(start_in))
+            .min(COPY_RANGE_BLOCKS * self.block_size);
+        if len == 0 {
+            return Ok(0);
+        }
+        if ino_in == ino_out && start_in < start_out + len && start_out < start_in + len {
+            return Err(FsError::InvalidOffset {
+                ino: ino_out,
+                offset: start_out as i64,
+            });
+        }
+        let blocks = len / self.block_size;
+        let aligned = start_in % self.block_size == 0 && start_out % self.block_size == 0;
+        if !aligned || blocks == 0 || source.inline_data.is_some() {
+            let data = self.read_data(ino_in, start_in, Some(len)).await?;
+            return self.write_data(ino_out, start_out, data.into()).await;
+        }
+
+        let mut inode = self.read_inode(ino_out).await?;
+        inode.ensure_mutable()?;
+        inode.ensure_appendable(start_out)?;
+        if self.posix.is_strict() {
+            inode.kill_suid();
+        }
+        if inode.inline_data.is_some() {
+            self.transfer_inline_data_to_block(&mut inode).await?;
+        }
+        let first_in = start_in / self.block_size;
+        let first_out = start_out / self.block_size;
+        let mut stored = HashMap::new();
+        for pair in self
+            .scan_blocks(ino_in, first_in..first_in + blocks)
+            .await?
+        {
+            if let ScopedKey::Block { ino: _, block } = ScopedKey::parse(pair.key().into())? {
+                stored.insert(block, pair.value().clone());
+            }
+        }
+        for i in 0..blocks {
+            let key = ScopedKey::block(ino_out, first_out + i);
+            match stored.remove(&(first_in + i)) {
+                Some(value) => self.put(key, value).await?,
+                None => self.delete(key).await?,
+            }
+        }
+        let copied = blocks * self.block_size;
+        inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.data_changed();
+        inode.set_size(inode.size.max(start_out + copied), self.block_size);
+        self.save_inode(&inode).await?;
+        if copied < len {
+            let data = self
+                .read_data(ino_in, start_in + copied, Some(len - copied))
+                .await?;
+            self.write_data(ino_out, start_out + copied, data.into())
+                .await?;
+        }
+        Ok(len as usize)
+    }
+
     #[instrument]
     pub async fn make_inode(
         &mut self,
@@ -128,17 +703,14 @@ impl Txn {
         uid: u32,
         rdev: u32,
     ) -> Result<Inode> {
-        let mut meta = self
-            .read_meta()
-            .await?
-            .unwrap_or_else(|| Meta::new(self.block_size));
-        let ino = meta.inode_next;
-        meta.inode_next += 1;
-
-        debug!("get ino({})", ino);
-        self.save_meta(&meta).await?;
+        if parent >= ROOT_INODE {
+            self.read_inode(parent).await?.ensure_mutable()?;
+        }
+        let (ino, generation) = self.next_ino().await?;
+        debug!("get ino({}) of generation {}", ino, generation);
 
-        let file_type = as_file_kind(mode);
+        let file_type = as_file_kind(mode).ok_or(FsError::UnknownFileType)?;
+        let mut dir_inode = None;
         if parent >= ROOT_INODE {
             if self.get_index(parent, name.clone()).await?.is_some() {
                 return Err(FsError::FileExist {
@@ -156,11 +728,11 @@ impl Txn {
                 typ: file_type,
             });
 
-            self.save_dir(parent, &dir).await?;
+            dir_inode = Some(self.save_dir(parent, &dir).await?);
             // TODO: update attributes of directory
         }
 
-        let inode = FileAttr {
+        let mut inode: Inode = FileAttr {
             ino,
             size: 0,
             blocks: 0,
@@ -169,25 +741,50 @@ impl Txn {
             ctime: SystemTime::now(),
             crtime: SystemTime::now(),
             kind: file_type,
-            perm: as_file_perm(mode),
+            perm: if file_type == FileType::Directory {
+                (mode & 0o7777) as _
+            } else {
+                as_file_perm(mode)
+            },
             nlink: 1,
             uid,
             gid,
-            rdev,
+            rdev: if is_device(file_type) { rdev } else { 0 },
             blksize: self.block_size as u32,
             padding: 0,
             flags: 0,
         }
         .into();
+        inode.generation = generation;
+        inode.parent = parent;
+
+        if let Some(dir) = dir_inode {
+            if let Some(worm) = dir.worm {
+                if file_type == FileType::Directory {
+                    inode.worm = Some(worm);
+                } else {
+                    inode.worm_after = Some(worm.lock_time(SystemTime::now()));
+                }
+            }
+            inode.inherit(&dir);
+        }
 
         debug!("made inode ({:?})", &inode);
 
         self.save_inode(&inode).await?;
+        if inode.ttl.is_some() {
+            self.save_scratch(ino, true).await?;
+        }
         Ok(inode.into())
     }
 
     #[instrument]
     pub async fn get_index(&self, parent: u64, name: ByteString) -> Result<Option<u64>> {
+        if self.hashed_names {
+            let hashed = hashed_name(&name);
+            let index = self.get_hashed_index(parent, &hashed).await?;
+            return Ok(index.and_then(|index| index.get(&name)));
+        }
         let key = ScopedKey::index(parent, &name);
         self.get(key)
             .await
@@ -201,6 +798,16 @@ impl Txn {
 
     #[instrument]
     pub async fn set_index(&mut self, parent: u64, name: ByteString, ino: u64) -> Result<()> {
+        if self.hashed_names {
+            let hashed = hashed_name(&name);
+            let mut index = self
+                .get_hashed_index(parent, &hashed)
+                .await?
+                .unwrap_or_default();
+            index.insert(&name, ino);
+            let value = index.serialize()?;
+            return Ok(self.put(ScopedKey::index(parent, &hashed), value).await?);
+        }
         let key = ScopedKey::index(parent, &name);
         let value = Index::new(ino).serialize()?;
         Ok(self.put(key, value).await?)
@@ -208,83 +815,645 @@ impl Txn {
 
     #[instrument]
     pub async fn remove_index(&mut self, parent: u64, name: ByteString) -> Result<()> {
+        if self.hashed_names {
+            let hashed = hashed_name(&name);
+            let mut index = match self.get_hashed_index(parent, &hashed).await? {
+                Some(index) => index,
+                None => return Ok(()),
+            };
+            index.remove(&name);
+            let key = ScopedKey::index(parent, &hashed);
+            if index.is_empty() {
+                return Ok(self.delete(key).await?);
+            }
+            let value = index.serialize()?;
+            return Ok(self.put(key, value).await?);
+        }
         let key = ScopedKey::index(parent, &name);
         Ok(self.delete(key).await?)
     }
 
+    /// The entries whose names hash to `hashed`, on volumes with `hashed_names`.
+    async fn get_hashed_index(&self, parent: u64, hashed: &str) -> Result<Option<HashedIndex>> {
+        self.get(ScopedKey::index(parent, hashed))
+            .await?
+            .map(|data| HashedIndex::deserialize(&data))
+            .transpose()
+    }
+
+    /// Whether any directory of the volume has an entry.
+    pub async fn has_index(&self) -> Result<bool> {
+        Ok(self
+            .scan(ScopedKey::index_range(), 1)
+            .await?
+            .next()
+            .is_some())
+    }
+
     #[instrument]
     pub async fn read_inode(&self, ino: u64) -> Result<Inode> {
-        let value = self
-            .get(ScopedKey::inode(ino))
+        let value = match self.cached_inode(ino) {
+            Some(value) => value,
+            None => {
+                let value = self
+                    .get(ScopedKey::inode(ino))
+                    .await?
+                    .ok_or_else(|| FsError::InodeNotFound { inode: ino })?;
+                if let Some(cache) = self.inode_cache.as_ref() {
+                    if !self.cache_saved.contains_key(&ino) {
+                        cache.fill(ino, value.clone(), self.cache_epoch);
+                    }
+                }
+                value
+            }
+        };
+        let mut inode = Inode::deserialize(&value)?;
+        self.apply_pending_atime(&mut inode);
+        Ok(inode)
+    }
+
+    /// The inode `ino` from the inode cache, unless this transaction has saved or removed it.
+    fn cached_inode(&self, ino: u64) -> Option<Value> {
+        if self.cache_saved.contains_key(&ino) {
+            return None;
+        }
+        let value = self.inode_cache.as_ref()?.get(ino)?;
+        self.cache_served.lock().unwrap().insert(ino, value.clone());
+        Some(value)
+    }
+
+    /// Read the inodes of `inos` in one request, in the same order.
+    #[instrument]
+    pub async fn read_inodes(&self, inos: &[u64]) -> Result<Vec<Inode>> {
+        let mut found = HashMap::with_capacity(inos.len());
+        for pair in self
+            .batch_get(inos.iter().map(|ino| ScopedKey::inode(*ino)))
             .await?
-            .ok_or_else(|| FsError::InodeNotFound { inode: ino })?;
-        Ok(Inode::deserialize(&value)?)
+        {
+            let inode = Inode::deserialize(pair.value())?;
+            found.insert(inode.ino, inode);
+        }
+        inos.iter()
+            .map(|ino| {
+                found
+                    .get(ino)
+                    .cloned()
+                    .ok_or_else(|| FsError::InodeNotFound { inode: *ino })
+            })
+            .collect()
     }
 
     #[instrument(skip(inode))]
     pub async fn save_inode(&mut self, inode: &Inode) -> Result<()> {
         let key = ScopedKey::inode(inode.ino);
+        let before = self.stored_blocks(inode.ino).await?;
 
         if inode.nlink == 0 && inode.opened_fh == 0 {
+            if let Some(mount_id) = self.held_by_kernel(inode.ino) {
+                let mut orphan = inode.clone();
+                orphan.held_by = mount_id;
+                let value = orphan.serialize()?;
+                self.cache_change(inode.ino, Some(&value));
+                self.put(key, value).await?;
+                self.usage.change(inode.ino, before, Some(orphan.blocks));
+                debug!(
+                    "keep inode({}) for the kernel of mount({})",
+                    inode.ino, mount_id
+                );
+                return Ok(());
+            }
             self.delete(key).await?;
+            self.cache_change(inode.ino, None);
+            self.usage.change(inode.ino, before, None);
+            self.queue_deletion(inode).await?;
         } else {
-            self.put(key, inode.serialize()?).await?;
+            let value = inode.serialize()?;
+            self.cache_change(inode.ino, Some(&value));
+            self.put(key, value).await?;
+            self.usage.change(inode.ino, before, Some(inode.blocks));
+            self.save_pending_atime(inode);
             debug!("save inode: {:?}", inode);
         }
         Ok(())
     }
 
-    #[instrument]
-    pub async fn remove_inode(&mut self, ino: u64) -> Result<()> {
-        self.delete(ScopedKey::inode(ino)).await?;
-        Ok(())
+    /// The blocks counted for `ino` as it is stored, if it is. The inode is mostly read by the
+    /// transaction already, so it comes from the buffer of the transaction.
+    ///
+    /// If the inode is taken from the inode cache, it fails with a conflict unless the cached
+    /// inode is the stored one, so the transaction is retried with the inode read again.
+    async fn stored_blocks(&self, ino: u64) -> Result<Option<u64>> {
+        let stored = self.get(ScopedKey::inode(ino)).await?;
+        let served = self.cache_served.lock().unwrap().remove(&ino);
+        if let (Some(served), Some(cache)) = (served, self.inode_cache.as_ref()) {
+            if stored.as_ref() != Some(&served) {
+                cache.invalidate(ino);
+                return Err(FsError::KeyError {
+                    msg: format!("stale cached inode({})", ino),
+                    key: Some(Key::from(ScopedKey::inode(ino)).into()),
+                });
+            }
+        }
+        stored
+            .map(|value| Inode::deserialize(&value).map(|inode| inode.blocks))
+            .transpose()
     }
 
-    #[instrument]
-    pub async fn read_meta(&self) -> Result<Option<Meta>> {
-        let opt_data = self.get(ScopedKey::meta()).await?;
-        opt_data.map(|data| Meta::deserialize(&data)).transpose()
+    /// Record a change of `ino`, to be settled in the inode cache once the transaction commits.
+    fn cache_change(&mut self, ino: u64, value: Option<&Value>) {
+        if self.inode_cache.is_some() {
+            self.cache_saved.insert(ino, value.cloned());
+        }
     }
 
-    #[instrument(skip(meta))]
-    pub async fn save_meta(&mut self, meta: &Meta) -> Result<()> {
-        self.put(ScopedKey::meta(), meta.serialize()?).await?;
-        Ok(())
+    /// The mount keeping the unlinked `ino` as long as its kernel refers to it, if any.
+    fn held_by_kernel(&self, ino: u64) -> Option<u64> {
+        self.lookups.as_ref().and_then(|lookups| lookups.hold(ino))
     }
 
+    /// Remove an inode whatever its links are, queueing its blocks like `save_inode` does
+    /// when the last link is gone.
     #[instrument(skip(inode))]
-    async fn transfer_inline_data_to_block(&mut self, inode: &mut Inode) -> Result<()> {
-        debug!(
-            "transfer_inline_data_to_block, size:{}, inline_data_threshold:{}",
-            inode.size,
-            self.inline_data_threshold()
-        );
-        let key = ScopedKey::block(inode.ino, 0);
-        let mut data = inode.inline_data.clone().unwrap();
-        data.resize(self.block_size as usize, 0);
-        self.put(key, data).await?;
-        inode.inline_data = None;
+    pub async fn remove_inode(&mut self, inode: &Inode) -> Result<()> {
+        let before = self.stored_blocks(inode.ino).await?;
+        self.delete(ScopedKey::inode(inode.ino)).await?;
+        self.cache_change(inode.ino, None);
+        self.usage.change(inode.ino, before, None);
+        self.queue_deletion(inode).await
+    }
+
+    /// Queue the blocks of a removed inode, they are deleted by `delete_dead_blocks` later.
+    #[instrument(skip(inode))]
+    async fn queue_deletion(&mut self, inode: &Inode) -> Result<()> {
+        let end_block =
+            ((inode.size + self.block_size - 1) / self.block_size).max(inode.dir_shards as u64);
+        if inode.inline_data.is_some() || end_block == 0 {
+            return self.release_ino(inode.ino, inode.generation).await;
+        }
+        let extent = DeadExtent::new(0..end_block, inode.generation);
+        self.put(ScopedKey::deletion(inode.ino), extent.serialize()?)
+            .await?;
         Ok(())
     }
 
-    #[instrument(skip(inode, data))]
-    async fn write_inline_data(
-        &mut self,
-        inode: &mut Inode,
-        start: u64,
-        data: &[u8],
-    ) -> Result<usize> {
-        // debug_assert!(inode.size <= self.inline_data_threshold());
-        let size = data.len() as u64;
-        // debug_assert!(start + size <= self.inline_data_threshold());
-        debug!(
-            "write_inline_data, start:{}, size:{}, threshold:{}",
-            start,
-            size,
-            self.inline_data_threshold()
-        );
+    /// Release the number of a removed inode once nothing is left of it, if inodes are reused.
+    async fn release_ino(&mut self, ino: u64, generation: u64) -> Result<()> {
+        if self.reuse_inodes {
+            self.delete(ScopedKey::scratch(ino)).await?;
+            self.put(
+                ScopedKey::free_inode(ino),
+                generation.to_be_bytes().to_vec(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
 
-        let size = data.len();
+    /// Whether the directory has any entry, by its first index key rather than the whole listing.
+    async fn has_entries(&self, ino: u64) -> Result<bool> {
+        Ok(self
+            .scan(ScopedKey::entries_range(ino), 1)
+            .await?
+            .next()
+            .is_some())
+    }
+
+    /// Take the lowest released inode number, with the last generation of it.
+    async fn take_free_ino(&mut self) -> Result<Option<(u64, u64)>> {
+        let pair = match self.scan(ScopedKey::free_inode_range(), 1).await?.next() {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+        let ino = match ScopedKey::parse(pair.key().into())? {
+            ScopedKey::FreeInode(ino) => ino,
+            _ => return Err(FsError::InvalidScopedKey(pair.key().clone().into())),
+        };
+        let generation = released_generation(pair.value());
+        self.delete(pair.into_key()).await?;
+        Ok(Some((ino, generation)))
+    }
+
+    /// Up to `limit` removed inodes with blocks left in the deletion queue.
+    #[instrument]
+    pub async fn dead_inodes(&self, limit: u32) -> Result<Vec<u64>> {
+        self.scan(ScopedKey::deletion_range(), limit)
+            .await?
+            .map(|pair| match ScopedKey::parse(pair.key().into())? {
+                ScopedKey::Deletion(ino) => Ok(ino),
+                _ => Err(FsError::InvalidScopedKey(pair.key().clone().into())),
+            })
+            .collect()
+    }
+
+    /// Delete up to `limit` queued blocks of a removed inode, returns the number of deleted
+    /// blocks. The inode leaves the queue once all of its blocks are deleted.
+    #[instrument]
+    pub async fn delete_dead_blocks(&mut self, ino: u64, limit: u64) -> Result<u64> {
+        let key = ScopedKey::deletion(ino);
+        let mut extent = match self.get(key).await? {
+            Some(value) => DeadExtent::deserialize(&value)?,
+            None => return Ok(0),
+        };
+        let batch = extent.take_batch(limit);
+        for block in batch.clone() {
+            self.delete(ScopedKey::block(ino, block)).await?;
+        }
+        if extent.is_empty() {
+            self.delete(key).await?;
+            self.release_ino(ino, extent.generation).await?;
+        } else {
+            self.put(key, extent.serialize()?).await?;
+        }
+        Ok(batch.end - batch.start)
+    }
+
+    /// The statistics added by the mount `mount_id`.
+    #[instrument]
+    pub async fn read_volume_stats(&self, mount_id: u64) -> Result<Option<VolumeStats>> {
+        let opt_data = self.get(ScopedKey::stats(mount_id)).await?;
+        opt_data
+            .map(|data| VolumeStats::deserialize(&data))
+            .transpose()
+    }
+
+    #[instrument]
+    pub async fn save_volume_stats(&mut self, mount_id: u64, stats: &VolumeStats) -> Result<()> {
+        self.put(ScopedKey::stats(mount_id), stats.serialize()?)
+            .await?;
+        Ok(())
+    }
+
+    /// The rows of the volume statistics by their ids, scanned `scan_page` at a time.
+    async fn stats_rows(&self) -> Result<Vec<(u64, VolumeStats)>> {
+        let range = ScopedKey::stats_range();
+        let mut from = range.start;
+        let mut rows = Vec::new();
+        loop {
+            let mut scanned = 0;
+            for pair in self
+                .scan(from.clone()..range.end.clone(), self.scan_page)
+                .await?
+            {
+                let id = match ScopedKey::parse(pair.key().into())? {
+                    ScopedKey::Stats(id) => id,
+                    _ => return Err(FsError::InvalidScopedKey(pair.key().clone().into())),
+                };
+                rows.push((id, VolumeStats::deserialize(pair.value())?));
+                from = Key::from(ScopedKey::stats(id + 1));
+                scanned += 1;
+            }
+            if scanned < self.scan_page {
+                return Ok(rows);
+            }
+        }
+    }
+
+    /// The statistics of the volume merged from all rows, and the number of mounts which have
+    /// added to them and are not folded yet.
+    #[instrument]
+    pub async fn volume_stats(&self) -> Result<(usize, VolumeStats)> {
+        let mut stats = VolumeStats::default();
+        let mut mounts = 0;
+        for (id, row) in self.stats_rows().await? {
+            stats.merge(&row);
+            if !is_usage_row(id) {
+                mounts += 1;
+            }
+        }
+        Ok((mounts, stats))
+    }
+
+    /// Fold the rows of the mounts not in `live` into `USAGE_BASE_ROW`, so the rows read by
+    /// `volume_stats` do not pile up with every mount ever made. Nothing is folded until the
+    /// base row is counted by `rebase_usage`. Returns the rows folded.
+    #[instrument(skip(live))]
+    pub async fn fold_stats(&mut self, live: &HashSet<u64>) -> Result<usize> {
+        let mut base = match self.read_volume_stats(USAGE_BASE_ROW).await? {
+            Some(base) => base,
+            None => return Ok(0),
+        };
+        let mut folded = 0;
+        for (id, row) in self.stats_rows().await? {
+            if is_usage_row(id) || live.contains(&id) {
+                continue;
+            }
+            base.merge(&row);
+            self.delete(ScopedKey::stats(id)).await?;
+            folded += 1;
+        }
+        if folded > 0 {
+            self.save_volume_stats(USAGE_BASE_ROW, &base).await?;
+        }
+        Ok(folded)
+    }
+
+    #[instrument]
+    pub async fn read_usage_rebase(&self) -> Result<Option<UsageRebase>> {
+        let opt_data = self.get(ScopedKey::usage_rebase()).await?;
+        opt_data.map(|data| UsageRebase::deserialize(&data)).transpose()
+    }
+
+    /// Count the files and blocks of the next `page` inodes of the volume, up to the next of
+    /// `bounds`, into the cursor of `TiFs::rebase_usage`, and once all are counted rebase the
+    /// counters of the volume statistics on them, see `VolumeStats::files`. Returns the files
+    /// and blocks counted once the rebase is done.
+    #[instrument(skip(bounds))]
+    pub async fn rebase_usage_batch(
+        &mut self,
+        page: u32,
+        bounds: &[u64],
+    ) -> Result<Option<(u64, u64)>> {
+        let now = SystemTime::now();
+        let next_inode = self
+            .read_meta()
+            .await?
+            .map(|meta| meta.inode_next)
+            .unwrap_or(ROOT_INODE);
+        let mut cursor = match self.read_usage_rebase().await? {
+            Some(cursor) if cursor.is_fresh(now) => cursor,
+            _ => UsageRebase::new(ROOT_INODE, now),
+        };
+        let end = bounds
+            .iter()
+            .copied()
+            .find(|bound| cursor.next < *bound && *bound < next_inode)
+            .unwrap_or(next_inode);
+        let mut scanned = 0;
+        if cursor.next < end {
+            for pair in self
+                .scan(ScopedKey::inode_range(cursor.next..end), page)
+                .await?
+            {
+                let inode = Inode::deserialize(pair.value())?;
+                cursor.blocks += inode.blocks;
+                cursor.files += 1;
+                cursor.next = inode.ino + 1;
+                scanned += 1;
+            }
+        }
+        if scanned < page && end < next_inode {
+            // the next batch starts on the next region
+            cursor.next = end;
+        }
+        if scanned == page || end < next_inode {
+            cursor.updated = now;
+            self.put(ScopedKey::usage_rebase(), cursor.serialize()?).await?;
+            return Ok(None);
+        }
+        self.delete(ScopedKey::usage_rebase()).await?;
+        let (_, counted) = self.volume_stats().await?;
+        let mut base = self
+            .read_volume_stats(USAGE_BASE_ROW)
+            .await?
+            .unwrap_or_default();
+        base.files += cursor.files as i64 - counted.files;
+        base.blocks += cursor.blocks as i64 - counted.blocks;
+        self.save_volume_stats(USAGE_BASE_ROW, &base).await?;
+        Ok(Some((cursor.files, cursor.blocks)))
+    }
+
+    #[instrument]
+    pub async fn read_meta(&self) -> Result<Option<Meta>> {
+        let opt_data = self.get(ScopedKey::meta()).await?;
+        opt_data.map(|data| Meta::deserialize(&data)).transpose()
+    }
+
+    /// Append `op` done by the mount `origin` to the change journal.
+    #[instrument]
+    pub async fn append_journal(&mut self, origin: u64, op: JournalOp) -> Result<u64> {
+        let mut meta = self
+            .read_meta()
+            .await?
+            .unwrap_or_else(|| Meta::new(self.block_size));
+        let seq = meta.journal_next;
+        meta.journal_next += 1;
+        self.save_meta(&meta).await?;
+
+        let entry = JournalEntry {
+            seq,
+            time: SystemTime::now(),
+            origin,
+            op,
+        };
+        self.put(ScopedKey::journal(seq), entry.serialize()?)
+            .await?;
+        Ok(seq)
+    }
+
+    /// Journal entries from sequence number `from` on, at most `limit` ones.
+    #[instrument]
+    pub async fn read_journal(&self, from: u64, limit: u32) -> Result<Vec<JournalEntry>> {
+        self.scan(ScopedKey::journal_range(from..std::u64::MAX), limit)
+            .await?
+            .map(|pair| JournalEntry::deserialize(pair.value()))
+            .collect()
+    }
+
+    #[instrument(skip(meta))]
+    pub async fn save_meta(&mut self, meta: &Meta) -> Result<()> {
+        self.put(ScopedKey::meta(), meta.serialize()?).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(safepoint))]
+    pub async fn save_safepoint(&mut self, safepoint: &SafePoint) -> Result<()> {
+        self.put(
+            ScopedKey::safepoint(&safepoint.service),
+            safepoint.serialize()?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn delete_safepoint(&mut self, service: &str) -> Result<()> {
+        self.delete(ScopedKey::safepoint(service)).await?;
+        Ok(())
+    }
+
+    /// Write raw pairs, as restored from a backup.
+    #[instrument(skip(pairs))]
+    pub async fn put_pairs(&mut self, pairs: Vec<(Key, Vec<u8>)>) -> Result<()> {
+        for (key, value) in pairs {
+            self.put(key, value).await?;
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn read_safepoints(&self) -> Result<Vec<SafePoint>> {
+        self.scan(ScopedKey::safepoint_range(), SAFEPOINT_SCAN_LIMIT)
+            .await?
+            .map(|pair| SafePoint::deserialize(pair.value()))
+            .collect()
+    }
+
+    #[instrument(skip(client))]
+    pub async fn save_client(&mut self, client: &Client) -> Result<()> {
+        self.put(ScopedKey::client(client.mount_id), client.serialize()?)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn delete_client(&mut self, mount_id: u64) -> Result<()> {
+        self.delete(ScopedKey::client(mount_id)).await?;
+        Ok(())
+    }
+
+    /// Entries of the clients registry, expired or not.
+    #[instrument]
+    pub async fn read_clients(&self) -> Result<Vec<Client>> {
+        self.scan(ScopedKey::client_range(), u32::MAX)
+            .await?
+            .map(|pair| Client::deserialize(pair.value()))
+            .collect()
+    }
+
+    #[instrument]
+    pub async fn read_freeze(&self) -> Result<Option<Freeze>> {
+        let opt_data = self.get(ScopedKey::freeze()).await?;
+        opt_data.map(|data| Freeze::deserialize(&data)).transpose()
+    }
+
+    /// Refuse to commit writes to the volume while it is frozen, whichever mount or command
+    /// makes them, see `Freeze`.
+    pub async fn ensure_not_frozen(&self) -> Result<()> {
+        match self.read_freeze().await? {
+            Some(freeze) if !freeze.is_expired() => Err(FsError::Frozen { id: freeze.id }),
+            _ => Ok(()),
+        }
+    }
+
+    #[instrument]
+    pub async fn save_freeze(&mut self, freeze: &Freeze) -> Result<()> {
+        self.put(ScopedKey::freeze(), freeze.serialize()?).await?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn delete_freeze(&mut self) -> Result<()> {
+        self.delete(ScopedKey::freeze()).await?;
+        Ok(())
+    }
+
+    /// Mounts in the clients registry which are not expired.
+    #[instrument]
+    pub async fn live_clients(&self) -> Result<HashSet<u64>> {
+        let mut live = HashSet::new();
+        for pair in self.scan(ScopedKey::client_range(), u32::MAX).await? {
+            let client = Client::deserialize(pair.value())?;
+            if !client.is_expired() {
+                live.insert(client.mount_id);
+            }
+        }
+        Ok(live)
+    }
+
+    /// File handlers of the inodes in `inos`, as `(ino, fh, handler)`.
+    #[instrument]
+    pub async fn read_handlers(&self, inos: Range<u64>) -> Result<Vec<(u64, u64, FileHandler)>> {
+        self.scan(ScopedKey::handler_range(inos), u32::MAX)
+            .await?
+            .map(|pair| match ScopedKey::parse(pair.key().into())? {
+                ScopedKey::FileHandler { ino, handler } => {
+                    Ok((ino, handler, FileHandler::deserialize(pair.value())?))
+                }
+                _ => Err(FsError::InvalidScopedKey(pair.key().clone().into())),
+            })
+            .collect()
+    }
+
+    /// The inodes numbered in `inos`, scanned `page` at a time.
+    #[instrument]
+    pub async fn scan_inodes(&self, inos: Range<u64>, page: u32) -> Result<Vec<Inode>> {
+        let mut inodes = Vec::new();
+        let mut from = inos.start;
+        while from < inos.end {
+            let mut scanned = 0;
+            for pair in self
+                .scan(ScopedKey::inode_range(from..inos.end), page)
+                .await?
+            {
+                let inode = Inode::deserialize(pair.value())?;
+                from = inode.ino + 1;
+                inodes.push(inode);
+                scanned += 1;
+            }
+            if scanned < page {
+                break;
+            }
+        }
+        Ok(inodes)
+    }
+
+    /// Drop the handlers of `ino` opened by mounts not in `live`, and count the handlers left as
+    /// open. Returns the count recorded before if anything is changed.
+    #[instrument(skip(live))]
+    pub async fn reconcile_handles(
+        &mut self,
+        ino: u64,
+        live: &HashSet<u64>,
+    ) -> Result<Option<u64>> {
+        let mut open = 0;
+        let mut stale = 0;
+        for (_, fh, handler) in self.read_handlers(ino..ino + 1).await? {
+            if live.contains(&handler.owner) {
+                open += 1;
+            } else {
+                self.delete(ScopedKey::handler(ino, fh)).await?;
+                stale += 1;
+            }
+        }
+        let mut inode = match self.read_inode(ino).await {
+            Err(FsError::InodeNotFound { inode: _ }) => {
+                return Ok(if stale > 0 { Some(0) } else { None })
+            }
+            result => result?,
+        };
+        if inode.opened_fh == open && stale == 0 {
+            return Ok(None);
+        }
+        let recorded = inode.opened_fh;
+        inode.opened_fh = open;
+        // removed files left open by crashed mounts are queued for deletion here
+        self.save_inode(&inode).await?;
+        Ok(Some(recorded))
+    }
+
+    #[instrument(skip(inode))]
+    async fn transfer_inline_data_to_block(&mut self, inode: &mut Inode) -> Result<()> {
+        debug!(
+            "transfer_inline_data_to_block, size:{}, inline_data_threshold:{}",
+            inode.size,
+            self.inline_data_threshold()
+        );
+        let key = ScopedKey::block(inode.ino, 0);
+        let mut data = inode.inline_data.clone().unwrap();
+        data.resize(self.block_size as usize, 0);
+        self.put(key, data).await?;
+        inode.inline_data = None;
+        Ok(())
+    }
+
+    #[instrument(skip(inode, data))]
+    async fn write_inline_data(
+        &mut self,
+        inode: &mut Inode,
+        start: u64,
+        data: &[u8],
+    ) -> Result<usize> {
+        // debug_assert!(inode.size <= self.inline_data_threshold());
+        let size = data.len() as u64;
+        // debug_assert!(start + size <= self.inline_data_threshold());
+        debug!(
+            "write_inline_data, start:{}, size:{}, threshold:{}",
+            start,
+            size,
+            self.inline_data_threshold()
+        );
+
+        let size = data.len();
         let start = start as usize;
 
         let mut inlined = inode.inline_data.take().unwrap_or_else(Vec::new);
@@ -296,6 +1465,7 @@ impl Txn {
         inode.atime = SystemTime::now();
         inode.mtime = SystemTime::now();
         inode.ctime = SystemTime::now();
+        inode.data_changed();
         inode.set_size(inlined.len() as u64, self.block_size);
         inode.inline_data = Some(inlined);
         self.save_inode(inode).await?;
@@ -328,8 +1498,7 @@ impl Txn {
             data[..to_copy].copy_from_slice(&inlined[start..start + to_copy]);
         }
 
-        inode.atime = SystemTime::now();
-        self.save_inode(inode).await?;
+        self.touch_atime(inode).await?;
 
         Ok(data)
     }
@@ -350,6 +1519,9 @@ impl Txn {
         let size = chunk_size.unwrap_or(max_size).min(max_size);
 
         if attr.inline_data.is_some() {
+            if let (Some(seal), Some(inlined)) = (&attr.seal, &attr.inline_data) {
+                seal.verify_block(ino, 0, inlined)?;
+            }
             return self.read_inline_data(&mut attr, start, size).await;
         }
 
@@ -357,14 +1529,21 @@ impl Txn {
         let start_block = start / self.block_size;
         let end_block = (target + self.block_size - 1) / self.block_size;
 
-        let pairs = self
-            .scan(
-                ScopedKey::block_range(ino, start_block..end_block),
-                (end_block - start_block) as u32,
-            )
-            .await?;
+        let pairs = self.scan_blocks(ino, start_block..end_block).await?;
+
+        if let Some(seal) = &attr.seal {
+            seal.verify_blocks(
+                ino,
+                start_block..end_block,
+                self.block_size,
+                pairs
+                    .iter()
+                    .map(|pair| (block_of(pair.key()), pair.value().as_slice())),
+            )?;
+        }
 
         let mut data = pairs
+            .into_iter()
             .enumerate()
             .flat_map(|(i, pair)| {
                 let key = if let Ok(ScopedKey::Block { ino: _, block }) =
@@ -397,8 +1576,7 @@ impl Txn {
             );
 
         data.resize(size as usize, 0);
-        attr.atime = SystemTime::now();
-        self.save_inode(&attr).await?;
+        self.touch_atime(&mut attr).await?;
         Ok(data)
     }
 
@@ -414,22 +1592,289 @@ impl Txn {
         let clear_size = attr.size;
         attr.size = 0;
         attr.atime = SystemTime::now();
+        attr.data_changed();
         self.save_inode(&attr).await?;
         Ok(clear_size)
     }
 
+    /// Truncate or extend the content to `size`. A file in blocks shrinking under the inline
+    /// threshold is demoted to inline data and its block keys are deleted.
+    #[instrument(skip(inode))]
+    pub async fn truncate(&mut self, inode: &mut Inode, size: u64) -> Result<()> {
+        if size == inode.size {
+            return Ok(());
+        }
+        if inode.inline_data.is_some() && (size > self.block_size || !self.inline) {
+            self.transfer_inline_data_to_block(inode).await?;
+        }
+
+        if let Some(inlined) = &mut inode.inline_data {
+            inlined.resize(size as usize, 0);
+        } else if size < inode.size {
+            let end_block = (inode.size + self.block_size - 1) / self.block_size;
+            if self.inline && size <= self.inline_data_threshold() {
+                let mut inlined = match size {
+                    0 => Vec::new(),
+                    _ => self
+                        .get(ScopedKey::block(inode.ino, 0))
+                        .await?
+                        .unwrap_or_default(),
+                };
+                inlined.resize(size as usize, 0);
+                for block in 0..end_block {
+                    self.delete(ScopedKey::block(inode.ino, block)).await?;
+                }
+                debug!(
+                    "demote inode({}) of size {} to inline data",
+                    inode.ino, size
+                );
+                inode.inline_data = Some(inlined);
+            } else {
+                let kept_blocks = (size + self.block_size - 1) / self.block_size;
+                for block in kept_blocks..end_block {
+                    self.delete(ScopedKey::block(inode.ino, block)).await?;
+                }
+                // zero the tail of the last block, which is read if the file is extended again
+                let tail = (size % self.block_size) as usize;
+                let key = ScopedKey::block(inode.ino, size / self.block_size);
+                if tail != 0 {
+                    if let Some(mut value) = self.get(key).await? {
+                        value[tail..].iter_mut().for_each(|byte| *byte = 0);
+                        self.put(key, value).await?;
+                    }
+                }
+            }
+        }
+
+        inode.set_size(size, self.block_size);
+        inode.data_changed();
+        Ok(())
+    }
+
+    /// Sha256 of the whole content, computed lazily and kept in the inode until the data changes.
+    #[instrument]
+    pub async fn content_hash(&mut self, ino: u64) -> Result<String> {
+        let mut inode = self.read_inode(ino).await?;
+        if let Some(hash) = &inode.content_hash {
+            return Ok(hash.clone());
+        }
+
+        let mut hasher = ContentHasher::new(inode.size, self.block_size);
+        if let Some(data) = &inode.inline_data {
+            hasher.update_inline(data);
+        } else {
+            let end_block = (inode.size + self.block_size - 1) / self.block_size;
+            let mut next_block = 0;
+            while next_block < end_block {
+                let pairs = self
+                    .scan(
+                        ScopedKey::block_range(ino, next_block..end_block),
+                        HASH_SCAN_LIMIT,
+                    )
+                    .await?;
+                let last_block = next_block;
+                for pair in pairs {
+                    let block = if let Ok(ScopedKey::Block { ino: _, block }) =
+                        ScopedKey::parse(pair.key().into())
+                    {
+                        block
+                    } else {
+                        unreachable!("the keys from scanning should be always valid block keys")
+                    };
+                    hasher.update_block(block, pair.value());
+                    next_block = block + 1;
+                }
+                if next_block == last_block {
+                    break;
+                }
+            }
+        }
+
+        let hash = hasher.finish();
+        inode.content_hash = Some(hash.clone());
+        self.save_inode(&inode).await?;
+        Ok(hash)
+    }
+
+    async fn block_leaves(&self, inode: &Inode) -> Result<Vec<Vec<u8>>> {
+        if let Some(data) = &inode.inline_data {
+            return Ok(vec![digest(data)]);
+        }
+
+        let end_block = (inode.size + self.block_size - 1) / self.block_size;
+        let hole = digest(&empty_block(self.block_size));
+        let mut leaves = Vec::with_capacity(end_block as usize);
+        while (leaves.len() as u64) < end_block {
+            let pairs = self
+                .scan(
+                    ScopedKey::block_range(inode.ino, leaves.len() as u64..end_block),
+                    HASH_SCAN_LIMIT,
+                )
+                .await?;
+            let scanned = leaves.len();
+            for pair in pairs {
+                leaves.resize(block_of(pair.key()) as usize, hole.clone());
+                leaves.push(digest(pair.value()));
+            }
+            if leaves.len() == scanned {
+                break;
+            }
+        }
+        leaves.resize(end_block as usize, hole);
+        Ok(leaves)
+    }
+
+    /// Inodes of the subtree rooted at `ino`, parents before children.
+    async fn subtree(&mut self, ino: u64) -> Result<Vec<Inode>> {
+        let mut inodes = vec![self.read_inode(ino).await?];
+        let mut index = 0;
+        while index < inodes.len() {
+            if inodes[index].kind == FileType::Directory {
+                let dir_ino = inodes[index].ino;
+                for item in self.read_dir(dir_ino).await? {
+                    inodes.push(self.read_inode(item.ino).await?);
+                }
+            }
+            index += 1;
+        }
+        Ok(inodes)
+    }
+
+    /// Derive the seals of the subtree rooted at `ino`, children before parents.
+    async fn derive_seals(&mut self, ino: u64) -> Result<Vec<(Inode, Seal)>> {
+        let inodes = self.subtree(ino).await?;
+        let mut roots = HashMap::new();
+        let mut seals = Vec::with_capacity(inodes.len());
+        for inode in inodes.into_iter().rev() {
+            let seal = if inode.kind == FileType::Directory {
+                let entries = self
+                    .read_dir(inode.ino)
+                    .await?
+                    .into_iter()
+                    .map(|item| {
+                        let root = roots.get(&item.ino).cloned().unwrap_or_default();
+                        (item.name, root)
+                    })
+                    .collect();
+                Seal::dir(entries)
+            } else {
+                Seal::file(inode.size, self.block_leaves(&inode).await?)
+            };
+            roots.insert(inode.ino, seal.root.clone());
+            seals.push((inode, seal));
+        }
+        Ok(seals)
+    }
+
+    /// Seal the subtree rooted at `ino` and return its Merkle root.
+    #[instrument]
+    pub async fn seal(&mut self, ino: u64) -> Result<String> {
+        let seals = self.derive_seals(ino).await?;
+        let root = hex(&seals.last().unwrap().1.root);
+        for (mut inode, seal) in seals {
+            inode.ensure_unsealed()?;
+            inode.seal = Some(seal);
+            self.save_inode(&inode).await?;
+        }
+        Ok(root)
+    }
+
+    /// Apply `worm` to the subtree rooted at directory `ino`.
+    #[instrument]
+    pub async fn set_worm(&mut self, ino: u64, worm: Worm) -> Result<()> {
+        let inodes = self.subtree(ino).await?;
+        if inodes[0].kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        if inodes[0].worm.is_some() {
+            return Err(FsError::WormLocked { ino });
+        }
+
+        let lock_time = worm.lock_time(SystemTime::now());
+        for mut inode in inodes {
+            if inode.kind == FileType::Directory {
+                inode.worm.get_or_insert(worm);
+            } else {
+                inode.worm_after.get_or_insert(lock_time);
+            }
+            self.save_inode(&inode).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the TTL of the directory `ino` and its subdirectories, or clear it, see `Ttl`.
+    #[instrument]
+    pub async fn set_ttl(&mut self, ino: u64, ttl: Option<Ttl>) -> Result<()> {
+        let inodes = self.subtree(ino).await?;
+        if inodes[0].kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        for mut inode in inodes {
+            if inode.kind == FileType::Directory {
+                inode.ttl = ttl;
+                self.save_inode(&inode).await?;
+                self.save_scratch(inode.ino, ttl.is_some()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_scratch(&mut self, ino: u64, scratch: bool) -> Result<()> {
+        if scratch {
+            self.put(ScopedKey::scratch(ino), Vec::new()).await?;
+        } else {
+            self.delete(ScopedKey::scratch(ino)).await?;
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` directories with a TTL from inode `from` on.
+    #[instrument]
+    pub async fn scratch_dirs(&self, from: u64, limit: u32) -> Result<Vec<u64>> {
+        let range = ScopedKey::scratch(from).into()..ScopedKey::scratch_range().end;
+        self.scan(range, limit)
+            .await?
+            .map(|pair| match ScopedKey::parse(pair.key().into())? {
+                ScopedKey::Scratch(ino) => Ok(ino),
+                _ => Err(FsError::InvalidScopedKey(pair.key().clone().into())),
+            })
+            .collect()
+    }
+
+    /// Verify the sealed subtree rooted at `ino` against the stored seals and return its Merkle root.
+    #[instrument]
+    pub async fn verify_seal(&mut self, ino: u64) -> Result<String> {
+        let seals = self.derive_seals(ino).await?;
+        for (inode, seal) in &seals {
+            inode
+                .seal
+                .as_ref()
+                .ok_or(FsError::SealMismatch { ino: inode.ino })?
+                .verify_root(inode.ino, seal)?;
+        }
+        Ok(hex(&seals.last().unwrap().1.root))
+    }
+
     #[instrument(skip(data))]
     pub async fn write_data(&mut self, ino: u64, start: u64, data: Bytes) -> Result<usize> {
         debug!("write data at ({})[{}]", ino, start);
         let mut inode = self.read_inode(ino).await?;
+        inode.ensure_mutable()?;
+        inode.ensure_appendable(start)?;
+        if self.posix.is_strict() {
+            inode.kill_suid();
+        }
         let size = data.len();
         let target = start + size as u64;
 
-        if inode.inline_data.is_some() && target > self.block_size {
+        if inode.inline_data.is_some() && (target > self.block_size || !self.inline) {
             self.transfer_inline_data_to_block(&mut inode).await?;
         }
 
-        if (inode.inline_data.is_some() || inode.size == 0) && target <= self.block_size {
+        if self.inline
+            && (inode.inline_data.is_some() || inode.size == 0)
+            && target <= self.block_size
+        {
             return self.write_inline_data(&mut inode, start, &data).await;
         }
 
@@ -471,6 +1916,7 @@ impl Txn {
         inode.atime = SystemTime::now();
         inode.mtime = SystemTime::now();
         inode.ctime = SystemTime::now();
+        inode.data_changed();
         inode.set_size(inode.size.max(target), self.block_size);
         self.save_inode(&inode.into()).await?;
         trace!("write data len: {}", data.len());
@@ -478,34 +1924,49 @@ impl Txn {
     }
 
     #[instrument(skip(inode, data))]
+    /// Set the target of a symlink, which is kept in the inode itself.
     pub async fn write_link(&mut self, inode: &mut Inode, data: Bytes) -> Result<usize> {
         debug_assert!(inode.file_attr.kind == FileType::Symlink);
-        inode.inline_data = None;
-        inode.set_size(0, self.block_size);
-        self.write_inline_data(inode, 0, &data).await
+        check_link_target(&data)?;
+        inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.set_size(data.len() as u64, self.block_size);
+        inode.inline_data = Some(data.to_vec());
+        self.save_inode(inode).await?;
+        Ok(data.len())
     }
 
+    /// The target of a symlink, reading it does not write the inode.
     #[instrument]
-    pub async fn read_link(&mut self, ino: u64) -> Result<Vec<u8>> {
-        let mut inode = self.read_inode(ino).await?;
+    pub async fn read_link(&self, ino: u64) -> Result<Vec<u8>> {
+        let inode = self.read_inode(ino).await?;
         debug_assert!(inode.file_attr.kind == FileType::Symlink);
-        let size = inode.size;
-        self.read_inline_data(&mut inode, 0, size).await
+        Ok(inode.inline_data.unwrap_or_default())
     }
 
     #[instrument]
     pub async fn link(&mut self, ino: u64, newparent: u64, newname: ByteString) -> Result<Inode> {
+        self.read_inode(newparent).await?.ensure_mutable()?;
         if let Some(old_ino) = self.get_index(newparent, newname.clone()).await? {
             let inode = self.read_inode(old_ino).await?;
+            // callers replacing entries of others check them by themselves, see `rename`.
+            // The replaced inode is released like any other removal: its blocks are queued for
+            // deletion once no link or handle is left.
             match inode.kind {
-                FileType::Directory => self.rmdir(newparent, newname.clone()).await?,
-                _ => self.unlink(newparent, newname.clone()).await?,
+                FileType::Directory => self.rmdir(newparent, newname.clone(), 0).await?,
+                _ => self.unlink(newparent, newname.clone(), 0).await?,
             }
         }
         self.set_index(newparent, newname.clone(), ino).await?;
 
         let mut inode = self.read_inode(ino).await?;
-        let mut dir = self.read_dir(newparent).await?;
+        // the type is derived from the inode, dropping any stale item of the same name
+        let mut dir: Directory = self
+            .read_dir(newparent)
+            .await?
+            .into_iter()
+            .filter(|item| item.name != &*newname)
+            .collect();
 
         dir.push(DirItem {
             ino,
@@ -515,18 +1976,26 @@ impl Txn {
 
         self.save_dir(newparent, &dir).await?;
         inode.nlink += 1;
+        inode.parent = newparent;
         inode.ctime = SystemTime::now();
         self.save_inode(&inode).await?;
         Ok(inode)
     }
 
     #[instrument]
-    pub async fn unlink(&mut self, parent: u64, name: ByteString) -> Result<()> {
+    pub async fn unlink(&mut self, parent: u64, name: ByteString, uid: u32) -> Result<()> {
         match self.get_index(parent, name.clone()).await? {
             None => Err(FsError::FileNotFound {
                 file: name.to_string(),
             }),
             Some(ino) => {
+                let dir = self.read_inode(parent).await?;
+                dir.ensure_mutable()?;
+                dir.ensure_not_append_only()?;
+                let inode = self.read_inode(ino).await?;
+                inode.ensure_mutable()?;
+                inode.ensure_not_append_only()?;
+                inode.ensure_removable(&dir, uid)?;
                 self.remove_index(parent, name.clone()).await?;
                 let parent_dir = self.read_dir(parent).await?;
                 let new_parent_dir: Directory = parent_dir
@@ -545,20 +2014,29 @@ impl Txn {
     }
 
     #[instrument]
-    pub async fn rmdir(&mut self, parent: u64, name: ByteString) -> Result<()> {
+    pub async fn rmdir(&mut self, parent: u64, name: ByteString, uid: u32) -> Result<()> {
         match self.get_index(parent, name.clone()).await? {
             None => Err(FsError::FileNotFound {
                 file: name.to_string(),
             }),
             Some(ino) => {
-                let target_dir = self.read_dir(ino).await?;
-                if target_dir.len() != 0 {
+                let dir = self.read_inode(parent).await?;
+                dir.ensure_mutable()?;
+                dir.ensure_not_append_only()?;
+                let inode = self.read_inode(ino).await?;
+                inode.ensure_mutable()?;
+                inode.ensure_not_append_only()?;
+                inode.ensure_removable(&dir, uid)?;
+                if self.has_entries(ino).await? {
                     let name_str = name.to_string();
                     debug!("dir({}) not empty", &name_str);
                     return Err(FsError::DirNotEmpty { dir: name_str });
                 }
+                if inode.ttl.is_some() {
+                    self.save_scratch(ino, false).await?;
+                }
                 self.remove_index(parent, name.clone()).await?;
-                self.remove_inode(ino).await?;
+                self.remove_inode(&inode).await?;
 
                 let parent_dir = self.read_dir(parent).await?;
                 let new_parent_dir: Directory = parent_dir
@@ -580,15 +2058,68 @@ impl Txn {
             })
     }
 
+    /// Resolve `names` one after another from `parent`, returning the inodes of all of them.
+    /// Entries are looked up in order, but the inodes are read together at the end, so a deep
+    /// path costs one more request than its components instead of two per component.
+    #[instrument]
+    pub async fn lookup_path(&self, parent: u64, names: Vec<ByteString>) -> Result<Vec<Inode>> {
+        let mut inos = Vec::with_capacity(names.len());
+        let mut dir = parent;
+        for name in names {
+            dir = match self.lookup(dir, name).await {
+                Err(FsError::FileNotFound { file }) => {
+                    if self.read_inode(dir).await?.kind != FileType::Directory {
+                        return Err(FsError::NotDirectory { ino: dir });
+                    }
+                    return Err(FsError::FileNotFound { file });
+                }
+                result => result?,
+            };
+            inos.push(dir);
+        }
+        self.read_inodes(&inos).await
+    }
+
     #[instrument]
-    pub async fn fallocate(&mut self, inode: &mut Inode, offset: i64, length: i64) -> Result<()> {
+    /// Allocate `length` bytes at `offset`. Blocks are not reserved ahead, so this only grows
+    /// the size, unless `mode` has `FALLOC_FL_KEEP_SIZE`. `FALLOC_FL_PUNCH_HOLE` deletes the
+    /// blocks in the range and zeroes the partial ones at its ends, `FALLOC_FL_ZERO_RANGE` does
+    /// the same and grows the size like a plain allocation.
+    pub async fn fallocate(
+        &mut self,
+        inode: &mut Inode,
+        offset: i64,
+        length: i64,
+        mode: i32,
+    ) -> Result<()> {
+        inode.ensure_mutable()?;
+        if offset < 0 || length <= 0 {
+            return Err(FsError::InvalidOffset {
+                ino: inode.ino,
+                offset,
+            });
+        }
+        let punch = mode & FALLOC_FL_PUNCH_HOLE != 0;
+        let zero = mode & FALLOC_FL_ZERO_RANGE != 0;
+        let keep_size = mode & FALLOC_FL_KEEP_SIZE != 0;
+        let known = FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE | FALLOC_FL_ZERO_RANGE;
+        // like Linux, holes are punched only along with keeping the size
+        if mode & !known != 0 || (punch && (zero || !keep_size)) {
+            return Err(FsError::FallocateMode {
+                ino: inode.ino,
+                mode,
+            });
+        }
         let target_size = (offset + length) as u64;
-        if target_size <= inode.size {
+        if punch || zero {
+            self.zero_range(inode, offset as u64, target_size).await?;
+        }
+        if keep_size || target_size <= inode.size {
             return Ok(());
         }
 
         if inode.inline_data.is_some() {
-            if target_size <= self.inline_data_threshold() {
+            if self.inline && target_size <= self.inline_data_threshold() {
                 let original_size = inode.size;
                 let data = vec![0; (target_size - original_size) as usize];
                 self.write_inline_data(inode, original_size, &data).await?;
@@ -600,6 +2131,43 @@ impl Txn {
 
         inode.set_size(target_size, self.block_size);
         inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.data_changed();
+        self.save_inode(inode).await?;
+        Ok(())
+    }
+
+    /// Zero the data of `inode` in `start..end`, up to its size. Blocks wholly in the range are
+    /// deleted and read as zeros like the holes of sparse files.
+    async fn zero_range(&mut self, inode: &mut Inode, start: u64, end: u64) -> Result<()> {
+        let end = end.min(inode.size);
+        if start >= end {
+            return Ok(());
+        }
+        if let Some(inlined) = &mut inode.inline_data {
+            let end = (end as usize).min(inlined.len());
+            if (start as usize) < end {
+                inlined[start as usize..end]
+                    .iter_mut()
+                    .for_each(|byte| *byte = 0);
+            }
+        } else {
+            for block in start / self.block_size..=(end - 1) / self.block_size {
+                let base = block * self.block_size;
+                let from = (start.max(base) - base) as usize;
+                let to = (end.min(base + self.block_size) - base) as usize;
+                let key = ScopedKey::block(inode.ino, block);
+                if from == 0 && to == self.block_size as usize {
+                    self.delete(key).await?;
+                } else if let Some(mut value) = self.get(key).await? {
+                    value[from..to].iter_mut().for_each(|byte| *byte = 0);
+                    self.put(key, value).await?;
+                }
+            }
+        }
+        inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.data_changed();
         self.save_inode(inode).await?;
         Ok(())
     }
@@ -614,29 +2182,264 @@ impl Txn {
         uid: u32,
     ) -> Result<Inode> {
         let dir_mode = make_mode(FileType::Directory, mode as _);
-        let mut inode = self.make_inode(parent, name, dir_mode, gid, uid, 0).await?;
-        inode.perm = mode as _;
-        self.save_inode(&inode).await?;
+        let inode = self.make_inode(parent, name, dir_mode, gid, uid, 0).await?;
         self.save_dir(inode.ino, &Directory::new()).await
     }
 
+    /// Apply the operations of `batch` in order, returning the created inodes.
+    #[instrument(skip(batch))]
+    pub async fn apply_batch(&mut self, batch: &Batch) -> Result<Vec<Inode>> {
+        let mut created = Vec::with_capacity(batch.len());
+        for op in &batch.ops {
+            let inode = match op {
+                BatchOp::Mkdir { parent, name, mode } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    self.mkdir(parent, name.clone(), *mode, batch.gid, batch.uid)
+                        .await?
+                }
+                BatchOp::Create {
+                    parent,
+                    name,
+                    mode,
+                    data,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let inode = self
+                        .make_inode(
+                            parent,
+                            name.clone(),
+                            make_mode(FileType::RegularFile, *mode as _),
+                            batch.gid,
+                            batch.uid,
+                            0,
+                        )
+                        .await?;
+                    if data.is_empty() {
+                        inode
+                    } else {
+                        self.write_data(inode.ino, 0, data.clone()).await?;
+                        self.read_inode(inode.ino).await?
+                    }
+                }
+                BatchOp::Symlink {
+                    parent,
+                    name,
+                    target,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let mut inode = self
+                        .make_inode(
+                            parent,
+                            name.clone(),
+                            make_mode(FileType::Symlink, 0o777),
+                            batch.gid,
+                            batch.uid,
+                            0,
+                        )
+                        .await?;
+                    self.write_link(&mut inode, target.clone().into_bytes())
+                        .await?;
+                    inode
+                }
+                BatchOp::Link {
+                    parent,
+                    name,
+                    target,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let target = resolve(*target, &created)?;
+                    self.link(target, parent, name.clone()).await?
+                }
+                BatchOp::Write {
+                    target,
+                    offset,
+                    data,
+                } => {
+                    let target = resolve(*target, &created)?;
+                    self.write_data(target, *offset, data.clone()).await?;
+                    self.read_inode(target).await?
+                }
+                BatchOp::Rename {
+                    parent,
+                    name,
+                    newparent,
+                    newname,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let newparent = resolve_parent(*newparent, &created)?;
+                    self.rename(parent, name.clone(), newparent, newname.clone(), batch.uid)
+                        .await?
+                }
+                BatchOp::Unlink { parent, name } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let ino = self.lookup(parent, name.clone()).await?;
+                    let inode = self.read_inode(ino).await?;
+                    match inode.kind {
+                        FileType::Directory => self.rmdir(parent, name.clone(), batch.uid).await?,
+                        _ => self.unlink(parent, name.clone(), batch.uid).await?,
+                    }
+                    inode
+                }
+            };
+            created.push(inode);
+        }
+        Ok(created)
+    }
+
+    /// Move the entry `name` of `parent` to `newname` of `newparent`, replacing the entry
+    /// there if `uid` may remove it, and returns the moved inode. A directory is refused under
+    /// itself, found by the parent pointers of the ancestors of `newparent`.
     #[instrument]
-    pub async fn read_dir(&mut self, ino: u64) -> Result<Directory> {
-        let data =
-            self.get(ScopedKey::block(ino, 0))
+    pub async fn rename(
+        &mut self,
+        parent: u64,
+        name: ByteString,
+        newparent: u64,
+        newname: ByteString,
+        uid: u32,
+    ) -> Result<Inode> {
+        let ino = self.lookup(parent, name.clone()).await?;
+        let inode = self.read_inode(ino).await?;
+        if newparent != parent {
+            self.ensure_not_moved_under(&inode, newparent).await?;
+        }
+        if let Some(old_ino) = self.get_index(newparent, newname.clone()).await? {
+            // links of the same file, which must not be released by `link`
+            if old_ino == ino {
+                return Ok(inode);
+            }
+            self.read_inode(old_ino)
                 .await?
-                .ok_or_else(|| FsError::BlockNotFound {
-                    inode: ino,
-                    block: 0,
-                })?;
-        trace!("read data: {}", String::from_utf8_lossy(&data));
-        super::dir::decode(&data)
+                .ensure_removable(&self.read_inode(newparent).await?, uid)?;
+        }
+        self.link(ino, newparent, newname).await?;
+        self.unlink(parent, name, uid).await?;
+        self.read_inode(ino).await
+    }
+
+    /// Refuse to move the directory `inode` under itself, found by the parent pointers of the
+    /// ancestors of `newparent`.
+    async fn ensure_not_moved_under(&self, inode: &Inode, newparent: u64) -> Result<()> {
+        if inode.kind != FileType::Directory {
+            return Ok(());
+        }
+        let mut ancestor = newparent;
+        for _ in 0..MAX_RENAME_DEPTH {
+            if ancestor == inode.ino {
+                return Err(FsError::MoveIntoItself { ino: inode.ino });
+            }
+            // inodes made before parent pointers are recorded point to 0
+            if ancestor == ROOT_INODE || ancestor == 0 {
+                break;
+            }
+            ancestor = self.read_inode(ancestor).await?.parent;
+        }
+        Ok(())
+    }
+
+    /// Swap the entry `name` of `parent` with the entry `newname` of `newparent`, as
+    /// `RENAME_EXCHANGE` does, and returns their inodes in that order. Both entries must exist,
+    /// and each is checked like a removal by `uid` from its directory.
+    #[instrument]
+    pub async fn exchange(
+        &mut self,
+        parent: u64,
+        name: ByteString,
+        newparent: u64,
+        newname: ByteString,
+        uid: u32,
+    ) -> Result<(Inode, Inode)> {
+        let ino = self.lookup(parent, name.clone()).await?;
+        let newino = self.lookup(newparent, newname.clone()).await?;
+        let mut inode = self.read_inode(ino).await?;
+        let mut newinode = self.read_inode(newino).await?;
+        // links of the same file, there is nothing to swap
+        if ino == newino {
+            return Ok((inode, newinode));
+        }
+        if newparent != parent {
+            self.ensure_not_moved_under(&inode, newparent).await?;
+            self.ensure_not_moved_under(&newinode, parent).await?;
+        }
+        for (moved, dir) in [(&inode, parent), (&newinode, newparent)].iter() {
+            let dir = self.read_inode(*dir).await?;
+            dir.ensure_mutable()?;
+            dir.ensure_not_append_only()?;
+            moved.ensure_mutable()?;
+            moved.ensure_not_append_only()?;
+            moved.ensure_removable(&dir, uid)?;
+        }
+
+        self.set_index(parent, name.clone(), newino).await?;
+        self.set_index(newparent, newname.clone(), ino).await?;
+        let dirs = if parent == newparent {
+            vec![parent]
+        } else {
+            vec![parent, newparent]
+        };
+        for dir_ino in dirs {
+            let mut dir = self.read_dir(dir_ino).await?;
+            for item in dir.iter_mut() {
+                if dir_ino == parent && item.name == &*name {
+                    item.ino = newino;
+                    item.typ = newinode.kind;
+                } else if dir_ino == newparent && item.name == &*newname {
+                    item.ino = ino;
+                    item.typ = inode.kind;
+                }
+            }
+            self.save_dir(dir_ino, &dir).await?;
+        }
+
+        let now = SystemTime::now();
+        inode.parent = newparent;
+        inode.ctime = now;
+        self.save_inode(&inode).await?;
+        newinode.parent = parent;
+        newinode.ctime = now;
+        self.save_inode(&newinode).await?;
+        Ok((inode, newinode))
+    }
+
+    #[instrument]
+    /// The entries of a directory, merged from its shards if it is sharded.
+    pub async fn read_dir(&mut self, ino: u64) -> Result<Directory> {
+        let pairs: Vec<KvPair> = self
+            .scan(
+                ScopedKey::block_range(ino, 0..MAX_DIR_SHARDS as u64),
+                MAX_DIR_SHARDS,
+            )
+            .await?
+            .collect();
+        if pairs.is_empty() {
+            return Err(FsError::BlockNotFound {
+                inode: ino,
+                block: 0,
+            });
+        }
+        let mut dir = Directory::new();
+        for pair in pairs {
+            trace!("read data: {}", String::from_utf8_lossy(pair.value()));
+            dir.extend(super::dir::decode(pair.value())?);
+        }
+        Ok(dir)
     }
 
     #[instrument]
     pub async fn save_dir(&mut self, ino: u64, dir: &Directory) -> Result<Inode> {
-        let data = super::dir::encode(dir)?;
         let mut inode = self.read_inode(ino).await?;
+        inode.ensure_unsealed()?;
+        if inode.dir_shards > 0 {
+            self.save_dir_shards(ino, DirShards(inode.dir_shards), dir)
+                .await?;
+            if self.posix.is_strict() {
+                inode.mtime = SystemTime::now();
+                inode.ctime = SystemTime::now();
+                self.save_inode(&inode).await?;
+            }
+            return Ok(inode);
+        }
+        let data = super::dir::encode(dir)?;
         inode.set_size(data.len() as u64, self.block_size);
         inode.atime = SystemTime::now();
         inode.mtime = SystemTime::now();
@@ -645,6 +2448,64 @@ impl Txn {
         self.put(ScopedKey::block(ino, 0), data).await?;
         Ok(inode)
     }
+
+    /// Write the shards of a directory which differ from the stored ones.
+    async fn save_dir_shards(
+        &mut self,
+        ino: u64,
+        shards: DirShards,
+        dir: &Directory,
+    ) -> Result<()> {
+        let empty = super::dir::encode(&Directory::new())?;
+        for (shard, items) in shards.split(dir).iter().enumerate() {
+            let key = ScopedKey::block(ino, shard as u64);
+            let data = super::dir::encode(items)?;
+            let stored = self.get(key).await?.unwrap_or_else(|| empty.clone());
+            if stored != data {
+                self.put(key, data).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Spread the entries of a directory over `shards`, or merge them back into one block if
+    /// it is 0.
+    #[instrument]
+    pub async fn set_dir_shards(&mut self, ino: u64, shards: DirShards) -> Result<()> {
+        let mut inode = self.read_inode(ino).await?;
+        if inode.kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        inode.ensure_unsealed()?;
+        let dir = self.read_dir(ino).await?;
+        for block in shards.0.max(1)..inode.dir_shards {
+            self.delete(ScopedKey::block(ino, block as u64)).await?;
+        }
+        inode.dir_shards = shards.0;
+        self.save_inode(&inode).await?;
+        self.save_dir(ino, &dir).await?;
+        Ok(())
+    }
+
+    /// Set the type of the entry `name` of `parent` to the type of its inode `ino`, returns
+    /// false if it is right already.
+    #[instrument]
+    pub async fn retype_dir_item(&mut self, parent: u64, name: &str, ino: u64) -> Result<bool> {
+        let kind = self.read_inode(ino).await?.kind;
+        let mut dir = self.read_dir(parent).await?;
+        let item = dir
+            .iter_mut()
+            .find(|item| item.name == name && item.ino == ino)
+            .ok_or_else(|| FsError::FileNotFound {
+                file: name.to_owned(),
+            })?;
+        if item.typ == kind {
+            return Ok(false);
+        }
+        item.typ = kind;
+        self.save_dir(parent, &dir).await?;
+        Ok(true)
+    }
 }
 
 impl Deref for Txn {
@@ -653,46 +2514,213 @@ impl Deref for Txn {
     fn deref(&self) -> &Self::Target {
         &self.txn
     }
-}
+}
+
+impl DerefMut for Txn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.txn
+    }
+}
+
+impl Debug for Txn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.block_size.fmt(f)
+    }
+}
+
+impl LocalTxn {
+    const INLINE_DATA_THRESHOLD_BASE: u64 = 1 << 4;
+
+    fn inline_data_threshold(&self) -> u64 {
+        self.block_size / Self::INLINE_DATA_THRESHOLD_BASE
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Take the numbers of new inodes from `pool`, see `InodePool`.
+    pub fn with_inode_pool(mut self, pool: Arc<InodePool>) -> Self {
+        self.inode_pool = Some(pool);
+        self
+    }
+
+    /// Release the numbers of removed inodes and take the lowest released number for new
+    /// inodes, see `-o inode_reuse`.
+    pub fn with_inode_reuse(mut self, reuse: bool) -> Self {
+        self.reuse_inodes = reuse;
+        self
+    }
+
+    /// Keep the data of every file in blocks, moving inline data out of the inodes written,
+    /// see `-o no_inline`. Symlink targets stay in their inodes.
+    pub fn with_inline_data(mut self, inline: bool) -> Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Key the entries of directories by hashes of their names, see `hashed_names`.
+    pub fn with_hashed_names(mut self, hashed: bool) -> Self {
+        self.hashed_names = hashed;
+        self
+    }
+
+    /// Record the access times of reads in `batch` instead of saving the inodes, see
+    /// `AtimeBatch`.
+    pub fn with_atime_batch(mut self, batch: Arc<AtimeBatch>) -> Self {
+        self.atimes = Some(batch);
+        self
+    }
+
+    /// Follow POSIX as closely as `posix` asks, see `-o posix`. A strict transaction saves
+    /// access times itself instead of recording them in the atime batch.
+    pub fn with_posix(mut self, posix: PosixMode) -> Self {
+        self.posix = posix;
+        self
+    }
+
+    /// Keep the inodes referred to by the kernel of the mount after their last link and
+    /// handle are gone, see `LookupCounts`.
+    pub fn with_lookups(mut self, lookups: Arc<LookupCounts>) -> Self {
+        self.lookups = Some(lookups);
+        self
+    }
+
+    /// The inode numbers reserved but not used by this transaction.
+    pub fn take_reserved(&mut self) -> Option<Range<u64>> {
+        self.reserved.take()
+    }
+
+    /// The access times of the batch saved by this transaction, see `AtimeBatch::settle`.
+    pub fn take_saved_atimes(&mut self) -> Vec<(u64, SystemTime)> {
+        std::mem::take(&mut self.saved_atimes)
+    }
+
+    /// Record the access time of a read, in the atime batch if there is one.
+    async fn touch_atime(&mut self, inode: &mut Inode) -> Result<()> {
+        inode.atime = SystemTime::now();
+        if let Some(batch) = self.atimes.as_ref().filter(|_| !self.posix.is_strict()) {
+            batch.touch(inode.ino, inode.atime);
+            return Ok(());
+        }
+        self.save_inode(inode).await
+    }
+
+    /// Apply the access time recorded in the atime batch to an inode read.
+    fn apply_pending_atime(&self, inode: &mut Inode) {
+        if let Some(atime) = self
+            .atimes
+            .as_ref()
+            .and_then(|batch| batch.pending(inode.ino))
+        {
+            inode.atime = inode.atime.max(atime);
+        }
+    }
+
+    /// Remember that the access time of the batch is saved with `inode`.
+    fn save_pending_atime(&mut self, inode: &Inode) {
+        let pending = self
+            .atimes
+            .as_ref()
+            .and_then(|batch| batch.pending(inode.ino));
+        if pending.is_some() {
+            self.saved_atimes.push((inode.ino, inode.atime));
+        }
+    }
+
+    /// Number and generation of a new inode, the lowest released number if inodes are reused,
+    /// or from the numbers reserved by the mount if there are any. Otherwise it is taken from
+    /// the meta, with a batch reserved for the pool.
+    async fn next_ino(&mut self) -> Result<(u64, u64)> {
+        if self.reuse_inodes {
+            if let Some((ino, generation)) = self.take_free_ino().await? {
+                return Ok((ino, generation + 1));
+            }
+        }
+        if let Some(ino) = self.reserved.as_mut().and_then(Iterator::next) {
+            return Ok((ino, 0));
+        }
+        if let Some(ino) = self.inode_pool.as_ref().and_then(|pool| pool.take()) {
+            return Ok((ino, 0));
+        }
+        let mut meta = self
+            .read_meta()
+            .await?
+            .unwrap_or_else(|| Meta::new(self.block_size));
+        let ino = meta.inode_next;
+        meta.inode_next += 1;
+        if self.inode_pool.is_some() {
+            self.reserved = Some(meta.inode_next..meta.inode_next + InodePool::BATCH);
+            meta.inode_next += InodePool::BATCH;
+        }
+        self.save_meta(&meta).await?;
+        Ok((ino, 0))
+    }
+
+    pub async fn begin_optimistic(
+        entry_map: Arc<Mutex<MemStore>>,
+        block_size: u64,
+    ) -> Result<Self> {
+        Ok(LocalTxn {
+            entry_map: entry_map,
+            block_size,
+            writes: LocalWrites::default(),
+            touched: Touched::default(),
+            inode_pool: None,
+            reserved: None,
+            reuse_inodes: false,
+            inline: true,
+            hashed_names: false,
+            atimes: None,
+            saved_atimes: Vec::new(),
+            posix: PosixMode::default(),
+            lookups: None,
+        })
+    }
 
-impl DerefMut for Txn {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.txn
+    fn local(&self) -> LocalView<'_, &LocalWrites> {
+        LocalView::new(self.entry_map.lock().unwrap(), &self.writes)
     }
-}
 
-impl Debug for Txn {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.block_size.fmt(f)
+    fn local_mut(&mut self) -> LocalView<'_, &mut LocalWrites> {
+        LocalView::new(self.entry_map.lock().unwrap(), &mut self.writes)
     }
-}
 
-impl LocalTxn {
-    const INLINE_DATA_THRESHOLD_BASE: u64 = 1 << 4;
+    /// Apply the buffered writes to the store, failing with a key error on write conflicts.
+    pub async fn commit(&mut self) -> Result<()> {
+        let writes = std::mem::take(&mut self.writes);
+        if writes.is_empty() {
+            return Ok(());
+        }
+        for key in writes.keys() {
+            self.touched.record(key.into());
+        }
+        self.entry_map.lock().unwrap().commit(writes)
+    }
 
-    fn inline_data_threshold(&self) -> u64 {
-        self.block_size / Self::INLINE_DATA_THRESHOLD_BASE
+    pub async fn rollback(&mut self) -> Result<()> {
+        self.writes = LocalWrites::default();
+        Ok(())
     }
 
-    pub fn block_size(&self) -> u64 {
-        self.block_size
+    /// Whether the transaction writes keys of the volume, so its commit is held by freezes.
+    pub fn writes_volume(&self) -> bool {
+        self.writes
+            .keys()
+            .any(|key| !ScopedKey::is_coordination(key.into()))
     }
 
-    pub async fn begin_optimistic(
-        entry_map: Arc<Mutex<BTreeMap<Key, Value>>>,
-        block_size: u64,
-    ) -> Result<Self> {
-        Ok(LocalTxn {
-            entry_map: entry_map,
-            block_size,
-        })
+    /// The inodes and directories written by the commit, see `PinCache::invalidate`.
+    pub fn take_touched(&mut self) -> Touched {
+        std::mem::take(&mut self.touched)
     }
 
     #[instrument]
-    pub async fn open(&mut self, ino: u64) -> Result<u64> {
+    pub async fn open(&mut self, ino: u64, flags: i32, owner: u64) -> Result<u64> {
         let mut inode = self.read_inode(ino).await?;
         let fh = inode.next_fh;
-        self.save_fh(ino, fh, &FileHandler::default()).await?;
+        self.save_fh(ino, fh, &FileHandler::with_flags(0, flags).owned_by(owner))
+            .await?;
         inode.next_fh += 1;
         inode.opened_fh += 1;
         self.save_inode(&inode).await?;
@@ -703,17 +2731,23 @@ impl LocalTxn {
     pub async fn close(&mut self, ino: u64, fh: u64) -> Result<()> {
         self.read_fh(ino, fh).await?;
         {
-            let mut local = self.entry_map.lock().unwrap();
+            let mut local = self.local_mut();
             local.remove(&Key::from(ScopedKey::handler(ino, fh)));
         }
         let mut inode = self.read_inode(ino).await?;
-        inode.opened_fh -= 1;
+        if inode.opened_fh == 0 {
+            warn!(
+                "no handler of inode({}) is counted open when closing {}",
+                ino, fh
+            );
+        }
+        inode.opened_fh = inode.opened_fh.saturating_sub(1);
         self.save_inode(&inode).await
     }
 
     #[instrument]
     pub async fn read_fh(&self, ino: u64, fh: u64) -> Result<FileHandler> {
-        let local = self.entry_map.lock().unwrap();
+        let local = self.local();
         let data = local
             .get(&Key::from(ScopedKey::handler(ino, fh)))
             .ok_or_else(|| FsError::FhNotFound { ino, fh })?;
@@ -722,8 +2756,8 @@ impl LocalTxn {
 
     #[instrument(skip(handler))]
     pub async fn save_fh(&mut self, ino: u64, fh: u64, handler: &FileHandler) -> Result<()> {
-        let mut local = self.entry_map.lock().unwrap();
-        local.insert(Key::from(ScopedKey::handler(ino, fh)), handler.serialize()?);
+        let mut local = self.local_mut();
+        local.insert(Key::from(ScopedKey::handler(ino, fh)), handler.serialize()?)?;
         Ok(())
     }
 
@@ -737,23 +2771,168 @@ impl LocalTxn {
                 offset: start,
             });
         }
+        handler.check_direct_io(ino, start as u64, size as u64)?;
         self.read_data(ino, start as u64, Some(size as u64)).await
     }
 
+    /// Read like `read`, serving whole blocks from and filling them into `cache`.
+    #[instrument(skip(cache))]
+    pub async fn read_cached(
+        &mut self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        cache: &DiskCache,
+    ) -> Result<Vec<u8>> {
+        let inode = self.read_inode(ino).await?;
+        if inode.inline_data.is_some() {
+            return self.read(ino, fh, offset, size).await;
+        }
+        let handler = self.read_fh(ino, fh).await?;
+        let start = handler.cursor as i64 + offset;
+        if start >= 0 {
+            handler.check_direct_io(ino, start as u64, size as u64)?;
+            if let Some(data) = cache.read(&inode, start as u64, size as u64).await {
+                trace!("read inode({}) from disk cache", ino);
+                return Ok(data);
+            }
+        }
+        let data = self.read(ino, fh, offset, size).await?;
+        cache.fill(&inode, start as u64, &data).await;
+        Ok(data)
+    }
+
     #[instrument(skip(data))]
     pub async fn write(&mut self, ino: u64, fh: u64, offset: i64, data: Bytes) -> Result<usize> {
         let handler = self.read_fh(ino, fh).await?;
-        let start = handler.cursor as i64 + offset;
+        // the size known to the kernel may be stale, another mount may have appended since
+        let start = if handler.is_append() {
+            self.read_inode(ino).await?.size as i64
+        } else {
+            handler.cursor as i64 + offset
+        };
         if start < 0 {
             return Err(FsError::InvalidOffset {
                 ino: ino,
                 offset: start,
             });
         }
+        handler.check_direct_io(ino, start as u64, data.len() as u64)?;
 
         self.write_data(ino, start as u64, data).await
     }
 
+    /// Check that the handler `fh` of `ino` may write, for the writes to be buffered until the
+    /// handler or the inode changes, see `WriteCheck`.
+    #[instrument]
+    pub async fn check_write(&mut self, ino: u64, fh: u64) -> Result<WriteCheck> {
+        let handler = self.read_fh(ino, fh).await?;
+        let inode = self.read_inode(ino).await?;
+        inode.ensure_mutable()?;
+        Ok(WriteCheck::new(&handler, &inode))
+    }
+
+    /// Copy `len` bytes of `ino_in` at `offset_in` to `ino_out` at `offset_out`, relative to
+    /// the cursors of their handlers like `read` and `write`. Whole blocks at aligned offsets
+    /// are copied as they are stored, holes included, the rest is read and written. Returns
+    /// the copied bytes, fewer than `len` at the end of the source or beyond
+    /// `COPY_RANGE_BLOCKS`.
+    #[instrument]
+    pub async fn copy_range(
+        &mut self,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+    ) -> Result<usize> {
+        let start_in = self.read_fh(ino_in, fh_in).await?.cursor as i64 + offset_in;
+        if start_in < 0 {
+            return Err(FsError::InvalidOffset {
+                ino: ino_in,
+                offset: start_in,
+            });
+        }
+        let start_out = self.read_fh(ino_out, fh_out).await?.cursor as i64 + offset_out;
+        if start_out < 0 {
+            return Err(FsError::InvalidOffset {
+                ino: ino_out,
+                offset: start_out,
+            });
+        }
+        let (start_in, start_out) = (start_in as u64, start_out as u64);
+        let source = self.read_inode(ino_in).await?;
+        let len = len
+            .min(source.size.saturating_sub(start_in))
+            .min(COPY_RANGE_BLOCKS * self.block_size);
+        if len == 0 {
+            return Ok(0);
+        }
+        if ino_in == ino_out && start_in < start_out + len && start_out < start_in + len {
+            return Err(FsError::InvalidOffset {
+                ino: ino_out,
+                offset: start_out as i64,
+            });
+        }
+        let blocks = len / self.block_size;
+        let aligned = start_in % self.block_size == 0 && start_out % self.block_size == 0;
+        if !aligned || blocks == 0 || source.inline_data.is_some() {
+            let data = self.read_data(ino_in, start_in, Some(len)).await?;
+            return self.write_data(ino_out, start_out, data.into()).await;
+        }
+
+        let mut inode = self.read_inode(ino_out).await?;
+        inode.ensure_mutable()?;
+        inode.ensure_appendable(start_out)?;
+        if self.posix.is_strict() {
+            inode.kill_suid();
+        }
+        if inode.inline_data.is_some() {
+            self.transfer_inline_data_to_block(&mut inode).await?;
+        }
+        let first_in = start_in / self.block_size;
+        let first_out = start_out / self.block_size;
+        let mut stored = HashMap::new();
+        {
+            let local = self.local();
+            let range = ScopedKey::block_range(ino_in, first_in..first_in + blocks);
+            for (key, value) in local.range(range) {
+                if let ScopedKey::Block { ino: _, block } =
+                    ScopedKey::parse(Into::<&'_ [u8]>::into(key))?
+                {
+                    stored.insert(block, value.clone());
+                }
+            }
+        }
+        {
+            let mut local = self.local_mut();
+            for i in 0..blocks {
+                let key = Key::from(ScopedKey::block(ino_out, first_out + i));
+                match stored.remove(&(first_in + i)) {
+                    Some(value) => local.insert(key, value)?,
+                    None => local.remove(&key),
+                }
+            }
+        }
+        let copied = blocks * self.block_size;
+        inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.data_changed();
+        inode.set_size(inode.size.max(start_out + copied), self.block_size);
+        self.save_inode(&inode).await?;
+        if copied < len {
+            let data = self
+                .read_data(ino_in, start_in + copied, Some(len - copied))
+                .await?;
+            self.write_data(ino_out, start_out + copied, data.into())
+                .await?;
+        }
+        Ok(len as usize)
+    }
+
     #[instrument]
     pub async fn make_inode(
         &mut self,
@@ -764,17 +2943,14 @@ impl LocalTxn {
         uid: u32,
         rdev: u32,
     ) -> Result<Inode> {
-        let mut meta = self
-            .read_meta()
-            .await?
-            .unwrap_or_else(|| Meta::new(self.block_size));
-        let ino = meta.inode_next;
-        meta.inode_next += 1;
-
-        debug!("get ino({})", ino);
-        self.save_meta(&meta).await?;
+        if parent >= ROOT_INODE {
+            self.read_inode(parent).await?.ensure_mutable()?;
+        }
+        let (ino, generation) = self.next_ino().await?;
+        debug!("get ino({}) of generation {}", ino, generation);
 
-        let file_type = as_file_kind(mode);
+        let file_type = as_file_kind(mode).ok_or(FsError::UnknownFileType)?;
+        let mut dir_inode = None;
         if parent >= ROOT_INODE {
             if self.get_index(parent, name.clone()).await?.is_some() {
                 return Err(FsError::FileExist {
@@ -792,11 +2968,11 @@ impl LocalTxn {
                 typ: file_type,
             });
 
-            self.save_dir(parent, &dir).await?;
+            dir_inode = Some(self.save_dir(parent, &dir).await?);
             // TODO: update attributes of directory
         }
 
-        let inode = FileAttr {
+        let mut inode: Inode = FileAttr {
             ino,
             size: 0,
             blocks: 0,
@@ -805,27 +2981,52 @@ impl LocalTxn {
             ctime: SystemTime::now(),
             crtime: SystemTime::now(),
             kind: file_type,
-            perm: as_file_perm(mode),
+            perm: if file_type == FileType::Directory {
+                (mode & 0o7777) as _
+            } else {
+                as_file_perm(mode)
+            },
             nlink: 1,
             uid,
             gid,
-            rdev,
+            rdev: if is_device(file_type) { rdev } else { 0 },
             blksize: self.block_size as u32,
             padding: 0,
             flags: 0,
         }
         .into();
+        inode.generation = generation;
+        inode.parent = parent;
+
+        if let Some(dir) = dir_inode {
+            if let Some(worm) = dir.worm {
+                if file_type == FileType::Directory {
+                    inode.worm = Some(worm);
+                } else {
+                    inode.worm_after = Some(worm.lock_time(SystemTime::now()));
+                }
+            }
+            inode.inherit(&dir);
+        }
 
         debug!("made inode ({:?})", &inode);
 
         self.save_inode(&inode).await?;
+        if inode.ttl.is_some() {
+            self.save_scratch(ino, true).await?;
+        }
         Ok(inode.into())
     }
 
     #[instrument]
     pub async fn get_index(&self, parent: u64, name: ByteString) -> Result<Option<u64>> {
+        if self.hashed_names {
+            let hashed = hashed_name(&name);
+            let index = self.get_hashed_index(parent, &hashed)?;
+            return Ok(index.and_then(|index| index.get(&name)));
+        }
         let key = ScopedKey::index(parent, &name);
-        let local = self.entry_map.lock().unwrap();
+        let local = self.local();
         let index = local.get(&Key::from(key));
         index
             .map(|value| Ok(Index::deserialize(&value)?.ino))
@@ -834,75 +3035,570 @@ impl LocalTxn {
 
     #[instrument]
     pub async fn set_index(&mut self, parent: u64, name: ByteString, ino: u64) -> Result<()> {
+        if self.hashed_names {
+            let hashed = hashed_name(&name);
+            let mut index = self.get_hashed_index(parent, &hashed)?.unwrap_or_default();
+            index.insert(&name, ino);
+            let value = index.serialize()?;
+            let mut local = self.local_mut();
+            local.insert(Key::from(ScopedKey::index(parent, &hashed)), value)?;
+            return Ok(());
+        }
         let key = ScopedKey::index(parent, &name);
         let value = Index::new(ino).serialize()?;
-        let mut local = self.entry_map.lock().unwrap();
-        local.insert(Key::from(key), value);
+        let mut local = self.local_mut();
+        local.insert(Key::from(key), value)?;
         Ok(())
     }
 
     #[instrument]
     pub async fn remove_index(&mut self, parent: u64, name: ByteString) -> Result<()> {
+        if self.hashed_names {
+            let hashed = hashed_name(&name);
+            let mut index = match self.get_hashed_index(parent, &hashed)? {
+                Some(index) => index,
+                None => return Ok(()),
+            };
+            index.remove(&name);
+            let key = Key::from(ScopedKey::index(parent, &hashed));
+            let mut local = self.local_mut();
+            if index.is_empty() {
+                local.remove(&key);
+            } else {
+                local.insert(key, index.serialize()?)?;
+            }
+            return Ok(());
+        }
         let key = ScopedKey::index(parent, &name);
-        let mut local = self.entry_map.lock().unwrap();
+        let mut local = self.local_mut();
         local.remove(&Key::from(key));
         Ok(())
     }
 
+    /// The entries whose names hash to `hashed`, on volumes with `hashed_names`.
+    fn get_hashed_index(&self, parent: u64, hashed: &str) -> Result<Option<HashedIndex>> {
+        let local = self.local();
+        let index = local.get(&Key::from(ScopedKey::index(parent, hashed)));
+        index
+            .map(|value| HashedIndex::deserialize(&value))
+            .transpose()
+    }
+
+    /// Whether any directory of the volume has an entry.
+    pub async fn has_index(&self) -> Result<bool> {
+        Ok(self
+            .local()
+            .range(ScopedKey::index_range())
+            .next()
+            .is_some())
+    }
+
     #[instrument]
     pub async fn read_inode(&self, ino: u64) -> Result<Inode> {
-        let local = self.entry_map.lock().unwrap();
+        let local = self.local();
         let value = local
             .get(&Key::from(ScopedKey::inode(ino)))
             .ok_or_else(|| FsError::InodeNotFound { inode: ino })?;
-        Ok(Inode::deserialize(&value)?)
+        let mut inode = Inode::deserialize(&value)?;
+        self.apply_pending_atime(&mut inode);
+        Ok(inode)
+    }
+
+    /// Read the inodes of `inos`, in the same order.
+    #[instrument]
+    pub async fn read_inodes(&self, inos: &[u64]) -> Result<Vec<Inode>> {
+        let mut inodes = Vec::with_capacity(inos.len());
+        for ino in inos {
+            inodes.push(self.read_inode(*ino).await?);
+        }
+        Ok(inodes)
     }
 
     #[instrument(skip(inode))]
     pub async fn save_inode(&mut self, inode: &Inode) -> Result<()> {
         let key = ScopedKey::inode(inode.ino);
         if inode.nlink == 0 && inode.opened_fh == 0 {
-            let mut local = self.entry_map.lock().unwrap();
-            local.remove(&Key::from(key));
+            if let Some(mount_id) = self.held_by_kernel(inode.ino) {
+                let mut orphan = inode.clone();
+                orphan.held_by = mount_id;
+                self.local_mut()
+                    .insert(Key::from(key), orphan.serialize()?)?;
+                debug!(
+                    "keep inode({}) for the kernel of mount({})",
+                    inode.ino, mount_id
+                );
+                return Ok(());
+            }
+            self.local_mut().remove(&Key::from(key));
             trace!(
                 "save inode:{:?} with nlink 0, size:{}, remove key",
                 inode,
                 inode.size
             );
-            let end_block = (inode.size + self.block_size - 1) / self.block_size;
-            {
-                for block in 0..end_block {
-                    local.remove(&Key::from(ScopedKey::block(inode.ino, block)));
-                }
+            self.queue_deletion(inode).await?;
+        } else {
+            self.local_mut()
+                .insert(Key::from(key), inode.serialize()?)?;
+            self.save_pending_atime(inode);
+            debug!("save inode: {:?}", inode);
+        }
+        Ok(())
+    }
+
+    /// The mount keeping the unlinked `ino` as long as its kernel refers to it, if any.
+    fn held_by_kernel(&self, ino: u64) -> Option<u64> {
+        self.lookups.as_ref().and_then(|lookups| lookups.hold(ino))
+    }
+
+    /// Remove an inode whatever its links are, queueing its blocks like `save_inode` does
+    /// when the last link is gone.
+    #[instrument(skip(inode))]
+    pub async fn remove_inode(&mut self, inode: &Inode) -> Result<()> {
+        self.local_mut()
+            .remove(&Key::from(ScopedKey::inode(inode.ino)));
+        self.queue_deletion(inode).await
+    }
+
+    /// Queue the blocks of a removed inode, they are deleted by `delete_dead_blocks` later.
+    #[instrument(skip(inode))]
+    async fn queue_deletion(&mut self, inode: &Inode) -> Result<()> {
+        let end_block =
+            ((inode.size + self.block_size - 1) / self.block_size).max(inode.dir_shards as u64);
+        if inode.inline_data.is_some() || end_block == 0 {
+            return self.release_ino(inode.ino, inode.generation).await;
+        }
+        let extent = DeadExtent::new(0..end_block, inode.generation);
+        let mut local = self.local_mut();
+        local.insert(
+            Key::from(ScopedKey::deletion(inode.ino)),
+            extent.serialize()?,
+        )?;
+        Ok(())
+    }
+
+    /// Release the number of a removed inode once nothing is left of it, if inodes are reused.
+    async fn release_ino(&mut self, ino: u64, generation: u64) -> Result<()> {
+        if self.reuse_inodes {
+            let mut local = self.local_mut();
+            local.remove(&Key::from(ScopedKey::scratch(ino)));
+            local.insert(
+                Key::from(ScopedKey::free_inode(ino)),
+                generation.to_be_bytes().to_vec(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Whether the directory has any entry, by its first index key rather than the whole listing.
+    async fn has_entries(&self, ino: u64) -> Result<bool> {
+        Ok(self
+            .local()
+            .range(ScopedKey::entries_range(ino))
+            .next()
+            .is_some())
+    }
+
+    /// Take the lowest released inode number, with the last generation of it.
+    async fn take_free_ino(&mut self) -> Result<Option<(u64, u64)>> {
+        let mut local = self.local_mut();
+        let (key, generation) = match local.range(ScopedKey::free_inode_range()).next() {
+            Some((key, value)) => (key.clone(), released_generation(value)),
+            None => return Ok(None),
+        };
+        let ino = match ScopedKey::parse(Into::<&'_ [u8]>::into(&key))? {
+            ScopedKey::FreeInode(ino) => ino,
+            _ => return Err(FsError::InvalidScopedKey(key.into())),
+        };
+        local.remove(&key);
+        Ok(Some((ino, generation)))
+    }
+
+    /// Up to `limit` removed inodes with blocks left in the deletion queue.
+    #[instrument]
+    pub async fn dead_inodes(&self, limit: u32) -> Result<Vec<u64>> {
+        let local = self.local();
+        local
+            .range(ScopedKey::deletion_range())
+            .take(limit as usize)
+            .map(
+                |(key, _)| match ScopedKey::parse(Into::<&'_ [u8]>::into(key))? {
+                    ScopedKey::Deletion(ino) => Ok(ino),
+                    _ => Err(FsError::InvalidScopedKey(key.clone().into())),
+                },
+            )
+            .collect()
+    }
+
+    /// Delete up to `limit` queued blocks of a removed inode, returns the number of deleted
+    /// blocks. The inode leaves the queue once all of its blocks are deleted.
+    #[instrument]
+    pub async fn delete_dead_blocks(&mut self, ino: u64, limit: u64) -> Result<u64> {
+        let key = Key::from(ScopedKey::deletion(ino));
+        let mut local = self.local_mut();
+        let mut extent = match local.get(&key) {
+            Some(value) => DeadExtent::deserialize(value)?,
+            None => return Ok(0),
+        };
+        let batch = extent.take_batch(limit);
+        for block in batch.clone() {
+            local.remove(&Key::from(ScopedKey::block(ino, block)));
+        }
+        if extent.is_empty() {
+            local.remove(&key);
+            drop(local);
+            self.release_ino(ino, extent.generation).await?;
+        } else {
+            local.insert(key, extent.serialize()?)?;
+        }
+        Ok(batch.end - batch.start)
+    }
+
+    /// The statistics added by the mount `mount_id`.
+    #[instrument]
+    pub async fn read_volume_stats(&self, mount_id: u64) -> Result<Option<VolumeStats>> {
+        let local = self.local();
+        let opt_data = local.get(&Key::from(ScopedKey::stats(mount_id)));
+        opt_data
+            .map(|data| VolumeStats::deserialize(&data))
+            .transpose()
+    }
+
+    #[instrument]
+    pub async fn save_volume_stats(&mut self, mount_id: u64, stats: &VolumeStats) -> Result<()> {
+        let mut local = self.local_mut();
+        local.insert(Key::from(ScopedKey::stats(mount_id)), stats.serialize()?)?;
+        Ok(())
+    }
+
+    /// The statistics of the volume merged from the rows of all mounts, and the number of rows.
+    #[instrument]
+    pub async fn volume_stats(&self) -> Result<(usize, VolumeStats)> {
+        let mut stats = VolumeStats::default();
+        let mut mounts = 0;
+        for (id, row) in self.stats_rows()? {
+            stats.merge(&row);
+            if !is_usage_row(id) {
+                mounts += 1;
+            }
+        }
+        Ok((mounts, stats))
+    }
+
+    /// The rows of the volume statistics by their ids.
+    fn stats_rows(&self) -> Result<Vec<(u64, VolumeStats)>> {
+        self.local()
+            .range(ScopedKey::stats_range())
+            .map(
+                |(key, value)| match ScopedKey::parse(Into::<&'_ [u8]>::into(key))? {
+                    ScopedKey::Stats(id) => Ok((id, VolumeStats::deserialize(value)?)),
+                    _ => Err(FsError::InvalidScopedKey(key.clone().into())),
+                },
+            )
+            .collect()
+    }
+
+    /// Fold the rows of the mounts not in `live` into `USAGE_BASE_ROW`, once it is counted.
+    /// Returns the rows folded.
+    #[instrument(skip(live))]
+    pub async fn fold_stats(&mut self, live: &HashSet<u64>) -> Result<usize> {
+        let mut base = match self.read_volume_stats(USAGE_BASE_ROW).await? {
+            Some(base) => base,
+            None => return Ok(0),
+        };
+        let mut folded = 0;
+        for (id, row) in self.stats_rows()? {
+            if is_usage_row(id) || live.contains(&id) {
+                continue;
+            }
+            base.merge(&row);
+            self.local_mut().remove(&Key::from(ScopedKey::stats(id)));
+            folded += 1;
+        }
+        if folded > 0 {
+            self.save_volume_stats(USAGE_BASE_ROW, &base).await?;
+        }
+        Ok(folded)
+    }
+
+    #[instrument]
+    pub async fn read_usage_rebase(&self) -> Result<Option<UsageRebase>> {
+        let local = self.local();
+        let opt_data = local.get(&Key::from(ScopedKey::usage_rebase()));
+        opt_data.map(|data| UsageRebase::deserialize(&data)).transpose()
+    }
+
+    /// Count the files and blocks of the next `page` inodes of the volume, up to the next of
+    /// `bounds`, into the cursor of `TiFs::rebase_usage`, and once all are counted rebase the
+    /// counters of the volume statistics on them. Returns the files and blocks counted once
+    /// the rebase is done.
+    #[instrument(skip(bounds))]
+    pub async fn rebase_usage_batch(
+        &mut self,
+        page: u32,
+        bounds: &[u64],
+    ) -> Result<Option<(u64, u64)>> {
+        let now = SystemTime::now();
+        let next_inode = self
+            .read_meta()
+            .await?
+            .map(|meta| meta.inode_next)
+            .unwrap_or(ROOT_INODE);
+        let mut cursor = match self.read_usage_rebase().await? {
+            Some(cursor) if cursor.is_fresh(now) => cursor,
+            _ => UsageRebase::new(ROOT_INODE, now),
+        };
+        let end = bounds
+            .iter()
+            .copied()
+            .find(|bound| cursor.next < *bound && *bound < next_inode)
+            .unwrap_or(next_inode);
+        let mut scanned = 0;
+        if cursor.next < end {
+            let inodes = self
+                .local()
+                .range(ScopedKey::inode_range(cursor.next..end))
+                .take(page as usize)
+                .map(|(_, value)| Inode::deserialize(value))
+                .collect::<Result<Vec<_>>>()?;
+            for inode in inodes {
+                cursor.blocks += inode.blocks;
+                cursor.files += 1;
+                cursor.next = inode.ino + 1;
+                scanned += 1;
+            }
+        }
+        if scanned < page && end < next_inode {
+            // the next batch starts on the next region
+            cursor.next = end;
+        }
+        if scanned == page || end < next_inode {
+            cursor.updated = now;
+            let mut local = self.local_mut();
+            local.insert(Key::from(ScopedKey::usage_rebase()), cursor.serialize()?)?;
+            return Ok(None);
+        }
+        self.local_mut().remove(&Key::from(ScopedKey::usage_rebase()));
+        let (_, counted) = self.volume_stats().await?;
+        let mut base = self
+            .read_volume_stats(USAGE_BASE_ROW)
+            .await?
+            .unwrap_or_default();
+        base.files += cursor.files as i64 - counted.files;
+        base.blocks += cursor.blocks as i64 - counted.blocks;
+        self.save_volume_stats(USAGE_BASE_ROW, &base).await?;
+        Ok(Some((cursor.files, cursor.blocks)))
+    }
+
+    #[instrument]
+    pub async fn read_meta(&self) -> Result<Option<Meta>> {
+        let local = self.local();
+        let opt_data = local.get(&Key::from(ScopedKey::meta()));
+        opt_data.map(|data| Meta::deserialize(&data)).transpose()
+    }
+
+    /// Append `op` done by the mount `origin` to the change journal.
+    #[instrument]
+    pub async fn append_journal(&mut self, origin: u64, op: JournalOp) -> Result<u64> {
+        let mut meta = self
+            .read_meta()
+            .await?
+            .unwrap_or_else(|| Meta::new(self.block_size));
+        let seq = meta.journal_next;
+        meta.journal_next += 1;
+        self.save_meta(&meta).await?;
+
+        let entry = JournalEntry {
+            seq,
+            time: SystemTime::now(),
+            origin,
+            op,
+        };
+        let mut local = self.local_mut();
+        local.insert(Key::from(ScopedKey::journal(seq)), entry.serialize()?)?;
+        Ok(seq)
+    }
+
+    /// Journal entries from sequence number `from` on, at most `limit` ones.
+    #[instrument]
+    pub async fn read_journal(&self, from: u64, limit: u32) -> Result<Vec<JournalEntry>> {
+        let local = self.local();
+        local
+            .range(ScopedKey::journal_range(from..std::u64::MAX))
+            .take(limit as usize)
+            .map(|(_, value)| JournalEntry::deserialize(value))
+            .collect()
+    }
+
+    #[instrument(skip(meta))]
+    pub async fn save_meta(&mut self, meta: &Meta) -> Result<()> {
+        let mut local = self.local_mut();
+        local.insert(Key::from(ScopedKey::meta()), meta.serialize()?)?;
+        Ok(())
+    }
+
+    #[instrument(skip(safepoint))]
+    pub async fn save_safepoint(&mut self, safepoint: &SafePoint) -> Result<()> {
+        let mut local = self.local_mut();
+        local.insert(
+            Key::from(ScopedKey::safepoint(&safepoint.service)),
+            safepoint.serialize()?,
+        )?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn delete_safepoint(&mut self, service: &str) -> Result<()> {
+        let mut local = self.local_mut();
+        local.remove(&Key::from(ScopedKey::safepoint(service)));
+        Ok(())
+    }
+
+    /// Write raw pairs, as restored from a backup.
+    #[instrument(skip(pairs))]
+    pub async fn put_pairs(&mut self, pairs: Vec<(Key, Vec<u8>)>) -> Result<()> {
+        let mut local = self.local_mut();
+        local.extend(pairs)?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn read_safepoints(&self) -> Result<Vec<SafePoint>> {
+        let local = self.local();
+        local
+            .range(ScopedKey::safepoint_range())
+            .map(|(_, value)| SafePoint::deserialize(value))
+            .collect()
+    }
+
+    #[instrument(skip(client))]
+    pub async fn save_client(&mut self, client: &Client) -> Result<()> {
+        let mut local = self.local_mut();
+        local.insert(
+            Key::from(ScopedKey::client(client.mount_id)),
+            client.serialize()?,
+        )?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn delete_client(&mut self, mount_id: u64) -> Result<()> {
+        let mut local = self.local_mut();
+        local.remove(&Key::from(ScopedKey::client(mount_id)));
+        Ok(())
+    }
+
+    /// Entries of the clients registry, expired or not.
+    #[instrument]
+    pub async fn read_clients(&self) -> Result<Vec<Client>> {
+        let local = self.local();
+        local
+            .range(ScopedKey::client_range())
+            .map(|(_, value)| Client::deserialize(value))
+            .collect()
+    }
+
+    #[instrument]
+    pub async fn read_freeze(&self) -> Result<Option<Freeze>> {
+        let local = self.local();
+        let opt_data = local.get(&Key::from(ScopedKey::freeze()));
+        opt_data.map(|data| Freeze::deserialize(&data)).transpose()
+    }
+
+    /// Refuse to commit writes to the volume while it is frozen, see `Freeze`.
+    pub async fn ensure_not_frozen(&self) -> Result<()> {
+        match self.read_freeze().await? {
+            Some(freeze) if !freeze.is_expired() => Err(FsError::Frozen { id: freeze.id }),
+            _ => Ok(()),
+        }
+    }
+
+    #[instrument]
+    pub async fn save_freeze(&mut self, freeze: &Freeze) -> Result<()> {
+        let mut local = self.local_mut();
+        local.insert(Key::from(ScopedKey::freeze()), freeze.serialize()?)?;
+        Ok(())
+    }
+
+    #[instrument]
+    pub async fn delete_freeze(&mut self) -> Result<()> {
+        let mut local = self.local_mut();
+        local.remove(&Key::from(ScopedKey::freeze()));
+        Ok(())
+    }
+
+    /// Mounts in the clients registry which are not expired.
+    #[instrument]
+    pub async fn live_clients(&self) -> Result<HashSet<u64>> {
+        let local = self.local();
+        let mut live = HashSet::new();
+        for (_, value) in local.range(ScopedKey::client_range()) {
+            let client = Client::deserialize(value)?;
+            if !client.is_expired() {
+                live.insert(client.mount_id);
             }
-            trace!("save inode:{:?} with nlink 0, after remove data", inode);
-        } else {
-            let mut local = self.entry_map.lock().unwrap();
-            local.insert(Key::from(key), inode.serialize()?);
-            debug!("save inode: {:?}", inode);
         }
-        Ok(())
+        Ok(live)
     }
 
+    /// File handlers of the inodes in `inos`, as `(ino, fh, handler)`.
     #[instrument]
-    pub async fn remove_inode(&mut self, ino: u64) -> Result<()> {
-        let mut local = self.entry_map.lock().unwrap();
-        local.remove(&Key::from(ScopedKey::inode(ino)));
-        Ok(())
+    pub async fn read_handlers(&self, inos: Range<u64>) -> Result<Vec<(u64, u64, FileHandler)>> {
+        let local = self.local();
+        local
+            .range(ScopedKey::handler_range(inos))
+            .map(
+                |(key, value)| match ScopedKey::parse(Into::<&'_ [u8]>::into(key))? {
+                    ScopedKey::FileHandler { ino, handler } => {
+                        Ok((ino, handler, FileHandler::deserialize(value)?))
+                    }
+                    _ => Err(FsError::InvalidScopedKey(key.clone().into())),
+                },
+            )
+            .collect()
     }
 
+    /// The inodes numbered in `inos`, the memory store is scanned at once.
     #[instrument]
-    pub async fn read_meta(&self) -> Result<Option<Meta>> {
-        let local = self.entry_map.lock().unwrap();
-        let opt_data = local.get(&Key::from(ScopedKey::meta()));
-        opt_data.map(|data| Meta::deserialize(&data)).transpose()
+    pub async fn scan_inodes(&self, inos: Range<u64>, _page: u32) -> Result<Vec<Inode>> {
+        self.local()
+            .range(ScopedKey::inode_range(inos))
+            .map(|(_, value)| Inode::deserialize(value))
+            .collect()
     }
 
-    #[instrument(skip(meta))]
-    pub async fn save_meta(&mut self, meta: &Meta) -> Result<()> {
-        let mut local = self.entry_map.lock().unwrap();
-        local.insert(Key::from(ScopedKey::meta()), meta.serialize()?);
-        Ok(())
+    /// Drop the handlers of `ino` opened by mounts not in `live`, and count the handlers left as
+    /// open. Returns the count recorded before if anything is changed.
+    #[instrument(skip(live))]
+    pub async fn reconcile_handles(
+        &mut self,
+        ino: u64,
+        live: &HashSet<u64>,
+    ) -> Result<Option<u64>> {
+        let mut open = 0;
+        let mut stale = 0;
+        for (_, fh, handler) in self.read_handlers(ino..ino + 1).await? {
+            if live.contains(&handler.owner) {
+                open += 1;
+            } else {
+                self.local_mut()
+                    .remove(&Key::from(ScopedKey::handler(ino, fh)));
+                stale += 1;
+            }
+        }
+        let mut inode = match self.read_inode(ino).await {
+            Err(FsError::InodeNotFound { inode: _ }) => {
+                return Ok(if stale > 0 { Some(0) } else { None })
+            }
+            result => result?,
+        };
+        if inode.opened_fh == open && stale == 0 {
+            return Ok(None);
+        }
+        let recorded = inode.opened_fh;
+        inode.opened_fh = open;
+        // removed files left open by crashed mounts are queued for deletion here
+        self.save_inode(&inode).await?;
+        Ok(Some(recorded))
     }
 
     #[instrument(skip(inode))]
@@ -915,8 +3611,8 @@ impl LocalTxn {
         let key = ScopedKey::block(inode.ino, 0);
         let mut data = inode.inline_data.clone().unwrap();
         data.resize(self.block_size as usize, 0);
-        let mut local = self.entry_map.lock().unwrap();
-        local.insert(Key::from(key), data);
+        let mut local = self.local_mut();
+        local.insert(Key::from(key), data)?;
         inode.inline_data = None;
         Ok(())
     }
@@ -950,6 +3646,7 @@ impl LocalTxn {
         inode.atime = SystemTime::now();
         inode.mtime = SystemTime::now();
         inode.ctime = SystemTime::now();
+        inode.data_changed();
         inode.set_size(inlined.len() as u64, self.block_size);
         inode.inline_data = Some(inlined);
         self.save_inode(inode).await?;
@@ -982,8 +3679,7 @@ impl LocalTxn {
             data[..to_copy].copy_from_slice(&inlined[start..start + to_copy]);
         }
 
-        inode.atime = SystemTime::now();
-        self.save_inode(inode).await?;
+        self.touch_atime(inode).await?;
 
         Ok(data)
     }
@@ -1004,6 +3700,9 @@ impl LocalTxn {
         let size = chunk_size.unwrap_or(max_size).min(max_size);
 
         if attr.inline_data.is_some() {
+            if let (Some(seal), Some(inlined)) = (&attr.seal, &attr.inline_data) {
+                seal.verify_block(ino, 0, inlined)?;
+            }
             return self.read_inline_data(&mut attr, start, size).await;
         }
 
@@ -1020,7 +3719,18 @@ impl LocalTxn {
         */
         let mut data: Vec<u8>;
         {
-            let local = self.entry_map.lock().unwrap();
+            let mut local = self.local();
+            local.touch(ino);
+            if let Some(seal) = &attr.seal {
+                seal.verify_blocks(
+                    ino,
+                    start_block..end_block,
+                    self.block_size,
+                    local
+                        .range(ScopedKey::block_range(ino, start_block..end_block))
+                        .map(|(key, value)| (block_of(key), value.as_slice())),
+                )?;
+            }
             let range_data = local.range(ScopedKey::block_range(ino, start_block..end_block));
             data = range_data
                 .enumerate()
@@ -1055,8 +3765,7 @@ impl LocalTxn {
         }
 
         data.resize(size as usize, 0);
-        attr.atime = SystemTime::now();
-        self.save_inode(&attr).await?;
+        self.touch_atime(&mut attr).await?;
         Ok(data)
     }
 
@@ -1065,7 +3774,7 @@ impl LocalTxn {
         let mut attr = self.read_inode(ino).await?;
         let end_block = (attr.size + self.block_size - 1) / self.block_size;
         {
-            let mut local = self.entry_map.lock().unwrap();
+            let mut local = self.local_mut();
             for block in 0..end_block {
                 local.remove(&Key::from(ScopedKey::block(ino, block)));
             }
@@ -1074,22 +3783,268 @@ impl LocalTxn {
         let clear_size = attr.size;
         attr.size = 0;
         attr.atime = SystemTime::now();
+        attr.data_changed();
         self.save_inode(&attr).await?;
         Ok(clear_size)
     }
 
+    /// Truncate or extend the content to `size`. A file in blocks shrinking under the inline
+    /// threshold is demoted to inline data and its block keys are deleted.
+    #[instrument(skip(inode))]
+    pub async fn truncate(&mut self, inode: &mut Inode, size: u64) -> Result<()> {
+        if size == inode.size {
+            return Ok(());
+        }
+        if inode.inline_data.is_some() && (size > self.block_size || !self.inline) {
+            self.transfer_inline_data_to_block(inode).await?;
+        }
+
+        if let Some(inlined) = &mut inode.inline_data {
+            inlined.resize(size as usize, 0);
+        } else if size < inode.size {
+            let end_block = (inode.size + self.block_size - 1) / self.block_size;
+            let block_size = self.block_size;
+            let demote = self.inline && size <= self.inline_data_threshold();
+            let mut local = self.local_mut();
+            if demote {
+                let mut inlined = match size {
+                    0 => Vec::new(),
+                    _ => local
+                        .get(&Key::from(ScopedKey::block(inode.ino, 0)))
+                        .cloned()
+                        .unwrap_or_default(),
+                };
+                inlined.resize(size as usize, 0);
+                for block in 0..end_block {
+                    local.remove(&Key::from(ScopedKey::block(inode.ino, block)));
+                }
+                debug!(
+                    "demote inode({}) of size {} to inline data",
+                    inode.ino, size
+                );
+                inode.inline_data = Some(inlined);
+            } else {
+                let kept_blocks = (size + block_size - 1) / block_size;
+                for block in kept_blocks..end_block {
+                    local.remove(&Key::from(ScopedKey::block(inode.ino, block)));
+                }
+                // zero the tail of the last block, which is read if the file is extended again
+                let tail = (size % block_size) as usize;
+                let key = Key::from(ScopedKey::block(inode.ino, size / block_size));
+                if tail != 0 {
+                    if let Some(mut value) = local.get(&key).cloned() {
+                        value[tail..].iter_mut().for_each(|byte| *byte = 0);
+                        local.insert(key, value)?;
+                    }
+                }
+            }
+        }
+
+        inode.set_size(size, self.block_size);
+        inode.data_changed();
+        Ok(())
+    }
+
+    /// Sha256 of the whole content, computed lazily and kept in the inode until the data changes.
+    #[instrument]
+    pub async fn content_hash(&mut self, ino: u64) -> Result<String> {
+        let mut inode = self.read_inode(ino).await?;
+        if let Some(hash) = &inode.content_hash {
+            return Ok(hash.clone());
+        }
+
+        let mut hasher = ContentHasher::new(inode.size, self.block_size);
+        if let Some(data) = &inode.inline_data {
+            hasher.update_inline(data);
+        } else {
+            let end_block = (inode.size + self.block_size - 1) / self.block_size;
+            let local = self.local();
+            for (key, value) in local.range(ScopedKey::block_range(ino, 0..end_block)) {
+                let key = Into::<&'_ [u8]>::into(key);
+                if let Ok(ScopedKey::Block { ino: _, block }) = ScopedKey::parse(key) {
+                    hasher.update_block(block, value);
+                }
+            }
+        }
+
+        let hash = hasher.finish();
+        inode.content_hash = Some(hash.clone());
+        self.save_inode(&inode).await?;
+        Ok(hash)
+    }
+
+    async fn block_leaves(&self, inode: &Inode) -> Result<Vec<Vec<u8>>> {
+        if let Some(data) = &inode.inline_data {
+            return Ok(vec![digest(data)]);
+        }
+
+        let end_block = (inode.size + self.block_size - 1) / self.block_size;
+        let hole = digest(&empty_block(self.block_size));
+        let mut leaves = Vec::with_capacity(end_block as usize);
+        let local = self.local();
+        for (key, value) in local.range(ScopedKey::block_range(inode.ino, 0..end_block)) {
+            leaves.resize(block_of(key) as usize, hole.clone());
+            leaves.push(digest(value));
+        }
+        leaves.resize(end_block as usize, hole);
+        Ok(leaves)
+    }
+
+    /// Inodes of the subtree rooted at `ino`, parents before children.
+    async fn subtree(&mut self, ino: u64) -> Result<Vec<Inode>> {
+        let mut inodes = vec![self.read_inode(ino).await?];
+        let mut index = 0;
+        while index < inodes.len() {
+            if inodes[index].kind == FileType::Directory {
+                let dir_ino = inodes[index].ino;
+                for item in self.read_dir(dir_ino).await? {
+                    inodes.push(self.read_inode(item.ino).await?);
+                }
+            }
+            index += 1;
+        }
+        Ok(inodes)
+    }
+
+    /// Derive the seals of the subtree rooted at `ino`, children before parents.
+    async fn derive_seals(&mut self, ino: u64) -> Result<Vec<(Inode, Seal)>> {
+        let inodes = self.subtree(ino).await?;
+        let mut roots = HashMap::new();
+        let mut seals = Vec::with_capacity(inodes.len());
+        for inode in inodes.into_iter().rev() {
+            let seal = if inode.kind == FileType::Directory {
+                let entries = self
+                    .read_dir(inode.ino)
+                    .await?
+                    .into_iter()
+                    .map(|item| {
+                        let root = roots.get(&item.ino).cloned().unwrap_or_default();
+                        (item.name, root)
+                    })
+                    .collect();
+                Seal::dir(entries)
+            } else {
+                Seal::file(inode.size, self.block_leaves(&inode).await?)
+            };
+            roots.insert(inode.ino, seal.root.clone());
+            seals.push((inode, seal));
+        }
+        Ok(seals)
+    }
+
+    /// Seal the subtree rooted at `ino` and return its Merkle root.
+    #[instrument]
+    pub async fn seal(&mut self, ino: u64) -> Result<String> {
+        let seals = self.derive_seals(ino).await?;
+        let root = hex(&seals.last().unwrap().1.root);
+        for (mut inode, seal) in seals {
+            inode.ensure_unsealed()?;
+            inode.seal = Some(seal);
+            self.save_inode(&inode).await?;
+        }
+        Ok(root)
+    }
+
+    /// Apply `worm` to the subtree rooted at directory `ino`.
+    #[instrument]
+    pub async fn set_worm(&mut self, ino: u64, worm: Worm) -> Result<()> {
+        let inodes = self.subtree(ino).await?;
+        if inodes[0].kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        if inodes[0].worm.is_some() {
+            return Err(FsError::WormLocked { ino });
+        }
+
+        let lock_time = worm.lock_time(SystemTime::now());
+        for mut inode in inodes {
+            if inode.kind == FileType::Directory {
+                inode.worm.get_or_insert(worm);
+            } else {
+                inode.worm_after.get_or_insert(lock_time);
+            }
+            self.save_inode(&inode).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the TTL of the directory `ino` and its subdirectories, or clear it, see `Ttl`.
+    #[instrument]
+    pub async fn set_ttl(&mut self, ino: u64, ttl: Option<Ttl>) -> Result<()> {
+        let inodes = self.subtree(ino).await?;
+        if inodes[0].kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        for mut inode in inodes {
+            if inode.kind == FileType::Directory {
+                inode.ttl = ttl;
+                self.save_inode(&inode).await?;
+                self.save_scratch(inode.ino, ttl.is_some()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_scratch(&mut self, ino: u64, scratch: bool) -> Result<()> {
+        let mut local = self.local_mut();
+        if scratch {
+            local.insert(Key::from(ScopedKey::scratch(ino)), Vec::new())?;
+        } else {
+            local.remove(&Key::from(ScopedKey::scratch(ino)));
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` directories with a TTL from inode `from` on.
+    #[instrument]
+    pub async fn scratch_dirs(&self, from: u64, limit: u32) -> Result<Vec<u64>> {
+        let local = self.local();
+        local
+            .range(Key::from(ScopedKey::scratch(from))..ScopedKey::scratch_range().end)
+            .take(limit as usize)
+            .map(
+                |(key, _)| match ScopedKey::parse(Into::<&'_ [u8]>::into(key))? {
+                    ScopedKey::Scratch(ino) => Ok(ino),
+                    _ => Err(FsError::InvalidScopedKey(key.clone().into())),
+                },
+            )
+            .collect()
+    }
+
+    /// Verify the sealed subtree rooted at `ino` against the stored seals and return its Merkle root.
+    #[instrument]
+    pub async fn verify_seal(&mut self, ino: u64) -> Result<String> {
+        let seals = self.derive_seals(ino).await?;
+        for (inode, seal) in &seals {
+            inode
+                .seal
+                .as_ref()
+                .ok_or(FsError::SealMismatch { ino: inode.ino })?
+                .verify_root(inode.ino, seal)?;
+        }
+        Ok(hex(&seals.last().unwrap().1.root))
+    }
+
     #[instrument(skip(data))]
     pub async fn write_data(&mut self, ino: u64, start: u64, data: Bytes) -> Result<usize> {
         debug!("write data at ({})[{}]", ino, start);
         let mut inode = self.read_inode(ino).await?;
+        inode.ensure_mutable()?;
+        inode.ensure_appendable(start)?;
+        if self.posix.is_strict() {
+            inode.kill_suid();
+        }
         let size = data.len();
         let target = start + size as u64;
 
-        if inode.inline_data.is_some() && target > self.block_size {
+        if inode.inline_data.is_some() && (target > self.block_size || !self.inline) {
             self.transfer_inline_data_to_block(&mut inode).await?;
         }
 
-        if (inode.inline_data.is_some() || inode.size == 0) && target <= self.block_size {
+        if self.inline
+            && (inode.inline_data.is_some() || inode.size == 0)
+            && target <= self.block_size
+        {
             return self.write_inline_data(&mut inode, start, &data).await;
         }
 
@@ -1103,7 +4058,7 @@ impl LocalTxn {
 
         let mut start_value;
         {
-            let local = self.entry_map.lock().unwrap();
+            let local = self.local();
             match local.get(&Key::from(start_key)) {
                 Some(x) => {
                     start_value = x.clone();
@@ -1115,8 +4070,8 @@ impl LocalTxn {
         }
         start_value[start_index..start_index + first_block.len()].copy_from_slice(first_block);
         {
-            let mut local = self.entry_map.lock().unwrap();
-            local.insert(Key::from(start_key), start_value);
+            let mut local = self.local_mut();
+            local.insert(Key::from(start_key), start_value)?;
         }
         while rest.len() != 0 {
             block_index += 1;
@@ -1127,7 +4082,7 @@ impl LocalTxn {
             if value.len() < self.block_size as usize {
                 let mut last_value;
                 {
-                    let local = self.entry_map.lock().unwrap();
+                    let local = self.local();
                     match local.get(&Key::from(key)) {
                         Some(x) => {
                             last_value = x.clone();
@@ -1141,8 +4096,8 @@ impl LocalTxn {
                 value = last_value.to_vec();
             }
             {
-                let mut local = self.entry_map.lock().unwrap();
-                local.insert(Key::from(key), value);
+                let mut local = self.local_mut();
+                local.insert(Key::from(key), value)?;
             }
             rest = current_rest;
         }
@@ -1150,6 +4105,7 @@ impl LocalTxn {
         inode.atime = SystemTime::now();
         inode.mtime = SystemTime::now();
         inode.ctime = SystemTime::now();
+        inode.data_changed();
         inode.set_size(inode.size.max(target), self.block_size);
         self.save_inode(&inode.into()).await?;
         trace!("write data len: {}", data.len());
@@ -1157,34 +4113,49 @@ impl LocalTxn {
     }
 
     #[instrument(skip(inode, data))]
+    /// Set the target of a symlink, which is kept in the inode itself.
     pub async fn write_link(&mut self, inode: &mut Inode, data: Bytes) -> Result<usize> {
         debug_assert!(inode.file_attr.kind == FileType::Symlink);
-        inode.inline_data = None;
-        inode.set_size(0, self.block_size);
-        self.write_inline_data(inode, 0, &data).await
+        check_link_target(&data)?;
+        inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.set_size(data.len() as u64, self.block_size);
+        inode.inline_data = Some(data.to_vec());
+        self.save_inode(inode).await?;
+        Ok(data.len())
     }
 
+    /// The target of a symlink, reading it does not write the inode.
     #[instrument]
-    pub async fn read_link(&mut self, ino: u64) -> Result<Vec<u8>> {
-        let mut inode = self.read_inode(ino).await?;
+    pub async fn read_link(&self, ino: u64) -> Result<Vec<u8>> {
+        let inode = self.read_inode(ino).await?;
         debug_assert!(inode.file_attr.kind == FileType::Symlink);
-        let size = inode.size;
-        self.read_inline_data(&mut inode, 0, size).await
+        Ok(inode.inline_data.unwrap_or_default())
     }
 
     #[instrument]
     pub async fn link(&mut self, ino: u64, newparent: u64, newname: ByteString) -> Result<Inode> {
+        self.read_inode(newparent).await?.ensure_mutable()?;
         if let Some(old_ino) = self.get_index(newparent, newname.clone()).await? {
             let inode = self.read_inode(old_ino).await?;
+            // callers replacing entries of others check them by themselves, see `rename`.
+            // The replaced inode is released like any other removal: its blocks are queued for
+            // deletion once no link or handle is left.
             match inode.kind {
-                FileType::Directory => self.rmdir(newparent, newname.clone()).await?,
-                _ => self.unlink(newparent, newname.clone()).await?,
+                FileType::Directory => self.rmdir(newparent, newname.clone(), 0).await?,
+                _ => self.unlink(newparent, newname.clone(), 0).await?,
             }
         }
         self.set_index(newparent, newname.clone(), ino).await?;
 
         let mut inode = self.read_inode(ino).await?;
-        let mut dir = self.read_dir(newparent).await?;
+        // the type is derived from the inode, dropping any stale item of the same name
+        let mut dir: Directory = self
+            .read_dir(newparent)
+            .await?
+            .into_iter()
+            .filter(|item| item.name != &*newname)
+            .collect();
 
         dir.push(DirItem {
             ino,
@@ -1194,18 +4165,26 @@ impl LocalTxn {
 
         self.save_dir(newparent, &dir).await?;
         inode.nlink += 1;
+        inode.parent = newparent;
         inode.ctime = SystemTime::now();
         self.save_inode(&inode).await?;
         Ok(inode)
     }
 
     #[instrument]
-    pub async fn unlink(&mut self, parent: u64, name: ByteString) -> Result<()> {
+    pub async fn unlink(&mut self, parent: u64, name: ByteString, uid: u32) -> Result<()> {
         match self.get_index(parent, name.clone()).await? {
             None => Err(FsError::FileNotFound {
                 file: name.to_string(),
             }),
             Some(ino) => {
+                let dir = self.read_inode(parent).await?;
+                dir.ensure_mutable()?;
+                dir.ensure_not_append_only()?;
+                let inode = self.read_inode(ino).await?;
+                inode.ensure_mutable()?;
+                inode.ensure_not_append_only()?;
+                inode.ensure_removable(&dir, uid)?;
                 self.remove_index(parent, name.clone()).await?;
                 let parent_dir = self.read_dir(parent).await?;
                 let new_parent_dir: Directory = parent_dir
@@ -1225,20 +4204,29 @@ impl LocalTxn {
     }
 
     #[instrument]
-    pub async fn rmdir(&mut self, parent: u64, name: ByteString) -> Result<()> {
+    pub async fn rmdir(&mut self, parent: u64, name: ByteString, uid: u32) -> Result<()> {
         match self.get_index(parent, name.clone()).await? {
             None => Err(FsError::FileNotFound {
                 file: name.to_string(),
             }),
             Some(ino) => {
-                let target_dir = self.read_dir(ino).await?;
-                if target_dir.len() != 0 {
+                let dir = self.read_inode(parent).await?;
+                dir.ensure_mutable()?;
+                dir.ensure_not_append_only()?;
+                let inode = self.read_inode(ino).await?;
+                inode.ensure_mutable()?;
+                inode.ensure_not_append_only()?;
+                inode.ensure_removable(&dir, uid)?;
+                if self.has_entries(ino).await? {
                     let name_str = name.to_string();
                     debug!("dir({}) not empty", &name_str);
                     return Err(FsError::DirNotEmpty { dir: name_str });
                 }
+                if inode.ttl.is_some() {
+                    self.save_scratch(ino, false).await?;
+                }
                 self.remove_index(parent, name.clone()).await?;
-                self.remove_inode(ino).await?;
+                self.remove_inode(&inode).await?;
 
                 let parent_dir = self.read_dir(parent).await?;
                 let new_parent_dir: Directory = parent_dir
@@ -1260,15 +4248,68 @@ impl LocalTxn {
             })
     }
 
+    /// Resolve `names` one after another from `parent`, returning the inodes of all of them.
+    /// Entries are looked up in order, but the inodes are read together at the end, so a deep
+    /// path costs one more request than its components instead of two per component.
+    #[instrument]
+    pub async fn lookup_path(&self, parent: u64, names: Vec<ByteString>) -> Result<Vec<Inode>> {
+        let mut inos = Vec::with_capacity(names.len());
+        let mut dir = parent;
+        for name in names {
+            dir = match self.lookup(dir, name).await {
+                Err(FsError::FileNotFound { file }) => {
+                    if self.read_inode(dir).await?.kind != FileType::Directory {
+                        return Err(FsError::NotDirectory { ino: dir });
+                    }
+                    return Err(FsError::FileNotFound { file });
+                }
+                result => result?,
+            };
+            inos.push(dir);
+        }
+        self.read_inodes(&inos).await
+    }
+
     #[instrument]
-    pub async fn fallocate(&mut self, inode: &mut Inode, offset: i64, length: i64) -> Result<()> {
+    /// Allocate `length` bytes at `offset`. Blocks are not reserved ahead, so this only grows
+    /// the size, unless `mode` has `FALLOC_FL_KEEP_SIZE`. `FALLOC_FL_PUNCH_HOLE` deletes the
+    /// blocks in the range and zeroes the partial ones at its ends, `FALLOC_FL_ZERO_RANGE` does
+    /// the same and grows the size like a plain allocation.
+    pub async fn fallocate(
+        &mut self,
+        inode: &mut Inode,
+        offset: i64,
+        length: i64,
+        mode: i32,
+    ) -> Result<()> {
+        inode.ensure_mutable()?;
+        if offset < 0 || length <= 0 {
+            return Err(FsError::InvalidOffset {
+                ino: inode.ino,
+                offset,
+            });
+        }
+        let punch = mode & FALLOC_FL_PUNCH_HOLE != 0;
+        let zero = mode & FALLOC_FL_ZERO_RANGE != 0;
+        let keep_size = mode & FALLOC_FL_KEEP_SIZE != 0;
+        let known = FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE | FALLOC_FL_ZERO_RANGE;
+        // like Linux, holes are punched only along with keeping the size
+        if mode & !known != 0 || (punch && (zero || !keep_size)) {
+            return Err(FsError::FallocateMode {
+                ino: inode.ino,
+                mode,
+            });
+        }
         let target_size = (offset + length) as u64;
-        if target_size <= inode.size {
+        if punch || zero {
+            self.zero_range(inode, offset as u64, target_size).await?;
+        }
+        if keep_size || target_size <= inode.size {
             return Ok(());
         }
 
         if inode.inline_data.is_some() {
-            if target_size <= self.inline_data_threshold() {
+            if self.inline && target_size <= self.inline_data_threshold() {
                 let original_size = inode.size;
                 let data = vec![0; (target_size - original_size) as usize];
                 self.write_inline_data(inode, original_size, &data).await?;
@@ -1280,6 +4321,45 @@ impl LocalTxn {
 
         inode.set_size(target_size, self.block_size);
         inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.data_changed();
+        self.save_inode(inode).await?;
+        Ok(())
+    }
+
+    /// Zero the data of `inode` in `start..end`, up to its size. Blocks wholly in the range are
+    /// deleted and read as zeros like the holes of sparse files.
+    async fn zero_range(&mut self, inode: &mut Inode, start: u64, end: u64) -> Result<()> {
+        let end = end.min(inode.size);
+        if start >= end {
+            return Ok(());
+        }
+        if let Some(inlined) = &mut inode.inline_data {
+            let end = (end as usize).min(inlined.len());
+            if (start as usize) < end {
+                inlined[start as usize..end]
+                    .iter_mut()
+                    .for_each(|byte| *byte = 0);
+            }
+        } else {
+            let block_size = self.block_size;
+            let mut local = self.local_mut();
+            for block in start / block_size..=(end - 1) / block_size {
+                let base = block * block_size;
+                let from = (start.max(base) - base) as usize;
+                let to = (end.min(base + block_size) - base) as usize;
+                let key = Key::from(ScopedKey::block(inode.ino, block));
+                if from == 0 && to == block_size as usize {
+                    local.remove(&key);
+                } else if let Some(mut value) = local.get(&key).cloned() {
+                    value[from..to].iter_mut().for_each(|byte| *byte = 0);
+                    local.insert(key, value)?;
+                }
+            }
+        }
+        inode.mtime = SystemTime::now();
+        inode.ctime = SystemTime::now();
+        inode.data_changed();
         self.save_inode(inode).await?;
         Ok(())
     }
@@ -1294,38 +4374,324 @@ impl LocalTxn {
         uid: u32,
     ) -> Result<Inode> {
         let dir_mode = make_mode(FileType::Directory, mode as _);
-        let mut inode = self.make_inode(parent, name, dir_mode, gid, uid, 0).await?;
-        inode.perm = mode as _;
-        self.save_inode(&inode).await?;
+        let inode = self.make_inode(parent, name, dir_mode, gid, uid, 0).await?;
         self.save_dir(inode.ino, &Directory::new()).await
     }
 
+    /// Apply the operations of `batch` in order, returning the created inodes.
+    #[instrument(skip(batch))]
+    pub async fn apply_batch(&mut self, batch: &Batch) -> Result<Vec<Inode>> {
+        let mut created = Vec::with_capacity(batch.len());
+        for op in &batch.ops {
+            let inode = match op {
+                BatchOp::Mkdir { parent, name, mode } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    self.mkdir(parent, name.clone(), *mode, batch.gid, batch.uid)
+                        .await?
+                }
+                BatchOp::Create {
+                    parent,
+                    name,
+                    mode,
+                    data,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let inode = self
+                        .make_inode(
+                            parent,
+                            name.clone(),
+                            make_mode(FileType::RegularFile, *mode as _),
+                            batch.gid,
+                            batch.uid,
+                            0,
+                        )
+                        .await?;
+                    if data.is_empty() {
+                        inode
+                    } else {
+                        self.write_data(inode.ino, 0, data.clone()).await?;
+                        self.read_inode(inode.ino).await?
+                    }
+                }
+                BatchOp::Symlink {
+                    parent,
+                    name,
+                    target,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let mut inode = self
+                        .make_inode(
+                            parent,
+                            name.clone(),
+                            make_mode(FileType::Symlink, 0o777),
+                            batch.gid,
+                            batch.uid,
+                            0,
+                        )
+                        .await?;
+                    self.write_link(&mut inode, target.clone().into_bytes())
+                        .await?;
+                    inode
+                }
+                BatchOp::Link {
+                    parent,
+                    name,
+                    target,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let target = resolve(*target, &created)?;
+                    self.link(target, parent, name.clone()).await?
+                }
+                BatchOp::Write {
+                    target,
+                    offset,
+                    data,
+                } => {
+                    let target = resolve(*target, &created)?;
+                    self.write_data(target, *offset, data.clone()).await?;
+                    self.read_inode(target).await?
+                }
+                BatchOp::Rename {
+                    parent,
+                    name,
+                    newparent,
+                    newname,
+                } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let newparent = resolve_parent(*newparent, &created)?;
+                    self.rename(parent, name.clone(), newparent, newname.clone(), batch.uid)
+                        .await?
+                }
+                BatchOp::Unlink { parent, name } => {
+                    let parent = resolve_parent(*parent, &created)?;
+                    let ino = self.lookup(parent, name.clone()).await?;
+                    let inode = self.read_inode(ino).await?;
+                    match inode.kind {
+                        FileType::Directory => self.rmdir(parent, name.clone(), batch.uid).await?,
+                        _ => self.unlink(parent, name.clone(), batch.uid).await?,
+                    }
+                    inode
+                }
+            };
+            created.push(inode);
+        }
+        Ok(created)
+    }
+
+    /// Move the entry `name` of `parent` to `newname` of `newparent`, replacing the entry
+    /// there if `uid` may remove it, and returns the moved inode. A directory is refused under
+    /// itself, found by the parent pointers of the ancestors of `newparent`.
+    #[instrument]
+    pub async fn rename(
+        &mut self,
+        parent: u64,
+        name: ByteString,
+        newparent: u64,
+        newname: ByteString,
+        uid: u32,
+    ) -> Result<Inode> {
+        let ino = self.lookup(parent, name.clone()).await?;
+        let inode = self.read_inode(ino).await?;
+        if newparent != parent {
+            self.ensure_not_moved_under(&inode, newparent).await?;
+        }
+        if let Some(old_ino) = self.get_index(newparent, newname.clone()).await? {
+            // links of the same file, which must not be released by `link`
+            if old_ino == ino {
+                return Ok(inode);
+            }
+            self.read_inode(old_ino)
+                .await?
+                .ensure_removable(&self.read_inode(newparent).await?, uid)?;
+        }
+        self.link(ino, newparent, newname).await?;
+        self.unlink(parent, name, uid).await?;
+        self.read_inode(ino).await
+    }
+
+    /// Refuse to move the directory `inode` under itself, found by the parent pointers of the
+    /// ancestors of `newparent`.
+    async fn ensure_not_moved_under(&self, inode: &Inode, newparent: u64) -> Result<()> {
+        if inode.kind != FileType::Directory {
+            return Ok(());
+        }
+        let mut ancestor = newparent;
+        for _ in 0..MAX_RENAME_DEPTH {
+            if ancestor == inode.ino {
+                return Err(FsError::MoveIntoItself { ino: inode.ino });
+            }
+            // inodes made before parent pointers are recorded point to 0
+            if ancestor == ROOT_INODE || ancestor == 0 {
+                break;
+            }
+            ancestor = self.read_inode(ancestor).await?.parent;
+        }
+        Ok(())
+    }
+
+    /// Swap the entry `name` of `parent` with the entry `newname` of `newparent`, as
+    /// `RENAME_EXCHANGE` does, and returns their inodes in that order. Both entries must exist,
+    /// and each is checked like a removal by `uid` from its directory.
+    #[instrument]
+    pub async fn exchange(
+        &mut self,
+        parent: u64,
+        name: ByteString,
+        newparent: u64,
+        newname: ByteString,
+        uid: u32,
+    ) -> Result<(Inode, Inode)> {
+        let ino = self.lookup(parent, name.clone()).await?;
+        let newino = self.lookup(newparent, newname.clone()).await?;
+        let mut inode = self.read_inode(ino).await?;
+        let mut newinode = self.read_inode(newino).await?;
+        // links of the same file, there is nothing to swap
+        if ino == newino {
+            return Ok((inode, newinode));
+        }
+        if newparent != parent {
+            self.ensure_not_moved_under(&inode, newparent).await?;
+            self.ensure_not_moved_under(&newinode, parent).await?;
+        }
+        for (moved, dir) in [(&inode, parent), (&newinode, newparent)].iter() {
+            let dir = self.read_inode(*dir).await?;
+            dir.ensure_mutable()?;
+            dir.ensure_not_append_only()?;
+            moved.ensure_mutable()?;
+            moved.ensure_not_append_only()?;
+            moved.ensure_removable(&dir, uid)?;
+        }
+
+        self.set_index(parent, name.clone(), newino).await?;
+        self.set_index(newparent, newname.clone(), ino).await?;
+        let dirs = if parent == newparent {
+            vec![parent]
+        } else {
+            vec![parent, newparent]
+        };
+        for dir_ino in dirs {
+            let mut dir = self.read_dir(dir_ino).await?;
+            for item in dir.iter_mut() {
+                if dir_ino == parent && item.name == &*name {
+                    item.ino = newino;
+                    item.typ = newinode.kind;
+                } else if dir_ino == newparent && item.name == &*newname {
+                    item.ino = ino;
+                    item.typ = inode.kind;
+                }
+            }
+            self.save_dir(dir_ino, &dir).await?;
+        }
+
+        let now = SystemTime::now();
+        inode.parent = newparent;
+        inode.ctime = now;
+        self.save_inode(&inode).await?;
+        newinode.parent = parent;
+        newinode.ctime = now;
+        self.save_inode(&newinode).await?;
+        Ok((inode, newinode))
+    }
+
     #[instrument]
+    /// The entries of a directory, merged from its shards if it is sharded.
     pub async fn read_dir(&mut self, ino: u64) -> Result<Directory> {
-        let local = self.entry_map.lock().unwrap();
-        let data = local
-            .get(&Key::from(ScopedKey::block(ino, 0)))
-            .ok_or_else(|| FsError::BlockNotFound {
+        let local = self.local();
+        let mut dir = Directory::new();
+        let mut found = false;
+        for (_, data) in local.range(ScopedKey::block_range(ino, 0..MAX_DIR_SHARDS as u64)) {
+            trace!("read data: {}", String::from_utf8_lossy(&data));
+            dir.extend(super::dir::decode(&data)?);
+            found = true;
+        }
+        if !found {
+            return Err(FsError::BlockNotFound {
                 inode: ino,
                 block: 0,
-            })?;
-        trace!("read data: {}", String::from_utf8_lossy(&data));
-        super::dir::decode(&data)
+            });
+        }
+        Ok(dir)
     }
 
     #[instrument]
     pub async fn save_dir(&mut self, ino: u64, dir: &Directory) -> Result<Inode> {
-        let data = super::dir::encode(dir)?;
         let mut inode = self.read_inode(ino).await?;
+        inode.ensure_unsealed()?;
+        if inode.dir_shards > 0 {
+            self.save_dir_shards(ino, DirShards(inode.dir_shards), dir)?;
+            if self.posix.is_strict() {
+                inode.mtime = SystemTime::now();
+                inode.ctime = SystemTime::now();
+                self.save_inode(&inode).await?;
+            }
+            return Ok(inode);
+        }
+        let data = super::dir::encode(dir)?;
         inode.set_size(data.len() as u64, self.block_size);
         inode.atime = SystemTime::now();
         inode.mtime = SystemTime::now();
         inode.ctime = SystemTime::now();
         self.save_inode(&inode).await?;
-        let mut local = self.entry_map.lock().unwrap();
-        local.insert(Key::from(ScopedKey::block(ino, 0)), data);
+        let mut local = self.local_mut();
+        local.insert(Key::from(ScopedKey::block(ino, 0)), data)?;
         Ok(inode)
     }
+
+    /// Write the shards of a directory which differ from the stored ones.
+    fn save_dir_shards(&mut self, ino: u64, shards: DirShards, dir: &Directory) -> Result<()> {
+        let empty = super::dir::encode(&Directory::new())?;
+        let mut local = self.local_mut();
+        for (shard, items) in shards.split(dir).iter().enumerate() {
+            let key = Key::from(ScopedKey::block(ino, shard as u64));
+            let data = super::dir::encode(items)?;
+            if *local.get(&key).unwrap_or(&empty) != data {
+                local.insert(key, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Spread the entries of a directory over `shards`, or merge them back into one block if
+    /// it is 0.
+    #[instrument]
+    pub async fn set_dir_shards(&mut self, ino: u64, shards: DirShards) -> Result<()> {
+        let mut inode = self.read_inode(ino).await?;
+        if inode.kind != FileType::Directory {
+            return Err(FsError::NotDirectory { ino });
+        }
+        inode.ensure_unsealed()?;
+        let dir = self.read_dir(ino).await?;
+        {
+            let mut local = self.local_mut();
+            for block in shards.0.max(1)..inode.dir_shards {
+                local.remove(&Key::from(ScopedKey::block(ino, block as u64)));
+            }
+        }
+        inode.dir_shards = shards.0;
+        self.save_inode(&inode).await?;
+        self.save_dir(ino, &dir).await?;
+        Ok(())
+    }
+
+    /// Set the type of the entry `name` of `parent` to the type of its inode `ino`, returns
+    /// false if it is right already.
+    #[instrument]
+    pub async fn retype_dir_item(&mut self, parent: u64, name: &str, ino: u64) -> Result<bool> {
+        let kind = self.read_inode(ino).await?.kind;
+        let mut dir = self.read_dir(parent).await?;
+        let item = dir
+            .iter_mut()
+            .find(|item| item.name == name && item.ino == ino)
+            .ok_or_else(|| FsError::FileNotFound {
+                file: name.to_owned(),
+            })?;
+        if item.typ == kind {
+            return Ok(false);
+        }
+        item.typ = kind;
+        self.save_dir(parent, &dir).await?;
+        Ok(true)
+    }
 }
 
 impl Debug for LocalTxn {
@@ -1333,3 +4699,99 @@ impl Debug for LocalTxn {
         self.block_size.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::flags::FS_IMMUTABLE_FL;
+
+    /// A volume in memory holding the immutable directory `dir` with the file `inside`, and
+    /// the file `outside` in the root. Returns the inode number of `dir`.
+    async fn immutable_dir() -> (LocalTxn, u64) {
+        let store = Arc::new(Mutex::new(MemStore::new(None, false)));
+        let mut txn = LocalTxn::begin_optimistic(store, 4096).await.unwrap();
+        let file_mode = make_mode(FileType::RegularFile, 0o644);
+        txn.mkdir(0, Default::default(), 0o755, 0, 0).await.unwrap();
+        let dir = txn.mkdir(ROOT_INODE, "dir".into(), 0o755, 0, 0).await.unwrap();
+        txn.make_inode(dir.ino, "inside".into(), file_mode, 0, 0, 0)
+            .await
+            .unwrap();
+        txn.make_inode(ROOT_INODE, "outside".into(), file_mode, 0, 0, 0)
+            .await
+            .unwrap();
+        let mut dir = txn.read_inode(dir.ino).await.unwrap();
+        dir.fs_flags |= FS_IMMUTABLE_FL;
+        txn.save_inode(&dir).await.unwrap();
+        (txn, dir.ino)
+    }
+
+    fn is_immutable<T>(result: Result<T>, dir: u64) -> bool {
+        matches!(result, Err(FsError::Immutable { ino }) if ino == dir)
+    }
+
+    #[async_std::test]
+    async fn create_in_immutable_dir() {
+        let (mut txn, dir) = immutable_dir().await;
+        let file_mode = make_mode(FileType::RegularFile, 0o644);
+        assert!(is_immutable(
+            txn.make_inode(dir, "new".into(), file_mode, 0, 0, 0).await,
+            dir
+        ));
+        assert!(is_immutable(
+            txn.mkdir(dir, "new".into(), 0o755, 0, 0).await,
+            dir
+        ));
+    }
+
+    #[async_std::test]
+    async fn link_into_immutable_dir() {
+        let (mut txn, dir) = immutable_dir().await;
+        let outside = txn.lookup(ROOT_INODE, "outside".into()).await.unwrap();
+        assert!(is_immutable(
+            txn.link(outside, dir, "linked".into()).await,
+            dir
+        ));
+        assert!(is_immutable(
+            txn.rename(ROOT_INODE, "outside".into(), dir, "moved".into(), 0)
+                .await,
+            dir
+        ));
+    }
+
+    #[async_std::test]
+    async fn exchange_through_immutable_dir() {
+        let (mut txn, dir) = immutable_dir().await;
+        assert!(is_immutable(
+            txn.exchange(ROOT_INODE, "outside".into(), dir, "inside".into(), 0)
+                .await,
+            dir
+        ));
+        assert!(is_immutable(
+            txn.exchange(dir, "inside".into(), ROOT_INODE, "outside".into(), 0)
+                .await,
+            dir
+        ));
+    }
+
+    #[async_std::test]
+    async fn rebase_usage_in_batches() {
+        let (mut txn, _) = immutable_dir().await;
+        let inodes = 4;
+        assert_eq!(None, txn.rebase_usage_batch(3, &[]).await.unwrap());
+        let cursor = txn.read_usage_rebase().await.unwrap().unwrap();
+        assert_eq!(3, cursor.files);
+        let (files, _) = txn.rebase_usage_batch(3, &[]).await.unwrap().unwrap();
+        assert_eq!(inodes, files);
+
+        // a batch stops at a bound even if its page is not full
+        let bounds = [ROOT_INODE + 1];
+        assert_eq!(None, txn.rebase_usage_batch(4, &bounds).await.unwrap());
+        let cursor = txn.read_usage_rebase().await.unwrap().unwrap();
+        assert_eq!((1, ROOT_INODE + 1), (cursor.files, cursor.next));
+        let (files, _) = txn.rebase_usage_batch(4, &bounds).await.unwrap().unwrap();
+        assert_eq!(inodes, files);
+        assert_eq!(None, txn.read_usage_rebase().await.unwrap());
+        let (_, stats) = txn.volume_stats().await.unwrap();
+        assert_eq!(inodes as i64, stats.files);
+    }
+}
@@ -0,0 +1,41 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::FsError;
+
+/// Extended attribute to turn a directory into scratch space, the value is the TTL in seconds.
+pub const TTL_XATTR: &str = "user.tifs.ttl";
+
+/// Interval of sweeping the scratch directories by mounts with `-o expire`.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time to live of the entries in a scratch directory.
+///
+/// Entries not modified for `secs` seconds are unlinked, and expired subdirectories once they
+/// are empty. Subdirectories inherit the TTL, so a whole tree drains over a few sweeps.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Ttl {
+    pub secs: u64,
+}
+
+impl Ttl {
+    pub fn is_expired(&self, mtime: SystemTime) -> bool {
+        mtime + Duration::from_secs(self.secs) <= SystemTime::now()
+    }
+}
+
+impl FromStr for Ttl {
+    type Err = FsError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .trim_end_matches('\0')
+            .trim()
+            .parse()
+            .map(|secs| Ttl { secs })
+            .map_err(|_| FsError::InvalidXattrValue {
+                name: TTL_XATTR.to_owned(),
+            })
+    }
+}
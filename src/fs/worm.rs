@@ -0,0 +1,39 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::error::FsError;
+
+/// Extended attribute to turn a directory into WORM, the value is the grace period in seconds.
+pub const WORM_XATTR: &str = "user.tifs.worm";
+
+/// Write-once-read-many policy of a directory.
+///
+/// Files in the directory become immutable and undeletable `grace` seconds after they are
+/// created, or after the policy is set for the files already there. Subdirectories inherit
+/// the policy, and it cannot be changed or removed once set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Worm {
+    pub grace: u64,
+}
+
+impl Worm {
+    pub fn lock_time(&self, from: SystemTime) -> SystemTime {
+        from + Duration::from_secs(self.grace)
+    }
+}
+
+impl FromStr for Worm {
+    type Err = FsError;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .trim_end_matches('\0')
+            .trim()
+            .parse()
+            .map(|grace| Worm { grace })
+            .map_err(|_| FsError::InvalidXattrValue {
+                name: WORM_XATTR.to_owned(),
+            })
+    }
+}
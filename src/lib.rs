@@ -1,17 +1,37 @@
 #![feature(async_closure)]
 #![feature(array_chunks)]
 #![type_length_limit = "3831949"]
+pub mod bench;
+pub mod cli;
 pub mod fs;
+pub mod loadgen;
+pub mod systemd;
+pub mod telemetry;
+pub mod unmount;
+
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::Path;
 
 use async_std::fs::read_to_string;
 use async_std::path::PathBuf;
+use async_std::task::spawn;
 use fs::async_fs::AsyncFs;
+use fs::browse::{self, serve_browser};
+use fs::notify::notify_changes;
 use fs::tikv_fs::TiFs;
-use fuser::MountOption as FuseMountOption;
+use fuser::{MountOption as FuseMountOption, Session};
 use paste::paste;
-use tracing::debug;
+use tikv_client::Config;
+use tracing::{debug, error, info};
 
+use fs::chaos::Chaos;
 use fs::client::TlsConfig;
+use fs::deny::DeniedOps;
+use fs::dir::ReadDirIsolation;
+use fs::interval::Interval;
+use fs::mode::FileMode;
+use fs::posix::PosixMode;
+use fs::size::{BlockSize, ByteSize};
 
 const DEFAULT_TLS_CONFIG_PATH: &str = "~/.tifs/tls.toml";
 
@@ -19,6 +39,69 @@ fn default_tls_config_path() -> anyhow::Result<PathBuf> {
     Ok(DEFAULT_TLS_CONFIG_PATH.parse()?)
 }
 
+/// Split comma separated options, keeping `key:value` items (e.g. `chaos=latency:50ms,error:1%`)
+/// and the operations of `deny=unlink,rename` with the option they belong to.
+fn split_options(options: &str) -> Vec<String> {
+    let mut splitted: Vec<String> = Vec::new();
+    for item in options.split(',') {
+        match splitted.last_mut() {
+            Some(last) if last.contains('=') && !item.contains('=') && item.contains(':') => {
+                last.push(',');
+                last.push_str(item);
+            }
+            Some(last) if last.starts_with("deny=") && DeniedOps::is_op(item) => {
+                last.push(',');
+                last.push_str(item);
+            }
+            _ => splitted.push(item.to_owned()),
+        }
+    }
+    splitted
+}
+
+/// Options of fuse which are not defined by `MountOption`, passed to fuse as they are.
+const FUSE_OPTIONS: &[&str] = &[
+    "allow_other",
+    "allow_root",
+    "auto_unmount",
+    "default_permissions",
+    "atime",
+    "noatime",
+    "relatime",
+    "strictatime",
+    "nodiratime",
+    "sync",
+    "async",
+    "fsname",
+    "subtype",
+    "max_read",
+];
+
+/// Options of fstab which are consumed by `mount` itself, and are dropped.
+const FSTAB_OPTIONS: &[&str] = &[
+    "defaults", "auto", "noauto", "user", "nouser", "users", "owner", "group", "_netdev", "nofail",
+];
+
+fn option_name(option: &str) -> &str {
+    option.splitn(2, '=').next().unwrap_or("")
+}
+
+/// Edit distance between two option names, to suggest the ones close to a mistyped name.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
 macro_rules! define_options {
     {
         $name: ident ($type: ident) {
@@ -35,7 +118,7 @@ macro_rules! define_options {
         }
         impl $name {
             pub fn to_vec<'a, I: Iterator<Item=&'a str>>(iter: I) -> Vec<Self> {
-                iter.map(|v| v.split(',').map(|v| Self::from(v))).flatten().collect()
+                iter.map(|v| split_options(v).into_iter().map(|v| Self::from(v))).flatten().collect()
             }
             pub fn to_builtin<'a, I: Iterator<Item=&'a Self>>(iter: I) -> Vec<$type> {
                 iter.filter_map(|v| v.into_builtin()).collect()
@@ -48,6 +131,13 @@ macro_rules! define_options {
             }
         }
         paste! {
+            impl $name {
+                /// Names of the options, as they are given by `-o`.
+                pub const NAMES: &'static [&'static str] = &[
+                    $( ( $($optname,)? stringify!([<$opt:lower>]), "" ).0, )*
+                    $( ( $($newoptname,)? stringify!([<$newopt:lower>]), "" ).0, )*
+                ];
+            }
             impl std::str::FromStr for $name {
                 type Err = anyhow::Error;
                 fn from_str(fullopt: &str) -> Result<Self, Self::Err> {
@@ -123,6 +213,62 @@ macro_rules! define_options {
     {@ignore $id: tt $($replacement: tt),* } => { $($replacement),* };
 }
 
+impl MountOption {
+    /// Parse options like `to_vec`, but fail on malformed options and on unknown ones, which
+    /// are neither options of fuse nor of fstab, suggesting the options with similar names.
+    pub fn parse_all<'a, I: Iterator<Item = &'a str>>(iter: I) -> anyhow::Result<Vec<Self>> {
+        let mut options = Vec::new();
+        for item in iter.map(split_options).flatten() {
+            if item.is_empty() {
+                continue;
+            }
+            let option = item
+                .parse::<Self>()
+                .map_err(|err| anyhow::anyhow!("invalid option `{}`: {}", item, err))?;
+            if let MountOption::Unknown(_) = option {
+                let name = option_name(&item);
+                if !FUSE_OPTIONS.contains(&name)
+                    && !FSTAB_OPTIONS.contains(&name)
+                    && !name.starts_with("x-")
+                {
+                    return Err(Self::unknown(&item));
+                }
+            }
+            options.push(option);
+        }
+        Ok(options)
+    }
+
+    fn unknown(item: &str) -> anyhow::Error {
+        let name = option_name(item);
+        let mut similar: Vec<(usize, &str)> = Self::NAMES
+            .iter()
+            .chain(FUSE_OPTIONS)
+            .map(|known| (edit_distance(name, known), *known))
+            .filter(|(distance, known)| {
+                *distance <= 2 || (name.len() >= 3 && known.starts_with(name))
+            })
+            .collect();
+        similar.sort();
+        match similar.first() {
+            Some((_, known)) => {
+                anyhow::anyhow!("unknown option `{}`, did you mean `{}`?", item, known)
+            }
+            None => anyhow::anyhow!("unknown option `{}`", item),
+        }
+    }
+
+    /// Options of fuse which are not defined, to be passed to fuse as they are.
+    pub fn passthrough(&self) -> Option<FuseMountOption> {
+        match self {
+            MountOption::Unknown(option) if FUSE_OPTIONS.contains(&option_name(option)) => {
+                Some(FuseMountOption::CUSTOM(option.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
 define_options! { MountOption (FuseMountOption) {
     builtin Dev,
     builtin NoDev,
@@ -134,8 +280,41 @@ define_options! { MountOption (FuseMountOption) {
     builtin NoExec,
     builtin DirSync,
     define "direct_io" DirectIO,
-    define BlkSize(u64),
+    define BlkSize(BlockSize),
     define Tls(String),
+    define Chaos(Chaos),
+    define Journal,
+    define Notify,
+    define "cache_dir" CacheDir(String),
+    define "cache_size" CacheSize(ByteSize),
+    define Delegation,
+    define "readdir" ReadDir(ReadDirIsolation),
+    define Expire,
+    define "mem_size" MemSize(ByteSize),
+    define "mem_evict" MemEvict,
+    define "store_file" StoreFile(String),
+    define "write_back" WriteBack,
+    define "write_buffer" WriteBuffer,
+    define "root_uid" RootUid(u32),
+    define "root_gid" RootGid(u32),
+    define "root_mode" RootMode(FileMode),
+    define Prefetch(String),
+    define "inode_reuse" InodeReuse,
+    define "retry_warn" RetryWarn(u64),
+    define "fifo_passthrough" FifoPassthrough,
+    define "fuse_threads" FuseThreads(NonZeroUsize),
+    define "no_inline" NoInline,
+    define "hashed_names" HashedNames,
+    define "eof_revalidate" EofRevalidate,
+    define "flush_on_close" FlushOnClose,
+    define Revalidate(Interval),
+    define Deny(DeniedOps),
+    define "degrade_after" DegradeAfter(Interval),
+    define Posix(PosixMode),
+    define "scan_page" ScanPage(NonZeroU32),
+    define "inode_cache" InodeCache(Interval),
+    define "http_browse" HttpBrowse(String),
+    define "contain_symlinks" ContainSymlinks,
 //    define "opt" OptionName(Display_Debug_Clone_PartialEq_FromStr_able)
 }}
 
@@ -274,6 +453,194 @@ mod tests {
             ),
             "[DirectIO, NoDev, BlkSize(32)]"
         );
+        assert_eq!(
+            MountOption::to_vec(
+                vec!["nodev,chaos=latency:50ms,error:0.1%,ops:read+write", "exec"]
+                    .iter()
+                    .map(|v| v.clone())
+            ),
+            vec![
+                MountOption::NoDev,
+                MountOption::Chaos(Chaos {
+                    latency: std::time::Duration::from_millis(50),
+                    eio: 0.001,
+                    eagain: 0.,
+                    ops: vec!["read".to_owned(), "write".to_owned()],
+                }),
+                MountOption::Exec,
+            ]
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                MountOption::to_vec(vec!["chaos=error:200%"].iter().map(|v| v.clone()))
+            ),
+            "[Unknown(\"chaos=error:200%\")]"
+        );
+        assert_eq!(
+            MountOption::to_vec(
+                vec!["cache_dir=/var/cache/tifs,cache_size=10G"]
+                    .iter()
+                    .map(|v| v.clone())
+            ),
+            vec![
+                MountOption::CacheDir("/var/cache/tifs".to_owned()),
+                MountOption::CacheSize(ByteSize(10 << 30)),
+            ]
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["readdir=relaxed"].iter().map(|v| v.clone())),
+            vec![MountOption::ReadDir(ReadDirIsolation::Relaxed)]
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                MountOption::to_vec(vec!["readdir=stale"].iter().map(|v| v.clone()))
+            ),
+            "[Unknown(\"readdir=stale\")]"
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["mem_size=2G,mem_evict"].iter().map(|v| v.clone())),
+            vec![
+                MountOption::MemSize(ByteSize(2 << 30)),
+                MountOption::MemEvict,
+            ]
+        );
+        assert_eq!(
+            MountOption::to_vec(
+                vec!["store_file=/var/lib/tifs/mem.img"]
+                    .iter()
+                    .map(|v| v.clone())
+            ),
+            vec![MountOption::StoreFile("/var/lib/tifs/mem.img".to_owned())]
+        );
+        assert_eq!(
+            MountOption::to_vec(
+                vec!["revalidate=5s,revalidate=500ms"]
+                    .iter()
+                    .map(|v| v.clone())
+            ),
+            vec![
+                MountOption::Revalidate(Interval(std::time::Duration::from_secs(5))),
+                MountOption::Revalidate(Interval(std::time::Duration::from_millis(500))),
+            ]
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                MountOption::to_vec(vec!["revalidate=5h"].iter().map(|v| v.clone()))
+            ),
+            "[Unknown(\"revalidate=5h\")]"
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["deny=unlink,rename,ro"].iter().map(|v| v.clone())),
+            vec![
+                MountOption::Deny("unlink,rename".parse().unwrap()),
+                MountOption::RO,
+            ]
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                MountOption::to_vec(vec!["deny=unlink:remove"].iter().map(|v| v.clone()))
+            ),
+            "[Unknown(\"deny=unlink:remove\")]"
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["degrade_after=30s"].iter().map(|v| v.clone())),
+            vec![MountOption::DegradeAfter(Interval(
+                std::time::Duration::from_secs(30)
+            ))]
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["posix=strict"].iter().map(|v| v.clone())),
+            vec![MountOption::Posix(PosixMode::Strict)]
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                MountOption::to_vec(vec!["posix=loose"].iter().map(|v| v.clone()))
+            ),
+            "[Unknown(\"posix=loose\")]"
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["scan_page=256"].iter().map(|v| v.clone())),
+            vec![MountOption::ScanPage(NonZeroU32::new(256).unwrap())]
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                MountOption::to_vec(vec!["scan_page=0"].iter().map(|v| v.clone()))
+            ),
+            "[Unknown(\"scan_page=0\")]"
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["inode_cache=500ms"].iter().map(|v| v.clone())),
+            vec![MountOption::InodeCache(Interval(
+                std::time::Duration::from_millis(500)
+            ))]
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["http_browse=127.0.0.1:8080"].iter().map(|v| v.clone())),
+            vec![MountOption::HttpBrowse("127.0.0.1:8080".to_owned())]
+        );
+        assert_eq!(
+            MountOption::to_vec(vec!["contain_symlinks"].iter().map(|v| v.clone())),
+            vec![MountOption::ContainSymlinks]
+        );
+    }
+
+    #[test]
+    fn strict_mount_options() {
+        assert_eq!(
+            MountOption::parse_all(
+                vec!["direct_io,allow_other,noauto,x-systemd.automount"].into_iter()
+            )
+            .unwrap(),
+            vec![
+                MountOption::DirectIO,
+                MountOption::Unknown("allow_other".to_owned()),
+                MountOption::Unknown("noauto".to_owned()),
+                MountOption::Unknown("x-systemd.automount".to_owned()),
+            ]
+        );
+        assert_eq!(
+            MountOption::parse_all(vec!["direct-io"].into_iter())
+                .unwrap_err()
+                .to_string(),
+            "unknown option `direct-io`, did you mean `direct_io`?"
+        );
+        assert_eq!(
+            MountOption::parse_all(vec!["frobnicate"].into_iter())
+                .unwrap_err()
+                .to_string(),
+            "unknown option `frobnicate`"
+        );
+        assert!(MountOption::parse_all(vec!["blksize=big"].into_iter()).is_err());
+        assert!(MountOption::parse_all(vec!["blksize=0"].into_iter()).is_err());
+        assert!(MountOption::parse_all(vec!["blksize=1048576"].into_iter()).is_err());
+        assert!(MountOption::parse_all(vec!["journal=1"].into_iter()).is_err());
+        assert_eq!(
+            MountOption::Unknown("allow_other".to_owned()).passthrough(),
+            Some(FuseMountOption::CUSTOM("allow_other".to_owned()))
+        );
+        assert_eq!(
+            MountOption::Unknown("noauto".to_owned()).passthrough(),
+            None
+        );
+    }
+
+    #[test]
+    fn round_trip_mount_options() {
+        let given = "ro,user,noauto,x-systemd.automount,allow_other,readdir=relaxed,cache_size=512M,blksize=65536";
+        let options = MountOption::parse_all(vec![given].into_iter()).unwrap();
+        assert!(options.contains(&MountOption::RO));
+        let serialized: Vec<String> = options.iter().map(String::from).collect();
+        assert_eq!(serialized.join(","), given);
+        assert_eq!(
+            MountOption::parse_all(serialized.iter().map(String::as_str)).unwrap(),
+            options
+        );
     }
 
     #[test]
@@ -287,15 +654,39 @@ mod tests {
             Some(FuseMountOption::DirSync)
         );
         assert_eq!(MountOption::DirectIO.into_builtin(), None);
-        assert_eq!(MountOption::BlkSize(123).into_builtin(), None);
+        assert_eq!(MountOption::BlkSize(BlockSize(123)).into_builtin(), None);
     }
 
     #[test]
     fn format_mount_options() {
         assert_eq!(String::from(MountOption::NoDev), "nodev");
         assert_eq!(String::from(MountOption::DirectIO), "direct_io");
-        assert_eq!(String::from(MountOption::BlkSize(123)), "blksize=123");
-        assert_eq!(String::from(MountOption::BlkSize(0)), "blksize=0");
+        assert_eq!(
+            String::from(MountOption::BlkSize(BlockSize(123))),
+            "blksize=123"
+        );
+        assert_eq!(
+            String::from(MountOption::BlkSize(BlockSize(0))),
+            "blksize=0"
+        );
+        assert_eq!(
+            String::from(MountOption::CacheSize(ByteSize(512 << 20))),
+            "cache_size=512M"
+        );
+        assert_eq!(
+            String::from(MountOption::RootMode(FileMode(0o1777))),
+            "root_mode=1777"
+        );
+        assert_eq!(
+            String::from(MountOption::RootMode(FileMode(0o750))),
+            "root_mode=0750"
+        );
+        assert_eq!(
+            "root_mode=755".parse::<MountOption>().unwrap(),
+            MountOption::RootMode(FileMode(0o755))
+        );
+        assert!("root_mode=10000".parse::<MountOption>().is_err());
+        assert!("root_mode=0789".parse::<MountOption>().is_err());
     }
 }
 
@@ -314,11 +705,162 @@ where
         FuseMountOption::DefaultPermissions,
     ];
 
+    // unmount by fusermount if the daemon dies without unmounting, e.g. by SIGKILL
     #[cfg(target_os = "linux")]
     fuse_options.push(FuseMountOption::AutoUnmount);
 
     fuse_options.extend(MountOption::to_builtin(options.iter()));
+    fuse_options.extend(options.iter().filter_map(MountOption::passthrough));
+
+    let notify = options
+        .iter()
+        .find(|option| matches!(option, MountOption::Notify))
+        .is_some();
+    let delegation = options
+        .iter()
+        .find(|option| matches!(option, MountOption::Delegation))
+        .is_some();
+    let expire = options
+        .iter()
+        .find(|option| matches!(option, MountOption::Expire))
+        .is_some();
+    let store_file = options
+        .iter()
+        .find(|option| matches!(option, MountOption::StoreFile(_)))
+        .is_some();
+    let write_back = options
+        .iter()
+        .find(|option| matches!(option, MountOption::WriteBack))
+        .is_some();
+    let write_buffer = options
+        .iter()
+        .find(|option| matches!(option, MountOption::WriteBuffer))
+        .is_some();
+    let prefetch = options.iter().find_map(|option| {
+        if let MountOption::Prefetch(path) = option {
+            Some(path.clone())
+        } else {
+            None
+        }
+    });
+    // read before mounting, so a missing list fails the mount
+    let prefetch = match prefetch {
+        Some(path) => Some((read_to_string(&path).await?, path)),
+        None => None,
+    };
+    let http_browse = options.iter().find_map(|option| {
+        if let MountOption::HttpBrowse(addr) = option {
+            Some(addr.clone())
+        } else {
+            None
+        }
+    });
+    // bind before mounting, so an address in use fails the mount
+    let browser = match http_browse {
+        Some(addr) => Some(browse::bind(&addr).await?),
+        None => None,
+    };
+    let client_cfg = load_client_config(&options).await?;
+
+    debug!("mount_tifs, config: {:?}", client_cfg);
+    let fs_impl = TiFs::construct(endpoints, client_cfg, options).await?;
+
+    let fs = AsyncFs::from(fs_impl);
+    let fs_impl = fs.inner();
+    let mut session = Session::new(fs, Path::new(&mountpoint), &fuse_options)?;
+
+    // daemonize once mounted, so failures to mount are seen by the caller of `mount.tifs`
+    make_daemon()?;
+    if let Err(err) = unmount::install_hooks(Path::new(&mountpoint)) {
+        error!(
+            "fail to install the hooks unmounting {}: {}",
+            mountpoint, err
+        );
+    }
+
+    if delegation {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_delegations().await });
+    }
+    if expire {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_expiry().await });
+    }
+    if store_file {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_saving().await });
+    }
+    if write_back {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_write_back().await });
+    }
+    if write_buffer {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_write_buffers().await });
+    }
+    if let Some((list, path)) = prefetch {
+        let fs_impl = fs_impl.clone();
+        spawn(async move {
+            match fs_impl.prefetch(&list).await {
+                Ok(pinned) => info!("prefetched {} directories listed in {}", pinned, path),
+                Err(err) => error!("fail to prefetch directories listed in {}: {}", path, err),
+            }
+        });
+    }
+    {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_pinning().await });
+    }
+    {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_deletion().await });
+    }
+    {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_stats().await });
+    }
+    {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_clients().await });
+    }
+    {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_freeze().await });
+    }
+    {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_atimes().await });
+    }
+    if let Some(listener) = browser {
+        let fs_impl = fs_impl.clone();
+        spawn(async move {
+            if let Err(err) = serve_browser(fs_impl, listener).await {
+                error!("stop serving the http browser: {}", err);
+            }
+        });
+    }
+    if let Some(interval) = systemd::watchdog_interval() {
+        let fs_impl = fs_impl.clone();
+        spawn(async move { fs_impl.serve_watchdog(interval).await });
+    }
+    if notify {
+        let notifier = session.notifier();
+        spawn(async move {
+            if let Err(err) = notify_changes(&fs_impl, notifier).await {
+                error!("stop notifying changes of other mounts: {}", err);
+            }
+        });
+    }
+
+    systemd::notify_or_warn("READY=1");
+    session.run()?;
+    systemd::notify_or_warn("STOPPING=1");
 
+    Ok(())
+}
+
+/// Load the tls config from the path in the `tls` option or the default path.
+pub async fn load_client_config(options: &[MountOption]) -> anyhow::Result<Config> {
     let tls_cfg_path = options
         .iter()
         .find_map(|opt| {
@@ -336,15 +878,7 @@ where
     } else {
         Default::default()
     };
-
-    debug!("mount_tifs, config: {:?}", client_cfg);
-    let fs_impl = TiFs::construct(endpoints, client_cfg, options).await?;
-
-    make_daemon()?;
-
-    fuser::mount2(AsyncFs::from(fs_impl), mountpoint, &fuse_options)?;
-
-    Ok(())
+    Ok(client_cfg)
 }
 
 pub async fn mount_tifs(
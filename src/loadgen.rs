@@ -0,0 +1,201 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use async_std::task::spawn;
+use bytes::Bytes;
+use bytestring::ByteString;
+use fuser::FileType;
+
+use crate::bench::percentile;
+use crate::fs::key::ROOT_INODE;
+use crate::fs::mode::{make_mode, FileMode};
+use crate::fs::stats::OpStats;
+use crate::fs::tikv_fs::TiFs;
+
+/// Synthetic load driven through `Txn` directly, bypassing FUSE and `AsyncFileSystem`.
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    pub files: usize,
+    pub file_size: u64,
+    pub concurrency: usize,
+    /// Max bytes written by one transaction, larger files take several transactions.
+    pub txn_size: u64,
+    /// Keep the generated files instead of removing them after the run.
+    pub keep: bool,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            files: 10000,
+            file_size: 1 << 20,
+            concurrency: 16,
+            txn_size: 1 << 20,
+            keep: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub files: usize,
+    pub bytes: u64,
+    pub stats: OpStats,
+    pub elapsed: Duration,
+    /// Time to create and fill each file.
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    fn merge(&mut self, other: LoadReport) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.stats.gets += other.stats.gets;
+        self.stats.puts += other.stats.puts;
+        self.stats.deletes += other.stats.deletes;
+        self.stats.scans += other.stats.scans;
+        self.stats.read_bytes += other.stats.read_bytes;
+        self.stats.written_bytes += other.stats.written_bytes;
+        self.stats.retries += other.stats.retries;
+        self.latencies.extend(other.latencies);
+    }
+
+    pub fn print(&self) {
+        let secs = self.elapsed.as_secs_f64();
+        println!("files:       {}", self.files);
+        println!("bytes:       {}", self.bytes);
+        println!("elapsed:     {:?}", self.elapsed);
+        println!("files/s:     {:.2}", self.files as f64 / secs);
+        println!(
+            "MiB/s:       {:.2}",
+            self.bytes as f64 / (1 << 20) as f64 / secs
+        );
+        println!("retries:     {}", self.stats.retries);
+        println!(
+            "tikv ops:    {} gets, {} puts, {} deletes, {} scans",
+            self.stats.gets, self.stats.puts, self.stats.deletes, self.stats.scans
+        );
+        println!(
+            "latency:     p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            percentile(&self.latencies, 0.5),
+            percentile(&self.latencies, 0.9),
+            percentile(&self.latencies, 0.99),
+            percentile(&self.latencies, 1.0),
+        );
+    }
+}
+
+async fn make_dir(fs: &TiFs, parent: u64, name: String, uid: u32, gid: u32) -> Result<u64> {
+    let name: ByteString = name.into();
+    let inode = fs
+        .spin(None, move |_, txn| {
+            Box::pin(txn.mkdir(parent, name.clone(), 0o755, gid, uid))
+        })
+        .await?;
+    Ok(inode.ino)
+}
+
+async fn remove_dir(fs: &TiFs, parent: u64, name: String) -> Result<()> {
+    let name: ByteString = name.into();
+    fs.spin(None, move |_, txn| {
+        Box::pin(txn.rmdir(parent, name.clone(), 0))
+    })
+    .await?;
+    Ok(())
+}
+
+async fn worker(
+    fs: Arc<TiFs>,
+    dir: u64,
+    files: usize,
+    cfg: LoadConfig,
+    uid: u32,
+    gid: u32,
+) -> Result<LoadReport> {
+    let mut report = LoadReport::default();
+    let chunk = cfg.txn_size.min(cfg.file_size).max(1);
+    let data = Bytes::from(vec![0x5a; chunk as usize]);
+    let mode = make_mode(FileType::RegularFile, 0o644);
+
+    for i in 0..files {
+        let start = Instant::now();
+        let name: ByteString = format!("file-{}", i).into();
+        let inode = fs
+            .spin(None, move |_, txn| {
+                Box::pin(txn.make_inode(dir, name.clone(), mode, gid, uid, 0))
+            })
+            .await?;
+
+        let ino = inode.ino;
+        let mut offset = 0;
+        while offset < cfg.file_size {
+            let len = chunk.min(cfg.file_size - offset);
+            let data = data.slice(..len as usize);
+            fs.spin(None, move |_, txn| {
+                Box::pin(txn.write_data(ino, offset, data.clone()))
+            })
+            .await?;
+            offset += len;
+        }
+
+        report.latencies.push(start.elapsed());
+        report.files += 1;
+        report.bytes += cfg.file_size;
+    }
+
+    if !cfg.keep {
+        for i in 0..files {
+            let name: ByteString = format!("file-{}", i).into();
+            fs.spin(None, move |_, txn| {
+                let name = name.clone();
+                Box::pin(async move {
+                    let ino = txn.lookup(dir, name.clone()).await?;
+                    txn.clear_data(ino).await?;
+                    txn.unlink(dir, name, 0).await
+                })
+            })
+            .await?;
+        }
+    }
+
+    report.stats = OpStats::take();
+    Ok(report)
+}
+
+/// Run the load, every worker fills its own directory to keep directory conflicts out of the
+/// picture, so contention comes from the cluster and the shared inode counter only.
+pub async fn generate_load(fs: TiFs, cfg: LoadConfig) -> Result<LoadReport> {
+    if cfg.concurrency == 0 {
+        return Err(anyhow!("concurrency must be greater than 0"));
+    }
+
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    fs.init_volume(gid, uid, FileMode::DEFAULT_ROOT).await?;
+
+    let root_name = format!("tifs-load-{}", std::process::id());
+    let root = make_dir(&fs, ROOT_INODE, root_name.clone(), uid, gid).await?;
+
+    let fs = Arc::new(fs);
+    let start = Instant::now();
+    let mut workers = Vec::with_capacity(cfg.concurrency);
+    for id in 0..cfg.concurrency {
+        let files = cfg.files / cfg.concurrency + (id < cfg.files % cfg.concurrency) as usize;
+        let dir = make_dir(&fs, root, format!("worker-{}", id), uid, gid).await?;
+        workers.push(spawn(worker(fs.clone(), dir, files, cfg.clone(), uid, gid)));
+    }
+
+    let mut report = LoadReport::default();
+    for worker in workers {
+        report.merge(worker.await?);
+    }
+    report.elapsed = start.elapsed();
+
+    if !cfg.keep {
+        for id in 0..cfg.concurrency {
+            remove_dir(&fs, root, format!("worker-{}", id)).await?;
+        }
+        remove_dir(&fs, ROOT_INODE, root_name).await?;
+    }
+    Ok(report)
+}
@@ -0,0 +1,81 @@
+//! Readiness and watchdog notifications to systemd, by the protocol of sd_notify(3).
+//!
+//! Both are no-ops unless the process runs as a unit with `Type=notify` or `WatchdogSec=`.
+use std::env;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use tracing::warn;
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+/// Send a state like `READY=1` to the notify socket of systemd.
+/// Returns false if the process is not run by systemd.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os(NOTIFY_SOCKET) {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+    let path = path.as_bytes();
+
+    // abstract sockets start with `@`, which is a nul byte in the address
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    if path.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "NOTIFY_SOCKET is too long",
+        ));
+    }
+    for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = *src as libc::c_char;
+    }
+    if path[0] == b'@' {
+        addr.sun_path[0] = 0;
+    }
+    let len = mem::size_of::<libc::sa_family_t>() + path.len();
+
+    let socket = UnixDatagram::unbound()?;
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            state.as_ptr() as *const libc::c_void,
+            state.len(),
+            libc::MSG_NOSIGNAL,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(true)
+}
+
+/// Like `notify`, but failures are only logged, as the mount works without systemd.
+pub fn notify_or_warn(state: &str) {
+    if let Err(err) = notify(state) {
+        warn!("fail to notify systemd of {:?}: {}", state, err);
+    }
+}
+
+/// The interval of `WATCHDOG=1` pings expected by systemd, half of `WatchdogSec=` as
+/// recommended by sd_watchdog_enabled(3), or `None` if the watchdog is disabled.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var(WATCHDOG_PID) {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var(WATCHDOG_USEC).ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,53 @@
+use tracing_libatrace as tracing_atrace;
+use tracing_subscriber::{layer::SubscriberExt, registry::Registry};
+
+/// Endpoint of the OTLP collector, spans are only exported if it is set. Only traces are
+/// exported, metrics stay in the local counters.
+#[cfg(feature = "otlp")]
+pub const OTLP_ENDPOINT_ENV: &str = "TIFS_OTLP_ENDPOINT";
+
+/// Keeps the exporters alive, pending spans are flushed when it is dropped.
+#[derive(Default)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    _otlp: Option<opentelemetry_otlp::Uninstall>,
+}
+
+pub fn setup_global_subscriber() -> TelemetryGuard {
+    let layer = tracing_atrace::layer()
+        .unwrap()
+        .with_data_field(Option::Some("data".to_string()));
+    let subscriber = Registry::default().with(layer);
+
+    #[cfg(feature = "otlp")]
+    {
+        if let Ok(endpoint) = std::env::var(OTLP_ENDPOINT_ENV) {
+            match opentelemetry_otlp::new_pipeline()
+                .with_endpoint(&endpoint)
+                .with_service_name("tifs")
+                .install()
+            {
+                Ok((tracer, uninstall)) => {
+                    let subscriber =
+                        subscriber.with(tracing_opentelemetry::layer().with_tracer(tracer));
+                    tracing::subscriber::set_global_default(subscriber).unwrap();
+                    return TelemetryGuard {
+                        _otlp: Some(uninstall),
+                    };
+                }
+                Err(err) => {
+                    tracing::subscriber::set_global_default(subscriber).unwrap();
+                    tracing::warn!(
+                        "cannot export spans to OTLP endpoint {}, tracing locally only: {}",
+                        endpoint,
+                        err
+                    );
+                    return TelemetryGuard::default();
+                }
+            }
+        }
+    }
+
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+    TelemetryGuard::default()
+}
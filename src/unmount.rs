@@ -0,0 +1,79 @@
+//! Best-effort unmounting when the daemon is killed or panics, so the mount point is not left
+//! wedged with a dead connection until a manual `fusermount -u`.
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use tracing::{error, info};
+
+/// The mount point, leaked for the signal handler, which cannot allocate.
+static MOUNTPOINT: AtomicPtr<libc::c_char> = AtomicPtr::new(ptr::null_mut());
+
+/// Signals which stop the daemon, `SIGKILL` cannot be handled and is left to `auto_unmount`.
+const STOP_SIGNALS: &[libc::c_int] = &[libc::SIGTERM, libc::SIGINT, libc::SIGHUP];
+
+/// Detach the mount by umount2(2), which needs privileges, or by fusermount otherwise.
+pub fn unmount(mountpoint: &Path) -> io::Result<()> {
+    let path = CString::new(mountpoint.as_os_str().as_bytes())?;
+    if unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) } == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    for fusermount in &["fusermount3", "fusermount"] {
+        let status = Command::new(fusermount)
+            .args(&["-u", "-z"])
+            .arg(mountpoint)
+            .stdin(Stdio::null())
+            .status();
+        if let Ok(status) = status {
+            if status.success() {
+                return Ok(());
+            }
+        }
+    }
+    Err(err)
+}
+
+extern "C" fn on_stop_signal(signal: libc::c_int) {
+    // detaching the mount makes the session return and the daemon exit as it's unmounted,
+    // otherwise die by the signal and leave the mount point to `auto_unmount`.
+    let path = MOUNTPOINT.load(Ordering::SeqCst);
+    unsafe {
+        if path.is_null() || libc::umount2(path, libc::MNT_DETACH) != 0 {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+/// Unmount `mountpoint` if the daemon is stopped by a signal or panics. A panic aborts the
+/// daemon after unmounting, as the mount cannot be served consistently anymore.
+pub fn install_hooks(mountpoint: &Path) -> io::Result<()> {
+    let path = CString::new(mountpoint.as_os_str().as_bytes())?;
+    let old = MOUNTPOINT.swap(path.into_raw(), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { CString::from_raw(old) });
+    }
+    for signal in STOP_SIGNALS {
+        let handler = on_stop_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(*signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let mountpoint = mountpoint.to_owned();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        match unmount(&mountpoint) {
+            Ok(()) => info!("unmounted {:?} after panic", mountpoint),
+            Err(err) => error!("fail to unmount {:?} after panic: {}", mountpoint, err),
+        }
+        std::process::abort();
+    }));
+    Ok(())
+}